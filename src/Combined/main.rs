@@ -0,0 +1,85 @@
+//! # Combined Module
+//!
+//! Runs the client, the messaging server and the syslog receiver in one process for single
+//! box installs. The client's emails are handed to the messaging server over a channel
+//! instead of its socket, everything else is the same as running them separately.
+
+use shared::{
+    client::{self, warm_state},
+    emails::Email,
+    log_file::warn,
+    mail_server,
+    notify::route_mail_in_process,
+    ssh_logger,
+};
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+fn main() {
+    // Only the client's shutdown thread may see the shutdown signals, so it can save its state
+    if let Err(e) = warm_state::block_shutdown_signals() {
+        warn(&format!(
+            "The client's state won't be saved on shutdown: {}",
+            e
+        ));
+    }
+
+    let (sender, receiver): (Sender<Email>, Receiver<Email>) = mpsc::channel();
+    if !route_mail_in_process(sender) {
+        warn("Emails were already routed, the client will use the messaging server's socket");
+    }
+
+    // The messaging server goes first so it's listening by the client's preflight
+    thread::spawn(move || mail_server::run(Some(receiver)));
+    thread::spawn(ssh_logger::main);
+
+    client::main();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{
+        ais_config::AisConfig,
+        emails::{EmailCategory, EmailPriority},
+        mail_server::receive_in_process,
+        notify::{EmailNotifier, Notifier},
+    };
+    use std::{
+        sync::{Arc, RwLock},
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test_in_process_email_round_trips() {
+        let (sender, receiver) = mpsc::channel();
+        assert!(route_mail_in_process(sender));
+
+        let emails = Arc::new(RwLock::new(Vec::new()));
+        {
+            let emails = Arc::clone(&emails);
+            let config = Arc::new(AisConfig::default());
+            thread::spawn(move || receive_in_process(receiver, emails, config));
+        }
+
+        let email = Email {
+            subject: "Service stopped".to_owned(),
+            body: "apache2.service stopped".to_owned(),
+            priority: EmailPriority::High,
+            category: EmailCategory::ServiceStatus,
+        };
+        EmailNotifier.notify(&email).unwrap();
+
+        // Queued by the messaging server without touching its socket or dusa
+        let started = Instant::now();
+        while emails.read().unwrap().is_empty() && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let queued = emails.read().unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].email.subject, email.subject);
+        assert_eq!(queued[0].email.category, EmailCategory::ServiceStatus);
+    }
+}
@@ -1,6 +1,7 @@
-use std::process::Command;
+use std::{process::Command, time::Duration};
 use hostname::set;
 use pretty::{halt, notice, output};
+use shared::command::run_with_timeout;
 use shared::errors::*;
 use shared::service::Services;
 use shared::{ais_data::AisInfo, service::ProcessInfo};
@@ -60,7 +61,7 @@ fn main() {
             let ssh_unit = match systemctl::Unit::from_systemctl(&ssh_process.unwrap().service) {
                 Ok(d) => d,
                 Err(err) => {
-                    halt(&format!("{}", &err.to_string()));
+                    halt(&err.to_string());
                     panic!();
                 }
             };
@@ -72,11 +73,10 @@ fn main() {
             };
 
             // Delete SSH keys
-            if let Err(err) = Command::new("rm")
-                .arg("-f")
-                .arg("/etc/ssh/ssh_host_*")
-                .status()
-            {
+            if let Err(err) = run_with_timeout(
+                Command::new("rm").arg("-f").arg("/etc/ssh/ssh_host_*"),
+                Duration::from_secs(30),
+            ) {
                 halt(&format!("Failed to delete SSH keys: {}", err));
             }
 
@@ -89,23 +89,7 @@ fn main() {
             // Creating a new manifest
             let ais_result: UnifiedErrorResult<AisInfo> = UnifiedErrorResult::new(AisInfo::new());
             let mut ais_data: AisInfo = ais_result.unwrap();
-            ais_data.machine_id = Some(
-                truncate(
-                    &create_hash(format!(
-                        "{}{}",
-                        &ais_data
-                            .clone()
-                            .machine_ip
-                            .unwrap_or(String::from("10.1.0.255")),
-                        &ais_data
-                            .clone()
-                            .machine_id
-                            .unwrap_or(String::from("00:00:00:00:00"))
-                    )),
-                    16,
-                )
-                .to_owned(),
-            );
+            ais_data.machine_id = Some(ais_data.machine_fingerprint());
 
             let _ = ais_data.create_manifest();
             //  Generating the new hostname
@@ -118,12 +102,15 @@ fn main() {
             match set(new_hostname.clone()) {
                 Ok(()) => {
                     // Regester it on the network 
-                    let output = Command::new("/sbin/dhclient")
-                    .output()
-                    .expect("Failed to execute command");
-                    match output.status.success() {
-                        true => println!("Hostname set successfully to: {}", new_hostname),
-                        false => halt("Error setting hostname")
+                    match run_with_timeout(
+                        &mut Command::new("/sbin/dhclient"),
+                        Duration::from_secs(60),
+                    ) {
+                        Ok(output) if output.status.success() => {
+                            println!("Hostname set successfully to: {}", new_hostname)
+                        }
+                        Ok(_) => halt("Error setting hostname"),
+                        Err(err) => halt(&format!("Failed to register the hostname: {}", err)),
                     }
                 }
                 Err(err) => halt(&format!("Failed to set hostname: {}", err)),
@@ -22,13 +22,13 @@ mod artisan {
     use rustpython_vm::builtins::PyStrRef;
     use shared::{
         ais_data::AisInfo,
-        emails::{Email, EmailSecure},
+        emails::{Email, EmailSecure, SigningKey},
         encrypt::Commands,
         errors::UnifiedErrorResult,
     };
 
     fn get_ais_info() -> AisInfo {
-        let d = AisInfo::new().unwrap();
+        let d = AisInfo::new_cached().unwrap();
         return d;
     }
 
@@ -59,15 +59,12 @@ mod artisan {
 
     #[pyfunction]
     fn send_email(subject: PyStrRef, body: PyStrRef) -> bool {
-        let message: Email = Email {
-            subject: subject.to_string(),
-            body: body.to_string(),
-        };
+        let message: Email = Email::new(subject.to_string(), body.to_string());
 
         let message_secure: EmailSecure =
             UnifiedErrorResult::new(EmailSecure::new(message)).unwrap();
 
-        match message_secure.send() {
+        match SigningKey::init().and_then(|_| message_secure.send()) {
             Ok(_) => return true,
             Err(e) => {
                 output("RED", &format!("Unified error: {}", e));
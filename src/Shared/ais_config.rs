@@ -0,0 +1,693 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::Read,
+    net::{IpAddr, SocketAddr},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    emails::{DropPolicy, EmailCategory, MailEndpoint, SmtpTls},
+    errors::{AisError, UnifiedError},
+    git_data::CloneScheme,
+    notify::{EscalationStage, NotifierConfig},
+    retention::{Retention, RetentionCategory},
+};
+use serde::{Deserialize, Serialize};
+use system::{path_present, PathType};
+
+/// Struct representing the operator configurable settings of the Ais system.
+///
+/// Every field has a default so a missing or partial config file still produces a usable config.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct AisConfig {
+    /// The scheme used when cloning repositories from github.
+    pub clone_scheme: CloneScheme,
+    /// The directory every site is checked out under.
+    pub site_root: PathBuf,
+    /// Where clients send emails and the messaging server listens, like
+    /// `unix:/run/ais/mail.sock` when both are on the same box. Clients use the profile's
+    /// messaging server and the server listens on every interface when unset.
+    pub mail_endpoint: Option<String>,
+    /// Refuses to start the client when the messaging server can't be reached, rather than
+    /// only warning about it.
+    pub mail_preflight_strict: bool,
+    /// The maximum number of emails the messaging server holds before applying the drop policy.
+    pub mail_queue_capacity: usize,
+    /// What the messaging server does with new emails when its queue is full.
+    pub mail_drop_policy: DropPolicy,
+    /// The most emails of a category the messaging server sends a minute, like
+    /// `{"SshAudit": 3}`, on top of its overall limit. The rest wait in the queue.
+    pub mail_category_limits: HashMap<EmailCategory, usize>,
+    /// More emails of one category than this arriving within the storm window is a storm, the
+    /// messaging server sends them as digests until the rate drops. 0 turns this off.
+    pub mail_storm_threshold: usize,
+    /// The window storms are counted over, also how often a digest is sent during one.
+    pub mail_storm_window_secs: u64,
+    /// How the messaging server secures its connection to the smtp relay.
+    pub smtp_tls: SmtpTls,
+    /// The smtp relay's port, the standard port for the tls mode is used when unset.
+    pub smtp_port: Option<u16>,
+    /// How long the messaging server waits on the smtp relay before giving up.
+    pub smtp_timeout_secs: u64,
+    /// Logs the metadata of dusa requests, the AIS_DUSA_DEBUG env var overrides this.
+    pub dusa_debugging: bool,
+    /// The longest dusa requests are held back for while encryption is unavailable. The wait
+    /// starts at half a second and doubles after every failed request up to this.
+    pub dusa_backoff_max_secs: u64,
+    /// Git actions on a site that take longer than this many seconds are reported as slow.
+    pub slow_git_threshold_secs: u64,
+    /// The most sites updated at once, each clone or pull holds one slot while it talks to
+    /// the remote. Raise it on boxes with a wide uplink and many sites.
+    pub git_concurrency: usize,
+    /// Removes untracked files from a checkout before deploying into it, so build artifacts
+    /// left behind can't block the update. Ignored files are never removed.
+    pub clean_untracked: bool,
+    /// Untracked paths in a checkout that must survive a clean, like `uploads`. A site with
+    /// any of them present isn't cleaned at all.
+    pub clean_keep: Vec<String>,
+    /// Sites aren't cloned or pulled while the filesystem holding the site root has less than
+    /// this many megabytes free, so a deploy can't fill the disk part way through.
+    pub min_deploy_space_mb: u64,
+    /// Where a record of every deploy is kept, one log per site. It's outside the checkout so
+    /// it survives re-clones, and has to be writable by www-data.
+    pub deploy_log_dir: PathBuf,
+    /// Syslog relays allowed to send to the ssh logger, localhost is always allowed.
+    pub syslog_relays: Vec<IpAddr>,
+    /// The most syslog packets a single source can send per minute before the rest are dropped.
+    pub syslog_rate_limit: u32,
+    /// Services escalated with a high priority email as soon as they stop, other services
+    /// stopping is only logged.
+    pub critical_services: Vec<String>,
+    /// Units monitored alongside the built in services, like `app@*.service` for every
+    /// instance of a templated unit. The instances running when the client starts are
+    /// monitored from then on.
+    pub service_patterns: Vec<String>,
+    /// Tries restarting a critical service that stopped before escalating it.
+    pub restart_critical_services: bool,
+    /// Sends the "Service running" notice for a service that comes up right after the client
+    /// starts, not only for ones that start once it's been watching them.
+    pub announce_first_running: bool,
+    /// How many of the unit's latest journal lines are included when a service stops or fails,
+    /// 0 leaves them out.
+    pub journal_excerpt_lines: usize,
+    /// The channels alerts are delivered through, every one of them is used.
+    pub notifiers: Vec<NotifierConfig>,
+    /// Who is notified, in order, as a stopped service stays down. Each stage's delay is
+    /// counted from when the service stopped.
+    pub escalation: Vec<EscalationStage>,
+    /// Logins that are expected, like service accounts, and aren't audited. A user logging in
+    /// from a source that isn't listed is still audited.
+    pub ssh_allow_list: Vec<SshAllowEntry>,
+    /// Encrypts the records kept on disk, like the mail audit log, through dusa. When dusa
+    /// can't be reached records of sensitive categories are dropped rather than written in
+    /// plain text.
+    pub encrypt_at_rest: bool,
+    /// Mirrors the operational output to this file as well as the journal, for boxes where
+    /// the journal isn't kept long enough.
+    pub log_file: Option<PathBuf>,
+    /// The log file is rotated once it grows past this many bytes.
+    pub log_file_max_bytes: u64,
+    /// How many rotated log files are kept, the oldest is deleted first.
+    pub log_file_retain: usize,
+    /// Alerts when the five minute load average per cpu stays above this, 0 turns it off.
+    pub load_alert_threshold: f64,
+    /// Alerts when the percentage of memory in use stays above this, 0 turns it off.
+    pub memory_alert_percent: f64,
+    /// Alerts when the filesystem holding the site root stays fuller than this percentage,
+    /// 0 turns it off.
+    pub disk_alert_percent: f64,
+    /// How many samples in a row, taken a minute apart, a resource has to stay over its
+    /// threshold before it's alerted on, so brief spikes are ignored.
+    pub resource_alert_samples: u32,
+    /// Services whose memory is sampled so the daily summary can show if it's growing.
+    pub memory_trend_services: Vec<String>,
+    /// Snapshots every network interface into the manifest and alerts when one is added,
+    /// removed or readdressed, not only when the primary MAC or IP changes.
+    pub watch_interfaces: bool,
+    /// How many checks in a row, taken a minute apart, a site's health url has to fail
+    /// before it's alerted on.
+    pub health_check_failures: u32,
+    /// How long a site's health url has to answer before the check fails.
+    pub health_check_timeout_secs: u64,
+    /// How big the logs kept on disk grow and how many and how old rotated copies are kept,
+    /// like `{"Deploys": {"max_bytes": 1048576, "max_files": 10, "max_age_days": 365}}`.
+    /// Categories that aren't set use their defaults, see `AisConfig::retention`.
+    pub retention: HashMap<RetentionCategory, Retention>,
+}
+
+/// A setting that can't be used as is, and why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigProblem {
+    /// The setting, like `notifiers[1].url` for a field inside a list.
+    pub field: String,
+    /// What's wrong with it.
+    pub problem: String,
+}
+
+/// A user and the source address they're expected to log in from.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SshAllowEntry {
+    /// The user logging in.
+    pub user: String,
+    /// The address the user logs in from.
+    pub source: IpAddr,
+}
+
+impl Default for AisConfig {
+    fn default() -> Self {
+        AisConfig {
+            clone_scheme: CloneScheme::Https,
+            site_root: PathBuf::from("/var/www/current"),
+            mail_endpoint: None,
+            mail_preflight_strict: false,
+            mail_queue_capacity: 500,
+            mail_drop_policy: DropPolicy::DropOldestLow,
+            mail_category_limits: HashMap::new(),
+            mail_storm_threshold: 20,
+            mail_storm_window_secs: 300,
+            smtp_tls: SmtpTls::Implicit,
+            smtp_port: None,
+            smtp_timeout_secs: 60,
+            dusa_debugging: false,
+            dusa_backoff_max_secs: 300,
+            slow_git_threshold_secs: 30,
+            git_concurrency: 4,
+            clean_untracked: false,
+            clean_keep: Vec::new(),
+            min_deploy_space_mb: 1024,
+            deploy_log_dir: PathBuf::from("/var/log/ais/deploys"),
+            syslog_relays: Vec::new(),
+            syslog_rate_limit: 120,
+            critical_services: vec!["ufw.service".to_owned(), "dusad.service".to_owned()],
+            service_patterns: Vec::new(),
+            restart_critical_services: false,
+            announce_first_running: false,
+            journal_excerpt_lines: 20,
+            notifiers: vec![NotifierConfig::Email],
+            escalation: Vec::new(),
+            ssh_allow_list: Vec::new(),
+            encrypt_at_rest: false,
+            log_file: None,
+            log_file_max_bytes: 10 * 1024 * 1024,
+            log_file_retain: 5,
+            load_alert_threshold: 2.0,
+            memory_alert_percent: 90.0,
+            disk_alert_percent: 90.0,
+            resource_alert_samples: 5,
+            memory_trend_services: Vec::new(),
+            watch_interfaces: false,
+            health_check_failures: 3,
+            health_check_timeout_secs: 5,
+            retention: HashMap::new(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.problem)
+    }
+}
+
+impl AisConfig {
+    /// Loads the config from the default location, falling back to the defaults if it doesn't exist.
+    pub fn new() -> Result<Self, UnifiedError> {
+        Self::load(&Self::fetch_config_path())
+    }
+
+    /// Loads the config from the given path, falling back to the defaults if it doesn't exist.
+    pub fn load(config_path: &PathType) -> Result<Self, UnifiedError> {
+        match path_present(config_path) {
+            Ok(true) => {
+                let mut file = File::open(config_path)
+                    .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))?;
+
+                let mut buffer = Vec::new();
+                file.read_to_end(&mut buffer)
+                    .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))?;
+
+                serde_json::from_slice(&buffer)
+                    .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))
+            }
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Loads the config from the default location and validates it, returning every problem
+    /// found so they can all be fixed in one pass.
+    pub fn load_and_validate() -> Result<Self, Vec<ConfigProblem>> {
+        Self::load_and_validate_from(&Self::fetch_config_path())
+    }
+
+    /// Loads the config from the given path and validates it, a file that can't be parsed is
+    /// a single problem.
+    pub fn load_and_validate_from(config_path: &PathType) -> Result<Self, Vec<ConfigProblem>> {
+        let config: AisConfig = Self::load(config_path).map_err(|e| {
+            vec![ConfigProblem {
+                field: config_path.to_string(),
+                problem: e.to_string(),
+            }]
+        })?;
+        match config.validate() {
+            problems if problems.is_empty() => Ok(config),
+            problems => Err(problems),
+        }
+    }
+
+    /// Checks the ranges and formats serde can't, like intervals being above zero and the
+    /// mail endpoint being an address. Problems are listed in the order of the fields.
+    pub fn validate(&self) -> Vec<ConfigProblem> {
+        let mut problems: Vec<ConfigProblem> = Vec::new();
+        let mut check = |valid: bool, field: &str, problem: &str| {
+            if !valid {
+                problems.push(ConfigProblem {
+                    field: field.to_owned(),
+                    problem: problem.to_owned(),
+                });
+            }
+        };
+        let absolute = "must be an absolute path";
+        let above_zero = "must be above 0";
+
+        check(self.site_root.is_absolute(), "site_root", absolute);
+        if let Some(endpoint) = &self.mail_endpoint {
+            let (valid, problem) = match MailEndpoint::parse(endpoint) {
+                MailEndpoint::Unix(path) => (path.is_absolute(), "must be an absolute socket path"),
+                MailEndpoint::Tcp(address) => (
+                    is_socket_address(&address),
+                    "must be host:port or unix:/path",
+                ),
+            };
+            check(valid, "mail_endpoint", problem);
+        }
+        check(
+            self.mail_queue_capacity > 0,
+            "mail_queue_capacity",
+            above_zero,
+        );
+        let mut limits: Vec<(&EmailCategory, &usize)> = self.mail_category_limits.iter().collect();
+        limits.sort_by_key(|(category, _)| category.to_string());
+        for (category, limit) in limits {
+            let field = format!("mail_category_limits.{}", category);
+            check(*limit > 0, &field, above_zero);
+        }
+        check(
+            self.mail_storm_threshold == 0 || self.mail_storm_window_secs > 0,
+            "mail_storm_window_secs",
+            above_zero,
+        );
+        check(self.smtp_port != Some(0), "smtp_port", above_zero);
+        check(self.smtp_timeout_secs > 0, "smtp_timeout_secs", above_zero);
+        check(
+            self.dusa_backoff_max_secs > 0,
+            "dusa_backoff_max_secs",
+            above_zero,
+        );
+        check(
+            self.slow_git_threshold_secs > 0,
+            "slow_git_threshold_secs",
+            above_zero,
+        );
+        check(self.git_concurrency > 0, "git_concurrency", above_zero);
+        for (index, keep) in self.clean_keep.iter().enumerate() {
+            let path: &Path = Path::new(keep);
+            let valid: bool = !keep.trim().is_empty()
+                && path
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)));
+            let field = format!("clean_keep[{}]", index);
+            check(valid, &field, "must be a path inside the checkout");
+        }
+        check(
+            self.deploy_log_dir.is_absolute(),
+            "deploy_log_dir",
+            absolute,
+        );
+        check(self.syslog_rate_limit > 0, "syslog_rate_limit", above_zero);
+        for (index, service) in self.critical_services.iter().enumerate() {
+            let field = format!("critical_services[{}]", index);
+            check(
+                is_unit_name(service),
+                &field,
+                "must be a systemd unit, like ufw.service",
+            );
+        }
+        for (index, pattern) in self.service_patterns.iter().enumerate() {
+            let field = format!("service_patterns[{}]", index);
+            // The glob characters stand in for any character of a unit name
+            let valid: bool = is_unit_name(&pattern.replace(['*', '?'], "x"));
+            check(
+                valid,
+                &field,
+                "must be a systemd unit or glob, like app@*.service",
+            );
+        }
+        check(
+            !self.notifiers.is_empty(),
+            "notifiers",
+            "must have at least one notifier",
+        );
+        for (index, notifier) in self.notifiers.iter().enumerate() {
+            if let NotifierConfig::Webhook { url, .. } = notifier {
+                let field = format!("notifiers[{}].url", index);
+                check(is_http_url(url), &field, "must be an http or https url");
+            }
+        }
+        let mut previous_after: u64 = 0;
+        for (index, stage) in self.escalation.iter().enumerate() {
+            let field = format!("escalation[{}].after_mins", index);
+            check(
+                stage.after_mins >= previous_after,
+                &field,
+                "must not be before the previous stage",
+            );
+            previous_after = stage.after_mins;
+            let field = format!("escalation[{}].notifiers", index);
+            check(
+                !stage.notifiers.is_empty(),
+                &field,
+                "must have at least one notifier",
+            );
+        }
+        for (index, entry) in self.ssh_allow_list.iter().enumerate() {
+            let field = format!("ssh_allow_list[{}].user", index);
+            check(is_user_name(&entry.user), &field, "must be a login name");
+        }
+        if let Some(log_file) = &self.log_file {
+            check(log_file.is_absolute(), "log_file", absolute);
+        }
+        check(
+            self.log_file_max_bytes > 0,
+            "log_file_max_bytes",
+            above_zero,
+        );
+        let percent = "must be between 0 and 100";
+        check(
+            self.load_alert_threshold >= 0.0,
+            "load_alert_threshold",
+            "must not be negative",
+        );
+        check(
+            (0.0..=100.0).contains(&self.memory_alert_percent),
+            "memory_alert_percent",
+            percent,
+        );
+        check(
+            (0.0..=100.0).contains(&self.disk_alert_percent),
+            "disk_alert_percent",
+            percent,
+        );
+        check(
+            self.resource_alert_samples > 0,
+            "resource_alert_samples",
+            above_zero,
+        );
+        for (index, service) in self.memory_trend_services.iter().enumerate() {
+            let field = format!("memory_trend_services[{}]", index);
+            check(
+                is_unit_name(service),
+                &field,
+                "must be a systemd unit, like ufw.service",
+            );
+        }
+        check(
+            self.health_check_failures > 0,
+            "health_check_failures",
+            above_zero,
+        );
+        check(
+            self.health_check_timeout_secs > 0,
+            "health_check_timeout_secs",
+            above_zero,
+        );
+        let mut retention: Vec<(&RetentionCategory, &Retention)> = self.retention.iter().collect();
+        retention.sort_by_key(|(category, _)| category.to_string());
+        for (category, retention) in retention {
+            let field = format!("retention.{}.max_bytes", category);
+            check(retention.max_bytes > 0, &field, above_zero);
+        }
+
+        problems
+    }
+
+    /// Checks if the service is one whose stopping is escalated.
+    pub fn is_critical_service(&self, service: &str) -> bool {
+        self.critical_services
+            .iter()
+            .any(|critical| critical == service)
+    }
+
+    /// The retention of the category. The log's default comes from `log_file_max_bytes` and
+    /// `log_file_retain`.
+    pub fn retention(&self, category: RetentionCategory) -> Retention {
+        match (self.retention.get(&category), category) {
+            (Some(retention), _) => *retention,
+            (None, RetentionCategory::Log) => Retention {
+                max_bytes: self.log_file_max_bytes,
+                max_files: self.log_file_retain,
+                max_age_days: 0,
+            },
+            (None, category) => category.default_retention(),
+        }
+    }
+
+    /// Checks if an untracked path, as listed by git, is or holds a path that's kept.
+    pub fn keeps_untracked(&self, path: &str) -> bool {
+        let path: &str = path.trim_end_matches('/');
+        self.clean_keep.iter().any(|keep| {
+            let keep: &str = keep.trim_end_matches('/');
+            path == keep
+                || path.starts_with(&format!("{}/", keep))
+                || keep.starts_with(&format!("{}/", path))
+        })
+    }
+
+    /// Checks if a login by the user from the source is on the allow list. A login from an
+    /// unknown source is never allowed.
+    pub fn is_ssh_allowed(&self, user: &str, source: Option<IpAddr>) -> bool {
+        match source {
+            Some(source) => self
+                .ssh_allow_list
+                .iter()
+                .any(|entry| entry.user == user && entry.source == source),
+            None => false,
+        }
+    }
+
+    /// Fetches the config file path.
+    fn fetch_config_path() -> PathType {
+        PathType::Str("/etc/artisan.config".into())
+    }
+}
+
+/// Checks if the address is `host:port`, the host isn't resolved since the messaging server
+/// may not be up yet.
+fn is_socket_address(address: &str) -> bool {
+    if let Ok(address) = address.parse::<SocketAddr>() {
+        return address.port() > 0;
+    }
+    match address.rsplit_once(':') {
+        Some((host, port)) => {
+            !host.is_empty()
+                && host
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-.".contains(c))
+                && port.parse::<u16>().is_ok_and(|port| port > 0)
+        }
+        None => false,
+    }
+}
+
+/// Checks if the name is a systemd unit, like `apache2.service`.
+fn is_unit_name(name: &str) -> bool {
+    match name.rsplit_once('.') {
+        Some((unit, kind)) => {
+            !unit.is_empty()
+                && !kind.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || ":-_.@\\".contains(c))
+        }
+        None => false,
+    }
+}
+
+/// Checks if the name is a login name as useradd accepts them.
+fn is_user_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 32
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+/// Checks if the url is http or https with a host.
+fn is_http_url(url: &str) -> bool {
+    url.strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .is_some_and(|rest| !rest.is_empty() && !rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io::Write};
+
+    #[test]
+    fn test_missing_config_uses_defaults() {
+        let config =
+            AisConfig::load(&PathType::Str("/tmp/ais_config_missing.config".into())).unwrap();
+        assert_eq!(config, AisConfig::default());
+    }
+
+    #[test]
+    fn test_load_clone_scheme() {
+        let path = "/tmp/ais_config_scheme.config";
+        let mut file = File::create(path).unwrap();
+        file.write_all(br#"{"clone_scheme":"Ssh"}"#).unwrap();
+
+        let config = AisConfig::load(&PathType::Str(path.into())).unwrap();
+        assert_eq!(config.clone_scheme, CloneScheme::Ssh);
+
+        let _ = fs::remove_file(path);
+    }
+
+    fn problem_fields(config: &AisConfig) -> Vec<String> {
+        config
+            .validate()
+            .into_iter()
+            .map(|problem| problem.field)
+            .collect()
+    }
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert!(AisConfig::default().validate().is_empty());
+    }
+
+    #[test]
+    fn test_zero_intervals_are_reported() {
+        let config = AisConfig {
+            smtp_timeout_secs: 0,
+            git_concurrency: 0,
+            health_check_timeout_secs: 0,
+            ..AisConfig::default()
+        };
+        assert_eq!(
+            problem_fields(&config),
+            vec![
+                "smtp_timeout_secs",
+                "git_concurrency",
+                "health_check_timeout_secs"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mail_endpoint_must_be_an_address() {
+        for valid in [
+            "10.1.0.11:1827",
+            "tcp://mail.internal:1827",
+            "unix:/run/ais/mail.sock",
+        ] {
+            let config = AisConfig {
+                mail_endpoint: Some(valid.to_owned()),
+                ..AisConfig::default()
+            };
+            assert!(config.validate().is_empty(), "{} was rejected", valid);
+        }
+        for invalid in [
+            "10.1.0.11",
+            "10.1.0.11:0",
+            "mail server:1827",
+            "unix:run/mail.sock",
+        ] {
+            let config = AisConfig {
+                mail_endpoint: Some(invalid.to_owned()),
+                ..AisConfig::default()
+            };
+            assert_eq!(
+                problem_fields(&config),
+                vec!["mail_endpoint"],
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_list_entries_report_their_index() {
+        let config = AisConfig {
+            notifiers: vec![
+                NotifierConfig::Email,
+                NotifierConfig::Webhook {
+                    url: "hooks.slack.com/services/T000".to_owned(),
+                    high_priority_only: false,
+                },
+            ],
+            ssh_allow_list: vec![SshAllowEntry {
+                user: "deploy bot".to_owned(),
+                source: "10.1.0.4".parse().unwrap(),
+            }],
+            critical_services: vec!["ufw.service".to_owned(), "apache2".to_owned()],
+            service_patterns: vec!["app@*.service".to_owned(), "app@*".to_owned()],
+            clean_keep: vec!["../uploads".to_owned()],
+            ..AisConfig::default()
+        };
+        assert_eq!(
+            problem_fields(&config),
+            vec![
+                "clean_keep[0]",
+                "critical_services[1]",
+                "service_patterns[1]",
+                "notifiers[1].url",
+                "ssh_allow_list[0].user"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_every_problem_is_reported_at_once() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("artisan.config");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(
+            br#"{"site_root":"www","memory_alert_percent":150,"escalation":[{"after_mins":30,"notifiers":["Email"]},{"after_mins":10,"notifiers":[]}],"retention":{"Deploys":{"max_bytes":0,"max_files":5}}}"#,
+        )
+        .unwrap();
+
+        let problems = AisConfig::load_and_validate_from(&PathType::PathBuf(path)).unwrap_err();
+        let fields: Vec<&str> = problems.iter().map(|p| p.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            vec![
+                "site_root",
+                "escalation[1].after_mins",
+                "escalation[1].notifiers",
+                "memory_alert_percent",
+                "retention.Deploys.max_bytes"
+            ]
+        );
+        assert_eq!(
+            problems[0].to_string(),
+            "site_root: must be an absolute path"
+        );
+    }
+
+    #[test]
+    fn test_unparseable_config_is_one_problem() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("artisan.config");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(br#"{"git_concurrency":-1}"#).unwrap();
+
+        let problems =
+            AisConfig::load_and_validate_from(&PathType::PathBuf(path.clone())).unwrap_err();
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].field, path.display().to_string());
+    }
+}
@@ -1,14 +1,17 @@
 use std::{
+    collections::BTreeMap,
     fmt,
-    fs::File,
+    fs::{self, File},
     io::{Read, Write},
+    sync::RwLock,
 };
 
 use crate::errors::{AisError, UnifiedError};
+use crate::health::Health;
 use if_addrs::get_if_addrs;
-use mac_address::get_mac_address;
+use mac_address::{get_mac_address, mac_address_by_name};
 use serde::{Deserialize, Serialize};
-use system::{path_present, PathType};
+use system::{create_hash, path_present, truncate, PathType};
 
 /// Struct representing information about the Ais system.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -23,12 +26,48 @@ pub struct AisInfo {
     pub machine_mac: Option<String>,
     /// IP address of the machine.
     pub machine_ip: Option<String>,
-    /// Number of SSH events.
+    /// Human friendly label for the machine, set at provisioning.
+    #[serde(default)]
+    pub label: Option<String>,
+    /// The machine's network interfaces when the snapshot was last taken, empty until the
+    /// interface watch takes one.
+    #[serde(default)]
+    pub interfaces: Vec<NetworkInterface>,
+    /// Number of SSH events recorded, kept in the manifest so it survives restarts.
     pub ssh_events: usize,
+    /// How each monitoring loop did on its last cycle, as of the last save for readers of the
+    /// manifest. The client starts with it empty.
+    #[serde(default)]
+    pub health: Health,
     /// Version information of the system.
     pub system_version: AisVersion,
 }
 
+/// A network interface and the addresses assigned to it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NetworkInterface {
+    /// The name of the interface, like "eth0".
+    pub name: String,
+    /// The MAC address of the interface, if it has one.
+    pub mac: Option<String>,
+    /// Every address assigned to the interface, sorted.
+    pub ips: Vec<String>,
+}
+
+/// How an interface differs between two snapshots.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InterfaceChange {
+    /// An interface that wasn't in the original snapshot.
+    Added(NetworkInterface),
+    /// An interface that's missing from the new snapshot.
+    Removed(NetworkInterface),
+    /// An interface whose MAC or addresses changed.
+    Changed {
+        old: NetworkInterface,
+        new: NetworkInterface,
+    },
+}
+
 /// Version information structure.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub struct AisVersion {
@@ -51,6 +90,61 @@ pub enum AisCode {
     Alpha,
 }
 
+/// Process wide copy of the manifest, so identity lookups don't re-read the disk.
+struct ManifestCache {
+    manifest: RwLock<Option<AisInfo>>,
+}
+
+static MANIFEST_CACHE: ManifestCache = ManifestCache::new();
+
+/// A field that differs between two manifests.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange {
+    /// The name of the field.
+    pub field: &'static str,
+    /// The value in the original manifest.
+    pub old: String,
+    /// The value in the new manifest.
+    pub new: String,
+}
+
+impl fmt::Display for AisVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.version_number, self.version_code)
+    }
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.field, self.old, self.new)
+    }
+}
+
+impl fmt::Display for NetworkInterface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (mac: {}, ips: {})",
+            self.name,
+            self.mac.as_deref().unwrap_or("none"),
+            match self.ips.is_empty() {
+                true => String::from("none"),
+                false => self.ips.join(", "),
+            }
+        )
+    }
+}
+
+impl fmt::Display for InterfaceChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterfaceChange::Added(interface) => write!(f, "Added: {}", interface),
+            InterfaceChange::Removed(interface) => write!(f, "Removed: {}", interface),
+            InterfaceChange::Changed { old, new } => write!(f, "Changed: {} -> {}", old, new),
+        }
+    }
+}
+
 impl fmt::Display for AisCode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let ais_code = match self {
@@ -63,7 +157,62 @@ impl fmt::Display for AisCode {
     }
 }
 
+impl ManifestCache {
+    const fn new() -> Self {
+        ManifestCache {
+            manifest: RwLock::new(None),
+        }
+    }
+
+    /// Returns the cached manifest, loading it on the first call.
+    fn get_or_load(
+        &self,
+        load: impl FnOnce() -> Result<AisInfo, UnifiedError>,
+    ) -> Result<AisInfo, UnifiedError> {
+        if let Ok(manifest) = self.manifest.read() {
+            if let Some(ais_info) = manifest.as_ref() {
+                return Ok(ais_info.clone());
+            }
+        }
+        self.reload(load)
+    }
+
+    /// Loads the manifest, replacing the cached copy.
+    fn reload(
+        &self,
+        load: impl FnOnce() -> Result<AisInfo, UnifiedError>,
+    ) -> Result<AisInfo, UnifiedError> {
+        let ais_info: AisInfo = load()?;
+        if let Ok(mut manifest) = self.manifest.write() {
+            *manifest = Some(ais_info.clone());
+        }
+        Ok(ais_info)
+    }
+
+    fn invalidate(&self) {
+        if let Ok(mut manifest) = self.manifest.write() {
+            *manifest = None;
+        }
+    }
+}
+
 impl AisInfo {
+    /// Returns the process wide copy of the manifest, only reading the disk the first time.
+    /// Use `refresh` when the on disk manifest has to be checked.
+    pub fn new_cached() -> Result<Self, UnifiedError> {
+        MANIFEST_CACHE.get_or_load(Self::new)
+    }
+
+    /// Re-reads the manifest from disk, updating the cached copy.
+    pub fn refresh() -> Result<Self, UnifiedError> {
+        MANIFEST_CACHE.reload(Self::new)
+    }
+
+    /// Drops the cached copy, the next `new_cached` call reads the disk again.
+    pub fn invalidate() {
+        MANIFEST_CACHE.invalidate()
+    }
+
     /// Creates a new instance of `AisInfo`.
     pub fn new() -> Result<Self, UnifiedError> {
         let manifest_data = Self::fetch_manifest()?;
@@ -90,7 +239,19 @@ impl AisInfo {
             machine_ip: manifest_data
                 .get("machine_ip")
                 .and_then(|v| v.as_str().map(|s| s.to_string())),
-            ssh_events: 0,
+            label: manifest_data
+                .get("label")
+                .and_then(|v| v.as_str())
+                .and_then(|s| Self::validate_label(s).ok()),
+            interfaces: manifest_data
+                .get("interfaces")
+                .and_then(|v| serde_json::from_value(v.clone()).ok())
+                .unwrap_or_default(),
+            ssh_events: manifest_data
+                .get("ssh_events")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0) as usize,
+            health: Health::new(),
             system_version: ais_version,
         })
     }
@@ -103,6 +264,9 @@ impl AisInfo {
         if let Some(machine_id) = &self.machine_id {
             println!("Machine ID: {:?}", machine_id);
         }
+        if let Some(label) = &self.label {
+            println!("Machine label: {}", label);
+        }
         if let Some(machine_mac) = &self.machine_mac {
             println!("Machine MAC: {}", machine_mac);
         }
@@ -111,12 +275,106 @@ impl AisInfo {
         }
     }
 
+    /// Lists the fields that differ between this manifest and another.
+    pub fn diff(&self, other: &AisInfo) -> Vec<FieldChange> {
+        let optional =
+            |value: &Option<String>| value.clone().unwrap_or_else(|| String::from("unset"));
+        let fields: [(&'static str, String, String); 8] = [
+            (
+                "pages_id",
+                optional(&self.pages_id),
+                optional(&other.pages_id),
+            ),
+            (
+                "client_id",
+                optional(&self.client_id),
+                optional(&other.client_id),
+            ),
+            (
+                "machine_id",
+                optional(&self.machine_id),
+                optional(&other.machine_id),
+            ),
+            (
+                "machine_mac",
+                optional(&self.machine_mac),
+                optional(&other.machine_mac),
+            ),
+            (
+                "machine_ip",
+                optional(&self.machine_ip),
+                optional(&other.machine_ip),
+            ),
+            ("label", optional(&self.label), optional(&other.label)),
+            (
+                "ssh_events",
+                self.ssh_events.to_string(),
+                other.ssh_events.to_string(),
+            ),
+            (
+                "system_version",
+                self.system_version.to_string(),
+                other.system_version.to_string(),
+            ),
+        ];
+
+        fields
+            .into_iter()
+            .filter(|(_, old, new)| old != new)
+            .map(|(field, old, new)| FieldChange { field, old, new })
+            .collect()
+    }
+
+    /// Validates a machine label, returning it trimmed.
+    /// Labels are 1 to 63 lowercase letters, digits and dashes, like "cust-acme-web01".
+    pub fn validate_label(label: &str) -> Result<String, UnifiedError> {
+        let label: &str = label.trim();
+        let valid: bool = !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+
+        match valid {
+            true => Ok(label.to_owned()),
+            false => Err(UnifiedError::from_ais_error(AisError::InvalidManifest(
+                Some(format!("Invalid machine label: {:?}", label)),
+            ))),
+        }
+    }
+
+    /// Describes the machine for operators, the label alongside the id when one is set.
+    pub fn identity(&self) -> String {
+        let machine_id: &str = self.machine_id.as_deref().unwrap_or("Uninitialized");
+        match &self.label {
+            Some(label) => format!("{} ({})", label, machine_id),
+            None => machine_id.to_owned(),
+        }
+    }
+
+    /// Returns a copy of the manifest brought up to the current version, if it's a provisioned
+    /// manifest from an older release. A manifest that was never provisioned, or that comes
+    /// from a newer release, can't be migrated.
+    pub fn migrated(&self) -> Option<AisInfo> {
+        let current: AisVersion = Self::current_version();
+        let older: bool = self.system_version != current
+            && self.system_version.version_number <= current.version_number;
+        match self.machine_id.is_some() && older {
+            true => Some(AisInfo {
+                system_version: current,
+                ..self.clone()
+            }),
+            false => None,
+        }
+    }
+
     pub fn current_version() -> AisVersion {
-        let new_ais_version = AisVersion {
+        AisVersion {
             version_number: 1.31,
             version_code: AisCode::Production,
-        };
-        return new_ais_version
+        }
     }
 
     /// Fetches the manifest data.
@@ -124,15 +382,18 @@ impl AisInfo {
         let manifest_path = Self::fetch_manifest_path();
         match path_present(&manifest_path) {
             Ok(true) => {
-                let mut file = File::open(&manifest_path)
-                    .map_err(|e| UnifiedError::from_ais_error(AisError::new(&e.to_string())))?;
+                let mut file = File::open(&manifest_path).map_err(|e| {
+                    UnifiedError::from_ais_error(AisError::with_context("opening the manifest", e))
+                })?;
 
                 let mut buffer = Vec::new();
-                file.read_to_end(&mut buffer)
-                    .map_err(|e| UnifiedError::from_ais_error(AisError::new(&e.to_string())))?;
+                file.read_to_end(&mut buffer).map_err(|e| {
+                    UnifiedError::from_ais_error(AisError::with_context("reading the manifest", e))
+                })?;
 
-                serde_json::from_slice(&buffer)
-                    .map_err(|e| UnifiedError::from_ais_error(AisError::new(&e.to_string())))
+                serde_json::from_slice(&buffer).map_err(|e| {
+                    UnifiedError::from_ais_error(AisError::with_context("parsing the manifest", e))
+                })
             }
             _ => {
                 let generic_ais = AisInfo {
@@ -141,15 +402,22 @@ impl AisInfo {
                     machine_id: None,
                     machine_mac: Self::fetch_machine_mac(),
                     machine_ip: Self::fetch_machine_ip(),
+                    label: None,
+                    interfaces: Vec::new(),
                     ssh_events: 0,
+                    health: Health::new(),
                     system_version: AisVersion {
                         version_number: 0.00,
                         version_code: AisCode::Alpha,
                     },
                 };
 
-                serde_json::to_value(&generic_ais)
-                    .map_err(|e| UnifiedError::from_ais_error(AisError::new(&e.to_string())))
+                serde_json::to_value(&generic_ais).map_err(|e| {
+                    UnifiedError::from_ais_error(AisError::with_context(
+                        "serializing the default manifest",
+                        e,
+                    ))
+                })
             }
         }
     }
@@ -161,15 +429,57 @@ impl AisInfo {
 
     /// Creates the manifest file.
     pub fn create_manifest(&self) -> Result<(), UnifiedError> {
-        let json_data = serde_json::to_string(self)
-            .map_err(|e| UnifiedError::from_ais_error(AisError::new(&e.to_string())))?;
+        let json_data = serde_json::to_string(self).map_err(|e| {
+            UnifiedError::from_ais_error(AisError::with_context("serializing the manifest", e))
+        })?;
+
+        let mut file = File::create(Self::fetch_manifest_path()).map_err(|e| {
+            UnifiedError::from_ais_error(AisError::with_context("creating the manifest", e))
+        })?;
+        file.write_all(json_data.as_bytes()).map_err(|e| {
+            UnifiedError::from_ais_error(AisError::with_context("writing the manifest", e))
+        })?;
+
+        Self::invalidate();
+        Ok(())
+    }
 
-        let mut file = File::create(Self::fetch_manifest_path())
-            .map_err(|e| UnifiedError::from_ais_error(AisError::new(&e.to_string())))?;
-        file.write_all(json_data.as_bytes())
-            .map_err(|e| UnifiedError::from_ais_error(AisError::new(&e.to_string())))?;
+    /// Derives a stable identifier for the machine.
+    /// The os machine-id is preferred, falling back to the MAC address. The ip isn't used
+    /// since it can change between boots.
+    pub fn machine_fingerprint(&self) -> String {
+        let machine_mac = self.machine_mac.clone().or_else(Self::fetch_machine_mac);
+        Self::derive_fingerprint(
+            Self::fetch_os_machine_id().as_deref(),
+            machine_mac.as_deref(),
+        )
+    }
 
-        Ok(())
+    /// Hashes the identifiers that make up a machine fingerprint.
+    fn derive_fingerprint(os_machine_id: Option<&str>, machine_mac: Option<&str>) -> String {
+        let source = match os_machine_id {
+            Some(os_machine_id) => os_machine_id.to_owned(),
+            None => machine_mac.unwrap_or("00:00:00:00:00:00").to_lowercase(),
+        };
+        truncate(&create_hash(source), 16).to_owned()
+    }
+
+    /// Fetches the machine-id systemd or dbus generated for this install.
+    fn fetch_os_machine_id() -> Option<String> {
+        Self::read_machine_id(&[
+            PathType::Str("/etc/machine-id".into()),
+            PathType::Str("/var/lib/dbus/machine-id".into()),
+        ])
+    }
+
+    /// Reads the first usable machine-id from the given paths.
+    fn read_machine_id(paths: &[PathType]) -> Option<String> {
+        paths.iter().find_map(|path| {
+            fs::read_to_string(path)
+                .ok()
+                .map(|data| data.trim().to_owned())
+                .filter(|machine_id| !machine_id.is_empty())
+        })
     }
 
     /// Fetches the machine's MAC address.
@@ -189,6 +499,66 @@ impl AisInfo {
         }
         None
     }
+
+    /// Fetches every interface on the machine except loopback, sorted by name.
+    pub fn fetch_interfaces() -> Vec<NetworkInterface> {
+        let mut interfaces: BTreeMap<String, NetworkInterface> = BTreeMap::new();
+        for iface in get_if_addrs().unwrap_or_default() {
+            if iface.is_loopback() {
+                continue;
+            }
+            let interface =
+                interfaces
+                    .entry(iface.name.clone())
+                    .or_insert_with(|| NetworkInterface {
+                        name: iface.name.clone(),
+                        mac: mac_address_by_name(&iface.name)
+                            .ok()
+                            .flatten()
+                            .map(|mac| mac.to_string()),
+                        ips: Vec::new(),
+                    });
+            interface.ips.push(iface.ip().to_string());
+        }
+
+        interfaces
+            .into_values()
+            .map(|mut interface| {
+                interface.ips.sort();
+                interface
+            })
+            .collect()
+    }
+
+    /// Lists the interfaces added, removed or changed between two snapshots.
+    pub fn diff_interfaces(
+        old: &[NetworkInterface],
+        new: &[NetworkInterface],
+    ) -> Vec<InterfaceChange> {
+        let find = |list: &[NetworkInterface], name: &str| -> Option<NetworkInterface> {
+            list.iter()
+                .find(|interface| interface.name == name)
+                .cloned()
+        };
+
+        let mut changes: Vec<InterfaceChange> = Vec::new();
+        for interface in old {
+            match find(new, &interface.name) {
+                None => changes.push(InterfaceChange::Removed(interface.clone())),
+                Some(current) if &current != interface => changes.push(InterfaceChange::Changed {
+                    old: interface.clone(),
+                    new: current,
+                }),
+                Some(_) => (),
+            }
+        }
+        for interface in new {
+            if find(old, &interface.name).is_none() {
+                changes.push(InterfaceChange::Added(interface.clone()));
+            }
+        }
+        changes
+    }
 }
 
 #[cfg(test)]
@@ -219,7 +589,10 @@ mod tests {
             machine_id: Some("789".to_string()),
             machine_mac: Some("00:11:22:33:44:55".to_string()),
             machine_ip: Some("192.168.1.100".to_string()),
+            label: None,
+            interfaces: Vec::new(),
             ssh_events: 5,
+            health: Health::new(),
             system_version: AisVersion {
                 version_number: 1.31,
                 version_code: AisCode::Beta,
@@ -230,6 +603,120 @@ mod tests {
         ais_info.print_all();
     }
 
+    #[test]
+    fn test_diff() {
+        let original = AisInfo {
+            pages_id: None,
+            client_id: Some("456".to_string()),
+            machine_id: Some("789".to_string()),
+            machine_mac: Some("00:11:22:33:44:55".to_string()),
+            machine_ip: Some("192.168.1.100".to_string()),
+            label: None,
+            interfaces: Vec::new(),
+            ssh_events: 0,
+            health: Health::new(),
+            system_version: AisVersion {
+                version_number: 1.30,
+                version_code: AisCode::Beta,
+            },
+        };
+        assert!(original.diff(&original).is_empty());
+
+        let mut updated = original.clone();
+        updated.client_id = None;
+        updated.system_version = AisInfo::current_version();
+
+        assert_eq!(
+            original.diff(&updated),
+            vec![
+                FieldChange {
+                    field: "client_id",
+                    old: "456".to_string(),
+                    new: "unset".to_string(),
+                },
+                FieldChange {
+                    field: "system_version",
+                    old: "1.3_b".to_string(),
+                    new: "1.31_P".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_migrated() {
+        let mut ais_info = AisInfo {
+            pages_id: None,
+            client_id: Some("456".to_string()),
+            machine_id: Some("789".to_string()),
+            machine_mac: None,
+            machine_ip: None,
+            label: None,
+            interfaces: Vec::new(),
+            ssh_events: 0,
+            health: Health::new(),
+            system_version: AisVersion {
+                version_number: 1.30,
+                version_code: AisCode::Production,
+            },
+        };
+        let migrated = ais_info.migrated().unwrap();
+        assert_eq!(migrated.system_version, AisInfo::current_version());
+        assert_eq!(migrated.machine_id, ais_info.machine_id);
+        assert_eq!(migrated.migrated(), None);
+
+        // A newer release's manifest is left alone
+        ais_info.system_version.version_number = 9.0;
+        assert_eq!(ais_info.migrated(), None);
+
+        // So is one that was never provisioned
+        ais_info.system_version.version_number = 0.0;
+        ais_info.machine_id = None;
+        assert_eq!(ais_info.migrated(), None);
+    }
+
+    #[test]
+    fn test_manifest_cache() {
+        let cache = ManifestCache::new();
+        let mut loads: usize = 0;
+        let mut load = || {
+            loads += 1;
+            Ok(AisInfo {
+                pages_id: None,
+                client_id: None,
+                machine_id: Some(loads.to_string()),
+                machine_mac: None,
+                machine_ip: None,
+                label: None,
+                interfaces: Vec::new(),
+                ssh_events: 0,
+                health: Health::new(),
+                system_version: AisInfo::current_version(),
+            })
+        };
+
+        let first = cache.get_or_load(&mut load).unwrap();
+        let second = cache.get_or_load(&mut load).unwrap();
+        assert_eq!(first, second);
+
+        cache.invalidate();
+        let reloaded = cache.get_or_load(&mut load).unwrap();
+        assert_eq!(reloaded.machine_id.as_deref(), Some("2"));
+        assert_eq!(loads, 2);
+    }
+
+    #[test]
+    fn test_validate_label() {
+        assert_eq!(
+            AisInfo::validate_label(" cust-acme-web01 ").unwrap(),
+            "cust-acme-web01"
+        );
+        assert!(AisInfo::validate_label("").is_err());
+        assert!(AisInfo::validate_label("Cust Acme").is_err());
+        assert!(AisInfo::validate_label("-web01").is_err());
+        assert!(AisInfo::validate_label(&"a".repeat(64)).is_err());
+    }
+
     #[test]
     fn test_fetch_manifest_path() {
         // Test fetching the manifest path
@@ -239,6 +726,74 @@ mod tests {
         assert_eq!(path, PathType::Str("/etc/artisan.manifest".into()));
     }
 
+    #[test]
+    fn test_machine_fingerprint_is_stable() {
+        let ais_info = AisInfo {
+            pages_id: None,
+            client_id: None,
+            machine_id: None,
+            machine_mac: Some("00:11:22:33:44:55".to_string()),
+            machine_ip: Some("192.168.1.100".to_string()),
+            label: None,
+            interfaces: Vec::new(),
+            ssh_events: 0,
+            health: Health::new(),
+            system_version: AisInfo::current_version(),
+        };
+
+        let fingerprint = ais_info.machine_fingerprint();
+        assert_eq!(fingerprint.len(), 16);
+        assert_eq!(fingerprint, ais_info.machine_fingerprint());
+
+        // Changing the ip must not change the identity
+        let mut moved = ais_info.clone();
+        moved.machine_ip = Some("10.1.0.20".to_string());
+        assert_eq!(fingerprint, moved.machine_fingerprint());
+
+        assert_eq!(
+            AisInfo::derive_fingerprint(None, Some("00:11:22:33:44:55")),
+            AisInfo::derive_fingerprint(None, Some("00:11:22:33:44:55"))
+        );
+        assert_ne!(
+            AisInfo::derive_fingerprint(None, Some("00:11:22:33:44:55")),
+            AisInfo::derive_fingerprint(None, Some("66:77:88:99:aa:bb"))
+        );
+    }
+
+    #[test]
+    fn test_read_machine_id_fixture() {
+        let fixture = std::env::temp_dir().join("ais_machine_id_fixture");
+        fs::write(&fixture, "4c4c4544003957108052b4c04f384833\n").unwrap();
+        let missing = PathType::Str("/nonexistent/machine-id".into());
+
+        let machine_id = AisInfo::read_machine_id(&[missing, PathType::PathBuf(fixture)]);
+        assert_eq!(
+            machine_id.as_deref(),
+            Some("4c4c4544003957108052b4c04f384833")
+        );
+
+        // The machine-id wins over the MAC address once it's available
+        assert_ne!(
+            AisInfo::derive_fingerprint(machine_id.as_deref(), Some("00:11:22:33:44:55")),
+            AisInfo::derive_fingerprint(None, Some("00:11:22:33:44:55"))
+        );
+        assert_eq!(
+            AisInfo::derive_fingerprint(machine_id.as_deref(), Some("00:11:22:33:44:55")),
+            AisInfo::derive_fingerprint(machine_id.as_deref(), Some("66:77:88:99:aa:bb"))
+        );
+    }
+
+    #[test]
+    fn test_read_machine_id_falls_back_to_mac() {
+        let machine_id =
+            AisInfo::read_machine_id(&[PathType::Str("/nonexistent/machine-id".into())]);
+        assert_eq!(machine_id, None);
+        assert_eq!(
+            AisInfo::derive_fingerprint(machine_id.as_deref(), Some("00:11:22:33:44:55")),
+            AisInfo::derive_fingerprint(None, Some("00:11:22:33:44:55"))
+        );
+    }
+
     #[test]
     fn test_fetch_machine_mac() {
         // Test fetching the machine's MAC address
@@ -256,4 +811,30 @@ mod tests {
         // Assert that IP address is not None
         assert!(ip.is_some());
     }
+
+    #[test]
+    fn test_diff_interfaces_detects_added_interface() {
+        let interface = |name: &str, mac: &str, ip: &str| NetworkInterface {
+            name: name.to_string(),
+            mac: Some(mac.to_string()),
+            ips: vec![ip.to_string()],
+        };
+        let old = vec![interface("eth0", "00:11:22:33:44:55", "192.168.1.100")];
+        let mut new = old.clone();
+        new.push(interface("eth1", "00:11:22:33:44:66", "10.0.0.5"));
+
+        assert!(AisInfo::diff_interfaces(&old, &old).is_empty());
+
+        let changes = AisInfo::diff_interfaces(&old, &new);
+        assert_eq!(changes, vec![InterfaceChange::Added(new[1].clone())]);
+        assert_eq!(
+            changes[0].to_string(),
+            "Added: eth1 (mac: 00:11:22:33:44:66, ips: 10.0.0.5)"
+        );
+
+        assert_eq!(
+            AisInfo::diff_interfaces(&new, &old),
+            vec![InterfaceChange::Removed(new[1].clone())]
+        );
+    }
 }
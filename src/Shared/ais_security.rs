@@ -11,27 +11,48 @@ use pretty::notice;
 use system::SystemError;
 use systemstat::Duration;
 
+/// How long to wait before checking the credentials again.
+const CF_RETRY_WAIT: Duration = Duration::from_secs(30);
+
 pub fn check_cf() -> Result<bool, UnifiedError> {
+    check_cf_with(GitCredentials::new, CF_RETRY_WAIT)
+}
+
+/// Checks the credentials from the loader. Returns false if there are none yet, while the
+/// encryption backend is down it waits and tries again.
+fn check_cf_with(
+    load: impl Fn() -> Result<GitCredentials, UnifiedError>,
+    wait: Duration,
+) -> Result<bool, UnifiedError> {
     // * Put the appilcation IN a hold state if no credential file is found
-    match GitCredentials::new() {
-        Ok(_) => return Ok(true), // true means We ok
-        Err(e) => match e {
-            // ? We look for a system error saying we could not find the artiisan.cf file.
-            // ? This means that the system has been initialized but no clients have been
-            // ? Registered. Theres is not point in running loops or monitoring when the
-            // ? Server is not in a usable state. We will also enter this state if dusad
-            // ? is not running or we cannot communicate with it.
-            UnifiedError::SystemError(k, d) => match d.kind {
-                system::errors::SystemErrorType::ErrorOpeningFile => {
-                    notice("Awating registration! Is dusad running?");
-                    thread::sleep(Duration::from_secs_f32(30.0));
-                    return Ok(false); // false means that we should exit because the file was not found
-                }
-                _ => return Err(UnifiedError::SystemError(k, SystemError::new(d.kind))),
+    loop {
+        match load() {
+            Ok(_) => return Ok(true), // true means We ok
+            // ? Dusad being unreachable is temporary, it's usually still starting or restarting.
+            // ? We wait for it instead of exiting since the credentials are there.
+            Err(UnifiedError::AisError(_, AisError::EncryptionNotReady(desc))) => {
+                notice(&format!(
+                    "Encryption backend unavailable, waiting for dusad: {}",
+                    desc.unwrap_or_default()
+                ));
+                thread::sleep(wait);
+            }
+            Err(e) => match e {
+                // ? We look for a system error saying we could not find the artiisan.cf file.
+                // ? This means that the system has been initialized but no clients have been
+                // ? Registered. Theres is not point in running loops or monitoring when the
+                // ? Server is not in a usable state.
+                UnifiedError::SystemError(k, d) => match d.kind {
+                    system::errors::SystemErrorType::ErrorOpeningFile => {
+                        notice("Awating registration!");
+                        return Ok(false); // false means the file was not found, the caller checks again later
+                    }
+                    _ => return Err(UnifiedError::SystemError(k, SystemError::new(d.kind))),
+                },
+                e => return Err(e),
             },
-            e => return Err(e),
-        },
-    };
+        }
+    }
 }
 
 pub fn check_manifest(ais: AisInfo) -> Result<(), UnifiedError> {
@@ -50,12 +71,62 @@ pub fn check_manifest(ais: AisInfo) -> Result<(), UnifiedError> {
     }
 }
 
+/// Rewrites a provisioned manifest from an older release at the current version. Returns false
+/// if the manifest can't be migrated.
+pub fn migrate_manifest(ais: &AisInfo) -> Result<bool, UnifiedError> {
+    match ais.migrated() {
+        Some(migrated) => {
+            migrated.create_manifest()?;
+            notice(&format!(
+                "Migrated the manifest from {} to {}",
+                ais.system_version, migrated.system_version
+            ));
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
 #[test]
 fn test_cf() {
     // Just ensure it returns something
     assert!(check_cf().is_ok() || check_cf().is_err())
 }
 
+#[test]
+fn test_cf_missing_file() {
+    let missing = || {
+        Err(UnifiedError::from_system_error(SystemError::new_details(
+            system::errors::SystemErrorType::ErrorOpeningFile,
+            "artisan credential file not found",
+        )))
+    };
+    assert!(!check_cf_with(missing, Duration::ZERO).unwrap());
+}
+
+#[test]
+fn test_cf_backend_down() {
+    // The backend comes back on the third check
+    let checks = std::cell::Cell::new(0);
+    let flaky = || {
+        checks.set(checks.get() + 1);
+        match checks.get() {
+            1 | 2 => Err(UnifiedError::from_ais_error(AisError::EncryptionNotReady(
+                Some("dusad is restarting".to_owned()),
+            ))),
+            _ => Ok(GitCredentials { auths: vec![] }),
+        }
+    };
+    assert!(check_cf_with(flaky, Duration::ZERO).unwrap());
+    assert_eq!(checks.get(), 3);
+}
+
+#[test]
+fn test_cf_valid_credentials() {
+    let valid = || Ok(GitCredentials { auths: vec![] });
+    assert!(check_cf_with(valid, Duration::ZERO).unwrap());
+}
+
 #[test]
 fn test_version_match() {
     // ? This ensures that the version we are expecting is the same one we'll create
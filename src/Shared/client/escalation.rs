@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+use crate::notify::EscalationStage;
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+/// A condition that hasn't been resolved yet.
+#[derive(Debug)]
+struct Condition {
+    started: Instant,
+    /// How many stages have been notified so far.
+    stages_fired: usize,
+}
+
+/// A condition that's due its next escalation stage.
+#[derive(Debug, PartialEq)]
+pub struct DueEscalation {
+    /// The condition, like the name of the service that's down.
+    pub condition: String,
+    /// The index of the stage to notify.
+    pub stage: usize,
+    /// How long the condition has lasted.
+    pub lasted: Duration,
+}
+
+/// A condition as it's saved across a restart, instants don't survive the process.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedCondition {
+    /// How long the condition had lasted when it was saved.
+    pub lasted_secs: u64,
+    /// How many stages had been notified.
+    pub stages_fired: usize,
+}
+
+/// Tracks when unresolved conditions started, so they can be escalated as they persist.
+#[derive(Debug, Default)]
+pub struct Escalations {
+    conditions: HashMap<String, Condition>,
+}
+
+impl Escalations {
+    /// Creates a new Escalations with nothing tracked.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Updates the tracked conditions to the ones currently active. New ones start now, ones
+    /// that are no longer active are resolved and forgotten.
+    pub fn update(&mut self, active: &[String], now: Instant) {
+        self.conditions
+            .retain(|condition, _| active.contains(condition));
+        for condition in active {
+            self.conditions
+                .entry(condition.clone())
+                .or_insert(Condition {
+                    started: now,
+                    stages_fired: 0,
+                });
+        }
+    }
+
+    /// Lists the conditions as they stand now, to be restored after a restart.
+    pub fn save(&self, now: Instant) -> BTreeMap<String, SavedCondition> {
+        self.conditions
+            .iter()
+            .map(|(name, condition)| {
+                let saved = SavedCondition {
+                    lasted_secs: now.duration_since(condition.started).as_secs(),
+                    stages_fired: condition.stages_fired,
+                };
+                (name.clone(), saved)
+            })
+            .collect()
+    }
+
+    /// Tracks the saved conditions again, as if they'd been tracked all along. The ones that
+    /// resolved while the client was down are dropped on the next update.
+    pub fn restore(&mut self, saved: &BTreeMap<String, SavedCondition>, now: Instant) {
+        for (name, saved) in saved {
+            let lasted: Duration = Duration::from_secs(saved.lasted_secs);
+            self.conditions.insert(
+                name.clone(),
+                Condition {
+                    started: now.checked_sub(lasted).unwrap_or(now),
+                    stages_fired: saved.stages_fired,
+                },
+            );
+        }
+    }
+
+    /// Returns the conditions that lasted long enough for their next stage, marking the stage
+    /// as fired. A condition only moves one stage per call.
+    pub fn due(&mut self, stages: &[EscalationStage], now: Instant) -> Vec<DueEscalation> {
+        let mut due: Vec<DueEscalation> = Vec::new();
+        for (name, condition) in self.conditions.iter_mut() {
+            let next = match stages.get(condition.stages_fired) {
+                Some(next) => next,
+                None => continue,
+            };
+            let lasted: Duration = now.duration_since(condition.started);
+            if lasted >= Duration::from_secs(next.after_mins * 60) {
+                due.push(DueEscalation {
+                    condition: name.clone(),
+                    stage: condition.stages_fired,
+                    lasted,
+                });
+                condition.stages_fired += 1;
+            }
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notify::NotifierConfig;
+
+    fn stage(after_mins: u64) -> EscalationStage {
+        EscalationStage {
+            after_mins,
+            notifiers: vec![NotifierConfig::Email],
+        }
+    }
+
+    #[test]
+    fn test_persisting_condition_reaches_second_stage() {
+        let stages = vec![stage(5), stage(15)];
+        let mut escalations = Escalations::new();
+        let start = Instant::now();
+        let down = vec!["ufw.service".to_owned()];
+
+        escalations.update(&down, start);
+        assert!(escalations.due(&stages, start).is_empty());
+
+        let at = start + Duration::from_secs(6 * 60);
+        escalations.update(&down, at);
+        let due = escalations.due(&stages, at);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].stage, 0);
+        // Each stage only fires once
+        assert!(escalations.due(&stages, at).is_empty());
+
+        let at = start + Duration::from_secs(16 * 60);
+        escalations.update(&down, at);
+        let due = escalations.due(&stages, at);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].condition, "ufw.service");
+        assert_eq!(due[0].stage, 1);
+    }
+
+    #[test]
+    fn test_resolved_condition_starts_over() {
+        let stages = vec![stage(5)];
+        let mut escalations = Escalations::new();
+        let start = Instant::now();
+
+        escalations.update(&["ufw.service".to_owned()], start);
+        escalations.update(&[], start + Duration::from_secs(60));
+
+        let at = start + Duration::from_secs(6 * 60);
+        escalations.update(&["ufw.service".to_owned()], at);
+        assert!(escalations.due(&stages, at).is_empty());
+    }
+
+    #[test]
+    fn test_restored_condition_keeps_its_progress() {
+        let stages = vec![stage(5), stage(15)];
+        let mut escalations = Escalations::new();
+        let start = Instant::now();
+        let down = vec!["ufw.service".to_owned()];
+
+        let at = start + Duration::from_secs(6 * 60);
+        escalations.update(&down, start);
+        escalations.update(&down, at);
+        assert_eq!(escalations.due(&stages, at).len(), 1);
+
+        // The first stage isn't notified again by the restarted client
+        let mut restored = Escalations::new();
+        restored.restore(&escalations.save(at), at);
+        restored.update(&down, at);
+        assert!(restored.due(&stages, at).is_empty());
+
+        let at = at + Duration::from_secs(10 * 60);
+        restored.update(&down, at);
+        assert_eq!(restored.due(&stages, at)[0].stage, 1);
+    }
+}
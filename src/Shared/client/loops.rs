@@ -0,0 +1,1503 @@
+use crate::client::escalation::{DueEscalation, Escalations};
+use crate::client::ssh_monitor::{ProcessSource, SshMonitor, SSH_EVENTS};
+use crate::client::trends::MEMORY_TRENDS;
+use crate::{
+    ais_config::AisConfig,
+    ais_data::{AisInfo, InterfaceChange, NetworkInterface},
+    command::{CommandRunner, SystemRunner},
+    emails::{Email, EmailCategory, EmailPriority},
+    errors::{AisError, Caller, ErrorInfo, UnifiedError},
+    git_actions::fetch_current_commit,
+    git_data::GitCredentials,
+    health::Health,
+    log_file::{notice, output, warn},
+    notify::{EscalationStage, Notifier, Notifiers},
+    resources::{ResourceAlert, ResourceMonitor, ResourceSource, ResourceUsage},
+    site_health::{HealthCheck, SiteHealthMonitor},
+    service::{Memory, ProcessInfo, Processes, Services, Status},
+    site_info::SiteInfo,
+};
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    thread,
+    time::Instant,
+};
+use system::PathType;
+use system_shutdown::reboot;
+use systemstat::{Duration, Platform, System as SystemStats};
+
+/// How often the host's load, memory and disk use are sampled, the loop runs far more often.
+const RESOURCE_SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Tracks the resources that are over their thresholds across runs of the resource loop.
+pub static RESOURCE_MONITOR: Mutex<ResourceMonitor> =
+    Mutex::new(ResourceMonitor::new(RESOURCE_SAMPLE_INTERVAL));
+
+/// How often the sites' health urls are checked.
+const SITE_HEALTH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The journal excerpt in a service alert is cut down to its last lines within this many bytes.
+/// Hexed, encrypted and signed the alert still fits well within `MAX_FRAME_BYTES`.
+pub(crate) const JOURNAL_EXCERPT_MAX_BYTES: usize = 2048;
+
+/// Tracks the sites failing their health checks across runs of the site health loop.
+pub static SITE_HEALTH_MONITOR: Mutex<SiteHealthMonitor> =
+    Mutex::new(SiteHealthMonitor::new(SITE_HEALTH_INTERVAL));
+
+/// Updates machine-specific information.
+pub fn machine_update_loop(
+    ais_data: Arc<RwLock<AisInfo>>,
+    notifier: &dyn Notifier,
+) -> Result<(), UnifiedError> {
+    let ais_new_data = AisInfo::refresh()?;
+    let mut ais_write_safe_data = acquire_write_lock(
+        &ais_data,
+        Caller::Function(true, Some("Machine Update Loop".to_owned())),
+    )?;
+
+    ais_write_safe_data.client_id = ais_new_data.client_id.clone();
+    ais_write_safe_data.machine_id = ais_new_data.machine_id.clone();
+    let ssh_events: usize = SSH_EVENTS.get();
+    ais_write_safe_data.ssh_events = ssh_events;
+    let mut save_manifest: bool = ais_new_data.ssh_events != ssh_events;
+
+    if ais_write_safe_data.machine_ip != ais_new_data.machine_ip {
+        let mail = Email {
+            subject: "Error Occurred".to_owned(),
+            body: format!(
+                "The system: {} Has encountered and error. The assigned IP address is not respected",
+                ais_write_safe_data.machine_id.clone().unwrap_or_else(|| String::from("Failed to parse"))
+            ),
+            priority: EmailPriority::High,
+            category: EmailCategory::MachineIdentity,
+        };
+        notifier.notify(&mail)?;
+        warn("An error occurred, Administrator notified");
+    };
+    if ais_write_safe_data.machine_mac != ais_new_data.machine_mac {
+        let mail = Email {
+            subject: "SOMETHING IS REALLY WRONG".to_owned(),
+            body: format!("The system: {} Has encountered a major error. The MAC address on file is not the MAC address the system is reporting. The system is going offline.",
+                          ais_write_safe_data.machine_id.clone().unwrap_or_else(|| String::from("Failed to parse"))),
+            priority: EmailPriority::High,
+            category: EmailCategory::MachineIdentity,
+        };
+        notifier.notify(&mail)?;
+        reboot().unwrap(); //todo  maybe handle this better one day
+    };
+
+    if AisConfig::new()?.watch_interfaces {
+        let machine_id: String = ais_write_safe_data.identity();
+        let snapshot_changed: bool = handle_interface_changes(
+            &mut ais_write_safe_data.interfaces,
+            AisInfo::fetch_interfaces(),
+            &machine_id,
+            notifier,
+        )?;
+        save_manifest |= snapshot_changed;
+    }
+
+    if save_manifest {
+        let manifest = AisInfo {
+            interfaces: ais_write_safe_data.interfaces.clone(),
+            ssh_events,
+            health: ais_write_safe_data.health.clone(),
+            ..ais_new_data
+        };
+        if let Err(e) = manifest.create_manifest() {
+            warn(&format!("Failed to save the manifest: {}", e));
+        }
+    }
+
+    drop(ais_write_safe_data);
+    thread::sleep(Duration::from_nanos(100));
+    Ok(())
+}
+
+/// Compares the interfaces against the snapshot, alerting on anything added, removed or
+/// readdressed. The first snapshot is taken silently. Returns true when the snapshot was
+/// updated and should be saved to the manifest.
+fn handle_interface_changes(
+    snapshot: &mut Vec<NetworkInterface>,
+    current: Vec<NetworkInterface>,
+    machine_id: &str,
+    notifier: &dyn Notifier,
+) -> Result<bool, UnifiedError> {
+    if snapshot.is_empty() {
+        if current.is_empty() {
+            return Ok(false);
+        }
+        notice(&format!(
+            "Took a snapshot of {} network interfaces",
+            current.len()
+        ));
+        *snapshot = current;
+        return Ok(true);
+    }
+
+    let changes: Vec<InterfaceChange> = AisInfo::diff_interfaces(snapshot, &current);
+    if changes.is_empty() {
+        return Ok(false);
+    }
+
+    let mail = Email {
+        subject: format!("{}: Network interfaces changed", machine_id),
+        body: format!(
+            "The network interfaces on {} no longer match the snapshot:\n{}",
+            machine_id,
+            changes
+                .iter()
+                .map(|change| format!("  {}", change))
+                .collect::<Vec<String>>()
+                .join("\n")
+        ),
+        priority: EmailPriority::High,
+        category: EmailCategory::MachineIdentity,
+    };
+    notifier.notify(&mail)?;
+    warn(&format!(
+        "{} network interface changes, Administrator notified",
+        changes.len()
+    ));
+    *snapshot = current;
+    Ok(true)
+}
+
+/// Builds the escalation for a service that stopped, only critical services are escalated.
+fn stopped_service_escalation(
+    service: &str,
+    machine_id: &str,
+    config: &AisConfig,
+) -> Option<Email> {
+    if !config.is_critical_service(service) {
+        return None;
+    }
+    Some(Email {
+        subject: format!("{}: Critical service stopped", machine_id),
+        body: format!("The critical service {} stopped unexpectedly", service),
+        priority: EmailPriority::High,
+        category: EmailCategory::ServiceStatus,
+    })
+}
+
+/// Reads the unit's latest journal lines, kept within `JOURNAL_EXCERPT_MAX_BYTES`. Returns
+/// None when excerpts are turned off or the journal can't be read.
+fn journal_excerpt(runner: &dyn CommandRunner, unit: &str, lines: usize) -> Option<String> {
+    if lines == 0 {
+        return None;
+    }
+    let journal: String = match runner.run(
+        "journalctl",
+        &["-u", unit, "-n", &lines.to_string(), "--no-pager"],
+    ) {
+        Ok(journal) => journal,
+        Err(e) => {
+            warn(&format!("Failed to read the journal of {}: {}", unit, e));
+            return None;
+        }
+    };
+    if journal.trim().is_empty() {
+        return None;
+    }
+
+    // The newest lines are the ones that explain the failure, the oldest are dropped
+    let mut excerpt: &str = journal.trim_end();
+    if excerpt.len() > JOURNAL_EXCERPT_MAX_BYTES {
+        let mut start: usize = excerpt.len() - JOURNAL_EXCERPT_MAX_BYTES;
+        while !excerpt.is_char_boundary(start) {
+            start += 1;
+        }
+        excerpt = &excerpt[start..];
+        excerpt = excerpt.split_once('\n').map_or(excerpt, |(_, rest)| rest);
+    }
+    Some(excerpt.to_owned())
+}
+
+/// Appends the journal excerpt to the alert.
+fn attach_journal(email: &mut Email, unit: &str, excerpt: &Option<String>) {
+    if let Some(excerpt) = excerpt {
+        email
+            .body
+            .push_str(&format!("\n\nThe latest journal of {}:\n{}", unit, excerpt));
+    }
+}
+
+/// Handles a service whose status changed since the last check: critical services that stopped
+/// are escalated, services in an unknown state are restarted and ones that started are reported,
+/// unless they were only coming up as the client started. A service that's no longer installed
+/// is reported once, it's a provisioning issue that a restart can't fix. Alerts about a service
+/// failing carry its latest journal lines, read before it's restarted.
+fn handle_service_change(
+    previous: &ProcessInfo,
+    current: &ProcessInfo,
+    machine_id: &str,
+    config: &AisConfig,
+    restart: &dyn Fn(&Services) -> Result<bool, UnifiedError>,
+    runner: &dyn CommandRunner,
+    notifier: &dyn Notifier,
+) -> Result<(), UnifiedError> {
+    if previous.status == current.status {
+        return Ok(());
+    }
+    let journal = || journal_excerpt(runner, &current.service, config.journal_excerpt_lines);
+
+    match current.status {
+        Status::Stopped => {
+            match stopped_service_escalation(&previous.service, machine_id, config) {
+                Some(mut email) => {
+                    attach_journal(&mut email, &current.service, &journal());
+                    if config.restart_critical_services {
+                        let restarted: bool = restart(&previous.refered)?;
+                        email.body.push_str(match restarted {
+                            true => "\n\nThe service was restarted automatically.",
+                            false => "\n\nRestarting the service automatically failed.",
+                        });
+                    }
+                    notifier.notify(&email)?;
+                    warn(&format!(
+                        "Critical service {} has stopped. Emails has been sent",
+                        previous.service
+                    ));
+                }
+                None => warn(&format!("Service {} has stopped", previous.service)),
+            }
+        }
+        Status::Error => {
+            let mut email = Email {
+                subject: format!("{}: Service in an unknown state", machine_id),
+                body: format!(
+                    "The service {} stopped unexpectedly, attempting the restart automatically.",
+                    previous.service
+                ),
+                priority: EmailPriority::High,
+                category: EmailCategory::ServiceStatus,
+            };
+            attach_journal(&mut email, &current.service, &journal());
+            match restart(&previous.refered)? {
+                true => {
+                    warn(&format!(
+                        "Service {} restarted successfully",
+                        previous.service
+                    ));
+                }
+                false => {
+                    warn(&format!(
+                        "Service {} has entered an erroneous state. Emails have been sent",
+                        previous.service
+                    ));
+                    notifier.notify(&email)?
+                }
+            }
+        }
+        Status::Missing => {
+            let mail = Email {
+                subject: format!("{}: Service not installed", machine_id),
+                body: format!(
+                    "The system: {} expects the service: {} but it's no longer installed. It won't be reported again until it's reinstalled.",
+                    machine_id, current.service
+                ),
+                priority: EmailPriority::High,
+                category: EmailCategory::ServiceStatus,
+            };
+            notifier.notify(&mail)?;
+            warn(&format!("Service {} is not installed", current.service));
+        }
+        // The service was starting up as the client started, it hasn't started since
+        Status::Running if previous.baseline && !config.announce_first_running => {
+            notice(&format!("Service {} is running", current.service));
+        }
+        Status::Running => {
+            let mail = Email {
+                subject: format!("{}: Service running", machine_id),
+                body: format!(
+                    "The system: {} Is happy to report that the service: {} has entered the state {}.",
+                    machine_id, current.service, current.status
+                ),
+                priority: EmailPriority::Low,
+                category: EmailCategory::ServiceStatus,
+            };
+            notifier.notify(&mail)?;
+            output("GREEN", "Service started !");
+        }
+    }
+    Ok(())
+}
+
+/// Warns when a service becomes active but disabled, it's running now but won't come back
+/// after a reboot.
+fn handle_enablement_drift(
+    previous: &ProcessInfo,
+    current: &ProcessInfo,
+    machine_id: &str,
+    notifier: &dyn Notifier,
+) -> Result<(), UnifiedError> {
+    if !current.is_active_but_disabled() || previous.is_active_but_disabled() {
+        return Ok(());
+    }
+
+    let mail = Email {
+        subject: format!("{}: Service disabled", machine_id),
+        body: format!(
+            "The service {} is running but disabled, it won't start again after a reboot.",
+            current.service
+        ),
+        priority: EmailPriority::Low,
+        category: EmailCategory::ServiceStatus,
+    };
+    notifier.notify(&mail)?;
+    warn(&format!(
+        "Service {} is active but disabled on boot",
+        current.service
+    ));
+    Ok(())
+}
+
+/// Updates system services and monitors their status.
+pub fn service_update_loop(
+    system_service_data: Arc<RwLock<Processes>>,
+    ais_data: Arc<RwLock<AisInfo>>,
+    notifier: &dyn Notifier,
+) -> Result<(), UnifiedError> {
+    let service_data = acquire_read_lock(
+        &system_service_data,
+        Caller::Function(true, Some("Service Update Loop, service_data".to_owned())),
+    )?;
+    let ais_info = acquire_read_lock(
+        &ais_data,
+        Caller::Function(true, Some("Service Update Loop, ais_info".to_owned())),
+    )?;
+    let config = AisConfig::new()?;
+    let machine_id: String = ais_info
+        .machine_id
+        .clone()
+        .unwrap_or_else(|| String::from("Failure parsing"));
+
+    let mut data = Vec::new();
+
+    for service_info in service_data.itr() {
+        let new_service_info = service_info.refered.get_info()?;
+        let new_service_to_update = new_service_info.clone();
+
+        if config
+            .memory_trend_services
+            .contains(&new_service_info.service)
+        {
+            if let (Some(bytes), Ok(mut trends)) =
+                (new_service_info.memory.bytes(), MEMORY_TRENDS.lock())
+            {
+                trends.record(&new_service_info.service, bytes, Instant::now());
+            }
+        }
+
+        handle_service_change(
+            &service_info,
+            &new_service_info,
+            &machine_id,
+            &config,
+            &|service: &Services| service.restart(),
+            &SystemRunner,
+            notifier,
+        )?;
+        handle_enablement_drift(&service_info, &new_service_info, &machine_id, notifier)?;
+
+        match new_service_info.memory {
+            Memory::MemoryConsumed(d) => {
+                if d.contains("G") && d.contains("2.") {
+                    let mail = Email {
+                        subject: "Warning".to_owned(),
+                        body: format!("The system: {} Wants you to know that: {} is consuming over 2G of resources. This should be safe to ignore.", ais_info.machine_id.clone()
+                            .unwrap_or_else(|| String::from("Failure parsing")), new_service_info.service),
+                        priority: EmailPriority::Low,
+                        category: EmailCategory::Resource,
+                    };
+                    notifier.notify(&mail)?;
+                }
+            }
+        }
+        data.push(new_service_to_update);
+    }
+    drop(ais_info);
+    drop(service_data);
+
+    let mut service_data_old = acquire_write_lock(
+        &system_service_data,
+        Caller::Function(
+            true,
+            Some("Service Update Loop, New service data".to_owned()),
+        ),
+    )?;
+
+    *service_data_old = Processes::Services(data);
+    Ok(())
+}
+
+/// Escalates services that stay down through the configured stages.
+pub fn escalation_loop(
+    system_service_data: Arc<RwLock<Processes>>,
+    ais_data: Arc<RwLock<AisInfo>>,
+    escalations: Arc<RwLock<Escalations>>,
+    stages: &[EscalationStage],
+) -> Result<(), UnifiedError> {
+    let service_data = acquire_read_lock(
+        &system_service_data,
+        Caller::Function(true, Some("Escalation Loop, service_data".to_owned())),
+    )?;
+    // Missing services are a provisioning issue, they're reported once rather than escalated
+    let down: Vec<String> = service_data
+        .itr()
+        .iter()
+        .filter(|service| !matches!(service.status, Status::Running | Status::Missing))
+        .map(|service| service.service.clone())
+        .collect();
+    drop(service_data);
+
+    let mut escalations = acquire_write_lock(
+        &escalations,
+        Caller::Function(true, Some("Escalation Loop, escalations".to_owned())),
+    )?;
+    let now: Instant = Instant::now();
+    escalations.update(&down, now);
+    let due: Vec<DueEscalation> = escalations.due(stages, now);
+    drop(escalations);
+
+    let machine_id: String = acquire_read_lock(
+        &ais_data,
+        Caller::Function(true, Some("Escalation Loop, ais_info".to_owned())),
+    )?
+    .machine_id
+    .clone()
+    .unwrap_or_else(|| String::from("Failure parsing"));
+
+    for escalation in due {
+        let email: Email = Email {
+            subject: format!("{}: {} is still down", machine_id, escalation.condition),
+            body: format!(
+                "The service {} on the system: {} has been down for {} minutes. This is escalation stage {}.",
+                escalation.condition,
+                machine_id,
+                escalation.lasted.as_secs() / 60,
+                escalation.stage + 1
+            ),
+            priority: EmailPriority::High,
+            category: EmailCategory::ServiceStatus,
+        };
+        if let Err(e) = Notifiers::from_list(&stages[escalation.stage].notifiers).notify(&email) {
+            warn(&format!(
+                "Failed to escalate {} to stage {}: {}",
+                escalation.condition,
+                escalation.stage + 1,
+                e
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Samples the host's load, memory and disk use when a sample is due, alerting on the
+/// resources that have stayed over their thresholds.
+pub fn resource_update_loop(
+    ais_data: Arc<RwLock<AisInfo>>,
+    monitor: &Mutex<ResourceMonitor>,
+    source: &dyn ResourceSource,
+    notifier: &dyn Notifier,
+) -> Result<(), UnifiedError> {
+    let mut monitor = monitor.lock().unwrap_or_else(|e| e.into_inner());
+    if !monitor.sample_due(Instant::now()) {
+        return Ok(());
+    }
+
+    let config = AisConfig::new()?;
+    let usage: ResourceUsage = source.usage(&config.site_root);
+    let alerts: Vec<ResourceAlert> = monitor.observe(&usage, &config);
+    drop(monitor);
+    if alerts.is_empty() {
+        return Ok(());
+    }
+
+    let machine_id: String = acquire_read_lock(
+        &ais_data,
+        Caller::Function(true, Some("Resource Update Loop, ais_info".to_owned())),
+    )?
+    .identity();
+
+    for alert in alerts {
+        let email: Email = Email {
+            subject: format!("{}: {} is high", machine_id, alert.resource),
+            body: format!("The system: {} is running hot. {}.", machine_id, alert),
+            priority: EmailPriority::High,
+            category: EmailCategory::Resource,
+        };
+        notifier.notify(&email)?;
+        warn(&alert.to_string());
+    }
+    Ok(())
+}
+
+/// GETs the health url of every site that has one when a round of checks is due, alerting
+/// on the sites that have failed for the configured number of checks in a row.
+pub fn site_health_loop(
+    ais_data: Arc<RwLock<AisInfo>>,
+    git_creds: Arc<RwLock<GitCredentials>>,
+    monitor: &Mutex<SiteHealthMonitor>,
+    notifier: &dyn Notifier,
+) -> Result<(), UnifiedError> {
+    let mut monitor = monitor.lock().unwrap_or_else(|e| e.into_inner());
+    if !monitor.check_due(Instant::now()) {
+        return Ok(());
+    }
+
+    let config = AisConfig::new()?;
+    let sites: Vec<(String, String)> = acquire_read_lock(
+        &git_creds,
+        Caller::Function(true, Some("Site Health Loop, git_info".to_owned())),
+    )?
+    .auths
+    .iter()
+    .filter_map(|auth| Some((auth.repo.clone(), auth.health_url.clone()?)))
+    .collect();
+    monitor.retain(
+        &sites
+            .iter()
+            .map(|(_, url)| url.as_str())
+            .collect::<Vec<&str>>(),
+    );
+
+    let timeout: Duration = Duration::from_secs(config.health_check_timeout_secs);
+    for (repo, url) in sites {
+        let check: HealthCheck = HealthCheck::fetch(&url, timeout);
+        let failures: u32 = match monitor.observe(&url, &check, &config) {
+            Some(failures) => failures,
+            None => continue,
+        };
+
+        let machine_id: String = acquire_read_lock(
+            &ais_data,
+            Caller::Function(true, Some("Site Health Loop, ais_info".to_owned())),
+        )?
+        .identity();
+        let email: Email = Email {
+            subject: format!("{}: {} is unhealthy", machine_id, repo),
+            body: format!(
+                "The system: {} has had {} failed health checks in a row for the repo: {}. {}.",
+                machine_id, failures, repo, check
+            ),
+            priority: EmailPriority::High,
+            category: EmailCategory::ServiceStatus,
+        };
+        notifier.notify(&email)?;
+        warn(&format!("{} is unhealthy: {}", repo, check));
+    }
+    Ok(())
+}
+
+/// Builds the daily summary email from the current service state and ssh event count.
+pub fn build_daily_summary(ais_info: &AisInfo, processes: &Processes, ssh_events: usize) -> Email {
+    let machine_id: String = ais_info
+        .machine_id
+        .clone()
+        .unwrap_or_else(|| String::from("Failure parsing"));
+
+    let services = processes.itr();
+    let degraded: Vec<String> = services
+        .iter()
+        .filter(|service| service.status != Status::Running)
+        .map(|service| service.service.clone())
+        .collect();
+
+    let subject: String = match degraded.is_empty() {
+        true => format!("{}: Daily summary, all good", machine_id),
+        false => format!(
+            "{}: Daily summary, {} service(s) degraded",
+            machine_id,
+            degraded.len()
+        ),
+    };
+
+    let mut body: String = format!("Daily summary for the system: {}\n\nServices:\n", machine_id);
+    for service in &services {
+        body.push_str(&format!(
+            "  {}: {} ({} memory){}\n",
+            service.service,
+            service.status,
+            service.memory,
+            match service.is_active_but_disabled() {
+                true => ", disabled on boot",
+                false => "",
+            }
+        ));
+    }
+    body.push_str(&format!("\nSSH events recorded: {}\n", ssh_events));
+
+    let priority: EmailPriority = match degraded.is_empty() {
+        true => EmailPriority::Low,
+        false => EmailPriority::High,
+    };
+
+    Email {
+        subject,
+        body,
+        priority,
+        category: EmailCategory::Summary,
+    }
+}
+
+/// Sends the daily summary, including resource usage and the deployed commit of every site.
+pub fn daily_summary_loop(
+    ais_data: Arc<RwLock<AisInfo>>,
+    system_service_data: Arc<RwLock<Processes>>,
+    git_creds: Arc<RwLock<GitCredentials>>,
+    notifier: &dyn Notifier,
+) -> Result<(), UnifiedError> {
+    let ais_info = acquire_read_lock(
+        &ais_data,
+        Caller::Function(true, Some("Daily Summary Loop, ais_info".to_owned())),
+    )?;
+    let service_data = acquire_read_lock(
+        &system_service_data,
+        Caller::Function(true, Some("Daily Summary Loop, service_data".to_owned())),
+    )?;
+
+    let mut summary: Email = build_daily_summary(&ais_info, &service_data, SSH_EVENTS.get());
+    let health: Health = ais_info.health.clone();
+    drop(service_data);
+    drop(ais_info);
+
+    let stats = SystemStats::new();
+    summary.body.push_str("\nResources:\n");
+    match stats.memory() {
+        Ok(mem) => summary.body.push_str(&format!(
+            "  Memory: {} free of {}\n",
+            mem.free, mem.total
+        )),
+        Err(e) => summary.body.push_str(&format!("  Memory: error: {}\n", e)),
+    }
+    match stats.mount_at("/") {
+        Ok(disk) => summary.body.push_str(&format!(
+            "  Disk: {} available of {}\n",
+            disk.avail, disk.total
+        )),
+        Err(e) => summary.body.push_str(&format!("  Disk: error: {}\n", e)),
+    }
+    summary.body.push_str(&format!(
+        "\nHealth as of the last cycle: {}\n",
+        match health.is_healthy() {
+            true => "healthy",
+            false => "degraded",
+        }
+    ));
+    summary.body.push_str(&health.summary());
+    if let Ok(trends) = MEMORY_TRENDS.lock() {
+        let trend_summary: String = trends.summary();
+        if !trend_summary.is_empty() {
+            summary.body.push_str("\nMemory trends (last hour):\n");
+            summary.body.push_str(&trend_summary);
+        }
+    }
+
+    let git_info = acquire_read_lock(
+        &git_creds,
+        Caller::Function(true, Some("Daily Summary Loop, git_info".to_owned())),
+    )?;
+    let site_root = AisConfig::new()?.site_root;
+    summary.body.push_str("\nDeployed commits:\n");
+    for git_credential in &git_info.auths {
+        let commit: String = match SiteInfo::get_site_folder(git_credential, &site_root) {
+            Ok(folder) => fetch_current_commit(&PathType::PathBuf(folder))
+                .unwrap_or_else(|e| format!("error: {}", e)),
+            Err(e) => format!("error: {}", e),
+        };
+        summary.body.push_str(&format!(
+            "  {}/{} ({}): {}\n",
+            git_credential.user, git_credential.repo, git_credential.branch, commit
+        ));
+    }
+    drop(git_info);
+
+    notifier.notify(&summary)
+}
+
+/// Monitors SSH connections. Every sshd process is checked, the first error is returned.
+pub fn monitor_ssh_connections(
+    ssh_monitor: SshMonitor,
+    processes: &dyn ProcessSource,
+    runner: &dyn CommandRunner,
+    ais_info: Arc<RwLock<AisInfo>>,
+    notifier: &dyn Notifier,
+) -> Result<(), UnifiedError> {
+    let mut result: Result<(), UnifiedError> = Ok(());
+    for process in processes.processes() {
+        if !process.name.contains("sshd") {
+            continue;
+        }
+        if let Err(e) = ssh_monitor.clone().process_ssh_connection(
+            &process,
+            runner,
+            Arc::clone(&ais_info),
+            notifier,
+        ) {
+            if result.is_ok() {
+                result = Err(e);
+            }
+        }
+    }
+
+    result
+}
+
+/// Helper function to acquire a read lock safely.
+pub fn acquire_read_lock<T: 'static>(
+    lock: &Arc<RwLock<T>>,
+    caller: Caller,
+) -> Result<RwLockReadGuard<'_, T>, UnifiedError> {
+    lock.read().map_err(|_| {
+        UnifiedError::AisError(
+            ErrorInfo::new(caller),
+            AisError::ThreadedDataError(Some("Error acquiring Read lock".to_string())),
+        )
+    })
+}
+
+/// Helper function to acquire a write lock safely.
+pub fn acquire_write_lock<T: 'static>(
+    lock: &Arc<RwLock<T>>,
+    caller: Caller,
+) -> Result<RwLockWriteGuard<'_, T>, UnifiedError> {
+    lock.write().map_err(|_| {
+        UnifiedError::AisError(
+            ErrorInfo::new(caller),
+            AisError::ThreadedDataError(Some("Error acquiring Write lock".to_string())),
+        )
+    })
+}
+
+/// Runs a monitoring loop, catching a panic and sending a high priority alert through `notify`
+/// so the loop can be restarted without going unnoticed. The outcome is recorded in the
+/// health kept in `ais_data`.
+pub fn supervise<F, N>(
+    name: &str,
+    ais_data: &Arc<RwLock<AisInfo>>,
+    task: F,
+    notify: N,
+) -> Result<(), UnifiedError>
+where
+    F: FnOnce() -> Result<(), UnifiedError>,
+    N: FnOnce(Email) -> Result<(), UnifiedError>,
+{
+    let payload = match panic::catch_unwind(AssertUnwindSafe(task)) {
+        Ok(result) => return record_health(name, ais_data, result),
+        Err(payload) => payload,
+    };
+
+    // The panic may have poisoned the lock, the alert still goes out without the id
+    let machine_id: String = ais_data
+        .read()
+        .ok()
+        .and_then(|ais_info| ais_info.machine_id.clone())
+        .unwrap_or_else(|| String::from("Failure parsing"));
+    let message: String = panic_message(payload.as_ref());
+
+    let alert: Email = Email {
+        subject: format!("{}: {} panicked", machine_id, name),
+        body: format!(
+            "The {} loop on the system: {} panicked and will be restarted.\n\nPanic: {}",
+            name, machine_id, message
+        ),
+        priority: EmailPriority::High,
+        category: EmailCategory::General,
+    };
+    if let Err(e) = notify(alert) {
+        warn(&format!(
+            "Failed to send the panic alert for {}: {}",
+            name, e
+        ));
+    }
+
+    record_health(
+        name,
+        ais_data,
+        Err(UnifiedError::AisError(
+            ErrorInfo::new(Caller::Function(true, Some("supervise".to_owned()))),
+            AisError::ThreadedDataError(Some(format!("{} panicked: {}", name, message))),
+        )),
+    )
+}
+
+/// Records the outcome of a loop's cycle in the health, passing the result through.
+fn record_health(
+    name: &str,
+    ais_data: &Arc<RwLock<AisInfo>>,
+    result: Result<(), UnifiedError>,
+) -> Result<(), UnifiedError> {
+    // A loop that panicked holding the lock poisoned it, its failure still has to show up
+    let mut ais_info = ais_data.write().unwrap_or_else(|e| e.into_inner());
+    ais_info.health.record(name, &result);
+    result
+}
+
+/// Pulls the message out of a panic payload.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => String::from("Unknown panic"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::HealthState;
+    use crate::client::ssh_monitor::ProcessEntry;
+    use crate::client::warm_state::WarmState;
+    use crate::git_data::{DeployMode, GitAuth};
+    use crate::service::SubProcesses;
+    use crate::test_support::RecordingNotifier;
+    use std::sync::Mutex;
+
+    /// Answers journalctl with the lines, or fails like a host without journalctl.
+    struct MockJournal(Option<String>);
+
+    /// A host without journalctl.
+    const NO_JOURNAL: MockJournal = MockJournal(None);
+
+    impl CommandRunner for MockJournal {
+        fn run(&self, program: &str, args: &[&str]) -> Result<String, UnifiedError> {
+            assert_eq!(program, "journalctl");
+            assert_eq!(args[0], "-u");
+            self.0.clone().ok_or_else(|| {
+                UnifiedError::from_ais_error(AisError::new("journalctl: command not found"))
+            })
+        }
+    }
+
+    #[test]
+    fn test_machine_update_loop_success() {
+        // Arrange
+        let ais_data = Arc::new(RwLock::new(AisInfo::new().unwrap()));
+
+        // Act
+        let result = machine_update_loop(ais_data, &RecordingNotifier::default());
+
+        // Assert
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_stopped_critical_service_escalates() {
+        let config = AisConfig::default();
+
+        let email = stopped_service_escalation("ufw.service", "a1b2c3d4", &config).unwrap();
+        assert_eq!(email.priority, EmailPriority::High);
+        assert_eq!(email.category, EmailCategory::ServiceStatus);
+        assert!(email.body.contains("ufw.service"));
+
+        assert!(stopped_service_escalation("netdata.service", "a1b2c3d4", &config).is_none());
+    }
+
+    #[test]
+    fn test_stopped_service_change_detected() {
+        let config = AisConfig {
+            restart_critical_services: true,
+            ..AisConfig::default()
+        };
+        let notifier = RecordingNotifier::default();
+        let restarts: Mutex<Vec<Services>> = Mutex::new(Vec::new());
+        let restart = |service: &Services| -> Result<bool, UnifiedError> {
+            restarts.lock().unwrap().push(service.clone());
+            Ok(true)
+        };
+
+        let running = ProcessInfo::new(
+            Services::FIREWALL,
+            Status::Running,
+            Memory::MemoryConsumed("2M".to_owned()),
+        );
+        let stopped = ProcessInfo::new(
+            Services::FIREWALL,
+            Status::Stopped,
+            Memory::MemoryConsumed("0B".to_owned()),
+        );
+
+        // No change, nothing happens
+        handle_service_change(
+            &running,
+            &running,
+            "a1b2c3d4",
+            &config,
+            &restart,
+            &NO_JOURNAL,
+            &notifier,
+        )
+        .unwrap();
+        assert!(notifier.0.lock().unwrap().is_empty());
+
+        handle_service_change(
+            &running,
+            &stopped,
+            "a1b2c3d4",
+            &config,
+            &restart,
+            &NO_JOURNAL,
+            &notifier,
+        )
+        .unwrap();
+        assert_eq!(*restarts.lock().unwrap(), vec![Services::FIREWALL]);
+        let sent = notifier.0.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].priority, EmailPriority::High);
+        assert!(sent[0].body.contains("restarted automatically"));
+
+        // A non critical service stopping is only logged
+        let netdata = ProcessInfo::new(
+            Services::MONITOR,
+            Status::Running,
+            Memory::MemoryConsumed("40M".to_owned()),
+        );
+        let netdata_stopped = ProcessInfo {
+            status: Status::Stopped,
+            ..netdata.clone()
+        };
+        handle_service_change(
+            &netdata,
+            &netdata_stopped,
+            "a1b2c3d4",
+            &config,
+            &restart,
+            &NO_JOURNAL,
+            &notifier,
+        )
+        .unwrap();
+        assert_eq!(notifier.0.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_failure_email_includes_journal_excerpt() {
+        let config = AisConfig::default();
+        let notifier = RecordingNotifier::default();
+        let restart = |_: &Services| -> Result<bool, UnifiedError> { Ok(false) };
+        let process = |status: Status| {
+            ProcessInfo::new(
+                Services::FIREWALL,
+                status,
+                Memory::MemoryConsumed("2M".to_owned()),
+            )
+        };
+        let journal = MockJournal(Some(
+            "Oct 16 09:12:01 host ufw[812]: ERROR: problem running ufw-init".to_owned(),
+        ));
+
+        for status in [Status::Stopped, Status::Error] {
+            handle_service_change(
+                &process(Status::Running),
+                &process(status),
+                "a1b2c3d4",
+                &config,
+                &restart,
+                &journal,
+                &notifier,
+            )
+            .unwrap();
+        }
+        let sent = notifier.0.lock().unwrap().clone();
+        assert_eq!(sent.len(), 2);
+        for email in &sent {
+            assert!(email.body.contains("The latest journal of ufw.service"));
+            assert!(email.body.ends_with("ERROR: problem running ufw-init"));
+        }
+
+        // The alert still goes out without journalctl, only the excerpt is missing
+        handle_service_change(
+            &process(Status::Running),
+            &process(Status::Stopped),
+            "a1b2c3d4",
+            &config,
+            &restart,
+            &NO_JOURNAL,
+            &notifier,
+        )
+        .unwrap();
+        let sent = notifier.0.lock().unwrap().clone();
+        assert_eq!(sent.len(), 3);
+        assert!(!sent[2].body.contains("journal"));
+
+        // A chatty unit is cut down to its newest whole lines
+        let flood: String = (0..1000)
+            .map(|line| format!("Oct 16 09:12:01 host ufw[812]: line {}", line))
+            .collect::<Vec<String>>()
+            .join("\n");
+        let excerpt = journal_excerpt(&MockJournal(Some(flood)), "ufw.service", 1000).unwrap();
+        assert!(excerpt.len() <= JOURNAL_EXCERPT_MAX_BYTES);
+        assert!(excerpt.starts_with("Oct 16"));
+        assert!(excerpt.ends_with("line 999"));
+        assert_eq!(journal_excerpt(&NO_JOURNAL, "ufw.service", 0), None);
+    }
+
+    #[test]
+    fn test_first_poll_establishes_baseline_silently() {
+        let config = AisConfig::default();
+        let notifier = RecordingNotifier::default();
+        let restart = |_: &Services| -> Result<bool, UnifiedError> { Ok(true) };
+        let process = |status: Status, baseline: bool| ProcessInfo {
+            baseline,
+            ..ProcessInfo::new(
+                Services::WEBSERVER,
+                status,
+                Memory::MemoryConsumed("20M".to_owned()),
+            )
+        };
+
+        // Apache was still coming up when the client read its status at startup
+        let startup = process(Status::Stopped, true);
+        let first_poll = process(Status::Running, false);
+        handle_service_change(
+            &startup,
+            &first_poll,
+            "a1b2c3d4",
+            &config,
+            &restart,
+            &NO_JOURNAL,
+            &notifier,
+        )
+        .unwrap();
+        assert!(notifier.0.lock().unwrap().is_empty());
+
+        // Once it's been watched, starting again is a genuine transition
+        let stopped = process(Status::Stopped, false);
+        handle_service_change(
+            &stopped,
+            &first_poll,
+            "a1b2c3d4",
+            &config,
+            &restart,
+            &NO_JOURNAL,
+            &notifier,
+        )
+        .unwrap();
+        assert_eq!(notifier.0.lock().unwrap().len(), 1);
+
+        let config = AisConfig {
+            announce_first_running: true,
+            ..AisConfig::default()
+        };
+        handle_service_change(
+            &startup,
+            &first_poll,
+            "a1b2c3d4",
+            &config,
+            &restart,
+            &NO_JOURNAL,
+            &notifier,
+        )
+        .unwrap();
+        assert_eq!(notifier.0.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_warm_baseline_catches_changes_while_down() {
+        let config = AisConfig::default();
+        let notifier = RecordingNotifier::default();
+        let restart = |_: &Services| -> Result<bool, UnifiedError> { Ok(false) };
+
+        // ufw stopped while the new version was being installed
+        let stopped = ProcessInfo {
+            baseline: true,
+            ..ProcessInfo::new(
+                Services::FIREWALL,
+                Status::Stopped,
+                Memory::MemoryConsumed("0B".to_owned()),
+            )
+        };
+        let starting = Processes::Services(vec![stopped.clone()]);
+        let state = WarmState {
+            services: [("ufw.service".to_owned(), Status::Running)].into(),
+            ..WarmState::default()
+        };
+
+        // Without the saved baseline the stop looks like how things have always been
+        handle_service_change(
+            &starting.itr()[0],
+            &stopped,
+            "a1b2c3d4",
+            &config,
+            &restart,
+            &NO_JOURNAL,
+            &notifier,
+        )
+        .unwrap();
+        assert!(notifier.0.lock().unwrap().is_empty());
+
+        let baseline = state.restore_services(&starting).itr();
+        assert!(!baseline[0].baseline);
+        handle_service_change(
+            &baseline[0],
+            &stopped,
+            "a1b2c3d4",
+            &config,
+            &restart,
+            &NO_JOURNAL,
+            &notifier,
+        )
+        .unwrap();
+        let sent = notifier.0.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].priority, EmailPriority::High);
+    }
+
+    #[test]
+    fn test_uninstalled_service_reported_once() {
+        let config = AisConfig::default();
+        let notifier = RecordingNotifier::default();
+        let restart = |_: &Services| -> Result<bool, UnifiedError> {
+            panic!("A missing service can't be restarted")
+        };
+        let running = ProcessInfo::new(
+            Services::DATABASE,
+            Status::Running,
+            Memory::MemoryConsumed("200M".to_owned()),
+        );
+        let missing = ProcessInfo::new(
+            Services::DATABASE,
+            Status::Missing,
+            Memory::MemoryConsumed("0B".to_owned()),
+        );
+
+        handle_service_change(
+            &running,
+            &missing,
+            "a1b2c3d4",
+            &config,
+            &restart,
+            &NO_JOURNAL,
+            &notifier,
+        )
+        .unwrap();
+        handle_service_change(
+            &missing,
+            &missing,
+            "a1b2c3d4",
+            &config,
+            &restart,
+            &NO_JOURNAL,
+            &notifier,
+        )
+        .unwrap();
+        let sent = notifier.0.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].subject, "a1b2c3d4: Service not installed");
+        assert!(sent[0].body.contains("mysql.service"));
+    }
+
+    #[test]
+    fn test_active_but_disabled_service_warns_once() {
+        let notifier = RecordingNotifier::default();
+        let process = |status: Status, enabled: bool| ProcessInfo {
+            enabled: Some(enabled),
+            ..ProcessInfo::new(
+                Services::WEBSERVER,
+                status,
+                Memory::MemoryConsumed("20M".to_owned()),
+            )
+        };
+        let enabled_active = process(Status::Running, true);
+        let disabled_active = process(Status::Running, false);
+        let enabled_inactive = process(Status::Stopped, true);
+
+        handle_enablement_drift(&enabled_active, &enabled_inactive, "a1b2c3d4", &notifier).unwrap();
+        assert!(notifier.0.lock().unwrap().is_empty());
+
+        handle_enablement_drift(&enabled_active, &disabled_active, "a1b2c3d4", &notifier).unwrap();
+        // Still drifted on the next check, it's not sent again
+        handle_enablement_drift(&disabled_active, &disabled_active, "a1b2c3d4", &notifier).unwrap();
+        let sent = notifier.0.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].category, EmailCategory::ServiceStatus);
+        assert!(sent[0].body.contains("apache2.service"));
+    }
+
+    struct MockResources(ResourceUsage);
+
+    impl ResourceSource for MockResources {
+        fn usage(&self, _disk_path: &std::path::Path) -> ResourceUsage {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_sustained_high_memory_alerts() {
+        let ais_data = Arc::new(RwLock::new(AisInfo::new().unwrap()));
+        let notifier = RecordingNotifier::default();
+        let monitor = Mutex::new(ResourceMonitor::new(Duration::ZERO));
+        let source = MockResources(ResourceUsage {
+            load: Some((0.5, 0.5, 0.5)),
+            cpus: 4,
+            memory_percent: Some(95.0),
+            disk_percent: Some(40.0),
+        });
+        let samples = AisConfig::default().resource_alert_samples;
+
+        for _ in 1..samples {
+            resource_update_loop(ais_data.clone(), &monitor, &source, &notifier).unwrap();
+        }
+        assert!(notifier.0.lock().unwrap().is_empty());
+
+        // Crossing for the required samples alerts, staying over doesn't alert again
+        resource_update_loop(ais_data.clone(), &monitor, &source, &notifier).unwrap();
+        resource_update_loop(ais_data, &monitor, &source, &notifier).unwrap();
+        let sent = notifier.0.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].priority, EmailPriority::High);
+        assert_eq!(sent[0].category, EmailCategory::Resource);
+        assert!(sent[0].subject.ends_with("Memory is high"));
+    }
+
+    #[test]
+    fn test_failing_health_url_alerts_after_consecutive_failures() {
+        use std::io::{Read, Write};
+
+        // Answers every request with a 500
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/health", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.read(&mut [0; 1024]);
+                let _ = stream.write_all(
+                    b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                );
+            }
+        });
+
+        let ais_data = Arc::new(RwLock::new(AisInfo::new().unwrap()));
+        let git_creds = Arc::new(RwLock::new(GitCredentials {
+            auths: vec![GitAuth {
+                user: "artisan".to_owned(),
+                repo: "website".to_owned(),
+                branch: "main".to_owned(),
+                token: String::new(),
+                deploy: DeployMode::Branch,
+                health_url: Some(url),
+            }],
+        }));
+        let notifier = RecordingNotifier::default();
+        let monitor = Mutex::new(SiteHealthMonitor::new(Duration::ZERO));
+        let failures = AisConfig::default().health_check_failures;
+
+        for _ in 1..failures {
+            site_health_loop(ais_data.clone(), git_creds.clone(), &monitor, &notifier).unwrap();
+        }
+        assert!(notifier.0.lock().unwrap().is_empty());
+
+        // Failing for the required checks alerts, failing after that doesn't alert again
+        site_health_loop(ais_data.clone(), git_creds.clone(), &monitor, &notifier).unwrap();
+        site_health_loop(ais_data, git_creds, &monitor, &notifier).unwrap();
+        let sent = notifier.0.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].priority, EmailPriority::High);
+        assert!(sent[0].subject.ends_with("website is unhealthy"));
+        assert!(sent[0].body.contains("500"));
+    }
+
+    #[test]
+    fn test_build_daily_summary() {
+        let ais_info = AisInfo::new().unwrap();
+        let processes = Processes::Services(vec![
+            ProcessInfo {
+                service: "apache2.service".to_owned(),
+                refered: Services::WEBSERVER,
+                status: Status::Running,
+                memory: Memory::MemoryConsumed("20M".to_owned()),
+                children: SubProcesses::Tasks(4),
+                timestamp: String::new(),
+                optional: false,
+                enabled: Some(true),
+                baseline: false,
+            },
+            ProcessInfo {
+                service: "ufw.service".to_owned(),
+                refered: Services::FIREWALL,
+                status: Status::Stopped,
+                memory: Memory::MemoryConsumed("0B".to_owned()),
+                children: SubProcesses::Pid(0),
+                timestamp: String::new(),
+                optional: false,
+                enabled: Some(true),
+                baseline: false,
+            },
+        ]);
+
+        let summary = build_daily_summary(&ais_info, &processes, 7);
+
+        assert!(summary.subject.contains("1 service(s) degraded"));
+        assert!(summary.body.contains("apache2.service: active"));
+        assert!(summary.body.contains("ufw.service: stopped"));
+        assert!(summary.body.contains("SSH events recorded: 7"));
+    }
+
+    #[test]
+    fn test_daily_summary_loop_notifies() {
+        let ais_data = Arc::new(RwLock::new(AisInfo::new().unwrap()));
+        let processes = Arc::new(RwLock::new(Processes::Services(Vec::new())));
+        let git_creds = Arc::new(RwLock::new(GitCredentials { auths: Vec::new() }));
+        let notifier = RecordingNotifier::default();
+
+        daily_summary_loop(ais_data, processes, git_creds, &notifier).unwrap();
+
+        let sent = notifier.0.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].category, EmailCategory::Summary);
+        assert_eq!(sent[0].priority, EmailPriority::Low);
+    }
+
+    #[test]
+    fn test_supervise_reports_panics() {
+        let ais_data = Arc::new(RwLock::new(AisInfo::new().unwrap()));
+        let mut alerts: Vec<Email> = Vec::new();
+
+        let result = supervise(
+            "Machine monitor",
+            &ais_data,
+            || panic!("disk exploded"),
+            |alert| {
+                alerts.push(alert);
+                Ok(())
+            },
+        );
+
+        assert!(result.is_err());
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].priority, EmailPriority::High);
+        assert!(alerts[0].subject.contains("Machine monitor panicked"));
+        assert!(alerts[0].body.contains("disk exploded"));
+
+        let result = supervise("Machine monitor", &ais_data, || Ok(()), |_| panic!());
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_failing_service_loop_marks_degraded() {
+        let ais_data = Arc::new(RwLock::new(AisInfo::new().unwrap()));
+
+        let result = supervise(
+            "Service monitor",
+            &ais_data,
+            || {
+                Err(UnifiedError::from_ais_error(AisError::new(
+                    "systemctl failed",
+                )))
+            },
+            |_| Ok(()),
+        );
+        assert!(result.is_err());
+        {
+            let health = &ais_data.read().unwrap().health;
+            match &health.get("Service monitor").unwrap().state {
+                HealthState::Degraded(error) => assert!(error.contains("systemctl failed")),
+                other => panic!("Expected the services to be degraded, got {:?}", other),
+            }
+            assert!(!health.is_healthy());
+        }
+
+        // The next good cycle clears it
+        supervise("Service monitor", &ais_data, || Ok(()), |_| Ok(())).unwrap();
+        assert_eq!(
+            ais_data
+                .read()
+                .unwrap()
+                .health
+                .get("Service monitor")
+                .unwrap()
+                .state,
+            HealthState::Ok
+        );
+    }
+
+    #[cfg(feature = "software")]
+    #[test]
+    fn test_service_update_loop_success() {
+        // Arrange
+        let system_service_data = Arc::new(RwLock::new(Processes::new().unwrap()));
+        let ais_data = Arc::new(RwLock::new(AisInfo::new().unwrap()));
+
+        // Act
+        let result =
+            service_update_loop(system_service_data, ais_data, &RecordingNotifier::default());
+
+        // Assert
+        assert!(result.is_ok()); // TODO will fail on dev computers
+    }
+
+    struct MockProcesses(Vec<ProcessEntry>);
+
+    impl ProcessSource for MockProcesses {
+        fn processes(&self) -> Vec<ProcessEntry> {
+            self.0.clone()
+        }
+    }
+
+    /// Answers the ss lookup with a single connection for pid 4100.
+    struct MockSs;
+
+    impl CommandRunner for MockSs {
+        fn run(&self, program: &str, _: &[&str]) -> Result<String, UnifiedError> {
+            assert_eq!(program, "ss");
+            Ok("0 0 10.0.0.2:22 203.0.113.7:51234 users:((\"sshd\",pid=4100,fd=4))".to_owned())
+        }
+    }
+
+    fn sshd(pid: u32, parent: Option<u32>, cmdline: &str) -> ProcessEntry {
+        ProcessEntry {
+            pid,
+            parent,
+            name: "sshd".to_owned(),
+            cmdline: cmdline.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_monitor_ssh_connections_audits_watched_users() {
+        let mut ais_info = AisInfo::new().unwrap();
+        ais_info.machine_ip = Some("10.0.0.2".to_owned());
+        let ais_info = Arc::new(RwLock::new(ais_info));
+        let ssh_monitor = SshMonitor::new();
+        let notifier = RecordingNotifier::default();
+        let processes = MockProcesses(vec![
+            sshd(
+                1,
+                None,
+                "sshd: /usr/sbin/sshd -D [listener] 0 of 10-100 startups",
+            ),
+            sshd(4100, Some(1), "sshd: root [priv]"),
+            sshd(4101, Some(4100), "sshd: root@pts/0"),
+            sshd(4200, Some(1), "sshd: deploy@notty"),
+            ProcessEntry {
+                pid: 4300,
+                parent: None,
+                name: "bash".to_owned(),
+                cmdline: "root@pts/0".to_owned(),
+            },
+        ]);
+
+        monitor_ssh_connections(
+            ssh_monitor.clone(),
+            &processes,
+            &MockSs,
+            Arc::clone(&ais_info),
+            &notifier,
+        )
+        .unwrap();
+
+        // Only the root session is audited, with the origin found through its parent
+        let sent = notifier.0.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].category, EmailCategory::SshAudit);
+        assert!(sent[0].body.contains("BY root"));
+        assert!(sent[0].body.contains("203.0.113.7"));
+
+        // Processes already seen aren't audited again
+        monitor_ssh_connections(ssh_monitor, &processes, &MockSs, ais_info, &notifier).unwrap();
+        assert_eq!(notifier.0.lock().unwrap().len(), 1);
+    }
+}
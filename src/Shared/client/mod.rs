@@ -0,0 +1,728 @@
+//! # Client Module
+//!
+//! This module contains the client's monitoring loops and its entry point, started by
+//! `ais_client` and `ais_combined`.
+
+pub mod escalation;
+pub mod loops;
+pub mod ssh_monitor;
+pub mod startup;
+pub mod status;
+pub mod trends;
+pub mod warm_state;
+#[cfg(feature = "website")]
+pub mod website;
+
+use std::{
+    env,
+    path::Path,
+    sync::{Arc, RwLock},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use nix::sys::signal::SigSet;
+use crate::{
+    ais_config::AisConfig,
+    ais_data::AisInfo,
+    ais_security::{check_cf, check_manifest, migrate_manifest},
+    command::SystemRunner,
+    dead_letter::DeadLetters,
+    emails::{
+        Email, EmailCategory, EmailPriority, EmailSecure, Heartbeat, MailEndpoint, SigningKey,
+    },
+    encrypt::DusaCipher,
+    errors::{AisError, Severity, UnifiedError, UnifiedErrorResult},
+    git_data::GitCredentials,
+    health::Health,
+    log_file::{self, dump, halt, notice, pass, warn},
+    notify::{MaintenanceNotifier, Notifier, Notifiers},
+    resources::SystemResources,
+    retention::RetentionCategory,
+    service::{Processes, Status},
+};
+
+use escalation::Escalations;
+use loops::{
+    daily_summary_loop, escalation_loop, machine_update_loop, monitor_ssh_connections,
+    resource_update_loop, service_update_loop, site_health_loop, supervise, RESOURCE_MONITOR,
+    SITE_HEALTH_MONITOR,
+};
+#[cfg(feature = "website")]
+use website::{
+    triggered_update_loop, website_update_loop, WebsiteContext, DEPLOY_TRIGGER_DIR,
+    DEPLOY_TRIGGER_INTERVAL,
+};
+use ssh_monitor::{SshMonitor, SystemProcesses, SSH_EVENTS};
+use startup::{run_checks, Disposition, Startup, StartupCheck};
+use status::{bind_status_socket, serve_status, STATUS_SOCKET};
+use warm_state::{block_shutdown_signals, wait_for_shutdown, WarmState, WARM_STATE_PATH};
+
+/// Env var that makes the client run a single monitoring pass, same as the --once flag.
+const RUN_ONCE_VAR: &str = "AIS_RUN_ONCE";
+
+/// How long the startup check waits on the messaging server.
+const MAIL_PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a server that hasn't been registered checks for credentials again.
+const CREDENTIALS_RECHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often the emails held while they couldn't be encrypted are retried.
+const DEAD_LETTER_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often an incorrectly initialized system checks its manifest again.
+const MANIFEST_RECHECK_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How many failed manifest checks go by between reminders, every 6 hours.
+const MANIFEST_RENOTIFY_CHECKS: u32 = 72;
+
+/// Entry point of the client
+pub fn main() {
+    // A typo in the config would otherwise surface deep in a loop, one restart at a time
+    let config: AisConfig = match AisConfig::load_and_validate() {
+        Ok(config) => config,
+        Err(problems) => {
+            for problem in &problems {
+                dump(&format!("Invalid config, {}", problem));
+            }
+            halt("Fix the config at /etc/artisan.config and restart");
+            std::process::exit(1);
+        }
+    };
+    log_file::init(&config);
+
+    // Everything monitoring relies on, each failure is handled as its check's disposition says
+    let mut health: Health = Health::new();
+    let startup: Startup = run_checks(
+        vec![
+            // Alerts raised before a misconfigured messaging server is noticed would be lost
+            mail_check(
+                MailEndpoint::current(),
+                MAIL_PREFLIGHT_TIMEOUT,
+                config.mail_preflight_strict,
+            ),
+            // Loaded while still root, the website threads drop to www-data and can't read it
+            StartupCheck::new("Signing key", Disposition::Fatal, || {
+                SigningKey::init().map(|_| ())
+            }),
+            // Without it emails that fail to encrypt are lost instead of held
+            StartupCheck::new("Dead letters", Disposition::Degraded, || {
+                DeadLetters::init(&config).map(|_| ())
+            }),
+            // There's no point monitoring a server that hasn't been registered yet
+            StartupCheck::new(
+                "Credentials",
+                Disposition::RetryLater(CREDENTIALS_RECHECK_INTERVAL),
+                credentials_present,
+            ),
+            // ? The PreExec for the service requires that the manifest be created before the
+            // ? can run. If we start and the manifest isn't valid phone home and wait for it to be fixed
+            StartupCheck::new(
+                "Manifest",
+                Disposition::RetryLater(MANIFEST_RECHECK_INTERVAL),
+                manifest_valid,
+            )
+            .on_failure(|failures| {
+                if manifest_renotify_due(failures) {
+                    notify_incorrect_initialization();
+                }
+            }),
+        ],
+        &mut health,
+        thread::sleep,
+    );
+    if let Startup::Exit(code) = startup {
+        std::process::exit(code);
+    }
+
+    // Initialize the AIS information
+    let ais_data: UnifiedErrorResult<AisInfo> = UnifiedErrorResult::new(AisInfo::new());
+    let ais_info: AisInfo = AisInfo {
+        health,
+        ..ais_data.unwrap()
+    };
+    SSH_EVENTS.set(ais_info.ssh_events);
+    let ais_rw: Arc<RwLock<AisInfo>> = Arc::new(RwLock::new(ais_info));
+
+    // Initializing GitHub information
+    let git_creds_data: GitCredentials = GitCredentials::new().unwrap();
+    let git_creds_rw: Arc<RwLock<GitCredentials>> = Arc::new(RwLock::new(git_creds_data));
+
+    // Getting system service information
+    let system_services_data: UnifiedErrorResult<Processes> =
+        UnifiedErrorResult::new(Processes::new());
+    let system_service_rw: Arc<RwLock<Processes>> =
+        Arc::new(RwLock::new(system_services_data.unwrap()));
+
+    // Initializing the SSH monitor
+    let ssh_data: SshMonitor = SshMonitor::new();
+
+    // Every loop alerts through the configured channels, routine notices wait out maintenance
+    let notifier: Arc<dyn Notifier> = Arc::new(MaintenanceNotifier::new(
+        Box::new(Notifiers::from_config(&config)),
+        config.retention(RetentionCategory::Suppressed),
+    ));
+
+    // Gathered while still root, the website threads drop to www-data as they start
+    #[cfg(feature = "website")]
+    if let Err(e) = WebsiteContext::init(config.clone(), Arc::clone(&notifier)) {
+        warn(&format!("Website updates are off: {}", e));
+    }
+
+    // A single diagnostic pass skips the background threads entirely
+    let args: Vec<String> = env::args().collect();
+    if run_once_requested(&args, env::var(RUN_ONCE_VAR).ok()) {
+        std::process::exit(run_once(
+            ais_rw,
+            git_creds_rw,
+            system_service_rw,
+            ssh_data,
+            notifier,
+        ));
+    }
+
+    // Shutting down saves what we've learned, so an upgrade doesn't alert on everything again.
+    // ? The signals have to be blocked before any thread starts, only the saving thread waits on them
+    let shutdown_signals: Option<SigSet> = match block_shutdown_signals() {
+        Ok(signals) => Some(signals),
+        Err(e) => {
+            warn(&format!("State won't be saved on shutdown: {}", e));
+            None
+        }
+    };
+    let escalations: Arc<RwLock<Escalations>> = Arc::new(RwLock::new(Escalations::new()));
+    if let Some(state) = WarmState::take(Path::new(WARM_STATE_PATH), SystemTime::now()) {
+        if let Ok(mut processes) = system_service_rw.write() {
+            *processes = state.restore_services(&processes);
+        }
+        let restored = match escalations.write() {
+            Ok(mut escalations) => state.restore(&ssh_data, &mut escalations),
+            Err(e) => Err(UnifiedError::from_ais_error(AisError::ThreadedDataError(
+                Some(e.to_string()),
+            ))),
+        };
+        match restored {
+            Ok(_) => pass("Restored the state saved by the previous run"),
+            Err(e) => warn(&format!("Failed to restore the saved state: {}", e)),
+        }
+    }
+    if let Some(signals) = shutdown_signals {
+        let system_service_rw_clone = Arc::clone(&system_service_rw);
+        let ssh_data_clone = ssh_data.clone();
+        let escalations_clone = Arc::clone(&escalations);
+        thread::spawn(move || {
+            match wait_for_shutdown(&signals) {
+                Ok(signal) => notice(&format!("Received {}, saving state", signal)),
+                Err(e) => warn(&format!("Failed waiting for shutdown, saving state: {}", e)),
+            }
+            let saved = match (system_service_rw_clone.read(), escalations_clone.read()) {
+                (Ok(processes), Ok(escalations)) => {
+                    WarmState::capture(&processes, &ssh_data_clone, &escalations, SystemTime::now())
+                        .and_then(|state| state.save(Path::new(WARM_STATE_PATH)))
+                }
+                _ => Err(UnifiedError::from_ais_error(AisError::ThreadedDataError(
+                    Some(String::from("a lock was poisoned")),
+                ))),
+            };
+            if let Err(e) = saved {
+                warn(&format!(
+                    "Failed to save the state for the next start: {}",
+                    e
+                ));
+            }
+            std::process::exit(0);
+        });
+    }
+
+    // Spawn a thread to log operational status periodically
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(600)); // Every 5 mins we just say hello
+        notice("Operational");
+    });
+
+    // Spawn a thread to answer status requests, including how each loop did on its last cycle
+    match bind_status_socket(Path::new(STATUS_SOCKET)) {
+        Ok(listener) => {
+            let ais_rw_clone = Arc::clone(&ais_rw);
+            thread::spawn(move || serve_status(listener, ais_rw_clone));
+        }
+        Err(e) => warn(&format!(
+            "Status won't be served on {}: {}",
+            STATUS_SOCKET, e
+        )),
+    }
+
+    // Spawn a thread to let the messaging server know we're still alive
+    {
+        let ais_rw_clone = Arc::clone(&ais_rw);
+        thread::spawn(move || loop {
+            let machine_id: Option<String> = match ais_rw_clone.read() {
+                Ok(ais_info) => ais_info.machine_id.clone(),
+                Err(_) => None,
+            };
+            let heartbeat =
+                Heartbeat::new(machine_id.unwrap_or_else(|| String::from("Failure parsing")));
+            if let Err(e) = heartbeat.send() {
+                warn(&format!("Failed to send heartbeat: {}", e));
+            }
+            thread::sleep(Duration::from_secs(300));
+        });
+    }
+
+    // Spawn a thread to retry the held emails, a quiet client could go hours without sending
+    // another one that would retry them
+    if let Some(dead_letters) = DeadLetters::current() {
+        thread::spawn(move || loop {
+            thread::sleep(DEAD_LETTER_RETRY_INTERVAL);
+            if dead_letters.is_empty() {
+                continue;
+            }
+            if let Err(e) = dead_letters.retry(&DusaCipher, EmailSecure::send) {
+                warn(&format!("Failed to deliver the held emails: {}", e));
+            }
+        });
+    }
+
+    // Spawn a thread to send the daily summary, if these stop arriving the monitor is dead
+    {
+        let ais_rw_clone = Arc::clone(&ais_rw);
+        let system_service_rw_clone = Arc::clone(&system_service_rw);
+        let git_creds_rw_clone = Arc::clone(&git_creds_rw);
+        let notifier_clone = Arc::clone(&notifier);
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(86400));
+            if let Err(e) = daily_summary_loop(
+                ais_rw_clone.clone(),
+                system_service_rw_clone.clone(),
+                git_creds_rw_clone.clone(),
+                notifier_clone.as_ref(),
+            ) {
+                warn(&format!("Failed to send the daily summary: {}", e));
+            }
+        });
+    }
+
+    // Spawn a thread to escalate services that stay down
+    {
+        let ais_rw_clone = Arc::clone(&ais_rw);
+        let system_service_rw_clone = Arc::clone(&system_service_rw);
+        let escalations: Arc<RwLock<Escalations>> = Arc::clone(&escalations);
+        let stages = AisConfig::new().unwrap_or_default().escalation;
+        if !stages.is_empty() {
+            thread::spawn(move || loop {
+                thread::sleep(Duration::from_secs(60));
+                if let Err(e) = escalation_loop(
+                    system_service_rw_clone.clone(),
+                    ais_rw_clone.clone(),
+                    escalations.clone(),
+                    &stages,
+                ) {
+                    warn(&format!("Failed to check escalations: {}", e));
+                }
+            });
+        }
+    }
+
+    // Spawn a thread to deploy sites as soon as their update is triggered
+    #[cfg(feature = "website")]
+    if let Some(website) = WebsiteContext::current() {
+        let ais_rw_clone = Arc::clone(&ais_rw);
+        let git_creds_rw_clone = Arc::clone(&git_creds_rw);
+        thread::spawn(move || {
+            // Deploys run as www-data, the same as the website loop
+            if let Err(e) = website.drop_to_www_data() {
+                warn(&format!("Triggered deploys are off: {}", e));
+                return;
+            }
+            loop {
+                thread::sleep(DEPLOY_TRIGGER_INTERVAL);
+                if let Err(e) = triggered_update_loop(
+                    ais_rw_clone.clone(),
+                    git_creds_rw_clone.clone(),
+                    &website.config,
+                    std::path::Path::new(DEPLOY_TRIGGER_DIR),
+                    &website.relay,
+                ) {
+                    warn(&format!("Triggered update failed: {}", e));
+                }
+            }
+        });
+    }
+
+    // Main application loop
+    loop {
+        // Initialize handlers for various tasks
+        let handlers = initialize_handlers(
+            // system_data_rw.clone(),
+            ais_rw.clone(),
+            git_creds_rw.clone(),
+            system_service_rw.clone(),
+            ssh_data.clone(),
+            notifier.clone(),
+        );
+
+        // Join all threads and handle errors
+        for handler in handlers {
+            match handler.join() {
+                Ok(result) => match result {
+                    Ok(_) => (),
+                    Err(e) => warn(&format!("Thread failed with error: {:?}", e)),
+                },
+                Err(e) => warn(&format!("Thread panicked: {:?}", e)),
+            }
+        }
+
+        // Introduce a sleep to reduce CPU usage
+        thread::sleep(Duration::from_nanos(90)); // Adjust the duration as needed
+    }
+}
+
+/// Checks there are credentials, a server that hasn't been registered has none.
+fn credentials_present() -> Result<(), UnifiedError> {
+    match check_cf()? {
+        true => Ok(()),
+        false => Err(UnifiedError::from_ais_error(AisError::NoCredentials(Some(
+            String::from("awaiting registration"),
+        )))),
+    }
+}
+
+/// Checks the manifest on disk is valid, migrating one from an older release.
+fn manifest_valid() -> Result<(), UnifiedError> {
+    let manifest: AisInfo = AisInfo::new()?;
+    match check_manifest(manifest.clone()) {
+        Ok(_) => Ok(()),
+        Err(e) => match migrate_manifest(&manifest)? {
+            true => Ok(()),
+            false => Err(e),
+        },
+    }
+}
+
+/// Checks if we're notified about the failed manifest check, on the first one and again
+/// every `MANIFEST_RENOTIFY_CHECKS` checks.
+fn manifest_renotify_due(failures: u32) -> bool {
+    (failures - 1).is_multiple_of(MANIFEST_RENOTIFY_CHECKS)
+}
+
+/// Phones home about a system whose manifest isn't valid.
+fn notify_incorrect_initialization() {
+    let message: Email = Email {
+        subject: "A system has been Initialized incorrectly".to_owned(),
+        body: format!(
+            "An error occoured while initializing the system at the following ip: {}",
+            AisInfo::fetch_machine_ip().unwrap_or("Error pulling Ip".to_owned())
+        ),
+        priority: EmailPriority::High,
+        category: EmailCategory::Initialization,
+    };
+    let secure_message: EmailSecure = UnifiedErrorResult::new(EmailSecure::new(message)).unwrap();
+    match secure_message.send() {
+        Ok(_) => (),
+        Err(e) => match e {
+            UnifiedError::AisError(ei, ek) => {
+                if ei.severity == Severity::NotFatal {
+                    warn(&format!("Non-fatal error: {}", ek));
+                }
+            }
+            _ => halt(&format!("{}", e)),
+        },
+    }
+}
+
+/// Checks the messaging server can be reached. When strict the client doesn't start without
+/// it, otherwise it starts and alerts are lost until it's fixed.
+fn mail_check(endpoint: MailEndpoint, timeout: Duration, strict: bool) -> StartupCheck<'static> {
+    let disposition: Disposition = match strict {
+        true => Disposition::Fatal,
+        false => Disposition::Degraded,
+    };
+    StartupCheck::new("Messaging server", disposition, move || {
+        endpoint.check_reachable(timeout)
+    })
+}
+
+/// Returns true if the client was asked to run a single pass and exit.
+fn run_once_requested(args: &[String], run_once_var: Option<String>) -> bool {
+    args.iter().any(|arg| arg == "--once")
+        || matches!(
+            run_once_var.as_deref().map(str::trim),
+            Some("1") | Some("true")
+        )
+}
+
+/// Runs every monitoring loop one time, prints the results and returns the exit code.
+/// The exit code is 1 if any loop failed or any service isn't running.
+fn run_once(
+    ais_rw: Arc<RwLock<AisInfo>>,
+    git_creds_rw: Arc<RwLock<GitCredentials>>,
+    system_service_rw: Arc<RwLock<Processes>>,
+    ssh_data: SshMonitor,
+    notifier: Arc<dyn Notifier>,
+) -> i32 {
+    let mut healthy: bool = MailEndpoint::current()
+        .check_reachable(MAIL_PREFLIGHT_TIMEOUT)
+        .inspect_err(|e| warn(&format!("Messaging server: {}", e)))
+        .is_ok();
+
+    let handlers = initialize_handlers(
+        ais_rw.clone(),
+        git_creds_rw,
+        system_service_rw.clone(),
+        ssh_data,
+        notifier,
+    );
+    let names = [
+        "SSH monitor",
+        "Machine monitor",
+        "Service monitor",
+        "Resource monitor",
+        "Site health monitor",
+        "Website monitor",
+    ];
+
+    for (name, handler) in names.iter().zip(handlers) {
+        match handler.join() {
+            Ok(Ok(_)) => pass(&format!("{}: ok", name)),
+            Ok(Err(e)) => {
+                healthy = false;
+                warn(&format!("{}: {}", name, e));
+            }
+            Err(_) => {
+                healthy = false;
+                warn(&format!("{}: panicked", name));
+            }
+        }
+    }
+
+    match system_service_rw.read() {
+        Ok(processes) => {
+            for service in processes.itr() {
+                notice(&format!("{}: {}", service.service, service.status));
+                if service.status != Status::Running {
+                    healthy = false;
+                }
+            }
+        }
+        Err(_) => healthy = false,
+    }
+
+    if let Ok(ais_info) = ais_rw.read() {
+        ais_info.print_all();
+    }
+
+    match healthy {
+        true => 0,
+        false => 1,
+    }
+}
+
+/// Initialize handlers for various tasks
+fn initialize_handlers(
+    ais_rw: Arc<RwLock<AisInfo>>,
+    git_creds_rw: Arc<RwLock<GitCredentials>>,
+    system_service_rw: Arc<RwLock<Processes>>,
+    ssh_data: SshMonitor,
+    notifier: Arc<dyn Notifier>,
+) -> Vec<thread::JoinHandle<Result<(), UnifiedError>>> {
+    // Spawn a thread to monitor SSH connections
+    let monitor_ssh = {
+        let ais_rw_clone = Arc::clone(&ais_rw);
+        let ssh_data_clone = ssh_data.clone();
+        let notifier_clone = Arc::clone(&notifier);
+        thread::spawn(move || {
+            supervise(
+                "SSH monitor",
+                &ais_rw_clone.clone(),
+                || {
+                    monitor_ssh_connections(
+                        ssh_data_clone,
+                        &SystemProcesses,
+                        &SystemRunner,
+                        ais_rw_clone,
+                        notifier_clone.as_ref(),
+                    )
+                },
+                |alert| notifier_clone.notify(&alert),
+            )
+        })
+    };
+
+    // Spawn a thread to monitor machine updates
+    let machine_monitor = {
+        let ais_rw_clone = Arc::clone(&ais_rw);
+        let notifier_clone = Arc::clone(&notifier);
+        thread::spawn(move || {
+            supervise(
+                "Machine monitor",
+                &ais_rw_clone.clone(),
+                || machine_update_loop(ais_rw_clone, notifier_clone.as_ref()),
+                |alert| notifier_clone.notify(&alert),
+            )
+        })
+    };
+
+    // Spawn a thread to monitor system services
+    let service_monitor = {
+        let system_service_rw_clone = Arc::clone(&system_service_rw);
+        let ais_rw_clone = Arc::clone(&ais_rw);
+        let notifier_clone = Arc::clone(&notifier);
+        thread::spawn(move || {
+            supervise(
+                "Service monitor",
+                &ais_rw_clone.clone(),
+                || {
+                    service_update_loop(
+                        system_service_rw_clone,
+                        ais_rw_clone,
+                        notifier_clone.as_ref(),
+                    )
+                },
+                |alert| notifier_clone.notify(&alert),
+            )
+        })
+    };
+
+    // Spawn a thread to monitor the host's load, memory and disk use
+    let resource_monitor = {
+        let ais_rw_clone = Arc::clone(&ais_rw);
+        let notifier_clone = Arc::clone(&notifier);
+        thread::spawn(move || {
+            supervise(
+                "Resource monitor",
+                &ais_rw_clone.clone(),
+                || {
+                    resource_update_loop(
+                        ais_rw_clone,
+                        &RESOURCE_MONITOR,
+                        &SystemResources,
+                        notifier_clone.as_ref(),
+                    )
+                },
+                |alert| notifier_clone.notify(&alert),
+            )
+        })
+    };
+
+    // Spawn a thread to check the health urls of the sites
+    let site_health_monitor = {
+        let ais_rw_clone = Arc::clone(&ais_rw);
+        let git_creds_rw_clone = Arc::clone(&git_creds_rw);
+        let notifier_clone = Arc::clone(&notifier);
+        thread::spawn(move || {
+            supervise(
+                "Site health monitor",
+                &ais_rw_clone.clone(),
+                || {
+                    site_health_loop(
+                        ais_rw_clone,
+                        git_creds_rw_clone,
+                        &SITE_HEALTH_MONITOR,
+                        notifier_clone.as_ref(),
+                    )
+                },
+                |alert| notifier_clone.notify(&alert),
+            )
+        })
+    };
+
+    // Spawn a thread to monitor website updates
+    #[cfg(feature = "website")]
+    let website_monitor = WebsiteContext::current().map(|website| {
+        let ais_rw_clone = Arc::clone(&ais_rw);
+        let git_creds_rw_clone = Arc::clone(&git_creds_rw);
+        thread::spawn(move || {
+            // Dropping priv for the website update loop
+            website.drop_to_www_data()?;
+            supervise(
+                "Website monitor",
+                &ais_rw_clone.clone(),
+                || {
+                    website_update_loop(
+                        ais_rw_clone,
+                        git_creds_rw_clone,
+                        &website.config,
+                        &website.relay,
+                    )
+                },
+                |alert| website.relay.notify(&alert),
+            )
+        })
+    });
+    #[cfg(not(feature = "website"))]
+    let website_monitor: Option<thread::JoinHandle<Result<(), UnifiedError>>> = None;
+
+    let mut handlers = vec![
+        monitor_ssh,
+        machine_monitor,
+        service_monitor,
+        resource_monitor,
+        site_health_monitor,
+    ];
+    handlers.extend(website_monitor);
+
+    handlers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_once_requested() {
+        let args = |list: &[&str]| list.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert!(run_once_requested(&args(&["ais_client", "--once"]), None));
+        assert!(run_once_requested(
+            &args(&["ais_client"]),
+            Some("1".to_owned())
+        ));
+        assert!(!run_once_requested(
+            &args(&["ais_client"]),
+            Some("0".to_owned())
+        ));
+        assert!(!run_once_requested(&args(&["ais_client"]), None));
+    }
+
+    #[test]
+    fn test_mail_check() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let reachable = MailEndpoint::Tcp(listener.local_addr().unwrap().to_string());
+        let startup = |endpoint: &MailEndpoint, strict: bool| {
+            run_checks(
+                vec![mail_check(endpoint.clone(), MAIL_PREFLIGHT_TIMEOUT, strict)],
+                &mut Health::new(),
+                |_| (),
+            )
+        };
+        assert_eq!(
+            startup(&reachable, true),
+            Startup::Ready { degraded: vec![] }
+        );
+
+        // Nothing listens once the listener is gone
+        drop(listener);
+        assert_eq!(
+            startup(&reachable, false),
+            Startup::Ready {
+                degraded: vec!["Messaging server"]
+            }
+        );
+        assert!(matches!(startup(&reachable, true), Startup::Exit(_)));
+
+        let missing = MailEndpoint::parse("unix:/nonexistent/ais/mail.sock");
+        assert!(matches!(startup(&missing, true), Startup::Exit(_)));
+    }
+
+    #[test]
+    fn test_manifest_renotify_due() {
+        // The manifest gets fixed on the 150th check
+        let notified = (1..150)
+            .filter(|failures| manifest_renotify_due(*failures))
+            .count();
+        // Notified on the first failure, then after every 72 failed checks
+        assert_eq!(notified, 3);
+        assert!(manifest_renotify_due(1));
+        assert!(manifest_renotify_due(MANIFEST_RENOTIFY_CHECKS + 1));
+    }
+}
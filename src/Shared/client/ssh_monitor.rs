@@ -0,0 +1,524 @@
+use crate::ais_config::AisConfig;
+use crate::ais_data::AisInfo;
+use crate::command::CommandRunner;
+use crate::errors::{AisError, UnifiedError};
+use crate::log_file::{notice, warn};
+use crate::time;
+use std::{
+    collections::{HashSet, VecDeque},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+};
+// use sysinfo::{Process, ProcessExt};
+use sysinfo::System;
+
+use crate::emails::{Email, EmailCategory, EmailPriority};
+use crate::notify::Notifier;
+
+/// A counter that can be bumped from any thread without taking a lock.
+#[derive(Debug, Default)]
+pub struct EventCounter(AtomicUsize);
+
+impl EventCounter {
+    pub const fn new() -> Self {
+        EventCounter(AtomicUsize::new(0))
+    }
+
+    /// Counts an event, returning the new total.
+    pub fn increment(&self) -> usize {
+        self.0.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Returns the current total.
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Replaces the total, used to carry on from the count saved in the manifest.
+    pub fn set(&self, total: usize) {
+        self.0.store(total, Ordering::Relaxed)
+    }
+}
+
+/// Ssh events recorded, seeded from the manifest at startup. The machine update loop copies
+/// this into `AisInfo::ssh_events` and saves it to the manifest when it changes.
+pub static SSH_EVENTS: EventCounter = EventCounter::new();
+
+/// How many ssh events the monitor remembers.
+pub const SSH_HISTORY_LEN: usize = 50;
+
+/// A running process, as the SSH monitor sees it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessEntry {
+    pub pid: u32,
+    /// The pid of the parent process, if it has one.
+    pub parent: Option<u32>,
+    pub name: String,
+    /// The command line, joined with spaces.
+    pub cmdline: String,
+}
+
+/// Lists running processes, tests swap in a mock so they don't depend on real sshd processes.
+pub trait ProcessSource {
+    /// Returns every running process.
+    fn processes(&self) -> Vec<ProcessEntry>;
+}
+
+/// Lists the processes running on the host system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemProcesses;
+
+impl ProcessSource for SystemProcesses {
+    fn processes(&self) -> Vec<ProcessEntry> {
+        let mut system = System::new_all();
+        system.refresh_all();
+
+        system
+            .processes()
+            .values()
+            .map(|process| ProcessEntry {
+                pid: process.pid().as_u32(),
+                parent: process.parent().map(|parent| parent.as_u32()),
+                name: process.name().to_string(),
+                cmdline: process.cmd().join(" "),
+            })
+            .collect()
+    }
+}
+
+/// Represents the SSH monitor, which tracks SSH connections.
+#[derive(Debug, Clone)]
+pub enum SshMonitor {
+    /// Tracks seen SSH processes and the most recent SSH events.
+    SeenProcesses(Arc<RwLock<HashSet<u32>>>, Arc<RwLock<SshHistory>>),
+}
+
+/// The most recent SSH events, the oldest is dropped once it's full.
+#[derive(Debug)]
+pub struct SshHistory {
+    capacity: usize,
+    events: VecDeque<SshInfo>,
+}
+
+/// Represents information about an SSH connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SshInfo {
+    pub time_stamp: String,
+    pub system_ip: String,
+    pub system_user: String,
+    /// The address the connection came from, if it could be found.
+    pub origin: Option<IpAddr>,
+    pub priority_status: bool,
+}
+
+impl SshInfo {
+    /// Prepares an email based on SSH connection information.
+    pub fn prepare(&mut self, ais_info: AisInfo) -> Email {
+        let (importance, priority) = if self.priority_status {
+            (String::from("HIGH"), EmailPriority::High)
+        } else {
+            (String::from("LOW"), EmailPriority::Low)
+        };
+
+        let origin = match self.origin {
+            Some(origin) => origin.to_string(),
+            None => String::from("UNKNOWN"),
+        };
+
+        let subject = format!("SSH ACCESS AUDIT {} IMPORTANCE", importance);
+        let body = format!(
+            "SSH ACCESS NOTIFICATION\nAt {} THE HOST ais_{}.local WAS ACCESSED \nBY {}, FROM AN ORIGIN {}.",
+            self.time_stamp, ais_info.client_id.unwrap_or("000000".to_owned()), self.system_user, origin
+        );
+
+        Email {
+            subject,
+            body,
+            priority,
+            category: EmailCategory::SshAudit,
+        }
+    }
+}
+
+impl SshHistory {
+    /// Creates a new SshHistory holding up to the given number of events.
+    pub fn new(capacity: usize) -> Self {
+        SshHistory {
+            capacity,
+            events: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Records an event, dropping the oldest one if the history is full.
+    pub fn push(&mut self, event: SshInfo) {
+        if self.events.len() >= self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Returns the events held, oldest first.
+    pub fn events(&self) -> Vec<SshInfo> {
+        self.events.iter().cloned().collect()
+    }
+}
+
+impl Default for SshMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SshMonitor {
+    /// Creates a new instance of `SshMonitor`.
+    pub fn new() -> Self {
+        Self::SeenProcesses(
+            Arc::new(RwLock::new(HashSet::new())),
+            Arc::new(RwLock::new(SshHistory::new(SSH_HISTORY_LEN))),
+        )
+    }
+
+    /// Retrieves the reference to the set of seen SSH processes.
+    pub fn access(self) -> Arc<RwLock<HashSet<u32>>> {
+        match self {
+            SshMonitor::SeenProcesses(d, _) => d.clone(),
+        }
+    }
+
+    /// Retrieves the reference to the recent SSH events.
+    pub fn history(&self) -> Arc<RwLock<SshHistory>> {
+        match self {
+            SshMonitor::SeenProcesses(_, h) => h.clone(),
+        }
+    }
+
+    /// Returns the most recent SSH events, oldest first.
+    pub fn recent_events(&self) -> Result<Vec<SshInfo>, UnifiedError> {
+        let history = self.history();
+        let history = history.read().map_err(|e| {
+            UnifiedError::from_ais_error(AisError::ThreadedDataError(Some(e.to_string())))
+        })?;
+        Ok(history.events())
+    }
+
+    /// Adds an SSH event to the history.
+    fn record_event(&self, event: SshInfo) -> Result<(), UnifiedError> {
+        let history = self.history();
+        let mut history = history.write().map_err(|e| {
+            UnifiedError::from_ais_error(AisError::ThreadedDataError(Some(e.to_string())))
+        })?;
+        history.push(event);
+        Ok(())
+    }
+
+    /// Processes an SSH connection.
+    pub fn process_ssh_connection(
+        self,
+        process: &ProcessEntry,
+        runner: &dyn CommandRunner,
+        ais_info: Arc<RwLock<AisInfo>>,
+        notifier: &dyn Notifier,
+    ) -> Result<(), UnifiedError> {
+        let binding = self.clone().access();
+        let mut seen_processes = match binding.write() {
+            Ok(d) => d,
+            Err(e) => {
+                return Err(UnifiedError::from_ais_error(AisError::ThreadedDataError(
+                    Some(e.to_string()),
+                )))
+            }
+        };
+
+        let pid: u32 = process.pid;
+
+        if seen_processes.insert(pid) {
+            let (auth, username) = self.validate_users(process.cmdline.clone());
+
+            if auth && username.is_none() {
+                return Err(UnifiedError::from_ais_error(AisError::SshUnknownUser(
+                    Some(String::from("Unknown")),
+                )));
+            };
+
+            match auth {
+                true => {
+                    // The session process may not hold the socket itself, its parent does
+                    let origin: Option<IpAddr> = connection_source(runner, pid).or_else(|| {
+                        process
+                            .parent
+                            .and_then(|parent| connection_source(runner, parent))
+                    });
+                    self.report_login(
+                        ais_info,
+                        username.unwrap_or_else(|| "Already established connection?".to_string()),
+                        origin,
+                        &AisConfig::new()?,
+                        notifier,
+                    )
+                }
+                false => Ok(()),
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Reports a login by a watched user, unless the user and source are on the allow list.
+    pub fn report_login(
+        &self,
+        ais_info: Arc<RwLock<AisInfo>>,
+        username: String,
+        origin: Option<IpAddr>,
+        config: &AisConfig,
+        notifier: &dyn Notifier,
+    ) -> Result<(), UnifiedError> {
+        if config.is_ssh_allowed(&username, origin) {
+            notice(&format!(
+                "Expected ssh login by {} from {}",
+                username,
+                origin.map(|origin| origin.to_string()).unwrap_or_default()
+            ));
+            return Ok(());
+        }
+        self.create_ssh_report(ais_info, username, origin, notifier)
+    }
+
+    /// Creates an SSH report, recording it in the history.
+    pub fn create_ssh_report(
+        &self,
+        ais_info: Arc<RwLock<AisInfo>>,
+        username: String,
+        origin: Option<IpAddr>,
+        notifier: &dyn Notifier,
+    ) -> Result<(), UnifiedError> {
+        let ais_data = match ais_info.read() {
+            Ok(d) => d,
+            Err(e) => {
+                return Err(UnifiedError::from_ais_error(AisError::ThreadedDataError(
+                    Some(String::from(&e.to_string())),
+                )))
+            }
+        };
+
+        let time_stamp = time::now();
+        let system_ip = &ais_data.machine_ip;
+        let system_user = username;
+        let priority_status = true;
+        let mut ssh_report = SshInfo {
+            time_stamp,
+            system_ip: match system_ip {
+                Some(d) => d.clone(),
+                None => {
+                    return Err(UnifiedError::from_ais_error(AisError::new(
+                        "The ip address provided was not valid",
+                    )))
+                }
+            },
+            system_user,
+            origin,
+            priority_status,
+        };
+        let ssh_report_data = ssh_report.prepare(ais_data.clone());
+        drop(ais_data);
+        self.record_event(ssh_report)?;
+
+        warn(&format!("Ssh events: {}", SSH_EVENTS.increment()));
+        notifier.notify(&ssh_report_data)
+    }
+
+    /// Validates users from SSH connection data.
+    pub fn validate_users(&self, mut data: String) -> (bool, Option<String>) {
+        let user_list_critical = [
+            "dwhitfield".to_string(),
+            "root".to_string(),
+            // "system".to_string(),
+            "admin".to_string(),
+        ];
+
+        if data.contains("[priv]") {
+            data = "[auth event]".to_string()
+        };
+        if data.contains("[net]") {
+            data = "[auth event]".to_string()
+        };
+        if data.contains("[listener]") {
+            data = "[server start]".to_string()
+        };
+
+        let data = data.replace("sshd:", "");
+        let data = data.replace(" ", "");
+        let data_expanded = data.split('@');
+        let data_parts: Vec<&str> = data_expanded.collect();
+
+        let contains = user_list_critical.contains(&data_parts[0].to_string());
+
+        (
+            contains,
+            if contains {
+                Some(data_parts[0].to_string())
+            } else {
+                None
+            },
+        )
+    }
+}
+
+/// Finds the address of the peer connected to the process, using ss.
+fn connection_source(runner: &dyn CommandRunner, pid: u32) -> Option<IpAddr> {
+    let output: String = runner.run("ss", &["-tnpH", "state", "established"]).ok()?;
+    parse_peer_address(&output, pid)
+}
+
+/// Pulls the peer address for the process out of `ss -tnpH state established` output, where
+/// the columns are: Recv-Q Send-Q Local Peer Process.
+fn parse_peer_address(output: &str, pid: u32) -> Option<IpAddr> {
+    let owner: String = format!("pid={},", pid);
+    output
+        .lines()
+        .filter(|line| line.contains(&owner))
+        .filter_map(|line| line.split_whitespace().nth(3))
+        .filter_map(|peer| peer.rsplit_once(':'))
+        .find_map(|(host, _)| host.trim_matches(|c| c == '[' || c == ']').parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::RecordingNotifier;
+
+    // Test case for validating SSH users
+    #[test]
+    fn test_validate_ssh_users() {
+        let ssh_monitor = SshMonitor::new();
+
+        let (auth, username) = ssh_monitor.validate_users("root@headhuncho.local".to_string());
+        assert!(auth);
+        assert_eq!(username, Some("root".to_string()));
+    }
+
+    #[test]
+    fn test_event_counter_is_exact_across_threads() {
+        let counter = Arc::new(EventCounter::new());
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let counter = Arc::clone(&counter);
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        counter.increment();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(counter.get(), 8000);
+    }
+
+    #[test]
+    fn test_ssh_history_keeps_most_recent() {
+        let mut history = SshHistory::new(3);
+        for n in 0..5 {
+            history.push(SshInfo {
+                time_stamp: format!("2024-04-29T12:00:0{}Z", n),
+                system_ip: "10.0.0.2".to_string(),
+                system_user: format!("user{}", n),
+                origin: None,
+                priority_status: true,
+            });
+        }
+
+        let users: Vec<String> = history
+            .events()
+            .into_iter()
+            .map(|event| event.system_user)
+            .collect();
+        assert_eq!(users, vec!["user2", "user3", "user4"]);
+    }
+
+    fn test_ais_info() -> Arc<RwLock<AisInfo>> {
+        let mut ais_info = AisInfo::new().unwrap();
+        ais_info.machine_ip = Some("10.0.0.2".to_string());
+        Arc::new(RwLock::new(ais_info))
+    }
+
+    fn allow_list_config() -> AisConfig {
+        AisConfig {
+            ssh_allow_list: vec![crate::ais_config::SshAllowEntry {
+                user: "admin".to_string(),
+                source: "10.0.0.10".parse().unwrap(),
+            }],
+            ..AisConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_allow_listed_login_is_suppressed() {
+        let monitor = SshMonitor::new();
+        let notifier = RecordingNotifier::default();
+
+        monitor
+            .report_login(
+                test_ais_info(),
+                "admin".to_string(),
+                Some("10.0.0.10".parse().unwrap()),
+                &allow_list_config(),
+                &notifier,
+            )
+            .unwrap();
+        assert!(notifier.0.lock().unwrap().is_empty());
+        assert!(monitor.recent_events().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_allow_listed_user_from_other_source_is_audited() {
+        let monitor = SshMonitor::new();
+        let notifier = RecordingNotifier::default();
+
+        monitor
+            .report_login(
+                test_ais_info(),
+                "admin".to_string(),
+                Some("203.0.113.7".parse().unwrap()),
+                &allow_list_config(),
+                &notifier,
+            )
+            .unwrap();
+        let sent = notifier.0.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].body.contains("203.0.113.7"));
+    }
+
+    #[test]
+    fn test_parse_peer_address() {
+        let output = "0      0      10.0.0.2:22     203.0.113.7:51234  users:((\"sshd\",pid=4123,fd=4))\n\
+                      0      0      [::1]:22        [::1]:40112        users:((\"sshd\",pid=4200,fd=4))";
+        assert_eq!(
+            parse_peer_address(output, 4123),
+            Some("203.0.113.7".parse().unwrap())
+        );
+        assert_eq!(
+            parse_peer_address(output, 4200),
+            Some("::1".parse().unwrap())
+        );
+        assert_eq!(parse_peer_address(output, 41), None);
+    }
+
+    // Integration test for creating an SSH report
+    #[cfg(feature = "dusa")]
+    #[test]
+    fn test_create_ssh_report() {
+        use crate::notify::EmailNotifier;
+
+        let ais_info = Arc::new(RwLock::new(AisInfo::new().unwrap()));
+
+        let result =
+            SshMonitor::new().create_ssh_report(ais_info, "root".to_string(), None, &EmailNotifier);
+        assert!(result.is_ok() || result.is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{
+    errors::UnifiedError,
+    health::Health,
+    log_file::{halt, pass, warn},
+};
+use std::time::Duration;
+
+/// Enum representing what the client does when a startup check fails.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Disposition {
+    /// The client can't run without it, it exits with the failure's exit code.
+    Fatal,
+    /// The check runs again after the interval until it passes, the client waits for it.
+    RetryLater(Duration),
+    /// The client starts with the subsystem the check covers turned off, it's reported as
+    /// degraded in the health passed to `run_checks`.
+    Degraded,
+}
+
+/// Something that has to hold before the client starts monitoring, and what to do when it
+/// doesn't.
+pub struct StartupCheck<'a> {
+    /// The subsystem or requirement checked, like `Manifest`.
+    pub name: &'static str,
+    /// What happens when the check fails.
+    pub disposition: Disposition,
+    check: Box<dyn FnMut() -> Result<(), UnifiedError> + 'a>,
+    on_failure: Box<dyn FnMut(u32) + 'a>,
+}
+
+/// Enum representing how the startup checks went.
+#[derive(Debug, PartialEq)]
+pub enum Startup {
+    /// Every check passed, except the degraded ones listed.
+    Ready {
+        /// The names of the checks that failed and whose subsystem is turned off.
+        degraded: Vec<&'static str>,
+    },
+    /// A fatal check failed, the client exits with the code.
+    Exit(i32),
+}
+
+impl<'a> StartupCheck<'a> {
+    /// Creates a new StartupCheck, the check returns the reason it failed.
+    pub fn new<C>(name: &'static str, disposition: Disposition, check: C) -> Self
+    where
+        C: FnMut() -> Result<(), UnifiedError> + 'a,
+    {
+        StartupCheck {
+            name,
+            disposition,
+            check: Box::new(check),
+            on_failure: Box::new(|_| ()),
+        }
+    }
+
+    /// Runs the callback after every failed check with how many have failed so far, like
+    /// to phone home about a check that's being retried.
+    pub fn on_failure<F>(mut self, on_failure: F) -> Self
+    where
+        F: FnMut(u32) + 'a,
+    {
+        self.on_failure = Box::new(on_failure);
+        self
+    }
+}
+
+/// Runs the checks in order, waiting out the ones to retry. The first fatal failure stops
+/// the rest from running.
+pub fn run_checks<S>(checks: Vec<StartupCheck>, health: &mut Health, mut sleep: S) -> Startup
+where
+    S: FnMut(Duration),
+{
+    let mut degraded: Vec<&'static str> = Vec::new();
+
+    for mut check in checks {
+        let mut failures: u32 = 0;
+        loop {
+            let error: UnifiedError = match (check.check)() {
+                Ok(_) => break,
+                Err(e) => e,
+            };
+            failures += 1;
+            (check.on_failure)(failures);
+
+            match check.disposition {
+                Disposition::Fatal => {
+                    halt(&format!(
+                        "{} failed, refusing to start: {}",
+                        check.name, error
+                    ));
+                    return Startup::Exit(error.exit_code());
+                }
+                Disposition::RetryLater(interval) => {
+                    if failures == 1 {
+                        warn(&format!(
+                            "{} failed, waiting for it before starting: {}",
+                            check.name, error
+                        ));
+                    }
+                    sleep(interval);
+                }
+                Disposition::Degraded => {
+                    warn(&format!(
+                        "{} failed, starting without it: {}",
+                        check.name, error
+                    ));
+                    // Shows up in the daily summary until the client is restarted
+                    health.record(check.name, &Err(error));
+                    degraded.push(check.name);
+                    break;
+                }
+            }
+        }
+
+        if failures > 0 && matches!(check.disposition, Disposition::RetryLater(_)) {
+            pass(&format!(
+                "{} passed after {} failed checks, continuing",
+                check.name, failures
+            ));
+        }
+    }
+
+    Startup::Ready { degraded }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::HealthState;
+    use crate::errors::AisError;
+    use std::cell::Cell;
+
+    fn failure(error: AisError) -> Result<(), UnifiedError> {
+        Err(UnifiedError::from_ais_error(error))
+    }
+
+    #[test]
+    fn test_fatal_exits_without_running_later_checks() {
+        let later_ran = Cell::new(false);
+        let checks = vec![
+            StartupCheck::new("Credentials", Disposition::Fatal, || {
+                failure(AisError::NoCredentials(None))
+            }),
+            StartupCheck::new("Manifest", Disposition::Fatal, || {
+                later_ran.set(true);
+                Ok(())
+            }),
+        ];
+
+        assert_eq!(
+            run_checks(checks, &mut Health::new(), |_| ()),
+            Startup::Exit(20)
+        );
+        assert!(!later_ran.get());
+    }
+
+    #[test]
+    fn test_retry_later_waits_until_it_passes() {
+        let interval = Duration::from_secs(300);
+        let checks_run = Cell::new(0);
+        let mut failures_seen: Vec<u32> = Vec::new();
+        let mut slept: Vec<Duration> = Vec::new();
+        let checks = vec![
+            StartupCheck::new("Manifest", Disposition::RetryLater(interval), || {
+                checks_run.set(checks_run.get() + 1);
+                match checks_run.get() {
+                    1..=3 => failure(AisError::InvalidManifest(None)),
+                    _ => Ok(()),
+                }
+            })
+            .on_failure(|failures| failures_seen.push(failures)),
+        ];
+
+        let startup = run_checks(checks, &mut Health::new(), |wait| slept.push(wait));
+        assert_eq!(startup, Startup::Ready { degraded: vec![] });
+        assert_eq!(checks_run.get(), 4);
+        assert_eq!(failures_seen, vec![1, 2, 3]);
+        assert_eq!(slept, vec![interval; 3]);
+    }
+
+    #[test]
+    fn test_degraded_starts_without_the_subsystem() {
+        let later_ran = Cell::new(false);
+        let checks = vec![
+            StartupCheck::new("Messaging server", Disposition::Degraded, || {
+                failure(AisError::EtNoHome(None))
+            }),
+            StartupCheck::new("Manifest", Disposition::Fatal, || {
+                later_ran.set(true);
+                Ok(())
+            }),
+        ];
+
+        let mut health = Health::new();
+        let startup = run_checks(checks, &mut health, |_| {
+            panic!("degraded checks aren't retried")
+        });
+        assert_eq!(
+            startup,
+            Startup::Ready {
+                degraded: vec!["Messaging server"]
+            }
+        );
+        assert!(later_ran.get());
+        assert!(matches!(
+            health.get("Messaging server").unwrap().state,
+            HealthState::Degraded(_)
+        ));
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{
+    ais_data::AisInfo,
+    errors::{AisError, UnifiedError},
+    log_file::{notice, warn},
+};
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+/// Where the client answers with its status, the manifest and how each loop did on its last
+/// cycle, as json.
+pub const STATUS_SOCKET: &str = "/run/ais/client_status.sock";
+
+/// Binds the status socket, replacing one left behind by an earlier run. Only root can
+/// connect, like the messaging server's control socket.
+pub fn bind_status_socket(path: &Path) -> io::Result<UnixListener> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    notice(&format!("Status server listening on {}", path.display()));
+    Ok(listener)
+}
+
+/// Answers every connection with the current `AisInfo`. It's read only, nothing is read from
+/// the peer.
+pub fn serve_status(listener: UnixListener, ais_data: Arc<RwLock<AisInfo>>) {
+    for stream in listener.incoming() {
+        let answered = stream
+            .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))
+            .and_then(|mut stream| write_status(&mut stream, &ais_data));
+        if let Err(e) = answered {
+            warn(&format!("Error answering a status request: {}", e));
+        }
+    }
+}
+
+fn write_status(stream: &mut UnixStream, ais_data: &RwLock<AisInfo>) -> Result<(), UnifiedError> {
+    let status: String = match ais_data.read() {
+        Ok(ais_info) => serde_json::to_string(&*ais_info)
+            .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))?,
+        Err(e) => {
+            return Err(UnifiedError::from_ais_error(AisError::ThreadedDataError(
+                Some(e.to_string()),
+            )))
+        }
+    };
+    stream.write_all(status.as_bytes()).map_err(|e| {
+        UnifiedError::from_ais_error(AisError::new(format!("Error sending status: {}", e)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::HealthState;
+    use std::{io::Read, thread};
+
+    #[test]
+    fn test_status_reports_health() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("client_status.sock");
+        let ais_data = Arc::new(RwLock::new(AisInfo::new().unwrap()));
+        ais_data.write().unwrap().health.record(
+            "Service monitor",
+            &Err(UnifiedError::from_ais_error(AisError::new(
+                "systemctl failed",
+            ))),
+        );
+
+        let listener = bind_status_socket(&path).unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        let ais_data_clone = Arc::clone(&ais_data);
+        thread::spawn(move || serve_status(listener, ais_data_clone));
+
+        let mut status = String::new();
+        UnixStream::connect(&path)
+            .unwrap()
+            .read_to_string(&mut status)
+            .unwrap();
+        let reported: AisInfo = serde_json::from_str(&status).unwrap();
+
+        assert!(!reported.health.is_healthy());
+        assert!(matches!(
+            reported.health.get("Service monitor").unwrap().state,
+            HealthState::Degraded(_)
+        ));
+        assert_eq!(reported, *ais_data.read().unwrap());
+    }
+}
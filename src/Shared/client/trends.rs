@@ -0,0 +1,133 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How far back a trend looks.
+pub const TREND_WINDOW: Duration = Duration::from_secs(3600);
+
+/// The least time between two samples of a service, the service loop runs far more often.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How much the memory has to change over the window, as a fraction, before it's a trend.
+const TREND_THRESHOLD: f64 = 0.1;
+
+/// Memory samples of the trended services, filled in by the service loop and read by the
+/// daily summary.
+pub static MEMORY_TRENDS: Mutex<MemoryTrends> = Mutex::new(MemoryTrends::new());
+
+/// Enum representing which way a service's memory is heading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trend {
+    Growing,
+    Stable,
+    Shrinking,
+}
+
+/// Keeps the memory use of services over the last hour, one sample a minute at most.
+#[derive(Debug, Default)]
+pub struct MemoryTrends {
+    samples: BTreeMap<String, VecDeque<(Instant, u64)>>,
+}
+
+impl MemoryTrends {
+    /// Creates a new MemoryTrends with no samples.
+    pub const fn new() -> Self {
+        MemoryTrends {
+            samples: BTreeMap::new(),
+        }
+    }
+
+    /// Records the memory a service is using. Samples closer than `SAMPLE_INTERVAL` to the
+    /// last one are skipped and samples older than `TREND_WINDOW` are dropped.
+    pub fn record(&mut self, service: &str, bytes: u64, now: Instant) {
+        let samples = self.samples.entry(service.to_owned()).or_default();
+        if let Some((last, _)) = samples.back() {
+            if now.duration_since(*last) < SAMPLE_INTERVAL {
+                return;
+            }
+        }
+        samples.push_back((now, bytes));
+        while let Some((oldest, _)) = samples.front() {
+            match now.duration_since(*oldest) > TREND_WINDOW {
+                true => samples.pop_front(),
+                false => break,
+            };
+        }
+    }
+
+    /// Returns which way the service's memory went over the window, or None if there aren't
+    /// enough samples to tell.
+    pub fn trend(&self, service: &str) -> Option<Trend> {
+        let samples = self.samples.get(service)?;
+        let (first, last) = match (samples.front(), samples.back()) {
+            (Some((_, first)), Some((_, last))) if samples.len() >= 2 => (*first, *last),
+            _ => return None,
+        };
+
+        let change: f64 = (last as f64 - first as f64) / (first.max(1) as f64);
+        Some(match change {
+            change if change >= TREND_THRESHOLD => Trend::Growing,
+            change if change <= -TREND_THRESHOLD => Trend::Shrinking,
+            _ => Trend::Stable,
+        })
+    }
+
+    /// Lists the trend of every sampled service, one per line, for the daily summary.
+    pub fn summary(&self) -> String {
+        self.samples
+            .keys()
+            .filter_map(|service| {
+                self.trend(service)
+                    .map(|trend| format!("  {}: {}\n", service, trend))
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Trend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let trend: &str = match self {
+            Trend::Growing => "growing",
+            Trend::Stable => "stable",
+            Trend::Shrinking => "shrinking",
+        };
+        write!(f, "{}", trend)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_increasing_series_is_growing() {
+        let mut trends = MemoryTrends::new();
+        let start = Instant::now();
+        for minute in 0..30u64 {
+            let at = start + Duration::from_secs(minute * 60);
+            trends.record("apache2.service", 100_000_000 + minute * 2_000_000, at);
+        }
+
+        assert_eq!(trends.trend("apache2.service"), Some(Trend::Growing));
+        assert_eq!(trends.summary(), "  apache2.service: growing\n");
+    }
+
+    #[test]
+    fn test_samples_stay_bounded() {
+        let mut trends = MemoryTrends::new();
+        let start = Instant::now();
+        // Ten seconds apart for three hours, only one sample a minute over the last hour is kept
+        for step in 0..1080u64 {
+            let at = start + Duration::from_secs(step * 10);
+            trends.record("netdata.service", 50_000_000, at);
+        }
+
+        let samples = trends.samples.get("netdata.service").unwrap();
+        assert!(samples.len() <= 61);
+        assert_eq!(trends.trend("netdata.service"), Some(Trend::Stable));
+        assert_eq!(trends.trend("mysql.service"), None);
+    }
+}
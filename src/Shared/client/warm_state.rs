@@ -0,0 +1,280 @@
+use crate::client::{
+    escalation::{Escalations, SavedCondition},
+    loops::{RESOURCE_MONITOR, SITE_HEALTH_MONITOR},
+    ssh_monitor::SshMonitor,
+};
+use nix::sys::signal::{SigSet, Signal};
+use serde::{Deserialize, Serialize};
+use crate::{
+    errors::{AisError, UnifiedError},
+    log_file::{notice, warn},
+    resources::Resource,
+    service::{ProcessInfo, Processes, Status},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Where the client's state is kept between shutting down and starting again.
+pub const WARM_STATE_PATH: &str = "/var/lib/ais/warm_state.json";
+
+/// Saved state older than this is ignored, too much may have changed while the client was down.
+const WARM_STATE_MAX_AGE: Duration = Duration::from_secs(15 * 60);
+
+/// Changes every boot, state from before a reboot refers to pids that are gone.
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+/// What the client has learned while running that it would otherwise alert on again after a
+/// restart, saved on shutdown so an upgrade picks up where the previous version left off.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct WarmState {
+    /// When the state was saved, in seconds since the epoch.
+    pub saved_at: u64,
+    /// The boot the state was saved in.
+    pub boot_id: String,
+    /// The status of every monitored service as of the last cycle, by unit name.
+    pub services: BTreeMap<String, Status>,
+    /// The ssh sessions already reported.
+    pub ssh_seen: BTreeSet<u32>,
+    /// The services being escalated and how far along they are.
+    pub escalations: BTreeMap<String, SavedCondition>,
+    /// The resources already alerted on for being over their threshold.
+    pub resources_alerted: Vec<Resource>,
+    /// The health urls already alerted on for failing.
+    pub sites_alerted: Vec<String>,
+}
+
+impl WarmState {
+    /// Captures the state the client holds right now.
+    pub fn capture(
+        processes: &Processes,
+        ssh_monitor: &SshMonitor,
+        escalations: &Escalations,
+        now: SystemTime,
+    ) -> Result<Self, UnifiedError> {
+        let ssh_seen: BTreeSet<u32> = ssh_monitor
+            .clone()
+            .access()
+            .read()
+            .map_err(|e| {
+                UnifiedError::from_ais_error(AisError::ThreadedDataError(Some(e.to_string())))
+            })?
+            .iter()
+            .copied()
+            .collect();
+
+        Ok(WarmState {
+            saved_at: epoch_secs(now),
+            boot_id: boot_id(),
+            services: processes
+                .itr()
+                .into_iter()
+                .map(|info| (info.service, info.status))
+                .collect(),
+            ssh_seen,
+            escalations: escalations.save(Instant::now()),
+            resources_alerted: RESOURCE_MONITOR
+                .lock()
+                .map(|monitor| monitor.alerted())
+                .unwrap_or_default(),
+            sites_alerted: SITE_HEALTH_MONITOR
+                .lock()
+                .map(|monitor| monitor.alerted())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Writes the state to the path, replacing what was there in one step.
+    pub fn save(&self, path: &Path) -> Result<(), UnifiedError> {
+        let io_error = |e: io::Error| {
+            UnifiedError::from_ais_error(AisError::with_context("saving the warm state", e))
+        };
+        let data: String = serde_json::to_string(self)
+            .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))?;
+
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).map_err(io_error)?;
+        }
+        let staged = path.with_extension("tmp");
+        fs::write(&staged, data).map_err(io_error)?;
+        fs::rename(&staged, path).map_err(io_error)
+    }
+
+    /// Reads the state saved at the path and removes it once it's known to be usable, so it's
+    /// only ever restored once. State from another boot, older than `WARM_STATE_MAX_AGE` or
+    /// that can't be read is ignored and left for the next save to replace.
+    pub fn take(path: &Path, now: SystemTime) -> Option<Self> {
+        let data: String = fs::read_to_string(path).ok()?;
+        let state: WarmState = match serde_json::from_str(&data) {
+            Ok(state) => state,
+            Err(e) => {
+                warn(&format!("Ignoring the warm state, it can't be read: {}", e));
+                return None;
+            }
+        };
+        let age: u64 = epoch_secs(now).saturating_sub(state.saved_at);
+        match (
+            state.boot_id == boot_id(),
+            age <= WARM_STATE_MAX_AGE.as_secs(),
+        ) {
+            (true, true) => (),
+            (false, _) => {
+                notice("Ignoring the warm state, it was saved before a reboot");
+                return None;
+            }
+            (true, false) => {
+                notice(&format!(
+                    "Ignoring the warm state, it was saved {}s ago",
+                    age
+                ));
+                return None;
+            }
+        }
+
+        // State that stays on disk could be restored again on the next start
+        match fs::remove_file(path) {
+            Ok(_) => Some(state),
+            Err(e) => {
+                warn(&format!(
+                    "Ignoring the warm state, it couldn't be removed: {}",
+                    e
+                ));
+                None
+            }
+        }
+    }
+
+    /// The processes with the saved statuses in place of the ones read at startup, so the first
+    /// cycle reports the services that changed while the client was down like any other change.
+    pub fn restore_services(&self, processes: &Processes) -> Processes {
+        let restored: Vec<ProcessInfo> = processes
+            .itr()
+            .into_iter()
+            .map(|info| match self.services.get(&info.service) {
+                Some(status) => ProcessInfo {
+                    status: status.clone(),
+                    baseline: false,
+                    ..info
+                },
+                None => info,
+            })
+            .collect();
+        Processes::Services(restored)
+    }
+
+    /// Restores everything but the services, see `restore_services`.
+    pub fn restore(
+        &self,
+        ssh_monitor: &SshMonitor,
+        escalations: &mut Escalations,
+    ) -> Result<(), UnifiedError> {
+        ssh_monitor
+            .clone()
+            .access()
+            .write()
+            .map_err(|e| {
+                UnifiedError::from_ais_error(AisError::ThreadedDataError(Some(e.to_string())))
+            })?
+            .extend(self.ssh_seen.iter().copied());
+        escalations.restore(&self.escalations, Instant::now());
+        if let Ok(mut monitor) = RESOURCE_MONITOR.lock() {
+            monitor.restore_alerted(&self.resources_alerted);
+        }
+        if let Ok(mut monitor) = SITE_HEALTH_MONITOR.lock() {
+            monitor.restore_alerted(&self.sites_alerted);
+        }
+        Ok(())
+    }
+}
+
+/// Blocks the shutdown signals in this thread and every thread it starts afterwards, so only
+/// `wait_for_shutdown` sees them. Call it before starting any threads.
+pub fn block_shutdown_signals() -> Result<SigSet, UnifiedError> {
+    let mut signals: SigSet = SigSet::empty();
+    signals.add(Signal::SIGTERM);
+    signals.add(Signal::SIGINT);
+    signals
+        .thread_block()
+        .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))?;
+    Ok(signals)
+}
+
+/// Waits for one of the blocked shutdown signals.
+pub fn wait_for_shutdown(signals: &SigSet) -> Result<Signal, UnifiedError> {
+    signals
+        .wait()
+        .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))
+}
+
+fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+fn boot_id() -> String {
+    fs::read_to_string(BOOT_ID_PATH)
+        .map(|id| id.trim().to_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_is_taken_once() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("warm_state.json");
+        let now = SystemTime::now();
+        let state = WarmState {
+            saved_at: epoch_secs(now),
+            boot_id: boot_id(),
+            ssh_seen: BTreeSet::from([4242]),
+            resources_alerted: vec![Resource::Disk],
+            ..WarmState::default()
+        };
+        state.save(&path).unwrap();
+
+        assert_eq!(WarmState::take(&path, now), Some(state));
+        assert!(!path.exists());
+        assert_eq!(WarmState::take(&path, now), None);
+    }
+
+    #[test]
+    fn test_stale_state_is_ignored() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("warm_state.json");
+        let now = SystemTime::now();
+        let saved = |saved_at: u64, boot_id: String| {
+            WarmState {
+                saved_at,
+                boot_id,
+                ..WarmState::default()
+            }
+            .save(&path)
+            .unwrap();
+        };
+
+        saved(epoch_secs(now) - 60 * 60, boot_id());
+        assert_eq!(WarmState::take(&path, now), None);
+        assert!(path.exists());
+
+        saved(epoch_secs(now), String::from("another boot"));
+        assert_eq!(WarmState::take(&path, now), None);
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_unreadable_state_is_left_in_place() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("warm_state.json");
+        fs::write(&path, "{\"saved_at\":").unwrap();
+
+        assert_eq!(WarmState::take(&path, SystemTime::now()), None);
+        assert!(path.exists());
+    }
+}
@@ -0,0 +1,1118 @@
+use crate::client::loops::acquire_read_lock;
+use nix::{
+    libc,
+    unistd::{Gid, Uid},
+};
+use crate::{
+    ais_config::AisConfig,
+    ais_data::AisInfo,
+    command::{Semaphore, SystemRunner},
+    emails::{Email, EmailCategory, EmailPriority},
+    errors::{AisError, Caller, ErrorInfo, GitError, UnifiedError},
+    git_actions::{
+        check_tag_ahead, fetch_current_commit, preview_clean, preview_pull, GitAction, GitTiming,
+        IncomingCommit, SiteLock,
+    },
+    git_data::{DeployMode, GitAuth, GitCredentials},
+    log_file::{dump, notice, output, warn},
+    notify::{Notifier, RelayNotifier},
+    resources::mount_for,
+    retention::{Retention, RetentionCategory},
+    site_info::{SiteInfo, Updates},
+    time,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock, RwLock,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use systemstat::{Platform, System as SystemStats};
+use system::{chown_recursive, create_hash, path_present, truncate, ClonePath, PathType};
+use users::{Groups, Users, UsersCache};
+
+/// Set while the site root is low on space, so the alert is only sent once until it recovers.
+static LOW_SPACE_ALERTED: AtomicBool = AtomicBool::new(false);
+
+/// What the website threads need root for, gathered once by `WebsiteContext::init`.
+static WEBSITE_CONTEXT: OnceLock<WebsiteContext> = OnceLock::new();
+
+/// Where deploys are triggered on demand, creating a file named after a repo here updates it
+/// right away instead of on the next cycle. It has to be writable by www-data.
+pub const DEPLOY_TRIGGER_DIR: &str = "/run/artisan/deploy";
+
+/// How often the trigger directory is checked.
+pub const DEPLOY_TRIGGER_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Everything the website threads need root for, gathered while the client is still root so
+/// the threads only have to drop to www-data when they start.
+pub struct WebsiteContext {
+    /// The config, www-data can't read it.
+    pub config: AisConfig,
+    /// Delivers the notifications of the website threads, running as root.
+    pub relay: RelayNotifier,
+    uid: Uid,
+    gid: Gid,
+}
+
+/// Reads the free space on the filesystem holding a path, tests swap in a mock.
+pub trait DiskSpace: Sync {
+    /// Returns the bytes available to unprivileged users on the filesystem holding the path.
+    fn available(&self, path: &Path) -> Result<u64, UnifiedError>;
+}
+
+/// Reads the free space of the host's filesystems.
+pub struct SystemDisk;
+
+impl DiskSpace for SystemDisk {
+    fn available(&self, path: &Path) -> Result<u64, UnifiedError> {
+        let mounts = SystemStats::new().mounts().map_err(|e| {
+            UnifiedError::from_ais_error(AisError::with_context("listing the mounts", e))
+        })?;
+        mount_for(&mounts, path)
+            .map(|mount| mount.avail.as_u64())
+            .ok_or_else(|| {
+                UnifiedError::from_ais_error(AisError::SystemError(Some(format!(
+                    "No filesystem is mounted at {}",
+                    path.display()
+                ))))
+            })
+    }
+}
+
+/// Resolves the uid and gid of the www-data user the website loop runs as.
+pub fn resolve_www_data() -> Result<(Uid, Gid), UnifiedError> {
+    resolve_user(&UsersCache::new(), "www-data")
+}
+
+/// Resolves the uid of the user and the gid of the group with the same name, tests pass a
+/// mock in place of the host's passwd and group files.
+fn resolve_user<U: Users + Groups>(users: &U, name: &str) -> Result<(Uid, Gid), UnifiedError> {
+    let uid = users.get_user_by_name(name).map(|user| user.uid());
+    let gid = users.get_group_by_name(name).map(|group| group.gid());
+
+    match (uid, gid) {
+        (Some(uid), Some(gid)) => Ok((Uid::from_raw(uid), Gid::from_raw(gid))),
+        _ => Err(UnifiedError::AisError(
+            ErrorInfo::new(Caller::Function(true, Some("resolve_www_data".to_owned()))),
+            AisError::PermissionDenied(Some(format!("The {} user or group doesn't exist", name))),
+        )),
+    }
+}
+
+impl WebsiteContext {
+    /// Resolves www-data, hands it the deploy logs and starts the relay for `current` to hand
+    /// out. It has to be called while still root, before any website thread starts.
+    pub fn init(
+        config: AisConfig,
+        notifier: Arc<dyn Notifier>,
+    ) -> Result<&'static WebsiteContext, UnifiedError> {
+        if let Some(context) = WEBSITE_CONTEXT.get() {
+            return Ok(context);
+        }
+        let (uid, gid) = resolve_www_data()?;
+        prepare_deploy_logs(&config.deploy_log_dir, uid, gid)?;
+        let context = WebsiteContext {
+            config,
+            relay: RelayNotifier::spawn(notifier),
+            uid,
+            gid,
+        };
+        Ok(WEBSITE_CONTEXT.get_or_init(|| context))
+    }
+
+    /// The context gathered by `init`, None if it wasn't.
+    pub fn current() -> Option<&'static WebsiteContext> {
+        WEBSITE_CONTEXT.get()
+    }
+
+    /// Drops the calling thread to www-data for the deploys.
+    pub fn drop_to_www_data(&self) -> Result<(), UnifiedError> {
+        drop_thread_privileges(self.uid, self.gid)
+    }
+}
+
+/// Hands the deploy log directory and the logs in it to the user, so the deploys can append
+/// to and rotate them once they've dropped their privileges.
+fn prepare_deploy_logs(log_dir: &Path, uid: Uid, gid: Gid) -> Result<(), UnifiedError> {
+    fs::create_dir_all(log_dir).map_err(|e| {
+        UnifiedError::from_ais_error(AisError::with_context("creating the deploy log dir", e))
+    })?;
+    chown_recursive(
+        PathType::PathBuf(log_dir.to_path_buf()),
+        Some(uid.as_raw()),
+        Some(gid.as_raw()),
+    )
+    .map_err(UnifiedError::from_system_error)
+}
+
+/// Drops the calling thread to the given user and group.
+/// The raw syscalls are used since the libc wrappers change every thread in the process, and
+/// the other loops need to stay root.
+pub fn drop_thread_privileges(uid: Uid, gid: Gid) -> Result<(), UnifiedError> {
+    let check = |result: libc::c_long, call: &str| match result {
+        0 => Ok(()),
+        _ => Err(UnifiedError::AisError(
+            ErrorInfo::new(Caller::Function(
+                true,
+                Some("drop_thread_privileges".to_owned()),
+            )),
+            AisError::PermissionDenied(Some(format!(
+                "{} failed: {}",
+                call,
+                io::Error::last_os_error()
+            ))),
+        )),
+    };
+
+    // The group has to go first, once the uid is dropped we can't change it anymore
+    check(
+        unsafe { libc::syscall(libc::SYS_setgroups, 0, std::ptr::null::<libc::gid_t>()) },
+        "setgroups",
+    )?;
+    check(
+        unsafe {
+            libc::syscall(
+                libc::SYS_setresgid,
+                gid.as_raw(),
+                gid.as_raw(),
+                gid.as_raw(),
+            )
+        },
+        "setresgid",
+    )?;
+    check(
+        unsafe {
+            libc::syscall(
+                libc::SYS_setresuid,
+                uid.as_raw(),
+                uid.as_raw(),
+                uid.as_raw(),
+            )
+        },
+        "setresuid",
+    )
+}
+
+/// Updates every site. It runs as www-data, so the config and the relay its notifications go
+/// through come from the `WebsiteContext` gathered while still root.
+pub fn website_update_loop(
+    ais_data: Arc<RwLock<AisInfo>>,
+    git_creds: Arc<RwLock<GitCredentials>>,
+    config: &AisConfig,
+    notifier: &dyn Notifier,
+) -> Result<(), UnifiedError> {
+    let ais_info = acquire_read_lock(
+        &ais_data,
+        Caller::Function(true, Some("Website Update Loop, ais_info".to_owned())),
+    )?;
+
+    let git_info = acquire_read_lock(
+        &git_creds,
+        Caller::Function(true, Some("Website Update Loop, git_info".to_owned())),
+    )?;
+
+    if !ensure_deploy_space(&SystemDisk, config, &ais_info, notifier, &LOW_SPACE_ALERTED)? {
+        return Ok(());
+    }
+
+    // Every site updates on its own thread, each holding a permit while it talks to the
+    // remote so the uplink isn't saturated. Threads start with the credentials of the thread
+    // spawning them, so they run as www-data too.
+    let limit: Semaphore = Semaphore::new(config.git_concurrency);
+    let results: Vec<Result<(), UnifiedError>> = thread::scope(|scope| {
+        let handles: Vec<_> = git_info
+            .auths
+            .iter()
+            .map(|git_credential| {
+                let (limit, ais_info) = (&limit, &*ais_info);
+                scope.spawn(move || {
+                    let _permit = limit.acquire();
+                    update_site(
+                        git_credential,
+                        ais_info,
+                        config,
+                        notifier,
+                        DeploySource::Scheduled,
+                    )
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .zip(&git_info.auths)
+            .map(|(handle, git_credential)| {
+                handle.join().unwrap_or_else(|_| {
+                    Err(UnifiedError::from_ais_error(AisError::SiteFailed(Some(
+                        format!("Updating {} panicked", git_credential.repo),
+                    ))))
+                })
+            })
+            .collect()
+    });
+
+    // Every site gets its chance before the first failure is reported
+    results.into_iter().collect()
+}
+
+/// Updates the sites whose deploy was triggered on demand, as www-data like the website loop.
+pub fn triggered_update_loop(
+    ais_data: Arc<RwLock<AisInfo>>,
+    git_creds: Arc<RwLock<GitCredentials>>,
+    config: &AisConfig,
+    trigger_dir: &Path,
+    notifier: &dyn Notifier,
+) -> Result<(), UnifiedError> {
+    let git_info = acquire_read_lock(
+        &git_creds,
+        Caller::Function(true, Some("Triggered Update Loop, git_info".to_owned())),
+    )?;
+    let ais_info = acquire_read_lock(
+        &ais_data,
+        Caller::Function(true, Some("Triggered Update Loop, ais_info".to_owned())),
+    )?;
+
+    run_triggered(trigger_dir, &git_info.auths, |git_credential| {
+        if !ensure_deploy_space(&SystemDisk, config, &ais_info, notifier, &LOW_SPACE_ALERTED)? {
+            return Ok(());
+        }
+        notice(&format!("Update of {} triggered", git_credential.repo));
+        update_site(
+            git_credential,
+            &ais_info,
+            config,
+            notifier,
+            DeploySource::Triggered,
+        )
+    })
+}
+
+/// Runs the update for every configured repo with a trigger in the directory. The trigger is
+/// removed before the update, so one created while it runs isn't lost.
+fn run_triggered<F>(
+    trigger_dir: &Path,
+    auths: &[GitAuth],
+    mut update: F,
+) -> Result<(), UnifiedError>
+where
+    F: FnMut(&GitAuth) -> Result<(), UnifiedError>,
+{
+    let mut results: Vec<Result<(), UnifiedError>> = Vec::new();
+    for git_credential in auths {
+        let trigger = trigger_dir.join(&git_credential.repo);
+        if !trigger.is_file() {
+            continue;
+        }
+        fs::remove_file(&trigger).map_err(|e| {
+            UnifiedError::from_ais_error(AisError::with_context("removing the deploy trigger", e))
+        })?;
+        results.push(update(git_credential));
+    }
+
+    // Every triggered site gets its chance before the first failure is reported
+    results.into_iter().collect()
+}
+
+/// Enum representing what started a deploy.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeploySource {
+    /// The website loop's regular cycle.
+    Scheduled,
+    /// A trigger in the deploy trigger directory.
+    Triggered,
+}
+
+/// A deploy of a site, kept in its deploy log for audits and rollbacks.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeployRecord {
+    pub repo: String,
+    /// The commit checked out before the deploy, None when the site was cloned fresh.
+    pub old_commit: Option<String>,
+    pub new_commit: String,
+    pub timestamp: String,
+    pub source: DeploySource,
+}
+
+/// Checks there's room to clone and pull under the site root. When there isn't the deploys
+/// are skipped, alerting once until the space is freed up.
+fn ensure_deploy_space(
+    disk: &dyn DiskSpace,
+    config: &AisConfig,
+    ais_info: &AisInfo,
+    notifier: &dyn Notifier,
+    alerted: &AtomicBool,
+) -> Result<bool, UnifiedError> {
+    let available: u64 = disk.available(&config.site_root)?;
+    let required: u64 = config.min_deploy_space_mb * 1024 * 1024;
+    if available >= required {
+        alerted.store(false, Ordering::Relaxed);
+        return Ok(true);
+    }
+
+    warn(&format!(
+        "Only {}MB free under {}, skipping deploys",
+        available / (1024 * 1024),
+        config.site_root.display()
+    ));
+    if !alerted.swap(true, Ordering::Relaxed) {
+        let mail = Email {
+            subject: "Deploys skipped, low disk space".to_owned(),
+            body: format!(
+                "The system: {} has {}MB free under {}, below the {}MB needed to deploy. Sites won't be cloned or updated until space is freed up.",
+                ais_info
+                    .machine_id
+                    .clone()
+                    .unwrap_or_else(|| String::from("Failed to parse")),
+                available / (1024 * 1024),
+                config.site_root.display(),
+                config.min_deploy_space_mb
+            ),
+            priority: EmailPriority::High,
+            category: EmailCategory::Resource,
+        };
+        notifier.notify(&mail)?;
+    }
+    Ok(false)
+}
+
+/// The git steps of one site update, tagged with an id so the emails it sends can be
+/// correlated and a failure says which step it stopped at.
+#[derive(Debug)]
+struct UpdateTrace {
+    id: String,
+    /// Held for the whole update, so a triggered deploy and a scheduled one of the same site
+    /// take turns rather than interleaving their steps.
+    lock: SiteLock,
+    steps: Vec<&'static str>,
+    failed: Option<&'static str>,
+}
+
+impl UpdateTrace {
+    /// Creates a new UpdateTrace for the repo under its site's lock, every update gets a new id.
+    fn new(repo: &str, lock: SiteLock) -> Self {
+        let started: u128 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or_default();
+        UpdateTrace {
+            id: truncate(&create_hash(format!("{}{}", repo, started)), 12).to_owned(),
+            lock,
+            steps: Vec::new(),
+            failed: None,
+        }
+    }
+
+    /// Runs the git action as the next step of the update.
+    fn run(&mut self, action: &GitAction) -> Result<(bool, GitTiming), UnifiedError> {
+        self.steps.push(action.name());
+        action
+            .execute_timed_held(&self.lock)
+            .inspect_err(|_| self.failed = Some(action.name()))
+    }
+
+    /// Sends the email tagged with the update's id and the steps taken so far.
+    fn notify(&self, notifier: &dyn Notifier, mut email: Email) -> Result<(), UnifiedError> {
+        email.body.push_str(&format!(
+            "\n\nUpdate {}, steps: {}",
+            self.id,
+            self.steps.join(" -> ")
+        ));
+        notifier.notify(&email)
+    }
+
+    /// The email reporting the step that failed, None when no git step has.
+    fn failure_email(&self, ais_info: &AisInfo, repo: &str, error: &UnifiedError) -> Option<Email> {
+        let step: &str = self.failed?;
+        Some(Email {
+            subject: "Update failed".to_owned(),
+            body: format!(
+                "The system: {} failed at step '{}' of update {} to the repo: {}.\n\n{}",
+                ais_info
+                    .machine_id
+                    .clone()
+                    .unwrap_or_else(|| String::from("Failed to parse")),
+                step,
+                self.id,
+                repo,
+                error
+            ),
+            priority: EmailPriority::High,
+            category: EmailCategory::UpdateFailed,
+        })
+    }
+}
+
+/// Clones or updates a single site, emailing the step that failed when a git step does and
+/// recording the deploy when the site moved to another commit. The site's `SiteLock` is held
+/// from the first look at the checkout to the deploy record.
+fn update_site(
+    git_credential: &GitAuth,
+    ais_info: &AisInfo,
+    config: &AisConfig,
+    notifier: &dyn Notifier,
+    source: DeploySource,
+) -> Result<(), UnifiedError> {
+    let site_folder = PathType::PathBuf(SiteInfo::site_path(git_credential, &config.site_root));
+    let lock: SiteLock = SiteLock::acquire(site_folder.as_ref())?;
+    let old_commit: Option<String> = fetch_current_commit(&site_folder).ok();
+
+    let mut trace = UpdateTrace::new(&git_credential.repo, lock);
+    let result = apply_update(git_credential, ais_info, config, notifier, &mut trace);
+    if result.is_ok() {
+        let new_commit: Option<String> = fetch_current_commit(&site_folder).ok();
+        if let Err(e) = record_deploy(
+            &config.deploy_log_dir,
+            &config.retention(RetentionCategory::Deploys),
+            git_credential,
+            old_commit,
+            new_commit,
+            source,
+        ) {
+            warn(&format!(
+                "Couldn't record the deploy of {}: {}",
+                git_credential.repo, e
+            ));
+        }
+    }
+    if let Err(e) = &result {
+        if let Some(mail) = trace.failure_email(ais_info, &git_credential.repo, e) {
+            if let Err(notify_error) = trace.notify(notifier, mail) {
+                warn(&format!(
+                    "Couldn't send the update failure: {}",
+                    notify_error
+                ));
+            }
+        }
+    }
+    result
+}
+
+/// Appends a record of the deploy to the site's deploy log, if the update moved the site to
+/// another commit. Returns if a record was written.
+fn record_deploy(
+    log_dir: &Path,
+    retention: &Retention,
+    git_credential: &GitAuth,
+    old_commit: Option<String>,
+    new_commit: Option<String>,
+    source: DeploySource,
+) -> Result<bool, UnifiedError> {
+    let new_commit: String = match new_commit {
+        Some(new_commit) if old_commit.as_ref() != Some(&new_commit) => new_commit,
+        _ => return Ok(false),
+    };
+    let io_error = |e: io::Error| {
+        UnifiedError::from_ais_error(AisError::with_context("writing the deploy log", e))
+    };
+
+    let record = DeployRecord {
+        repo: git_credential.repo.clone(),
+        old_commit,
+        new_commit,
+        timestamp: time::now(),
+        source,
+    };
+    let line: String = serde_json::to_string(&record)
+        .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))?;
+
+    let path = log_dir.join(format!(
+        "{}-{}.log",
+        git_credential.user, git_credential.repo
+    ));
+    fs::create_dir_all(log_dir).map_err(io_error)?;
+    retention
+        .make_room(&path, line.len() as u64 + 1)
+        .map_err(io_error)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(io_error)?;
+    writeln!(file, "{}", line).map_err(io_error)?;
+    Ok(true)
+}
+
+/// Applies one update to a site, deploying the latest tag for sites that follow tags. Every
+/// git step runs through the trace.
+fn apply_update(
+    git_credential: &GitAuth,
+    ais_info: &AisInfo,
+    config: &AisConfig,
+    notifier: &dyn Notifier,
+    trace: &mut UpdateTrace,
+) -> Result<(), UnifiedError> {
+    let new_site_data = SiteInfo::new(git_credential, &config.site_root)?;
+    // Ensure the path thats in the manifest exists before we try to update
+
+    match path_present(&new_site_data.application_folder) {
+        Ok(b) => match b {
+            true => (), // Beautiful we are already initialized
+            false => {
+                // Clone the git repo properly
+                config.clone_scheme.verify()?;
+                git_credential.validate_branch(&SystemRunner, &config.clone_scheme)?;
+                let repo_url: String = git_credential.repo_url(&config.clone_scheme);
+                let repo_path: PathType = new_site_data.application_folder.clone_path();
+
+                match trace.run(&GitAction::Clone {
+                    repo_url,
+                    destination: repo_path,
+                }) {
+                    Ok((d, timing)) => {
+                        report_git_timing(&timing, &git_credential.repo, config);
+                        match d {
+                            true => notice("New repo added"),          // We've cloned the repo
+                            false => dump("Error while cloning repo"), // Since I have no error we'll let this be caught later
+                        }
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        },
+        Err(e) => {
+            return Err(UnifiedError::SystemError(
+                ErrorInfo::with_severity(
+                    Caller::Function(true, Some(String::from("Website update loop"))),
+                    crate::errors::Severity::Warning,
+                ),
+                e,
+            ))
+        }
+    }
+
+    // A checkout another repo left in the folder is replaced rather than pulled into
+    let new_site_data: SiteInfo = match SiteInfo::origin_matches(
+        new_site_data.application_folder.as_ref(),
+        git_credential,
+    )? {
+        true => new_site_data,
+        false => {
+            warn(&format!(
+                "{} holds a different repo than {}, cloning it again",
+                new_site_data.application_folder, git_credential.repo
+            ));
+            replace_checkout(
+                git_credential,
+                &new_site_data.application_folder,
+                config,
+                trace,
+            )?;
+            SiteInfo::new(git_credential, &config.site_root)?
+        }
+    };
+
+    // Sites deployed by tag don't follow the branch at all
+    if let DeployMode::Tag { pattern } = &git_credential.deploy {
+        deploy_latest_tag(
+            git_credential,
+            pattern,
+            &new_site_data,
+            ais_info,
+            config,
+            notifier,
+            trace,
+        )?;
+        return Ok(());
+    }
+
+    // Perform site updates based on new_site_data
+    match new_site_data.application_status {
+        Updates::UpToDate => {
+            let (_, timing) = trace.run(&GitAction::Switch {
+                branch: git_credential.branch.clone(),
+                destination: new_site_data.application_folder.clone_path(),
+            })?;
+            report_git_timing(&timing, &git_credential.repo, config);
+            // chown_recursive(new_site_data.application_folder, Some(33), Some(33))?;
+        }
+        Updates::Pinned => {
+            // Checked out at a commit on purpose, switching to the branch would undo that
+            notice(&format!(
+                "{} is pinned to a commit, not updating it",
+                git_credential.repo
+            ));
+        }
+        Updates::NoUpstream => {
+            // Put the configured branch back and point it at the remote, nothing to alert on
+            let destination: PathType = new_site_data.application_folder.clone_path();
+            trace.run(&GitAction::Switch {
+                branch: git_credential.branch.clone(),
+                destination: destination.clone_path(),
+            })?;
+            let (_, timing) = trace.run(&GitAction::SetUpstream {
+                branch: git_credential.branch.clone(),
+                destination,
+            })?;
+            report_git_timing(&timing, &git_credential.repo, config);
+            notice(&format!("Upstream set for {}", git_credential.repo));
+        }
+        Updates::OutOfDate => {
+            // Handle out-of-date scenario, noting what's coming in before it's applied
+            let incoming: Vec<IncomingCommit> =
+                match preview_pull(&new_site_data.application_folder.clone_path()) {
+                    Ok(incoming) => incoming,
+                    Err(e) => {
+                        warn(&format!("Couldn't preview {}: {}", git_credential.repo, e));
+                        Vec::new()
+                    }
+                };
+            notice(&format!(
+                "{} commits incoming for {}",
+                incoming.len(),
+                git_credential.repo
+            ));
+            clean_checkout(
+                git_credential,
+                &new_site_data.application_folder,
+                config,
+                trace,
+            )?;
+            match pull_remote_authoritative(
+                git_credential,
+                &new_site_data.application_folder,
+                ais_info,
+                notifier,
+                trace,
+            ) {
+                Ok((ok, timing)) => {
+                    report_git_timing(&timing, &git_credential.repo, config);
+                    if ok {
+                        // Successful update
+                        let mail = Email {
+                            subject: "Applied Update".to_owned(),
+                            body: format!(
+                                "The system: {} has just applied a new update from the repo: {}.{}",
+                                ais_info
+                                    .machine_id
+                                    .clone()
+                                    .unwrap_or_else(|| String::from("Failed to parse")),
+                                git_credential.repo,
+                                describe_incoming(&incoming)
+                            ),
+                            priority: EmailPriority::Low,
+                            category: EmailCategory::UpdateApplied,
+                        };
+                        trace.notify(notifier, mail)?;
+                        output("GREEN", "UPDATE FINISHED SUCCESSFULLY");
+                    } else {
+                        // Update failed
+                        let mail = Email {
+                            subject: "Update failed".to_owned(),
+                            body: format!("The system: {} failed at step 'pull' of update {} to the repo: {}.", ais_info.machine_id.clone().unwrap_or_else(|| String::from("Failed to parse")), trace.id, git_credential.repo),
+                            priority: EmailPriority::High,
+                            category: EmailCategory::UpdateFailed,
+                        };
+                        trace.notify(notifier, mail)?;
+                        warn("An error occurred while updating");
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+            // chown_recursive(new_site_data.application_folder, Some(33), Some(33))?;
+        }
+    }
+    Ok(())
+}
+
+/// Pulls the site, deploys are remote-authoritative so when local changes get in the way
+/// they're reset to the remote, the pull is retried and the discarded changes are reported.
+fn pull_remote_authoritative(
+    git_credential: &GitAuth,
+    site_folder: &PathType,
+    ais_info: &AisInfo,
+    notifier: &dyn Notifier,
+    trace: &mut UpdateTrace,
+) -> Result<(bool, GitTiming), UnifiedError> {
+    let pull = GitAction::Pull {
+        target_branch: git_credential.branch.clone(),
+        destination: site_folder.clone_path(),
+    };
+    let details: String = match trace.run(&pull) {
+        Err(UnifiedError::GitError(_, GitError::MergeConflict(details))) => details,
+        result => return result,
+    };
+    // The conflict is handled, so it isn't what the update failed at
+    trace.failed = None;
+
+    warn(&format!(
+        "Local changes in {} conflict with the remote, resetting to origin/{}",
+        git_credential.repo, git_credential.branch
+    ));
+    trace.run(&GitAction::Reset {
+        target_branch: git_credential.branch.clone(),
+        destination: site_folder.clone_path(),
+    })?;
+
+    let mail = Email {
+        subject: "Local changes discarded".to_owned(),
+        body: format!(
+            "The system: {} had local changes in the repo: {} that conflicted with the update. They were discarded and the site was reset to origin/{}.\n\n{}",
+            ais_info
+                .machine_id
+                .clone()
+                .unwrap_or_else(|| String::from("Failed to parse")),
+            git_credential.repo,
+            git_credential.branch,
+            details
+        ),
+        priority: EmailPriority::High,
+        category: EmailCategory::UpdateApplied,
+    };
+    trace.notify(notifier, mail)?;
+
+    trace.run(&pull)
+}
+
+/// Removes untracked files from the checkout before an update, when the config allows it.
+/// The clean is previewed first and skipped if it would remove anything that's kept.
+fn clean_checkout(
+    git_credential: &GitAuth,
+    site_folder: &PathType,
+    config: &AisConfig,
+    trace: &mut UpdateTrace,
+) -> Result<(), UnifiedError> {
+    if !config.clean_untracked {
+        return Ok(());
+    }
+    let untracked: Vec<String> = preview_clean(site_folder)?;
+    if untracked.is_empty() {
+        return Ok(());
+    }
+
+    let kept: Vec<&String> = untracked
+        .iter()
+        .filter(|path| config.keeps_untracked(path))
+        .collect();
+    if !kept.is_empty() {
+        warn(&format!(
+            "Not cleaning {}, it would remove kept paths: {}",
+            git_credential.repo,
+            kept.iter()
+                .map(|path| path.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ));
+        return Ok(());
+    }
+
+    trace.run(&GitAction::Clean {
+        directory: site_folder.clone_path(),
+        dry_run: false,
+    })?;
+    notice(&format!(
+        "Removed {} untracked paths from {}: {}",
+        untracked.len(),
+        git_credential.repo,
+        untracked.join(", ")
+    ));
+    Ok(())
+}
+
+/// Removes the checkout in the site folder and clones the expected repo in its place.
+fn replace_checkout(
+    git_credential: &GitAuth,
+    site_folder: &PathType,
+    config: &AisConfig,
+    trace: &mut UpdateTrace,
+) -> Result<(), UnifiedError> {
+    fs::remove_dir_all(site_folder).map_err(|e| {
+        UnifiedError::from_ais_error(AisError::SiteFailed(Some(format!(
+            "Failed to remove {}: {}",
+            site_folder, e
+        ))))
+    })?;
+
+    config.clone_scheme.verify()?;
+    let (_, timing) = trace.run(&GitAction::Clone {
+        repo_url: git_credential.repo_url(&config.clone_scheme),
+        destination: site_folder.clone_path(),
+    })?;
+    report_git_timing(&timing, &git_credential.repo, config);
+    SiteInfo::write_site_marker(site_folder.as_ref(), git_credential)
+}
+
+/// Checks out the highest tag matching the pattern when it's newer than the one deployed.
+fn deploy_latest_tag(
+    git_credential: &GitAuth,
+    pattern: &str,
+    site_data: &SiteInfo,
+    ais_info: &AisInfo,
+    config: &AisConfig,
+    notifier: &dyn Notifier,
+    trace: &mut UpdateTrace,
+) -> Result<(), UnifiedError> {
+    let destination: PathType = site_data.application_folder.clone_path();
+    let tag: String = match check_tag_ahead(&destination, pattern)? {
+        Some(tag) => tag,
+        None => return Ok(()),
+    };
+
+    clean_checkout(git_credential, &destination, config, trace)?;
+    let (_, timing) = trace.run(&GitAction::CheckoutTag {
+        tag: tag.clone(),
+        destination,
+    })?;
+    report_git_timing(&timing, &git_credential.repo, config);
+
+    let mail = Email {
+        subject: "Applied Update".to_owned(),
+        body: format!(
+            "The system: {} has just deployed the tag {} from the repo: {}.",
+            ais_info
+                .machine_id
+                .clone()
+                .unwrap_or_else(|| String::from("Failed to parse")),
+            tag,
+            git_credential.repo
+        ),
+        priority: EmailPriority::Low,
+        category: EmailCategory::UpdateApplied,
+    };
+    trace.notify(notifier, mail)?;
+    output("GREEN", &format!("DEPLOYED {}", tag));
+    Ok(())
+}
+
+/// Lists the commits an update brought in, for the update emails.
+fn describe_incoming(incoming: &[IncomingCommit]) -> String {
+    if incoming.is_empty() {
+        return String::new();
+    }
+    let mut description: String = format!("\n\n{} commits incoming:", incoming.len());
+    for commit in incoming {
+        description.push_str(&format!("\n  {} {}", commit.hash, commit.summary));
+    }
+    description
+}
+
+/// Warns about git actions on a repo that took longer than the configured threshold.
+fn report_git_timing(timing: &GitTiming, repo: &str, config: &AisConfig) {
+    if timing.elapsed >= Duration::from_secs(config.slow_git_threshold_secs) {
+        warn(&format!(
+            "Slow git {} on {} ({}): {}ms",
+            timing.action,
+            repo,
+            timing.target,
+            timing.elapsed.as_millis()
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::RecordingNotifier;
+    use nix::unistd::{getgid, getuid};
+    use users::mock::{Group, MockUsers, User};
+
+    #[test]
+    fn test_describe_incoming() {
+        assert_eq!(describe_incoming(&[]), "");
+        let incoming = vec![IncomingCommit {
+            hash: "1a2b3c4".to_owned(),
+            summary: "fix the footer".to_owned(),
+        }];
+        assert_eq!(
+            describe_incoming(&incoming),
+            "\n\n1 commits incoming:\n  1a2b3c4 fix the footer"
+        );
+    }
+
+    /// Reports the same free space for every path.
+    struct MockDisk(u64);
+
+    impl DiskSpace for MockDisk {
+        fn available(&self, _: &Path) -> Result<u64, UnifiedError> {
+            Ok(self.0)
+        }
+    }
+
+    #[test]
+    fn test_low_disk_space_skips_deploys() {
+        let config = AisConfig {
+            min_deploy_space_mb: 1024,
+            ..AisConfig::default()
+        };
+        let ais_info = AisInfo::new().unwrap();
+        let notifier = RecordingNotifier::default();
+        let alerted = AtomicBool::new(false);
+        let low = MockDisk(200 * 1024 * 1024);
+
+        assert!(!ensure_deploy_space(&low, &config, &ais_info, &notifier, &alerted).unwrap());
+        assert!(!ensure_deploy_space(&low, &config, &ais_info, &notifier, &alerted).unwrap());
+        {
+            let sent = notifier.0.lock().unwrap();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(sent[0].category, EmailCategory::Resource);
+            assert!(sent[0].body.contains("200MB free"));
+        }
+
+        // Once space is freed up deploys resume and a new shortage alerts again
+        let plenty = MockDisk(20 * 1024 * 1024 * 1024);
+        assert!(ensure_deploy_space(&plenty, &config, &ais_info, &notifier, &alerted).unwrap());
+        assert!(!ensure_deploy_space(&low, &config, &ais_info, &notifier, &alerted).unwrap());
+        assert_eq!(notifier.0.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_update_emails_share_correlation_id() {
+        let ais_info = AisInfo::new().unwrap();
+        let notifier = RecordingNotifier::default();
+        let not_a_repo = std::env::temp_dir().join("ais_update_trace");
+        let _ = fs::remove_dir_all(&not_a_repo);
+        fs::create_dir_all(&not_a_repo).unwrap();
+        // The steps run under the trace's lock, taking it again for each would never return
+        let mut trace = UpdateTrace::new("website", SiteLock::acquire(&not_a_repo).unwrap());
+
+        let mail = Email {
+            subject: "Local changes discarded".to_owned(),
+            body: "Reset to origin/main.".to_owned(),
+            priority: EmailPriority::High,
+            category: EmailCategory::UpdateApplied,
+        };
+        trace.notify(&notifier, mail).unwrap();
+        assert!(trace.failed.is_none());
+
+        let error = trace
+            .run(&GitAction::Switch {
+                branch: "main".to_owned(),
+                destination: PathType::PathBuf(not_a_repo.clone()),
+            })
+            .unwrap_err();
+        let failure = trace.failure_email(&ais_info, "website", &error).unwrap();
+        trace.notify(&notifier, failure).unwrap();
+
+        let sent = notifier.0.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        let tag = format!("Update {}", trace.id);
+        assert!(sent.iter().all(|email| email.body.contains(&tag)));
+        assert!(sent[1]
+            .body
+            .contains(&format!("failed at step 'switch' of update {}", trace.id)));
+        assert!(sent[1].body.ends_with("steps: switch"));
+        let other = SiteLock::acquire(&not_a_repo.join("other")).unwrap();
+        assert_ne!(UpdateTrace::new("website", other).id, trace.id);
+
+        let _ = fs::remove_dir_all(&not_a_repo);
+        let _ = fs::remove_file(SiteLock::lock_path(&not_a_repo));
+    }
+
+    #[test]
+    fn test_trigger_updates_only_targeted_repo() {
+        let trigger_dir = std::env::temp_dir().join("ais_deploy_trigger");
+        let _ = fs::remove_dir_all(&trigger_dir);
+        fs::create_dir_all(&trigger_dir).unwrap();
+        let auth = |repo: &str| GitAuth {
+            user: "artisan".to_owned(),
+            repo: repo.to_owned(),
+            branch: "main".to_owned(),
+            token: String::new(),
+            deploy: DeployMode::Branch,
+            health_url: None,
+        };
+        let auths = vec![auth("website"), auth("blog")];
+        let mut checked: Vec<String> = Vec::new();
+
+        // Nothing triggered, nothing checked
+        run_triggered(&trigger_dir, &auths, |auth| {
+            checked.push(auth.repo.clone());
+            Ok(())
+        })
+        .unwrap();
+        assert!(checked.is_empty());
+
+        fs::write(trigger_dir.join("blog"), "").unwrap();
+        run_triggered(&trigger_dir, &auths, |auth| {
+            checked.push(auth.repo.clone());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(checked, vec!["blog".to_owned()]);
+        // The trigger is used up
+        assert!(!trigger_dir.join("blog").exists());
+
+        let _ = fs::remove_dir_all(&trigger_dir);
+    }
+
+    #[test]
+    fn test_deploy_writes_one_record() {
+        let log_dir = std::env::temp_dir().join("ais_deploy_log");
+        let _ = fs::remove_dir_all(&log_dir);
+        let auth = GitAuth {
+            user: "artisan".to_owned(),
+            repo: "website".to_owned(),
+            branch: "main".to_owned(),
+            token: String::new(),
+            deploy: DeployMode::Branch,
+            health_url: None,
+        };
+        let (old, new) = ("1a2b3c4".to_owned(), "5d6e7f8".to_owned());
+
+        // An update that didn't move the site isn't a deploy
+        assert!(!record_deploy(
+            &log_dir,
+            &RetentionCategory::Deploys.default_retention(),
+            &auth,
+            Some(old.clone()),
+            Some(old.clone()),
+            DeploySource::Scheduled
+        )
+        .unwrap());
+        assert!(record_deploy(
+            &log_dir,
+            &RetentionCategory::Deploys.default_retention(),
+            &auth,
+            Some(old.clone()),
+            Some(new.clone()),
+            DeploySource::Triggered
+        )
+        .unwrap());
+
+        let log = fs::read_to_string(log_dir.join("artisan-website.log")).unwrap();
+        let records: Vec<DeployRecord> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].repo, "website");
+        assert_eq!(records[0].old_commit, Some(old));
+        assert_eq!(records[0].new_commit, new);
+        assert_eq!(records[0].source, DeploySource::Triggered);
+        assert!(time::parse(&records[0].timestamp).is_ok());
+
+        let _ = fs::remove_dir_all(&log_dir);
+    }
+
+    #[test]
+    fn test_resolve_www_data() {
+        let mut passwd = MockUsers::with_current_uid(0);
+        passwd.add_user(User::new(33, "www-data", 33));
+        passwd.add_group(Group::new(33, "www-data"));
+
+        let (uid, gid) = resolve_user(&passwd, "www-data").unwrap();
+        assert_eq!(uid.as_raw(), 33);
+        assert_eq!(gid.as_raw(), 33);
+
+        let missing = resolve_user(&MockUsers::with_current_uid(0), "www-data");
+        assert!(matches!(
+            missing,
+            Err(UnifiedError::AisError(_, AisError::PermissionDenied(_)))
+        ));
+    }
+
+    #[test]
+    fn test_prepare_deploy_logs_creates_the_dir() {
+        let scratch = tempfile::tempdir().unwrap();
+        let log_dir = scratch.path().join("deploys");
+
+        prepare_deploy_logs(&log_dir, getuid(), getgid()).unwrap();
+        assert!(log_dir.is_dir());
+    }
+}
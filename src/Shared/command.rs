@@ -0,0 +1,225 @@
+use std::{
+    io::Read,
+    process::{Command, Output, Stdio},
+    sync::{Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::errors::{AisError, Caller, ErrorInfo, UnifiedError};
+
+/// How long an external command can run before it's killed, unless the call site picks its own.
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How often a running command is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(25);
+
+/// Runs external programs, tests swap in a mock so they don't depend on the host.
+pub trait CommandRunner {
+    /// Runs the program with the given args, returning its trimmed stdout on success.
+    fn run(&self, program: &str, args: &[&str]) -> Result<String, UnifiedError>;
+}
+
+/// Runs programs on the host system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<String, UnifiedError> {
+        let output = run_with_timeout(Command::new(program).args(args), DEFAULT_COMMAND_TIMEOUT)?;
+
+        match output.status.success() {
+            true => Ok(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+            false => Err(UnifiedError::AisError(
+                ErrorInfo::new(Caller::Impl(true, Some("SystemRunner::run".to_owned()))),
+                AisError::SystemError(Some(format!(
+                    "{} exited with {}: {}",
+                    program,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ))),
+            )),
+        }
+    }
+}
+
+/// Bounds how many callers do something at once, like git operations sharing an uplink.
+pub struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// A slot held in a Semaphore, it's given back when dropped.
+pub struct Permit<'a>(&'a Semaphore);
+
+impl Semaphore {
+    /// Creates a new Semaphore with the given number of slots, at least one.
+    pub fn new(permits: usize) -> Self {
+        Semaphore {
+            available: Mutex::new(permits.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Waits for a free slot and holds it until the permit is dropped.
+    pub fn acquire(&self) -> Permit<'_> {
+        // The count is always left consistent, so a poisoned lock is still usable
+        let mut available = self.available.lock().unwrap_or_else(|e| e.into_inner());
+        while *available == 0 {
+            available = self
+                .released
+                .wait(available)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        *available -= 1;
+        Permit(self)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut available = self.0.available.lock().unwrap_or_else(|e| e.into_inner());
+        *available += 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// Runs a command, killing it and returning an error if it doesn't finish within the timeout.
+/// Stdout and stderr are captured like `Command::output`.
+pub fn run_with_timeout(command: &mut Command, timeout: Duration) -> Result<Output, UnifiedError> {
+    let program: String = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            UnifiedError::AisError(
+                ErrorInfo::new(Caller::Function(true, Some("run_with_timeout".to_owned()))),
+                AisError::SystemError(Some(format!("Failed to run {}: {}", program, e))),
+            )
+        })?;
+
+    // The pipes are drained as the command runs so a chatty command can't fill them and stall
+    let stdout: JoinHandle<Vec<u8>> = drain_pipe(child.stdout.take());
+    let stderr: JoinHandle<Vec<u8>> = drain_pipe(child.stderr.take());
+
+    let started: Instant = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() >= timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(UnifiedError::AisError(
+                    ErrorInfo::new(Caller::Function(true, Some("run_with_timeout".to_owned()))),
+                    AisError::CommandTimedOut(Some(format!(
+                        "{} didn't finish within {}s and was killed",
+                        program,
+                        timeout.as_secs_f32()
+                    ))),
+                ));
+            }
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(e) => {
+                return Err(UnifiedError::AisError(
+                    ErrorInfo::new(Caller::Function(true, Some("run_with_timeout".to_owned()))),
+                    AisError::SystemError(Some(format!("Failed to wait on {}: {}", program, e))),
+                ))
+            }
+        }
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Reads a pipe to the end on its own thread.
+fn drain_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer: Vec<u8> = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_runner() {
+        assert_eq!(SystemRunner.run("echo", &["hello"]).unwrap(), "hello");
+        assert!(SystemRunner.run("false", &[]).is_err());
+        assert!(SystemRunner.run("/nonexistent/program", &[]).is_err());
+    }
+
+    /// Records how many git commands are running at once.
+    #[derive(Default)]
+    struct ConcurrencyRunner {
+        running: Mutex<usize>,
+        most: Mutex<usize>,
+    }
+
+    impl CommandRunner for ConcurrencyRunner {
+        fn run(&self, _: &str, _: &[&str]) -> Result<String, UnifiedError> {
+            {
+                let mut running = self.running.lock().unwrap();
+                *running += 1;
+                let mut most = self.most.lock().unwrap();
+                *most = (*most).max(*running);
+            }
+            thread::sleep(Duration::from_millis(20));
+            *self.running.lock().unwrap() -= 1;
+            Ok(String::new())
+        }
+    }
+
+    #[test]
+    fn test_semaphore_bounds_concurrency() {
+        let runner = ConcurrencyRunner::default();
+        let limit = Semaphore::new(2);
+
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    let _permit = limit.acquire();
+                    runner.run("git", &["pull"]).unwrap();
+                });
+            }
+        });
+
+        assert_eq!(*runner.most.lock().unwrap(), 2);
+        assert_eq!(*runner.running.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_run_with_timeout_kills_slow_commands() {
+        let started = Instant::now();
+        let result = run_with_timeout(Command::new("sleep").arg("10"), Duration::from_millis(200));
+
+        assert!(matches!(
+            result,
+            Err(UnifiedError::AisError(_, AisError::CommandTimedOut(_)))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_run_with_timeout_captures_output() {
+        let output = run_with_timeout(
+            Command::new("sh").args(["-c", "echo out; echo err >&2"]),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "out");
+        assert_eq!(String::from_utf8_lossy(&output.stderr).trim(), "err");
+    }
+}
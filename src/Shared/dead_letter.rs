@@ -0,0 +1,438 @@
+use crate::{
+    ais_config::AisConfig,
+    emails::{Email, EmailSecure},
+    encrypt::Cipher,
+    errors::{AisError, UnifiedError},
+    log_file::{notice, warn},
+    retention::{Retention, RetentionCategory},
+};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+/// Where emails that couldn't be encrypted wait for dusa to come back. Only the ones that are
+/// fine to keep in the clear are written here, see `DeadLetters`.
+pub const DEAD_LETTER_PATH: &str = "/var/lib/ais/dead_letters.jsonl";
+
+/// How many emails that require encryption are held in memory, the oldest are dropped past it.
+pub const SENSITIVE_LIMIT: usize = 100;
+
+/// The dead letters of the emails sent through `EmailNotifier`, opened by `DeadLetters::init`.
+static DEAD_LETTERS: OnceLock<DeadLetters> = OnceLock::new();
+
+/// Holds the emails that failed to encrypt until encrypting works again.
+///
+/// Emails of categories that require encryption are never written out in the clear, they're
+/// only held in memory. Those are lost when the process stops or restarts before dusa
+/// recovers, only the others survive a restart. The file is kept under the retention's
+/// `max_bytes` and the memory under `SENSITIVE_LIMIT` by dropping the oldest emails.
+pub struct DeadLetters {
+    /// Kept open so threads that dropped their privileges can still hold emails.
+    file: Mutex<File>,
+    retention: Retention,
+    sensitive: Mutex<Vec<Email>>,
+}
+
+impl DeadLetters {
+    /// Opens the file the dead letters are kept in, creating it readable by its owner only
+    /// since the emails are in the clear.
+    pub fn open(path: &Path, retention: Retention) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file: File = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)?;
+        Ok(DeadLetters {
+            file: Mutex::new(file),
+            retention,
+            sensitive: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Opens the dead letters at `DEAD_LETTER_PATH` for `current` to hand out. Only root can
+    /// open them, so call this at startup before any thread drops its privileges.
+    pub fn init(config: &AisConfig) -> Result<&'static DeadLetters, UnifiedError> {
+        if let Some(dead_letters) = DEAD_LETTERS.get() {
+            return Ok(dead_letters);
+        }
+        let retention: Retention = config.retention(RetentionCategory::DeadLetters);
+        let dead_letters: DeadLetters = Self::open(Path::new(DEAD_LETTER_PATH), retention)
+            .map_err(|e| {
+                UnifiedError::from_ais_error(AisError::with_context("opening the dead letters", e))
+            })?;
+        Ok(DEAD_LETTERS.get_or_init(|| dead_letters))
+    }
+
+    /// The dead letters opened by `init`, None if they weren't.
+    pub fn current() -> Option<&'static DeadLetters> {
+        DEAD_LETTERS.get()
+    }
+
+    /// Encrypts the email and sends it. An email that can't be encrypted is held until a later
+    /// one encrypts or `retry` is called, the email is reported as delivered in the meantime.
+    /// Sending isn't retried here, its errors are returned as is. Once the email is sent the
+    /// held ones are retried, failing to send those is only logged.
+    pub fn deliver<S>(
+        &self,
+        email: &Email,
+        cipher: &dyn Cipher,
+        send: S,
+    ) -> Result<(), UnifiedError>
+    where
+        S: Fn(&EmailSecure) -> Result<(), UnifiedError>,
+    {
+        // Invalid emails would never encrypt, they aren't worth holding
+        if !email.is_valid() {
+            return Err(UnifiedError::from_ais_error(AisError::new(
+                "Invalid Email Data",
+            )));
+        }
+
+        let secure: EmailSecure = match EmailSecure::new_with(email.clone(), cipher) {
+            Ok(secure) => secure,
+            Err(e) => {
+                warn(&format!(
+                    "Holding the email {} until it can be encrypted: {}",
+                    email.subject, e
+                ));
+                return self.store(email);
+            }
+        };
+        send(&secure)?;
+        if let Err(e) = self.retry(cipher, send) {
+            warn(&format!("The held emails are still held: {}", e));
+        }
+        Ok(())
+    }
+
+    /// Holds the email, in memory if it requires encryption and in the file otherwise.
+    pub fn store(&self, email: &Email) -> Result<(), UnifiedError> {
+        let mut sensitive = self.sensitive.lock().unwrap_or_else(|e| e.into_inner());
+        match email.category.requires_encryption() {
+            true => {
+                hold_sensitive(&mut sensitive, email.clone());
+                Ok(())
+            }
+            false => self.append(email).map_err(|e| {
+                UnifiedError::from_ais_error(AisError::with_context("storing a dead letter", e))
+            }),
+        }
+    }
+
+    /// Encrypts and sends the held emails. The first one that fails and the ones after it are
+    /// held again.
+    pub fn retry<S>(&self, cipher: &dyn Cipher, send: S) -> Result<(), UnifiedError>
+    where
+        S: Fn(&EmailSecure) -> Result<(), UnifiedError>,
+    {
+        let mut sensitive = self.sensitive.lock().unwrap_or_else(|e| e.into_inner());
+        let mut held: Vec<Email> = self.take().map_err(|e| {
+            UnifiedError::from_ais_error(AisError::with_context("reading the dead letters", e))
+        })?;
+        held.append(&mut sensitive);
+
+        let mut delivered: usize = 0;
+        let mut result: Result<(), UnifiedError> = Ok(());
+        let mut held = held.into_iter();
+        for email in held.by_ref() {
+            match EmailSecure::new_with(email.clone(), cipher).and_then(|secure| send(&secure)) {
+                Ok(_) => delivered += 1,
+                Err(e) => {
+                    result = Err(e);
+                    self.hold_again(email, &mut sensitive);
+                    break;
+                }
+            }
+        }
+        for email in held {
+            self.hold_again(email, &mut sensitive);
+        }
+
+        if delivered > 0 {
+            notice(&format!(
+                "Delivered {} email(s) held while they couldn't be encrypted",
+                delivered
+            ));
+        }
+        result
+    }
+
+    /// How many emails are held.
+    pub fn len(&self) -> usize {
+        let sensitive = self.sensitive.lock().unwrap_or_else(|e| e.into_inner());
+        let stored: usize = self
+            .read()
+            .map(|data| data.lines().filter(|line| !line.trim().is_empty()).count())
+            .unwrap_or(0);
+        sensitive.len() + stored
+    }
+
+    /// Whether no emails are held.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn hold_again(&self, email: Email, sensitive: &mut Vec<Email>) {
+        match email.category.requires_encryption() {
+            true => hold_sensitive(sensitive, email),
+            false => {
+                if let Err(e) = self.append(&email) {
+                    warn(&format!(
+                        "Dropping the email {}, it couldn't be held again: {}",
+                        email.subject, e
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Appends the email to the file, one json object per line. The oldest emails are dropped
+    /// if it would grow past the retention's `max_bytes`, the file can't be rotated since the
+    /// threads that dropped their privileges couldn't create a new one.
+    fn append(&self, email: &Email) -> io::Result<()> {
+        let line: String = format!("{}\n", serde_json::to_string(email)?);
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let size: u64 = file.metadata()?.len();
+        if size > 0 && size + line.len() as u64 > self.retention.max_bytes {
+            let mut data: String = String::new();
+            file.seek(SeekFrom::Start(0))?;
+            file.read_to_string(&mut data)?;
+            let (dropped, kept) = trim_oldest(&data, self.retention.max_bytes, line.len());
+            warn(&format!(
+                "Dropping the {} oldest dead letter(s), holding them would pass {} bytes",
+                dropped, self.retention.max_bytes
+            ));
+            file.set_len(0)?;
+            file.write_all(kept.as_bytes())?;
+        }
+        file.write_all(line.as_bytes())
+    }
+
+    /// Reads the whole file, the writes are appends so where it's left reading doesn't matter.
+    fn read(&self) -> io::Result<String> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let mut data: String = String::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_string(&mut data)?;
+        Ok(data)
+    }
+
+    /// Reads the emails in the file and empties it, lines that can't be read are dropped.
+    fn take(&self) -> io::Result<Vec<Email>> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let mut data: String = String::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_string(&mut data)?;
+        file.set_len(0)?;
+        Ok(data
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+}
+
+/// Holds the email in memory, dropping the oldest past `SENSITIVE_LIMIT`.
+fn hold_sensitive(sensitive: &mut Vec<Email>, email: Email) {
+    if sensitive.len() >= SENSITIVE_LIMIT {
+        let dropped: Email = sensitive.remove(0);
+        warn(&format!(
+            "Dropping the email {}, more than {} are held",
+            dropped.subject, SENSITIVE_LIMIT
+        ));
+    }
+    sensitive.push(email);
+}
+
+/// Drops the oldest lines until the rest and the incoming bytes fit in the limit, returning
+/// how many were dropped and what's kept.
+fn trim_oldest(data: &str, max_bytes: u64, incoming: usize) -> (usize, &str) {
+    let mut kept: &str = data;
+    let mut dropped: usize = 0;
+    while !kept.is_empty() && (kept.len() + incoming) as u64 > max_bytes {
+        kept = kept.split_once('\n').map(|(_, rest)| rest).unwrap_or("");
+        dropped += 1;
+    }
+    (dropped, kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emails::EmailCategory;
+    use crate::test_support::MockCipher;
+    use std::cell::Cell;
+
+    fn retention() -> Retention {
+        RetentionCategory::DeadLetters.default_retention()
+    }
+
+    fn email(subject: &str, category: EmailCategory) -> Email {
+        let mut email = Email::new(subject.to_owned(), "Body".to_owned());
+        email.category = category;
+        email
+    }
+
+    #[test]
+    fn test_encrypt_failure_is_held_until_it_recovers() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("dead_letters.jsonl");
+        let dead_letters = DeadLetters::open(&path, retention()).unwrap();
+        let cipher = MockCipher::unavailable();
+        let sent: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        let send = |secure: &EmailSecure| {
+            sent.lock().unwrap().push(secure.data.clone());
+            Ok(())
+        };
+
+        let stopped = email("Service stopped", EmailCategory::ServiceStatus);
+        let login = email("Ssh login", EmailCategory::SshAudit);
+        dead_letters.deliver(&stopped, &cipher, send).unwrap();
+        dead_letters.deliver(&login, &cipher, send).unwrap();
+        assert!(sent.lock().unwrap().is_empty());
+        assert_eq!(dead_letters.len(), 2);
+        // Only the one that's fine to keep in the clear is on disk
+        let stored = fs::read_to_string(&path).unwrap();
+        assert!(stored.contains("Service stopped"));
+        assert!(!stored.contains("Ssh login"));
+
+        cipher.available.set(true);
+        let recovered = email("Service started", EmailCategory::ServiceStatus);
+        dead_letters.deliver(&recovered, &cipher, send).unwrap();
+        assert_eq!(sent.lock().unwrap().len(), 3);
+        assert!(dead_letters.is_empty());
+        assert!(fs::read_to_string(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_failed_retry_is_held_again() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("dead_letters.jsonl");
+        let dead_letters = DeadLetters::open(&path, retention()).unwrap();
+        let cipher = MockCipher::new();
+        dead_letters
+            .store(&email("Disk full", EmailCategory::Resource))
+            .unwrap();
+        dead_letters
+            .store(&email("Update failed", EmailCategory::UpdateFailed))
+            .unwrap();
+
+        let result = dead_letters.retry(&cipher, |_| {
+            Err(UnifiedError::from_ais_error(AisError::EtNoHome(None)))
+        });
+        assert!(result.is_err());
+        assert_eq!(dead_letters.len(), 2);
+    }
+
+    #[test]
+    fn test_only_clear_emails_survive_a_restart() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("dead_letters.jsonl");
+        let dead_letters = DeadLetters::open(&path, retention()).unwrap();
+        dead_letters
+            .store(&email("Disk full", EmailCategory::Resource))
+            .unwrap();
+        dead_letters
+            .store(&email("Ssh login", EmailCategory::SshAudit))
+            .unwrap();
+        assert_eq!(dead_letters.len(), 2);
+        drop(dead_letters);
+
+        let restarted = DeadLetters::open(&path, retention()).unwrap();
+        assert_eq!(restarted.len(), 1);
+        let sent: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        let cipher = MockCipher::new();
+        restarted
+            .retry(&cipher, |secure: &EmailSecure| {
+                sent.lock().unwrap().push(secure.data.clone());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(sent.lock().unwrap().len(), 1);
+        assert!(restarted.is_empty());
+    }
+
+    #[test]
+    fn test_delivered_email_isnt_failed_by_the_retry() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("dead_letters.jsonl");
+        let dead_letters = DeadLetters::open(&path, retention()).unwrap();
+        let cipher = MockCipher::new();
+        dead_letters
+            .store(&email("Disk full", EmailCategory::Resource))
+            .unwrap();
+
+        // Only the first send, the new email's, goes through
+        let sends: Cell<usize> = Cell::new(0);
+        let result = dead_letters.deliver(
+            &email("Service stopped", EmailCategory::ServiceStatus),
+            &cipher,
+            |_| {
+                sends.set(sends.get() + 1);
+                match sends.get() {
+                    1 => Ok(()),
+                    _ => Err(UnifiedError::from_ais_error(AisError::EtNoHome(None))),
+                }
+            },
+        );
+        assert!(result.is_ok());
+        assert_eq!(sends.get(), 2);
+        assert_eq!(dead_letters.len(), 1);
+    }
+
+    #[test]
+    fn test_file_drops_the_oldest_past_max_bytes() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("dead_letters.jsonl");
+        let line: usize = serde_json::to_string(&email("Disk full 1", EmailCategory::Resource))
+            .unwrap()
+            .len()
+            + 1;
+        let retention = Retention {
+            max_bytes: (line * 2) as u64,
+            max_files: 0,
+            max_age_days: 0,
+        };
+        let dead_letters = DeadLetters::open(&path, retention).unwrap();
+        for index in 1..=3 {
+            dead_letters
+                .store(&email(
+                    &format!("Disk full {}", index),
+                    EmailCategory::Resource,
+                ))
+                .unwrap();
+        }
+
+        assert_eq!(dead_letters.len(), 2);
+        let stored = fs::read_to_string(&path).unwrap();
+        assert!(!stored.contains("Disk full 1"));
+        assert!(stored.contains("Disk full 2"));
+        assert!(stored.contains("Disk full 3"));
+    }
+
+    #[test]
+    fn test_sensitive_emails_are_capped() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("dead_letters.jsonl");
+        let dead_letters = DeadLetters::open(&path, retention()).unwrap();
+        for index in 0..=SENSITIVE_LIMIT {
+            dead_letters
+                .store(&email(
+                    &format!("Ssh login {}", index),
+                    EmailCategory::SshAudit,
+                ))
+                .unwrap();
+        }
+
+        let sensitive = dead_letters.sensitive.lock().unwrap();
+        assert_eq!(sensitive.len(), SENSITIVE_LIMIT);
+        assert_eq!(sensitive[0].subject, "Ssh login 1");
+    }
+}
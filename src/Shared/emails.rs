@@ -1,7 +1,27 @@
-use crate::encrypt::Commands;
+use crate::ais_config::AisConfig;
+use crate::ais_data::AisInfo;
+use crate::encrypt::{Cipher, Commands, DusaCipher};
 use crate::errors::{AisError, Caller, ErrorInfo, Severity, UnifiedError};
+use crate::profile::Profile;
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
-use std::{fmt, io::Write, net::TcpStream};
+use sha2::Sha256;
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    net::{TcpStream, ToSocketAddrs},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use system::{
+    create_hash,
+    errors::{SystemError, SystemErrorType},
+    PathType,
+};
+
+type HmacSha256 = Hmac<Sha256>;
 
 /// Represents an email message.
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -10,13 +30,126 @@ pub struct Email {
     pub subject: String,
     /// The body of the email.
     pub body: String,
+    /// The priority of the email, high priority emails are never dropped in favor of low ones.
+    #[serde(default)]
+    pub priority: EmailPriority,
+    /// The category of the email, used to manage emails of one kind together.
+    #[serde(default)]
+    pub category: EmailCategory,
+}
+
+/// Enum representing the kind of event an email reports.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum EmailCategory {
+    /// Ssh access audits.
+    SshAudit,
+    /// Service state changes.
+    ServiceStatus,
+    /// Resource usage warnings.
+    Resource,
+    /// Site updates that were applied.
+    UpdateApplied,
+    /// Site updates that failed.
+    UpdateFailed,
+    /// Changes to the machine's identity, ip or mac.
+    MachineIdentity,
+    /// Systems that were initialized incorrectly.
+    Initialization,
+    /// Missed heartbeats.
+    Heartbeat,
+    /// The daily summary.
+    Summary,
+    /// Anything else.
+    #[default]
+    General,
+}
+
+/// Enum representing the priority of an email.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmailPriority {
+    /// Alerts that need attention.
+    High,
+    /// Informational notices.
+    #[default]
+    Low,
+}
+
+/// Enum representing what the messaging server does when its queue is full.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Evict the oldest low priority email to make room for the new one.
+    DropOldestLow,
+    /// Reject the new email, unless it's high priority and a low priority email can be evicted.
+    RejectNew,
+}
+
+/// Enum representing how the messaging server secures its connection to the smtp relay.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    /// Tls from the start of the connection, port 465 by default.
+    Implicit,
+    /// A plain connection upgraded with STARTTLS, port 587 by default.
+    StartTls,
+    /// No encryption, port 25 by default. Only for relays on a trusted network.
+    None,
+}
+
+/// Address of the production messaging server, see `Profile::endpoints` for the one in use.
+pub const MAIL_SERVER_ADDRESS: &str = "10.1.0.11:1827";
+
+/// Enum representing where the messaging server is reached, selected by the endpoint's scheme.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MailEndpoint {
+    /// A TCP address like `10.1.0.11:1827`, written with or without `tcp://`.
+    Tcp(String),
+    /// A Unix domain socket for a client on the same box, written like `unix:/run/ais/mail.sock`.
+    Unix(PathBuf),
+}
+
+/// Prefix marking a heartbeat, the mail server records these instead of decrypting them.
+pub const HEARTBEAT_PREFIX: &str = "AIS-HEARTBEAT:";
+
+/// Prefix marking an email frame sent without encryption, the mail server reads these as is.
+pub const PLAIN_PREFIX: &str = "AIS-PLAIN:";
+
+/// Where the key emails are signed with is kept, encrypted by dusa.
+pub const SIGNING_KEY_PATH: &str = "/etc/ais_mail.key";
+
+/// Separates an email's data from its id.
+pub const EMAIL_ID_SEPARATOR: &str = "|id:";
+
+/// Separates a frame from its signature.
+pub const SIGNATURE_SEPARATOR: &str = "|sig:";
+
+/// The largest frame the mail server reads, anything longer fails its signature check.
+pub const MAX_FRAME_BYTES: usize = 64 * 1024;
+
+/// The signing key loaded by `SigningKey::init`, decrypting it for every email would tie each
+/// one to dusa.
+static SIGNING_KEY: OnceLock<SigningKey> = OnceLock::new();
+
+/// The secret shared by the clients and the messaging server, frames are signed with an
+/// hmac over their contents so the server can reject ones that didn't come from a client.
+pub struct SigningKey {
+    key: Vec<u8>,
+}
+
+/// Represents a heartbeat letting the messaging server know a client is still alive.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Heartbeat {
+    /// The machine id of the client sending the heartbeat.
+    pub machine_id: String,
 }
 
 /// Represents an encrypted email message.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EmailSecure {
-    /// The encrypted email data.
+    /// The encrypted email data, or the plain frame for emails created with `new_plain`.
     pub data: String,
+    /// A hash of the email and when it was created. It stays the same when the email is
+    /// resent, so the messaging server can drop retransmits it already has.
+    #[serde(default)]
+    pub id: String,
 }
 
 // Display implementations
@@ -26,6 +159,34 @@ impl fmt::Display for Email {
     }
 }
 
+impl fmt::Display for EmailPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let priority: &str = match self {
+            EmailPriority::High => "High",
+            EmailPriority::Low => "Low",
+        };
+        write!(f, "{}", priority)
+    }
+}
+
+impl fmt::Display for EmailCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let category: &str = match self {
+            EmailCategory::SshAudit => "SshAudit",
+            EmailCategory::ServiceStatus => "ServiceStatus",
+            EmailCategory::Resource => "Resource",
+            EmailCategory::UpdateApplied => "UpdateApplied",
+            EmailCategory::UpdateFailed => "UpdateFailed",
+            EmailCategory::MachineIdentity => "MachineIdentity",
+            EmailCategory::Initialization => "Initialization",
+            EmailCategory::Heartbeat => "Heartbeat",
+            EmailCategory::Summary => "Summary",
+            EmailCategory::General => "General",
+        };
+        write!(f, "{}", category)
+    }
+}
+
 impl fmt::Display for EmailSecure {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.data)
@@ -35,7 +196,21 @@ impl fmt::Display for EmailSecure {
 impl Email {
     /// Creates a new Email instance with the given subject and body.
     pub fn new(subject: String, body: String) -> Self {
-        Email { subject, body }
+        Email {
+            subject,
+            body,
+            priority: EmailPriority::Low,
+            category: EmailCategory::General,
+        }
+    }
+
+    /// Labels the email with the machine it came from, when the machine has a label.
+    pub fn prepare(mut self, ais_info: &AisInfo) -> Self {
+        if let Some(label) = &ais_info.label {
+            self.subject = format!("[{}] {}", label, self.subject);
+            self.body = format!("{}\n\nMachine: {}", self.body, ais_info.identity());
+        }
+        self
     }
 
     /// Checks if the email data is valid.
@@ -44,62 +219,417 @@ impl Email {
     }
 }
 
+impl EmailPriority {
+    /// Parses a priority as written by its Display implementation, defaulting to Low.
+    pub fn parse(data: &str) -> Self {
+        match data.trim() {
+            "High" => EmailPriority::High,
+            _ => EmailPriority::Low,
+        }
+    }
+}
+
+impl EmailCategory {
+    /// Checks if emails of this category carry data that must never leave the machine
+    /// unencrypted.
+    pub fn requires_encryption(&self) -> bool {
+        matches!(
+            self,
+            EmailCategory::SshAudit | EmailCategory::MachineIdentity
+        )
+    }
+
+    /// Checks if emails of this category are routine notices, the ones held back during
+    /// maintenance.
+    pub fn is_routine(&self) -> bool {
+        matches!(
+            self,
+            EmailCategory::ServiceStatus
+                | EmailCategory::UpdateApplied
+                | EmailCategory::UpdateFailed
+                | EmailCategory::Resource
+        )
+    }
+
+    /// Parses a category as written by its Display implementation.
+    pub fn parse(data: &str) -> Option<Self> {
+        match data.trim() {
+            "SshAudit" => Some(EmailCategory::SshAudit),
+            "ServiceStatus" => Some(EmailCategory::ServiceStatus),
+            "Resource" => Some(EmailCategory::Resource),
+            "UpdateApplied" => Some(EmailCategory::UpdateApplied),
+            "UpdateFailed" => Some(EmailCategory::UpdateFailed),
+            "MachineIdentity" => Some(EmailCategory::MachineIdentity),
+            "Initialization" => Some(EmailCategory::Initialization),
+            "Heartbeat" => Some(EmailCategory::Heartbeat),
+            "Summary" => Some(EmailCategory::Summary),
+            "General" => Some(EmailCategory::General),
+            _ => None,
+        }
+    }
+}
+
+impl SigningKey {
+    /// Creates a new SigningKey from the raw secret.
+    pub fn new(key: Vec<u8>) -> Self {
+        SigningKey { key }
+    }
+
+    /// Loads and decrypts the key stored at the given path.
+    pub fn load(file_location: &PathType) -> Result<Self, UnifiedError> {
+        let encrypted_key: String = fs::read_to_string(file_location)
+            .map_err(|e| {
+                UnifiedError::from_system_error(SystemError::new_details(
+                    SystemErrorType::ErrorReadingFile,
+                    &format!("Failed to read the signing key {}: {}", file_location, e),
+                ))
+            })?
+            .replace("\n", "");
+
+        let decrypted_key: String = match Commands::DecryptText(encrypted_key).execute()? {
+            Some(d) => d.replace("\0", ""),
+            None => {
+                return Err(UnifiedError::from_ais_error(AisError::CryptFailed(Some(
+                    "No data returned decrypting the signing key".to_owned(),
+                ))))
+            }
+        };
+        let key: Vec<u8> = hex::decode(decrypted_key.trim()).map_err(|e| {
+            UnifiedError::from_ais_error(AisError::CryptFailed(Some(format!(
+                "The signing key isn't valid hex: {}",
+                e
+            ))))
+        })?;
+
+        Ok(SigningKey::new(key))
+    }
+
+    /// Loads the key at `SIGNING_KEY_PATH` for `current` to hand out. Only root can read it,
+    /// so call this at startup before any thread drops its privileges.
+    pub fn init() -> Result<&'static SigningKey, UnifiedError> {
+        if let Some(key) = SIGNING_KEY.get() {
+            return Ok(key);
+        }
+        let key: SigningKey = Self::load(&PathType::Str(SIGNING_KEY_PATH.into()))?;
+        Ok(SIGNING_KEY.get_or_init(|| key))
+    }
+
+    /// The key loaded by `init`.
+    pub fn current() -> Result<&'static SigningKey, UnifiedError> {
+        SIGNING_KEY.get().ok_or_else(|| {
+            UnifiedError::from_ais_error(AisError::CryptFailed(Some(
+                "The signing key wasn't loaded at startup".to_owned(),
+            )))
+        })
+    }
+
+    fn mac(&self, data: &str) -> HmacSha256 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.key)
+            .expect("hmac accepts keys of any length");
+        mac.update(data.as_bytes());
+        mac
+    }
+
+    /// Returns the hex encoded signature of the data.
+    pub fn sign(&self, data: &str) -> String {
+        hex::encode(self.mac(data).finalize().into_bytes())
+    }
+
+    /// Appends the data's signature to it, making the frame sent over the wire.
+    pub fn seal(&self, data: &str) -> String {
+        format!("{}{}{}", data, SIGNATURE_SEPARATOR, self.sign(data))
+    }
+
+    /// Checks a sealed frame's signature, returning the data it covers.
+    pub fn open<'a>(&self, frame: &'a str) -> Result<&'a str, UnifiedError> {
+        let (data, signature) = frame
+            .trim()
+            .rsplit_once(SIGNATURE_SEPARATOR)
+            .ok_or_else(|| {
+                UnifiedError::from_ais_error(AisError::SignatureInvalid(Some(
+                    "The frame isn't signed".to_owned(),
+                )))
+            })?;
+        let signature: Vec<u8> = hex::decode(signature).map_err(|_| {
+            UnifiedError::from_ais_error(AisError::SignatureInvalid(Some(
+                "The frame's signature isn't valid hex".to_owned(),
+            )))
+        })?;
+
+        // verify_slice compares in constant time
+        match self.mac(data).verify_slice(&signature) {
+            Ok(_) => Ok(data),
+            Err(_) => Err(UnifiedError::from_ais_error(AisError::SignatureInvalid(
+                Some("The frame's signature doesn't match its contents".to_owned()),
+            ))),
+        }
+    }
+}
+
+impl Heartbeat {
+    /// Creates a new Heartbeat for the given machine.
+    pub fn new(machine_id: String) -> Self {
+        Heartbeat { machine_id }
+    }
+
+    /// Builds the signed frame sent over the wire, unsigned heartbeats could be forged to
+    /// hide a client that's down.
+    pub fn frame(&self, key: &SigningKey) -> String {
+        key.seal(&format!("{}{}", HEARTBEAT_PREFIX, self.machine_id))
+    }
+
+    /// Parses the data of a received frame once its signature is checked, returning None if
+    /// it isn't a heartbeat.
+    pub fn parse(data: &str) -> Option<Self> {
+        data.trim()
+            .strip_prefix(HEARTBEAT_PREFIX)
+            .filter(|machine_id| !machine_id.is_empty())
+            .map(|machine_id| Heartbeat::new(machine_id.to_owned()))
+    }
+
+    /// Sends the heartbeat to the messaging server.
+    pub fn send(&self) -> Result<(), UnifiedError> {
+        let key: &SigningKey = SigningKey::current()?;
+        send_frame(
+            &MailEndpoint::current(),
+            &self.frame(key),
+            "heartbeat.send()",
+        )
+    }
+}
+
 impl EmailSecure {
     /// Creates a new EmailSecure instance by encrypting the provided email.
     pub fn new(email: Email) -> Result<Self, UnifiedError> {
-        if !email.is_valid() {
-            return Err(UnifiedError::from_ais_error(AisError::new(
-                "Invalid Email Data",
-            )));
-        }
+        Self::new_with(email, &DusaCipher)
+    }
 
-        let plain_email_data = format!("{}-=-{}", email.subject, email.body);
-        let encrypted_data = match Commands::execute(&Commands::EncryptText(plain_email_data)) {
+    /// Creates a new EmailSecure instance by encrypting the provided email with the given cipher.
+    pub fn new_with(email: Email, cipher: &dyn Cipher) -> Result<Self, UnifiedError> {
+        let plain_email_data: String = Self::plain_data(email)?;
+        let id: String = Self::create_id(&plain_email_data);
+        let encrypted_data = match cipher.encrypt(&plain_email_data) {
             Ok(Some(d)) => d,
             Ok(None) => {
                 return Err(UnifiedError::from_ais_error(AisError::new(
                     "No data was provided to encrypt",
                 )))
             }
-            Err(e) => return Err(e.into()),
+            Err(e) => return Err(e),
         };
 
         Ok(EmailSecure {
             data: encrypted_data,
+            id,
+        })
+    }
+
+    /// Creates a new EmailSecure instance without encrypting the provided email, for notices
+    /// over a trusted internal link that shouldn't depend on dusa being up.
+    /// Categories that require encryption are refused.
+    pub fn new_plain(email: Email) -> Result<Self, UnifiedError> {
+        if email.category.requires_encryption() {
+            return Err(UnifiedError::from_ais_error(AisError::new(format!(
+                "{} emails can't be sent unencrypted",
+                email.category
+            ))));
+        }
+        let plain_email_data: String = Self::plain_data(email)?;
+
+        Ok(EmailSecure {
+            id: Self::create_id(&plain_email_data),
+            data: format!("{}{}", PLAIN_PREFIX, hex::encode(plain_email_data)),
         })
     }
 
-    /// Sends the encrypted email data over a TCP stream.
+    /// Labels and validates the email, then lays it out the way the mail server reads it.
+    fn plain_data(email: Email) -> Result<String, UnifiedError> {
+        let email: Email = match AisInfo::new_cached() {
+            Ok(ais_info) => email.prepare(&ais_info),
+            Err(_) => email,
+        };
+        if !email.is_valid() {
+            return Err(UnifiedError::from_ais_error(AisError::new(
+                "Invalid Email Data",
+            )));
+        }
+
+        Ok(format!(
+            "{}-=-{}-=-{}-=-{}",
+            email.subject, email.body, email.priority, email.category
+        ))
+    }
+
+    /// Hashes the email with the time it was created, the same alert raised twice gets two ids.
+    fn create_id(plain_email_data: &str) -> String {
+        let created: u128 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or_default();
+        create_hash(format!("{}{}", plain_email_data, created))
+    }
+
+    /// Builds the signed frame sent over the wire.
+    pub fn frame(&self, key: &SigningKey) -> String {
+        key.seal(&format!("{}{}{}", self.data, EMAIL_ID_SEPARATOR, self.id))
+    }
+
+    /// Signs and sends the encrypted email data to the messaging server.
     pub fn send(&self) -> Result<(), UnifiedError> {
-        let mut stream = match TcpStream::connect("10.1.0.11:1827") {
-            Ok(d) => d,
-            Err(_) => {
-                return Err(UnifiedError::AisError(
-                    ErrorInfo::with_severity(
-                        Caller::Impl(true, Some("secure_message.send()".to_owned())),
-                        Severity::NotFatal,
-                    ),
-                    AisError::EtNoHome(Some("Unable to contact messaging server".to_owned())),
-                ))
-            }
-            // Err(e) => return Err(UnifiedError::from_ais_error(AisError::new(&e.to_string()))),
+        let key: &SigningKey = SigningKey::current()?;
+        self.send_to(&MailEndpoint::current(), key)
+    }
+
+    /// Signs the encrypted email data with the key and sends it to the given endpoint.
+    pub fn send_to(&self, endpoint: &MailEndpoint, key: &SigningKey) -> Result<(), UnifiedError> {
+        send_frame(endpoint, &self.frame(key), "secure_message.send()")
+    }
+}
+
+impl MailEndpoint {
+    /// Reads an endpoint, `unix:` selects a socket and anything else is a TCP address.
+    pub fn parse(endpoint: &str) -> Self {
+        let endpoint: &str = endpoint.trim();
+        match endpoint.strip_prefix("unix:") {
+            Some(path) => MailEndpoint::Unix(PathBuf::from(path.trim_start_matches("//"))),
+            None => MailEndpoint::Tcp(endpoint.trim_start_matches("tcp://").to_owned()),
+        }
+    }
+
+    /// The endpoint in the config, or the profile's messaging server when it isn't set.
+    pub fn current() -> Self {
+        match AisConfig::new()
+            .ok()
+            .and_then(|config| config.mail_endpoint)
+        {
+            Some(endpoint) => Self::parse(&endpoint),
+            None => MailEndpoint::Tcp(Profile::current().endpoints().aggregator_address.to_owned()),
+        }
+    }
+
+    /// Checks the messaging server is listening on the endpoint, giving up on a TCP address
+    /// after the timeout.
+    pub fn check_reachable(&self, timeout: Duration) -> Result<(), UnifiedError> {
+        let result: io::Result<()> = match self {
+            MailEndpoint::Tcp(address) => address.to_socket_addrs().and_then(|addresses| {
+                let mut result: io::Result<()> = Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "the address didn't resolve",
+                ));
+                for address in addresses {
+                    result = TcpStream::connect_timeout(&address, timeout).map(|_| ());
+                    if result.is_ok() {
+                        break;
+                    }
+                }
+                result
+            }),
+            MailEndpoint::Unix(path) => UnixStream::connect(path).map(|_| ()),
         };
-        match stream.write_all(self.data.as_bytes()) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(UnifiedError::from_ais_error(AisError::new(&e.to_string()))),
+
+        result.map_err(|e| {
+            UnifiedError::from_ais_error(AisError::EtNoHome(Some(format!(
+                "Unable to reach the messaging server at {}: {}",
+                self, e
+            ))))
+        })
+    }
+
+    /// Opens a connection to the endpoint.
+    pub fn connect(&self) -> io::Result<Box<dyn Write>> {
+        Ok(match self {
+            MailEndpoint::Tcp(address) => Box::new(TcpStream::connect(address)?),
+            MailEndpoint::Unix(path) => Box::new(UnixStream::connect(path)?),
+        })
+    }
+}
+
+impl fmt::Display for MailEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MailEndpoint::Tcp(address) => write!(f, "{}", address),
+            MailEndpoint::Unix(path) => write!(f, "unix:{}", path.display()),
         }
     }
 }
 
+/// Writes a frame to the messaging server.
+fn send_frame(endpoint: &MailEndpoint, data: &str, caller: &str) -> Result<(), UnifiedError> {
+    let mut stream = match endpoint.connect() {
+        Ok(d) => d,
+        Err(_) => {
+            return Err(UnifiedError::AisError(
+                ErrorInfo::with_severity(
+                    Caller::Impl(true, Some(caller.to_owned())),
+                    Severity::NotFatal,
+                ),
+                AisError::EtNoHome(Some("Unable to contact messaging server".to_owned())),
+            ))
+        } // Err(e) => return Err(UnifiedError::from_ais_error(AisError::new(&e.to_string()))),
+    };
+    match stream.write_all(data.as_bytes()) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(UnifiedError::from_ais_error(AisError::with_context(
+            "writing to the messaging server",
+            e,
+        ))),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::health::Health;
+    use crate::test_support::MockCipher;
+
+    #[test]
+    fn test_mail_endpoint_parse() {
+        assert_eq!(
+            MailEndpoint::parse("unix:/run/ais/mail.sock"),
+            MailEndpoint::Unix(PathBuf::from("/run/ais/mail.sock"))
+        );
+        assert_eq!(
+            MailEndpoint::parse("unix:///run/ais/mail.sock"),
+            MailEndpoint::Unix(PathBuf::from("/run/ais/mail.sock"))
+        );
+        assert_eq!(
+            MailEndpoint::parse("tcp://10.1.0.11:1827"),
+            MailEndpoint::Tcp("10.1.0.11:1827".to_owned())
+        );
+        assert_eq!(
+            MailEndpoint::parse("10.1.0.11:1827"),
+            MailEndpoint::Tcp("10.1.0.11:1827".to_owned())
+        );
+    }
 
     #[test]
     fn test_email_new() {
         let email = Email::new("Subject".to_string(), "Body".to_string());
         assert_eq!(email.subject, "Subject");
         assert_eq!(email.body, "Body");
+        assert_eq!(email.priority, EmailPriority::Low);
+    }
+
+    #[test]
+    fn test_email_priority_parse() {
+        assert_eq!(
+            EmailPriority::parse(&EmailPriority::High.to_string()),
+            EmailPriority::High
+        );
+        assert_eq!(
+            EmailPriority::parse(&EmailPriority::Low.to_string()),
+            EmailPriority::Low
+        );
+        assert_eq!(EmailPriority::parse("garbage"), EmailPriority::Low);
+    }
+
+    #[test]
+    fn test_email_category_parse() {
+        assert_eq!(
+            EmailCategory::parse(&EmailCategory::SshAudit.to_string()),
+            Some(EmailCategory::SshAudit)
+        );
+        assert_eq!(EmailCategory::parse("garbage"), None);
     }
 
     #[test]
@@ -111,6 +641,106 @@ mod tests {
         assert!(!invalid_email.is_valid());
     }
 
+    #[test]
+    fn test_email_prepare_label() {
+        let mut ais_info = AisInfo {
+            pages_id: None,
+            client_id: None,
+            machine_id: Some("a1b2c3d4e5f60718".to_string()),
+            machine_mac: None,
+            machine_ip: None,
+            label: None,
+            interfaces: Vec::new(),
+            ssh_events: 0,
+            health: Health::new(),
+            system_version: AisInfo::current_version(),
+        };
+
+        let email = Email::new("Subject".to_string(), "Body".to_string());
+        let prepared = email.clone().prepare(&ais_info);
+        assert_eq!(prepared.subject, "Subject");
+        assert_eq!(prepared.body, "Body");
+
+        ais_info.label = Some("cust-acme-web01".to_string());
+        let prepared = email.prepare(&ais_info);
+        assert_eq!(prepared.subject, "[cust-acme-web01] Subject");
+        assert_eq!(
+            prepared.body,
+            "Body\n\nMachine: cust-acme-web01 (a1b2c3d4e5f60718)"
+        );
+    }
+
+    #[test]
+    fn test_heartbeat_frame_round_trip() {
+        let key = SigningKey::new(b"shared secret".to_vec());
+        let heartbeat = Heartbeat::new("a1b2c3d4".to_string());
+        let frame: String = heartbeat.frame(&key);
+        let parsed = Heartbeat::parse(key.open(&frame).unwrap());
+        assert_eq!(parsed, Some(heartbeat));
+
+        let forger = SigningKey::new(b"guessed secret".to_vec());
+        assert!(key
+            .open(&Heartbeat::new("a1b2c3d4".to_string()).frame(&forger))
+            .is_err());
+    }
+
+    #[test]
+    fn test_heartbeat_parse_rejects_other_frames() {
+        assert_eq!(Heartbeat::parse("deadbeef"), None);
+        assert_eq!(Heartbeat::parse(HEARTBEAT_PREFIX), None);
+    }
+
+    #[test]
+    fn test_emailsecure_new_plain() {
+        let email = Email::new("Subject".to_string(), "Body".to_string());
+        let email_secure = EmailSecure::new_plain(email).unwrap();
+        assert!(email_secure.data.starts_with(PLAIN_PREFIX));
+
+        let mut audit = Email::new("Subject".to_string(), "Body".to_string());
+        audit.category = EmailCategory::SshAudit;
+        assert!(EmailSecure::new_plain(audit).is_err());
+    }
+
+    #[test]
+    fn test_signing_key_open_valid_frame() {
+        let key = SigningKey::new(b"shared secret".to_vec());
+        let frame = key.seal("AIS-PLAIN:deadbeef");
+        assert_eq!(key.open(&frame).unwrap(), "AIS-PLAIN:deadbeef");
+    }
+
+    #[test]
+    fn test_signing_key_rejects_tampering() {
+        let key = SigningKey::new(b"shared secret".to_vec());
+        let frame = key.seal("AIS-PLAIN:deadbeef");
+
+        let tampered = frame.replacen("deadbeef", "deadbeee", 1);
+        assert!(key.open(&tampered).is_err());
+        assert!(key.open("AIS-PLAIN:deadbeef").is_err());
+
+        let forger = SigningKey::new(b"guessed secret".to_vec());
+        assert!(key.open(&forger.seal("AIS-PLAIN:deadbeef")).is_err());
+    }
+
+    #[test]
+    fn test_emailsecure_new_with_mock_cipher() {
+        let cipher = MockCipher::new();
+        let email = Email::new("Subject".to_string(), "Body".to_string());
+        let email_secure = EmailSecure::new_with(email, &cipher).unwrap();
+
+        assert_eq!(cipher.calls.get(), 1);
+        assert!(!email_secure.id.is_empty());
+        let plain = hex::decode(cipher.decrypt(&email_secure.data).unwrap().unwrap()).unwrap();
+        assert!(plain.starts_with(b"Subject-=-Body-=-"));
+    }
+
+    #[test]
+    fn test_emailsecure_new_rejects_invalid_email() {
+        let cipher = MockCipher::new();
+        let email = Email::new(String::new(), "Body".to_string());
+        assert!(EmailSecure::new_with(email, &cipher).is_err());
+        assert_eq!(cipher.calls.get(), 0);
+    }
+
     #[cfg(feature = "dusa")]
     #[test]
     fn test_emailsecure_new() {
@@ -129,6 +759,7 @@ mod tests {
         let encrypted_data = "dummy_encrypted_data".to_string();
         let email_secure = EmailSecure {
             data: encrypted_data,
+            id: String::new(),
         };
 
         // Attempt to send the encrypted email
@@ -1,9 +1,12 @@
 use nix::unistd::{chown, Gid, Uid};
 use std::{
+    env,
     io::{Read, Write},
     os::unix::net::UnixStream,
     path::{Path, PathBuf},
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, OnceLock, RwLock},
+    thread,
+    time::{Duration, Instant},
 };
 use system::{
     create_hash,
@@ -13,10 +16,40 @@ use system::{
 use users::{Groups, Users, UsersCache};
 
 use crate::{
-    errors::{AisError, ErrorInfo, UnifiedError},
+    ais_config::AisConfig,
+    errors::{AisError, Caller, ErrorInfo, UnifiedError},
+    log_file::{notice, warn},
     service::{ProcessInfo, Processes, Status},
 };
 
+/// Env var that turns dusa debugging on or off, overriding the config.
+pub const DUSA_DEBUG_VAR: &str = "AIS_DUSA_DEBUG";
+
+static DUSA_DEBUGGING: OnceLock<bool> = OnceLock::new();
+
+/// The backoff every dusa request goes through, shared so the loops back off together.
+static DUSA_AVAILABILITY: OnceLock<Mutex<DusaBackoff>> = OnceLock::new();
+
+/// How many times a request is tried before dusa is reported as unavailable.
+pub const DUSA_ATTEMPTS: u32 = 4;
+
+/// How long to wait before the first retry, it doubles after every failed attempt.
+pub const DUSA_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Marks a record that was encrypted before it was written to disk.
+pub const SEALED_PREFIX: &str = "AIS-SEALED:";
+
+/// Returns true if dusa requests should be traced. Only metadata is ever logged, never
+/// the plain text or cipher text.
+pub fn debugging_enabled() -> bool {
+    *DUSA_DEBUGGING.get_or_init(|| match env::var(DUSA_DEBUG_VAR) {
+        Ok(value) => matches!(value.trim(), "1" | "true" | "yes"),
+        Err(_) => AisConfig::new()
+            .map(|config| config.dusa_debugging)
+            .unwrap_or(false),
+    })
+}
+
 /// Represents a Dusa instance used for encryption and decryption operations.
 #[derive(Debug, Clone)]
 pub struct Dusa {
@@ -27,6 +60,17 @@ pub struct Dusa {
     pub process_status: Status,
 }
 
+/// Holds dusa requests back while encryption is unavailable, so a restarting dusa isn't
+/// hammered at loop speed. The wait doubles after every failed request up to the max, and
+/// resets once a request succeeds.
+#[derive(Debug)]
+pub struct DusaBackoff {
+    base: Duration,
+    max: Duration,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
 /// Represents commands that can be executed by Dusa.
 pub enum Commands {
     EncryptFile(PathBuf, String, String), // path, owner, name
@@ -36,30 +80,236 @@ pub enum Commands {
     RemoveFile(String, String),           // owner, name
 }
 
+/// Encrypts and decrypts text, tests swap in a mock so they don't depend on dusa.
+pub trait Cipher {
+    /// Encrypts the plain text, returning the cipher data.
+    fn encrypt(&self, plain_text: &str) -> Result<Option<String>, UnifiedError>;
+
+    /// Decrypts the cipher data, returning the hex encoded plain text.
+    fn decrypt(&self, cipher_data: &str) -> Result<Option<String>, UnifiedError>;
+}
+
+/// Encrypts and decrypts text through the dusa socket.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DusaCipher;
+
+impl Cipher for DusaCipher {
+    fn encrypt(&self, plain_text: &str) -> Result<Option<String>, UnifiedError> {
+        with_backoff(dusa_availability(), Instant::now(), || {
+            Commands::EncryptText(plain_text.to_owned()).execute()
+        })
+    }
+
+    fn decrypt(&self, cipher_data: &str) -> Result<Option<String>, UnifiedError> {
+        with_backoff(dusa_availability(), Instant::now(), || {
+            Commands::DecryptText(cipher_data.to_owned()).execute()
+        })
+    }
+}
+
+impl DusaBackoff {
+    /// Creates a new DusaBackoff with encryption available, waiting `base` after the first
+    /// failure and never more than `max`.
+    pub const fn new(base: Duration, max: Duration) -> Self {
+        DusaBackoff {
+            base,
+            max,
+            failures: 0,
+            retry_at: None,
+        }
+    }
+
+    /// Checks if a request can be made, while backing off it isn't sent at all.
+    pub fn ready(&self, now: Instant) -> bool {
+        self.retry_at.is_none_or(|retry_at| now >= retry_at)
+    }
+
+    /// Records a failed request, pushing the next one back. Returns true on the first failure
+    /// so encryption being unavailable is reported once.
+    pub fn failed(&mut self, now: Instant) -> bool {
+        let delay: Duration = self
+            .base
+            .saturating_mul(2u32.saturating_pow(self.failures))
+            .min(self.max);
+        self.failures += 1;
+        self.retry_at = Some(now + delay);
+        self.failures == 1
+    }
+
+    /// Records a successful request, returning how many failed before it.
+    pub fn succeeded(&mut self) -> u32 {
+        self.retry_at = None;
+        std::mem::take(&mut self.failures)
+    }
+}
+
+/// The shared backoff, its max wait is read from the config the first time it's used.
+fn dusa_availability() -> &'static Mutex<DusaBackoff> {
+    DUSA_AVAILABILITY.get_or_init(|| {
+        let max_secs: u64 = AisConfig::new()
+            .map(|config| config.dusa_backoff_max_secs)
+            .unwrap_or(300);
+        Mutex::new(DusaBackoff::new(
+            DUSA_BACKOFF,
+            Duration::from_secs(max_secs),
+        ))
+    })
+}
+
+/// Makes a dusa request through the backoff. While backing off an EncryptionNotReady error
+/// is returned without touching dusa. The lock isn't held during the request.
+pub fn with_backoff<T, F>(
+    backoff: &Mutex<DusaBackoff>,
+    now: Instant,
+    request: F,
+) -> Result<T, UnifiedError>
+where
+    F: FnOnce() -> Result<T, UnifiedError>,
+{
+    if !backoff.lock().unwrap_or_else(|e| e.into_inner()).ready(now) {
+        return Err(UnifiedError::from_ais_error(AisError::EncryptionNotReady(
+            Some(String::from(
+                "Encryption unavailable, backing off dusa requests",
+            )),
+        )));
+    }
+
+    let result = request();
+    let mut backoff = backoff.lock().unwrap_or_else(|e| e.into_inner());
+    match &result {
+        Ok(_) => {
+            let failures: u32 = backoff.succeeded();
+            if failures > 0 {
+                notice(&format!(
+                    "Encryption available again after {} failed requests",
+                    failures
+                ));
+            }
+        }
+        Err(e) => {
+            if backoff.failed(now) {
+                warn(&format!(
+                    "Encryption unavailable, backing off dusa requests: {}",
+                    e
+                ));
+            }
+        }
+    }
+    result
+}
+
+/// Prepares a record for writing to disk. Without a cipher it's written as is, with one it's
+/// encrypted first. When encrypting fails a sensitive record is refused, returning None,
+/// rather than written in plain text. Other records fall back to plain text.
+pub fn seal_record(
+    record: &str,
+    sensitive: bool,
+    cipher: Option<&dyn Cipher>,
+) -> Result<Option<String>, UnifiedError> {
+    let cipher: &dyn Cipher = match cipher {
+        Some(cipher) => cipher,
+        None => return Ok(Some(record.to_owned())),
+    };
+
+    match cipher.encrypt(record) {
+        Ok(Some(cipher_data)) => Ok(Some(format!("{}{}", SEALED_PREFIX, cipher_data))),
+        Ok(None) | Err(_) if sensitive => Ok(None),
+        Ok(None) => Ok(Some(record.to_owned())),
+        Err(e) => {
+            warn(&format!(
+                "Writing a record in plain text, encrypting it failed: {}",
+                e
+            ));
+            Ok(Some(record.to_owned()))
+        }
+    }
+}
+
+/// Reads back a record written by `seal_record`, records that weren't sealed are returned as is.
+pub fn open_record(line: &str, cipher: &dyn Cipher) -> Result<String, UnifiedError> {
+    let cipher_data: &str = match line.strip_prefix(SEALED_PREFIX) {
+        Some(cipher_data) => cipher_data,
+        None => return Ok(line.to_owned()),
+    };
+    let invalid = |detail: String| {
+        UnifiedError::from_ais_error(AisError::EncryptionNotReady(Some(format!(
+            "Failed to open a sealed record: {}",
+            detail
+        ))))
+    };
+
+    let plain_hex: String = cipher
+        .decrypt(cipher_data)?
+        .ok_or_else(|| invalid(String::from("nothing was decrypted")))?;
+    let plain: Vec<u8> = hex::decode(plain_hex.trim()).map_err(|e| invalid(e.to_string()))?;
+    String::from_utf8(plain).map_err(|e| invalid(e.to_string()))
+}
+
+/// Decrypts the cipher data, retrying with backoff so dusa restarting doesn't fail the caller.
+/// Once the attempts run out an EncryptionNotReady error is returned.
+pub fn decrypt_with_retry(
+    cipher: &dyn Cipher,
+    cipher_data: &str,
+    backoff: Duration,
+) -> Result<Option<String>, UnifiedError> {
+    let mut delay: Duration = backoff;
+    let mut attempt: u32 = 1;
+    loop {
+        match cipher.decrypt(cipher_data) {
+            Ok(data) => return Ok(data),
+            Err(e) if attempt >= DUSA_ATTEMPTS => {
+                return Err(UnifiedError::AisError(
+                    ErrorInfo::new(Caller::Function(
+                        true,
+                        Some("decrypt_with_retry".to_owned()),
+                    )),
+                    AisError::EncryptionNotReady(Some(format!(
+                        "Encryption backend unavailable after {} attempts: {}",
+                        attempt, e
+                    ))),
+                ))
+            }
+            Err(e) => {
+                warn(&format!(
+                    "dusa request failed, attempt {} of {}: {}",
+                    attempt, DUSA_ATTEMPTS, e
+                ));
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
 impl Dusa {
     /// Initializes a new Dusa instance.
     pub fn initialize(process_info: Arc<RwLock<Processes>>) -> Result<Self, UnifiedError> {
         let system_process_info = process_info
             .read()
-            .map_err(|e| UnifiedError::from_ais_error(AisError::new(&e.to_string())))?;
+            .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))?;
         let dusa_process_info = system_process_info.itr();
         let dusa_data: &ProcessInfo = dusa_process_info
             .get(5)
             .ok_or_else(|| AisError::new("Dusad system status unknown"))?;
         let service_name = dusa_data.service.clone();
         let socket_path = PathType::Str("/var/run/dusa/dusa.sock".into());
-        let debugging = true;
+        let debugging = debugging_enabled();
         let process_status = dusa_data.status.clone();
 
-        match &process_status {
-            Status::Error => {
-                return Err(AisError::EncryptionNotReady(Some(format!(
+        // A dusa in error counts against the backoff like a failed request
+        with_backoff(
+            dusa_availability(),
+            Instant::now(),
+            || match &process_status {
+                Status::Error => Err(AisError::EncryptionNotReady(Some(format!(
                     "Service: {} is not running or is in an unknown state",
                     &service_name
-                ))).into());
-            }
-            _ => (),
-        };
+                )))
+                .into()),
+                _ => Ok(()),
+            },
+        )?;
 
         if !path_present(&socket_path.clone_path())? {
             return Err(AisError::EncryptionNotReady(Some(format!(
@@ -91,36 +341,33 @@ impl Commands {
                 let (uid, gid) = Self::get_id();
                 Self::set_file_ownership(path, uid, gid);
 
-                let mut command_data: Vec<String> = vec![];
-                command_data.push(String::from("insert"));
-                command_data.push(owner.to_owned());
-                command_data.push(name.to_owned());
-                command_data.push(path.clone().into_os_string().into_string().unwrap());
+                let command_data: Vec<String> = vec![
+                    String::from("insert"),
+                    owner.to_owned(),
+                    name.to_owned(),
+                    path.clone().into_os_string().into_string().unwrap(),
+                ];
 
                 let message: String = Self::create_message(command_data);
 
-                let response = Self::send_message(message)?;
+                let response = Self::send_traced("insert", message)?;
                 Ok(Some(response))
             }
             Commands::DecryptFile(_, _) => Ok(None),
             Commands::DecryptText(cipher_data) => {
-                let mut command_data: Vec<String> = vec![];
-                command_data.push("0x011".to_owned());
-                command_data.push(cipher_data.to_owned());
+                let command_data: Vec<String> = vec!["0x011".to_owned(), cipher_data.to_owned()];
 
                 let message: String = Self::create_message(command_data);
 
-                let response: String = Self::send_message(message)?;
+                let response: String = Self::send_traced("0x011", message)?;
                 Ok(Some(response))
             }
             Commands::EncryptText(data) => {
-                let mut command_data: Vec<String> = vec![];
-                command_data.push("0x001".to_owned());
-                command_data.push(data.to_owned());
+                let command_data: Vec<String> = vec!["0x001".to_owned(), data.to_owned()];
 
                 let message: String = Self::create_message(command_data);
 
-                let response = Self::send_message(message)?;
+                let response = Self::send_traced("0x001", message)?;
                 Ok(Some(response))
             }
             Commands::RemoveFile(_, _) => Ok(None),
@@ -136,13 +383,38 @@ impl Commands {
         let hexed_hash: String =
             hex::encode(truncate(&create_hash(hexed_command.clone())[7..], 50));
 
-        let mut secure_command_array: Vec<String> = vec![];
-        secure_command_array.push(hexed_command);
-        secure_command_array.push(hexed_hash);
+        let secure_command_array: Vec<String> = vec![hexed_command, hexed_hash];
 
         secure_command_array.join("Z")
     }
 
+    /// Sends a message to dusa, logging its size and timing when debugging is enabled.
+    fn send_traced(opcode: &str, command: String) -> Result<String, UnifiedError> {
+        if !debugging_enabled() {
+            return Self::send_message(command);
+        }
+
+        let sent_bytes: usize = command.len();
+        let started = Instant::now();
+        let result = Self::send_message(command);
+        let elapsed = started.elapsed().as_millis();
+
+        match &result {
+            Ok(response) => notice(&format!(
+                "dusa {}: sent {} bytes, received {} bytes in {}ms",
+                opcode,
+                sent_bytes,
+                response.len(),
+                elapsed
+            )),
+            Err(_) => warn(&format!(
+                "dusa {}: sent {} bytes, failed after {}ms",
+                opcode, sent_bytes, elapsed
+            )),
+        }
+        result
+    }
+
     fn send_message(command: String) -> Result<String, UnifiedError> {
         let socket_path: &Path = Path::new("/var/run/dusa/dusa.sock");
 
@@ -179,11 +451,45 @@ impl Commands {
     }
 }
 
-#[cfg(feature = "dusa")]
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_failures_back_off_exponentially() {
+        let backoff = Mutex::new(DusaBackoff::new(
+            Duration::from_millis(500),
+            Duration::from_secs(4),
+        ));
+        let start = Instant::now();
+        let mut attempts: Vec<Duration> = Vec::new();
+
+        // Retry at loop speed for 20 seconds with dusa down
+        for tick in 0..2000 {
+            let now = start + Duration::from_millis(tick * 10);
+            let _ = with_backoff(&backoff, now, || -> Result<(), UnifiedError> {
+                attempts.push(now - start);
+                Err(UnifiedError::from_ais_error(AisError::EncryptionNotReady(
+                    None,
+                )))
+            });
+        }
+
+        let seconds: Vec<f64> = attempts.iter().map(|at| at.as_secs_f64()).collect();
+        assert_eq!(
+            seconds,
+            vec![0.0, 0.5, 1.5, 3.5, 7.5, 11.5, 15.5, 19.5],
+            "the wait doubles from half a second up to the max"
+        );
+
+        // One success resets the wait
+        let now = start + Duration::from_secs(30);
+        with_backoff(&backoff, now, || Ok(())).unwrap();
+        assert!(backoff.lock().unwrap().ready(now));
+        assert!(backoff.lock().unwrap().failed(now));
+    }
+
+    #[cfg(feature = "dusa")]
     #[test]
     fn test_decrypt_text() {
         let cipher_data = "32393566616261616365666662613064666565333261366634383830633634653d3330333132643532333132653330326533313264363333303339333533353635333736333632363233383336363433303334363436323331363336363264333132393264353533313939386432383330613135366262356439363437643262614e6f766836783252554f32744b545853333330656663343565393161616262366134613031356434626166623461613934376134356538313661653762623863353130656339393666336563633164633d31";
@@ -194,6 +500,7 @@ mod tests {
         assert!(result.is_some());
     }
 
+    #[cfg(feature = "dusa")]
     #[test]
     fn test_encrypt_text() {
         let plain_text = "test_plain_text";
@@ -2,11 +2,13 @@ use chrono::{DateTime, Utc};
 use logging::errors::LoggerError;
 use pretty::output;
 use recs::errors::RecsError;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
 use std::{fmt, io, process::ExitStatus, str::Utf8Error};
 use system::errors::SystemError;
+use crate::time;
 
 /// Enum representing the severity level of an error.
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize)]
 pub enum Severity {
     /// Indicates a fatal error, causing the program to terminate.
     Fatal,
@@ -106,18 +108,18 @@ impl<T> UnifiedErrorResult<T> {
         UnifiedErrorResult(result)
     }
 
-    /// Unwraps the result, panicking if it contains an error.
+    /// Unwraps the result, exiting if it contains an error.
     ///
     /// # Panics
     ///
-    /// Panics with a message containing information about the error if it is `Err`.
+    /// Exits with the code from `UnifiedError::exit_code` after printing the error if it is `Err`.
     pub fn unwrap(self) -> T {
         // self.0.unwrap()
         match self.0 {
             Ok(d) => d,
             Err(err) => {
-                output("RED", &format!("UnifiedError: {}", err.to_string()));
-                std::process::exit(700);
+                output("RED", &format!("UnifiedError: {}", err));
+                std::process::exit(err.exit_code());
             },
         }
     }
@@ -298,6 +300,51 @@ impl UnifiedError {
         let error_info = ErrorInfo::new(Caller::Library(false, None));
         UnifiedError::AisError(error_info, error)
     }
+
+    /// Returns the process exit code for this error, so init scripts and monitoring can tell
+    /// failures apart.
+    ///
+    /// | Code | Failure                                      |
+    /// |------|----------------------------------------------|
+    /// | 10   | Logger error                                 |
+    /// | 11   | System error                                 |
+    /// | 12   | Recs error                                   |
+    /// | 13   | Git command error                            |
+    /// | 14   | Git isn't installed                          |
+    /// | 20   | Credentials are missing or invalid           |
+    /// | 21   | Dusa isn't ready or encryption failed        |
+    /// | 22   | The manifest is invalid                      |
+    /// | 23   | The messaging server couldn't be reached     |
+    /// | 24   | Permission denied                            |
+    /// | 25   | First run failed                             |
+    /// | 26   | Git action failed                            |
+    /// | 27   | An external command timed out                |
+    /// | 29   | Any other AIS error                          |
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            UnifiedError::LoggerError(_, _) => 10,
+            UnifiedError::SystemError(_, _) => 11,
+            UnifiedError::RecsError(_, _) => 12,
+            UnifiedError::GitError(_, GitError::GitNotInstalled) => 14,
+            UnifiedError::GitError(_, _) => 13,
+            UnifiedError::AisError(_, error) => match error {
+                AisError::NoCredentials(_)
+                | AisError::GitCredentialsInvalid(_)
+                | AisError::GitCredentialsUnknown(_) => 20,
+                AisError::EncryptionNotReady(_) | AisError::CryptFailed(_) => 21,
+                AisError::InvalidManifest(_) => 22,
+                AisError::EtNoHome(_) => 23,
+                AisError::PermissionDenied(_) => 24,
+                AisError::FirstRun(_) => 25,
+                AisError::GitCommandFailed(_)
+                | AisError::GitInvalidRelease(_)
+                | AisError::GitInvalidCommit(_)
+                | AisError::GitNetworkError(_) => 26,
+                AisError::CommandTimedOut(_) => 27,
+                _ => 29,
+            },
+        }
+    }
 }
 
 /// Enum representing different types of Ais errors.
@@ -349,6 +396,16 @@ pub enum AisError {
     NoCredentials(Option<String>),
     /// When we can't connect to the messagging server
     EtNoHome(Option<String>),
+    /// When the system doesn't have permission to act on a path
+    PermissionDenied(Option<String>),
+    /// When an external command didn't finish in time and was killed
+    CommandTimedOut(Option<String>),
+    /// When the smtp relay can't be reached
+    SmtpConnectionFailed(Option<String>),
+    /// When the smtp relay rejects our credentials
+    SmtpAuthFailed(Option<String>),
+    /// When a message's signature is missing or doesn't match its contents
+    SignatureInvalid(Option<String>),
 }
 
 impl AisError {
@@ -357,6 +414,46 @@ impl AisError {
         AisError::SystemError(Some(description.into()))
     }
 
+    /// Creates a new AisError saying which operation failed and why, as
+    /// "while <operation>: <source>".
+    pub fn with_context(operation: &str, source: impl fmt::Display) -> AisError {
+        AisError::new(format!("while {}: {}", operation, source))
+    }
+
+    /// Returns the name of the variant, the `kind` in the serialized error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AisError::SshFlaggedUser(_) => "SshFlaggedUser",
+            AisError::SshUnknownUser(_) => "SshUnknownUser",
+            AisError::SshUnflaggedUser(_) => "SshUnflaggedUser",
+            AisError::ThreadedDataError(_) => "ThreadedDataError",
+            AisError::ThreadedDataNotPopulated(_) => "ThreadedDataNotPopulated",
+            AisError::SiteInfoInvalid(_) => "SiteInfoInvalid",
+            AisError::SiteInitializationFailed(_) => "SiteInitializationFailed",
+            AisError::SiteFailed(_) => "SiteFailed",
+            AisError::GitCommandFailed(_) => "GitCommandFailed",
+            AisError::GitCredentialsInvalid(_) => "GitCredentialsInvalid",
+            AisError::GitCredentialsUnknown(_) => "GitCredentialsUnknown",
+            AisError::GitInvalidRelease(_) => "GitInvalidRelease",
+            AisError::GitInvalidCommit(_) => "GitInvalidCommit",
+            AisError::GitNetworkError(_) => "GitNetworkError",
+            AisError::CryptFailed(_) => "CryptFailed",
+            AisError::UpdateError(_) => "UpdateError",
+            AisError::UpToDate(_) => "UpToDate",
+            AisError::SystemError(_) => "SystemError",
+            AisError::EncryptionNotReady(_) => "EncryptionNotReady",
+            AisError::FirstRun(_) => "FirstRun",
+            AisError::InvalidManifest(_) => "InvalidManifest",
+            AisError::NoCredentials(_) => "NoCredentials",
+            AisError::EtNoHome(_) => "EtNoHome",
+            AisError::PermissionDenied(_) => "PermissionDenied",
+            AisError::CommandTimedOut(_) => "CommandTimedOut",
+            AisError::SmtpConnectionFailed(_) => "SmtpConnectionFailed",
+            AisError::SmtpAuthFailed(_) => "SmtpAuthFailed",
+            AisError::SignatureInvalid(_) => "SignatureInvalid",
+        }
+    }
+
     /// Returns the description of the AisError.
     pub fn description(&self) -> &str {
         match self {
@@ -382,6 +479,11 @@ impl AisError {
             | AisError::NoCredentials(desc)
             | AisError::EncryptionNotReady(desc)
             | AisError::EtNoHome(desc)
+            | AisError::PermissionDenied(desc)
+            | AisError::CommandTimedOut(desc)
+            | AisError::SmtpConnectionFailed(desc)
+            | AisError::SmtpAuthFailed(desc)
+            | AisError::SignatureInvalid(desc)
             | AisError::FirstRun(desc) => {
                 desc.as_deref().unwrap_or("An unspecified error occurred")
             }
@@ -402,9 +504,22 @@ pub enum GitError {
     // Warning(GitWarning),
     /// Git not installed error.
     GitNotInstalled,
+    /// A pull couldn't merge the remote into local changes, with what git reported.
+    MergeConflict(String),
 }
 
 impl GitError {
+    /// Returns the name of the variant, the `kind` in the serialized error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GitError::CommandFailed(_) => "CommandFailed",
+            GitError::IoError(_) => "IoError",
+            GitError::Utf8Error(_) => "Utf8Error",
+            GitError::GitNotInstalled => "GitNotInstalled",
+            GitError::MergeConflict(_) => "MergeConflict",
+        }
+    }
+
     /// Returns the description of the GitError.
     pub fn description(&self) -> &str {
         match self {
@@ -412,6 +527,7 @@ impl GitError {
             GitError::IoError(_) => "IO error",
             GitError::Utf8Error(_) => "UTF-8 error",
             GitError::GitNotInstalled => "Git is not installed",
+            GitError::MergeConflict(_) => "Git pull hit a merge conflict",
         }
     }
 }
@@ -452,8 +568,8 @@ impl fmt::Display for Severity {
 impl fmt::Display for TimestampType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            TimestampType::CurrentTime(dt) => write!(f, "Current Time: {}", dt),
-            TimestampType::_Custom(dt) => write!(f, "Custom Timestamp: {}", dt),
+            TimestampType::CurrentTime(dt) => write!(f, "Current Time: {}", time::format(*dt)),
+            TimestampType::_Custom(dt) => write!(f, "Custom Timestamp: {}", time::format(*dt)),
         }
     }
 }
@@ -500,10 +616,66 @@ impl fmt::Display for GitError {
             GitError::Utf8Error(_) => write!(f, "UTF-8 error"),
             // GitError::Warning(_) => write!(f, "Git warning"),
             GitError::GitNotInstalled => write!(f, "Git is not installed"),
+            GitError::MergeConflict(_) => write!(f, "Git pull hit a merge conflict"),
         }
     }
 }
 
+/// Serialized as `{"type":"AisError","kind":"GitNetworkError","message":"...","severity":"Fatal"}`.
+/// The kind of library errors whose types we don't know is null.
+impl Serialize for UnifiedError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (error_type, kind, message, info): (&str, Option<String>, String, &ErrorInfo) =
+            match self {
+                UnifiedError::LoggerError(info, error) => {
+                    ("LoggerError", None, error.to_string(), info)
+                }
+                UnifiedError::SystemError(info, error) => (
+                    "SystemError",
+                    Some(format!("{:?}", error.kind)),
+                    error.to_string(),
+                    info,
+                ),
+                UnifiedError::RecsError(info, error) => {
+                    ("RecsError", None, error.to_string(), info)
+                }
+                UnifiedError::GitError(info, error) => {
+                    let message: String = match error {
+                        GitError::CommandFailed(status) => format!("{}: {}", error, status),
+                        GitError::IoError(e) => format!("{}: {}", error, e),
+                        GitError::Utf8Error(e) => format!("{}: {}", error, e),
+                        GitError::GitNotInstalled => error.to_string(),
+                        GitError::MergeConflict(details) => format!("{}: {}", error, details),
+                    };
+                    ("GitError", Some(error.kind().to_owned()), message, info)
+                }
+                UnifiedError::AisError(info, error) => (
+                    "AisError",
+                    Some(error.kind().to_owned()),
+                    error.description().to_owned(),
+                    info,
+                ),
+            };
+
+        let mut state = serializer.serialize_struct("UnifiedError", 4)?;
+        state.serialize_field("type", error_type)?;
+        state.serialize_field("kind", &kind)?;
+        state.serialize_field("message", &message)?;
+        state.serialize_field("severity", &info.severity)?;
+        state.end()
+    }
+}
+
+/// Serialized as `{"kind":"GitNetworkError","message":"..."}`.
+impl Serialize for AisError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("AisError", 2)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", self.description())?;
+        state.end()
+    }
+}
+
 impl fmt::Display for ErrorInfo {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -513,3 +685,320 @@ impl fmt::Display for ErrorInfo {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use recs::errors::RecsErrorType;
+    use std::os::unix::process::ExitStatusExt;
+    use system::errors::SystemErrorType;
+
+    #[test]
+    fn test_exit_codes() {
+        let system =
+            UnifiedError::from_system_error(SystemError::new(SystemErrorType::ErrorOpeningFile));
+        let recs = UnifiedError::from_recs_error(RecsError::new_details(
+            RecsErrorType::Error,
+            "No data returned",
+        ));
+        assert_eq!(system.exit_code(), 11);
+        assert_eq!(recs.exit_code(), 12);
+        assert_eq!(
+            UnifiedError::from_git_error(GitError::CommandFailed(ExitStatus::from_raw(1)))
+                .exit_code(),
+            13
+        );
+        assert_eq!(
+            UnifiedError::from_git_error(GitError::GitNotInstalled).exit_code(),
+            14
+        );
+
+        let ais_codes = [
+            (AisError::NoCredentials(None), 20),
+            (AisError::GitCredentialsInvalid(None), 20),
+            (AisError::EncryptionNotReady(None), 21),
+            (AisError::CryptFailed(None), 21),
+            (AisError::InvalidManifest(None), 22),
+            (AisError::EtNoHome(None), 23),
+            (AisError::PermissionDenied(None), 24),
+            (AisError::FirstRun(None), 25),
+            (AisError::GitCommandFailed(None), 26),
+            (AisError::CommandTimedOut(None), 27),
+            (AisError::new("generic"), 29),
+        ];
+        for (error, code) in ais_codes {
+            assert_eq!(UnifiedError::from_ais_error(error).exit_code(), code);
+        }
+    }
+
+    #[test]
+    fn test_with_context_message() {
+        let source = io::Error::new(io::ErrorKind::NotFound, "No such file or directory");
+        let error = AisError::with_context("opening the manifest", source);
+        assert_eq!(
+            error.to_string(),
+            "while opening the manifest: No such file or directory"
+        );
+    }
+
+    #[test]
+    fn test_serialize_errors() {
+        let serialized = |error: UnifiedError| serde_json::to_value(&error).unwrap();
+
+        assert_eq!(
+            serialized(UnifiedError::from_ais_error(AisError::GitNetworkError(
+                Some("github is down".to_owned())
+            ))),
+            serde_json::json!({
+                "type": "AisError",
+                "kind": "GitNetworkError",
+                "message": "github is down",
+                "severity": "Fatal",
+            })
+        );
+        assert_eq!(
+            serialized(UnifiedError::from_git_error(GitError::CommandFailed(
+                ExitStatus::from_raw(256)
+            ))),
+            serde_json::json!({
+                "type": "GitError",
+                "kind": "CommandFailed",
+                "message": "Git command failed: exit status: 1",
+                "severity": "Fatal",
+            })
+        );
+
+        let system = serialized(UnifiedError::from_system_error(SystemError::new(
+            SystemErrorType::ErrorOpeningFile,
+        )));
+        assert_eq!(system["type"], "SystemError");
+        assert_eq!(system["kind"], "ErrorOpeningFile");
+
+        let recs = serialized(UnifiedError::from_recs_error(RecsError::new_details(
+            RecsErrorType::Error,
+            "No data returned",
+        )));
+        assert_eq!(recs["type"], "RecsError");
+        assert!(recs["kind"].is_null());
+        assert!(recs["message"].is_string());
+
+        assert_eq!(
+            serde_json::to_value(Severity::NotFatal).unwrap(),
+            serde_json::json!("NotFatal")
+        );
+    }
+
+    /// How many variants AisError has.
+    const AIS_ERROR_VARIANTS: usize = 28;
+
+    /// One constructor per AisError variant, in the order of `ais_error_slot`.
+    const EVERY_AIS_ERROR: [fn(Option<String>) -> AisError; AIS_ERROR_VARIANTS] = [
+        AisError::SshFlaggedUser,
+        AisError::SshUnknownUser,
+        AisError::SshUnflaggedUser,
+        AisError::ThreadedDataError,
+        AisError::ThreadedDataNotPopulated,
+        AisError::SiteInfoInvalid,
+        AisError::SiteInitializationFailed,
+        AisError::SiteFailed,
+        AisError::GitCommandFailed,
+        AisError::GitCredentialsInvalid,
+        AisError::GitCredentialsUnknown,
+        AisError::GitInvalidRelease,
+        AisError::GitInvalidCommit,
+        AisError::GitNetworkError,
+        AisError::CryptFailed,
+        AisError::UpdateError,
+        AisError::UpToDate,
+        AisError::SystemError,
+        AisError::EncryptionNotReady,
+        AisError::FirstRun,
+        AisError::InvalidManifest,
+        AisError::NoCredentials,
+        AisError::EtNoHome,
+        AisError::PermissionDenied,
+        AisError::CommandTimedOut,
+        AisError::SmtpConnectionFailed,
+        AisError::SmtpAuthFailed,
+        AisError::SignatureInvalid,
+    ];
+
+    /// The position of the variant in `EVERY_AIS_ERROR` and the kind it serializes as. There's
+    /// no wildcard, so a new variant doesn't compile until it's given a slot here, and the
+    /// tests fail until it's listed above.
+    fn ais_error_slot(error: &AisError) -> (usize, &'static str) {
+        match error {
+            AisError::SshFlaggedUser(_) => (0, "SshFlaggedUser"),
+            AisError::SshUnknownUser(_) => (1, "SshUnknownUser"),
+            AisError::SshUnflaggedUser(_) => (2, "SshUnflaggedUser"),
+            AisError::ThreadedDataError(_) => (3, "ThreadedDataError"),
+            AisError::ThreadedDataNotPopulated(_) => (4, "ThreadedDataNotPopulated"),
+            AisError::SiteInfoInvalid(_) => (5, "SiteInfoInvalid"),
+            AisError::SiteInitializationFailed(_) => (6, "SiteInitializationFailed"),
+            AisError::SiteFailed(_) => (7, "SiteFailed"),
+            AisError::GitCommandFailed(_) => (8, "GitCommandFailed"),
+            AisError::GitCredentialsInvalid(_) => (9, "GitCredentialsInvalid"),
+            AisError::GitCredentialsUnknown(_) => (10, "GitCredentialsUnknown"),
+            AisError::GitInvalidRelease(_) => (11, "GitInvalidRelease"),
+            AisError::GitInvalidCommit(_) => (12, "GitInvalidCommit"),
+            AisError::GitNetworkError(_) => (13, "GitNetworkError"),
+            AisError::CryptFailed(_) => (14, "CryptFailed"),
+            AisError::UpdateError(_) => (15, "UpdateError"),
+            AisError::UpToDate(_) => (16, "UpToDate"),
+            AisError::SystemError(_) => (17, "SystemError"),
+            AisError::EncryptionNotReady(_) => (18, "EncryptionNotReady"),
+            AisError::FirstRun(_) => (19, "FirstRun"),
+            AisError::InvalidManifest(_) => (20, "InvalidManifest"),
+            AisError::NoCredentials(_) => (21, "NoCredentials"),
+            AisError::EtNoHome(_) => (22, "EtNoHome"),
+            AisError::PermissionDenied(_) => (23, "PermissionDenied"),
+            AisError::CommandTimedOut(_) => (24, "CommandTimedOut"),
+            AisError::SmtpConnectionFailed(_) => (25, "SmtpConnectionFailed"),
+            AisError::SmtpAuthFailed(_) => (26, "SmtpAuthFailed"),
+            AisError::SignatureInvalid(_) => (27, "SignatureInvalid"),
+        }
+    }
+
+    #[test]
+    fn test_serialize_every_ais_error() {
+        for (index, make) in EVERY_AIS_ERROR.iter().enumerate() {
+            let message: String = format!("details {}", index);
+            let (slot, kind) = ais_error_slot(&make(None));
+            assert_eq!(slot, index, "{} is out of place", kind);
+
+            assert_eq!(
+                serde_json::to_value(make(Some(message.clone()))).unwrap(),
+                serde_json::json!({ "kind": kind, "message": message })
+            );
+            assert_eq!(
+                serde_json::to_value(make(None)).unwrap(),
+                serde_json::json!({ "kind": kind, "message": "An unspecified error occurred" })
+            );
+            assert_eq!(
+                serde_json::to_value(UnifiedError::from_ais_error(make(Some(message.clone()))))
+                    .unwrap(),
+                serde_json::json!({
+                    "type": "AisError",
+                    "kind": kind,
+                    "message": message,
+                    "severity": "Fatal",
+                })
+            );
+        }
+    }
+
+    /// The kind and message a GitError serializes with, there's no wildcard so a new variant
+    /// doesn't compile until it's covered here.
+    fn git_error_expected(error: &GitError) -> (&'static str, String) {
+        match error {
+            GitError::CommandFailed(_) => (
+                "CommandFailed",
+                "Git command failed: exit status: 1".to_owned(),
+            ),
+            GitError::IoError(_) => ("IoError", "IO error: gone".to_owned()),
+            GitError::Utf8Error(_) => (
+                "Utf8Error",
+                "UTF-8 error: invalid utf-8 sequence of 1 bytes from index 0".to_owned(),
+            ),
+            GitError::GitNotInstalled => ("GitNotInstalled", "Git is not installed".to_owned()),
+            GitError::MergeConflict(_) => (
+                "MergeConflict",
+                "Git pull hit a merge conflict: CONFLICT in index.html".to_owned(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_serialize_every_git_error() {
+        let utf8_error = String::from_utf8(vec![0xff]).unwrap_err().utf8_error();
+        let git_errors = vec![
+            GitError::CommandFailed(ExitStatus::from_raw(256)),
+            GitError::IoError(io::Error::new(io::ErrorKind::NotFound, "gone")),
+            GitError::Utf8Error(utf8_error),
+            GitError::GitNotInstalled,
+            GitError::MergeConflict("CONFLICT in index.html".to_owned()),
+        ];
+        let mut kinds: Vec<&str> = Vec::new();
+        for error in git_errors {
+            let (kind, message) = git_error_expected(&error);
+            assert_eq!(error.kind(), kind);
+            kinds.push(kind);
+            assert_eq!(
+                serde_json::to_value(UnifiedError::from_git_error(error)).unwrap(),
+                serde_json::json!({
+                    "type": "GitError",
+                    "kind": kind,
+                    "message": message,
+                    "severity": "Fatal",
+                })
+            );
+        }
+        kinds.sort_unstable();
+        kinds.dedup();
+        assert_eq!(kinds.len(), 5);
+    }
+
+    /// The type a UnifiedError serializes as, there's no wildcard so a new wrapper doesn't
+    /// compile until it's covered here.
+    fn unified_error_type(error: &UnifiedError) -> &'static str {
+        match error {
+            UnifiedError::LoggerError(_, _) => "LoggerError",
+            UnifiedError::SystemError(_, _) => "SystemError",
+            UnifiedError::RecsError(_, _) => "RecsError",
+            UnifiedError::GitError(_, _) => "GitError",
+            UnifiedError::AisError(_, _) => "AisError",
+        }
+    }
+
+    #[test]
+    fn test_serialize_every_unified_error() {
+        // LoggerErrors only come out of the logging crate, so there's no sample of one here
+        let system = SystemError::new(SystemErrorType::ErrorOpeningFile);
+        let system_message: String = system.to_string();
+        let recs = RecsError::new_details(RecsErrorType::Error, "No data returned");
+        let recs_message: String = recs.to_string();
+        let samples = vec![
+            (
+                UnifiedError::from_system_error(system),
+                serde_json::json!("ErrorOpeningFile"),
+                system_message,
+            ),
+            (
+                UnifiedError::from_recs_error(recs),
+                serde_json::Value::Null,
+                recs_message,
+            ),
+            (
+                UnifiedError::from_git_error(GitError::GitNotInstalled),
+                serde_json::json!("GitNotInstalled"),
+                "Git is not installed".to_owned(),
+            ),
+            (
+                UnifiedError::from_ais_error(AisError::EtNoHome(Some("refused".to_owned()))),
+                serde_json::json!("EtNoHome"),
+                "refused".to_owned(),
+            ),
+        ];
+        for (error, kind, message) in samples {
+            let error_type: &str = unified_error_type(&error);
+            assert_eq!(
+                serde_json::to_value(&error).unwrap(),
+                serde_json::json!({
+                    "type": error_type,
+                    "kind": kind,
+                    "message": message,
+                    "severity": "Fatal",
+                })
+            );
+        }
+
+        let warning = UnifiedError::AisError(
+            ErrorInfo::with_severity(Caller::Function(true, None), Severity::Warning),
+            AisError::UpToDate(None),
+        );
+        assert_eq!(
+            serde_json::to_value(&warning).unwrap()["severity"],
+            "Warning"
+        );
+    }
+}
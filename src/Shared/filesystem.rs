@@ -0,0 +1,67 @@
+use std::{fs, io::ErrorKind};
+
+use crate::errors::{AisError, Caller, ErrorInfo, UnifiedError};
+use system::{
+    errors::{SystemError, SystemErrorType},
+    PathType,
+};
+
+/// Creates a directory along with any missing parents.
+///
+/// Returns `Ok(true)` when the directory was created and `Ok(false)` when it already existed.
+/// Permission problems are reported as `AisError::PermissionDenied` so they can be told apart
+/// from other failures.
+pub fn make_dir_all(path: &PathType) -> Result<bool, UnifiedError> {
+    if path.as_ref().is_dir() {
+        return Ok(false);
+    }
+
+    match fs::create_dir_all(path) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => Err(UnifiedError::AisError(
+            ErrorInfo::new(Caller::Function(true, Some("make_dir_all".to_owned()))),
+            AisError::PermissionDenied(Some(format!(
+                "Permission denied creating: {}",
+                path.display()
+            ))),
+        )),
+        Err(e) => Err(UnifiedError::from_system_error(SystemError::new_details(
+            SystemErrorType::ErrorCreatingDir,
+            &format!("Failed creating {}: {}", path.display(), e),
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_make_dir_all_creates_parents() {
+        let root = env::temp_dir().join("ais_make_dir_all");
+        let _ = fs::remove_dir_all(&root);
+        let deep = PathType::PathBuf(root.join("var/www/current/a1b2c3d4"));
+
+        assert!(make_dir_all(&deep).unwrap());
+        assert!(deep.as_ref().is_dir());
+
+        // Running again reports the directory already existed
+        assert!(!make_dir_all(&deep).unwrap());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_make_dir_all_file_in_the_way() {
+        let root = env::temp_dir().join("ais_make_dir_all_file");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("blocker"), b"").unwrap();
+
+        let result = make_dir_all(&PathType::PathBuf(root.join("blocker/child")));
+        assert!(matches!(result, Err(UnifiedError::SystemError(_, _))));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
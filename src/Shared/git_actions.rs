@@ -1,29 +1,70 @@
 use std::{
-    os::unix::process::ExitStatusExt,
+    fs::{self, File, OpenOptions},
+    os::unix::{io::AsRawFd, process::ExitStatusExt},
+    path::{Path, PathBuf},
     process::{Command, ExitStatus},
+    time::{Duration, Instant},
 };
 
+use crate::command::{run_with_timeout, CommandRunner, DEFAULT_COMMAND_TIMEOUT};
 use crate::errors::{AisError, Caller, ErrorInfo, GitError, UnifiedError};
+use nix::fcntl::{flock, FlockArg};
 use system::{path_present, PathType};
 
+/// The longest commit message accepted, in bytes.
+pub const MAX_COMMIT_MESSAGE_BYTES: usize = 64 * 1024;
+
 /// Function to check if Git is installed.
 fn check_git_installed() -> Result<(), UnifiedError> {
-    let output: std::process::Output = match Command::new("git").arg("--version").output() {
-        Ok(output) => output,
-        Err(io_err) => {
-            return Err(UnifiedError::from_ais_error(AisError::new(
-                &io_err.to_string(),
-            )))
-        }
-    };
-
-    if output.status.success() {
+    if execute_git_capture(&["--version"])?.success() {
         Ok(())
     } else {
         Err(UnifiedError::from_git_error(GitError::GitNotInstalled))
     }
 }
 
+/// Enum representing the state of a directory we want to clone into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloneDestination {
+    /// Nothing exists at the path.
+    Missing,
+    /// The directory exists but has nothing in it.
+    Empty,
+    /// The directory holds a usable checkout.
+    Repository,
+    /// The directory has contents but no usable checkout, usually left by a failed clone.
+    Stale,
+}
+
+/// Inspects a clone destination to decide if it can be used as is.
+pub fn inspect_clone_destination(destination: &PathType) -> Result<CloneDestination, UnifiedError> {
+    let path = destination.as_ref();
+    if !path.exists() {
+        return Ok(CloneDestination::Missing);
+    }
+
+    let mut entries = fs::read_dir(path).map_err(|e| {
+        UnifiedError::from_ais_error(AisError::new(format!(
+            "Failed to read {}: {}",
+            destination.display(),
+            e
+        )))
+    })?;
+    if entries.next().is_none() {
+        return Ok(CloneDestination::Empty);
+    }
+
+    if !path.join(".git").exists() {
+        return Ok(CloneDestination::Stale);
+    }
+
+    // A clone that died part way through has a .git folder but no commit checked out
+    match execute_git_hash_command(&["-C", destination.to_str().unwrap(), "rev-parse", "HEAD"]) {
+        Ok(_) => Ok(CloneDestination::Repository),
+        Err(_) => Ok(CloneDestination::Stale),
+    }
+}
+
 /// Enum representing Git actions.
 #[derive(Debug)]
 pub enum GitAction {
@@ -38,6 +79,11 @@ pub enum GitAction {
     Push {
         directory: PathType,
     },
+    // git init, or git init --bare for a repo that's only pushed to
+    Init {
+        directory: PathType,
+        bare: bool,
+    },
     Stage {
         directory: PathType,
         files: Vec<String>,
@@ -53,12 +99,175 @@ pub enum GitAction {
     },
     // git config --global --add safe.directory /var/www/current/path
     SetSafe(PathType),
+    // git branch --set-upstream-to=origin/branch branch
+    SetUpstream {
+        branch: String,
+        destination: PathType,
+    },
+    // git fetch --tags --force
+    Tags {
+        destination: PathType,
+    },
+    // git checkout refs/tags/tag
+    CheckoutTag {
+        tag: String,
+        destination: PathType,
+    },
+    // git reset --hard origin/branch, discards local changes
+    Reset {
+        target_branch: String,
+        destination: PathType,
+    },
+    // git clean -fd, or -nd to only list what would be removed. Ignored files are kept
+    Clean {
+        directory: PathType,
+        dry_run: bool,
+    },
+}
+
+/// Struct representing a commit on the upstream that a pull would bring in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncomingCommit {
+    /// The abbreviated commit hash.
+    pub hash: String,
+    /// The first line of the commit message.
+    pub summary: String,
+}
+
+/// Struct representing what a git command printed and how it exited, the parsers read this
+/// rather than running git themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitOutput {
+    /// What the command printed to stdout.
+    pub stdout: String,
+    /// What the command printed to stderr.
+    pub stderr: String,
+    /// How the command exited.
+    pub status: ExitStatus,
+}
+
+/// Struct representing how long a git action took to run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitTiming {
+    /// The name of the action that ran.
+    pub action: &'static str,
+    /// The directory the action ran against.
+    pub target: String,
+    /// Wall clock time the action took.
+    pub elapsed: Duration,
+}
+
+/// An exclusive lock on a site's checkout, held from `acquire` until it's dropped. It's a
+/// flock so it also keeps out the other processes deploying to the box, like `ais_git_clone`.
+#[derive(Debug)]
+pub struct SiteLock {
+    _file: File,
 }
 
 impl GitAction {
+    /// The name of the action, used when reporting on it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            GitAction::Clone { .. } => "clone",
+            GitAction::Pull { .. } => "pull",
+            GitAction::Push { .. } => "push",
+            GitAction::Init { .. } => "init",
+            GitAction::Stage { .. } => "stage",
+            GitAction::Commit { .. } => "commit",
+            GitAction::CheckRemoteAhead(_) => "check remote ahead",
+            GitAction::Switch { .. } => "switch",
+            GitAction::SetSafe(_) => "set safe",
+            GitAction::SetUpstream { .. } => "set upstream",
+            GitAction::Tags { .. } => "tags",
+            GitAction::CheckoutTag { .. } => "checkout tag",
+            GitAction::Reset { .. } => "reset",
+            GitAction::Clean { .. } => "clean",
+        }
+    }
+
+    /// The directory the action works on. The repo url isn't used since it can hold a token.
+    pub fn target(&self) -> String {
+        let directory: &PathType = match self {
+            GitAction::Clone { destination, .. } => destination,
+            GitAction::Pull { destination, .. } => destination,
+            GitAction::Push { directory } => directory,
+            GitAction::Init { directory, .. } => directory,
+            GitAction::Stage { directory, .. } => directory,
+            GitAction::Commit { directory, .. } => directory,
+            GitAction::CheckRemoteAhead(directory) => directory,
+            GitAction::Switch { destination, .. } => destination,
+            GitAction::SetSafe(directory) => directory,
+            GitAction::SetUpstream { destination, .. } => destination,
+            GitAction::Tags { destination } => destination,
+            GitAction::CheckoutTag { destination, .. } => destination,
+            GitAction::Reset { destination, .. } => destination,
+            GitAction::Clean { directory, .. } => directory,
+        };
+        directory.to_string()
+    }
+
+    /// Whether the action changes the checkout, those hold the site's lock while they run.
+    pub fn mutates(&self) -> bool {
+        match self {
+            GitAction::Clone { .. }
+            | GitAction::Pull { .. }
+            | GitAction::Init { .. }
+            | GitAction::Stage { .. }
+            | GitAction::Commit { .. }
+            | GitAction::Switch { .. }
+            | GitAction::SetUpstream { .. }
+            | GitAction::Tags { .. }
+            | GitAction::CheckoutTag { .. }
+            | GitAction::Reset { .. } => true,
+            GitAction::Clean { dry_run, .. } => !dry_run,
+            GitAction::Push { .. } | GitAction::CheckRemoteAhead(_) | GitAction::SetSafe(_) => {
+                false
+            }
+        }
+    }
+
+    /// Execute the Git action, returning how long it took alongside the result.
+    pub fn execute_timed(&self) -> Result<(bool, GitTiming), UnifiedError> {
+        self.timed(|| self.execute())
+    }
+
+    /// Like `execute_timed`, for an action run under a `SiteLock` the caller holds.
+    pub fn execute_timed_held(&self, lock: &SiteLock) -> Result<(bool, GitTiming), UnifiedError> {
+        self.timed(|| self.execute_held(lock))
+    }
+
+    fn timed<F>(&self, execute: F) -> Result<(bool, GitTiming), UnifiedError>
+    where
+        F: FnOnce() -> Result<bool, UnifiedError>,
+    {
+        let started: Instant = Instant::now();
+        let result: bool = execute()?;
+        let timing = GitTiming {
+            action: self.name(),
+            target: self.target(),
+            elapsed: started.elapsed(),
+        };
+        Ok((result, timing))
+    }
+
     /// Execute the Git action.
     pub fn execute(&self) -> Result<bool, UnifiedError> {
         check_git_installed()?;
+        let _lock: Option<SiteLock> = match self.mutates() {
+            true => Some(SiteLock::acquire(Path::new(&self.target()))?),
+            false => None,
+        };
+        self.run()
+    }
+
+    /// Execute the Git action under the `SiteLock` the caller already holds on its target, so
+    /// a series of actions runs as one. Taking the lock again here would wait on the caller.
+    pub fn execute_held(&self, _lock: &SiteLock) -> Result<bool, UnifiedError> {
+        check_git_installed()?;
+        self.run()
+    }
+
+    fn run(&self) -> Result<bool, UnifiedError> {
         match self {
             GitAction::Clone {
                 repo_url,
@@ -72,13 +281,21 @@ impl GitAction {
                 destination,
             } => {
                 path_present(destination)?;
-                execute_git_command(&["-C", destination.to_str().unwrap(), "pull"])?;
+                execute_git_pull(destination)?;
                 execute_git_command(&["-C", destination.to_str().unwrap(), "switch", target_branch])
             }
             GitAction::Push { directory } => {
                 path_present(directory)?;
                 execute_git_command(&["-C", directory.to_str().unwrap(), "push"])
             }
+            GitAction::Init { directory, bare } => {
+                let mut args = vec!["init"];
+                if *bare {
+                    args.push("--bare");
+                }
+                args.push(directory.to_str().unwrap());
+                execute_git_command(&args)
+            }
             GitAction::Stage { directory, files } => {
                 path_present(directory)?;
                 let mut args = vec!["-C", directory.to_str().unwrap(), "add"];
@@ -86,8 +303,17 @@ impl GitAction {
                 execute_git_command(&args)
             }
             GitAction::Commit { directory, message } => {
+                validate_commit_message(message)?;
                 path_present(directory)?;
-                execute_git_command(&["-C", directory.to_str().unwrap(), "commit", "-m", message])
+                // Only whitespace is cleaned up, by default lines starting with # are dropped
+                execute_git_command(&[
+                    "-C",
+                    directory.to_str().unwrap(),
+                    "commit",
+                    "--cleanup=whitespace",
+                    "-m",
+                    message,
+                ])
             }
             GitAction::CheckRemoteAhead(directory) => {
                 path_present(directory)?;
@@ -101,22 +327,207 @@ impl GitAction {
                 "config --global --add safe.directory",
                 directory.to_str().unwrap(),
             ]),
+            GitAction::SetUpstream {
+                branch,
+                destination,
+            } => {
+                path_present(destination)?;
+                execute_git_command(&[
+                    "-C",
+                    destination.to_str().unwrap(),
+                    "branch",
+                    &format!("--set-upstream-to=origin/{}", branch),
+                    branch,
+                ])
+            }
+            GitAction::Tags { destination } => {
+                path_present(destination)?;
+                execute_git_command(&[
+                    "-C",
+                    destination.to_str().unwrap(),
+                    "fetch",
+                    "--tags",
+                    "--force",
+                ])
+            }
+            GitAction::CheckoutTag { tag, destination } => {
+                path_present(destination)?;
+                execute_git_command(&[
+                    "-C",
+                    destination.to_str().unwrap(),
+                    "-c",
+                    "advice.detachedHead=false",
+                    "checkout",
+                    &format!("refs/tags/{}", tag),
+                ])
+            }
+            GitAction::Reset {
+                target_branch,
+                destination,
+            } => {
+                path_present(destination)?;
+                execute_git_command(&[
+                    "-C",
+                    destination.to_str().unwrap(),
+                    "reset",
+                    "--hard",
+                    &format!("origin/{}", target_branch),
+                ])
+            }
+            GitAction::Clean { directory, dry_run } => {
+                path_present(directory)?;
+                execute_git_command(&[
+                    "-C",
+                    directory.to_str().unwrap(),
+                    "clean",
+                    match dry_run {
+                        true => "-nd",
+                        false => "-fd",
+                    },
+                ])
+            }
         }
     }
 }
 
-/// Execute a Git command.
-fn execute_git_command(args: &[&str]) -> Result<bool, UnifiedError> {
-    let output: std::process::Output = match Command::new("git").args(args).output() {
-        Ok(output) => output,
-        Err(io_err) => {
-            return Err(UnifiedError::from_ais_error(AisError::new(
-                &io_err.to_string(),
-            )))
+impl SiteLock {
+    /// Waits for the site's lock and takes it. The lock file sits next to the checkout rather
+    /// than in it, so a clone has an empty folder to go into and `git clean` leaves it alone.
+    /// The folder the checkout goes in is created when it's missing.
+    pub fn acquire(site: &Path) -> Result<Self, UnifiedError> {
+        let path: PathBuf = Self::lock_path(site);
+        let lock_error = |e: String| {
+            UnifiedError::from_ais_error(AisError::SiteFailed(Some(format!(
+                "Failed to lock {}: {}",
+                path.display(),
+                e
+            ))))
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| lock_error(e.to_string()))?;
         }
+        // Opened for reading when it's there, the file may belong to whoever deployed first
+        let file: File = match path.exists() {
+            true => File::open(&path),
+            false => OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path),
+        }
+        .map_err(|e| lock_error(e.to_string()))?;
+        flock(file.as_raw_fd(), FlockArg::LockExclusive).map_err(|e| lock_error(e.to_string()))?;
+
+        Ok(SiteLock { _file: file })
+    }
+
+    /// The lock file of the site, `/var/www/current/<hash>.lock` for a site checked out to
+    /// `/var/www/current/<hash>`.
+    pub fn lock_path(site: &Path) -> PathBuf {
+        let site: &Path = match site.to_str() {
+            Some(path) => Path::new(path.trim_end_matches('/')),
+            None => site,
+        };
+        let mut path = site.to_path_buf().into_os_string();
+        path.push(".lock");
+        PathBuf::from(path)
+    }
+}
+
+/// Checks the message can be committed as is: it has some text, no nul bytes, which can't be
+/// passed to git, and is no longer than `MAX_COMMIT_MESSAGE_BYTES`.
+fn validate_commit_message(message: &str) -> Result<(), UnifiedError> {
+    let problem: Option<String> = if message.trim().is_empty() {
+        Some(String::from("The commit message is empty"))
+    } else if message.contains('\0') {
+        Some(String::from("The commit message contains a nul byte"))
+    } else if message.len() > MAX_COMMIT_MESSAGE_BYTES {
+        Some(format!(
+            "The commit message is {} bytes, the most accepted is {}",
+            message.len(),
+            MAX_COMMIT_MESSAGE_BYTES
+        ))
+    } else {
+        None
     };
 
-    if output.status.success() {
+    match problem {
+        Some(problem) => Err(UnifiedError::AisError(
+            ErrorInfo::new(Caller::Function(
+                true,
+                Some("validate_commit_message".to_owned()),
+            )),
+            AisError::GitInvalidCommit(Some(problem)),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Pulls into the checkout, a pull that fails because of local changes is reported as a
+/// MergeConflict so the caller can decide what wins.
+fn execute_git_pull(destination: &PathType) -> Result<bool, UnifiedError> {
+    let output: GitOutput = execute_git_capture(&["-C", destination.to_str().unwrap(), "pull"])?;
+    if output.success() {
+        return Ok(true);
+    }
+
+    // Merge conflicts are reported on stdout, the rest on stderr
+    let GitOutput { stdout, stderr, .. } = output;
+    match is_merge_conflict(&stdout) || is_merge_conflict(&stderr) {
+        true => Err(UnifiedError::GitError(
+            ErrorInfo::new(Caller::Function(true, Some("execute_git_pull".to_owned()))),
+            GitError::MergeConflict(
+                format!("{}\n{}", stdout.trim(), stderr.trim())
+                    .trim()
+                    .to_owned(),
+            ),
+        )),
+        false => Err(UnifiedError::AisError(
+            ErrorInfo::new(Caller::Function(true, Some("execute_git_pull".to_owned()))),
+            AisError::SystemError(Some(stderr)),
+        )),
+    }
+}
+
+/// Checks if git's output is about local changes or commits getting in the way of a merge.
+fn is_merge_conflict(output: &str) -> bool {
+    [
+        "CONFLICT (",
+        "Automatic merge failed",
+        "would be overwritten by merge",
+        "you have unmerged files",
+        "You have not concluded your merge",
+        "reconcile divergent branches",
+    ]
+    .iter()
+    .any(|marker| output.contains(marker))
+}
+
+impl GitOutput {
+    /// Whether the command exited with 0.
+    pub fn success(&self) -> bool {
+        self.status.success()
+    }
+}
+
+/// Runs git with the args and captures what it printed, killing it if it runs past
+/// `DEFAULT_COMMAND_TIMEOUT`. Exiting with an error isn't one here, check `success`.
+pub fn execute_git_capture(args: &[&str]) -> Result<GitOutput, UnifiedError> {
+    let output: std::process::Output =
+        run_with_timeout(Command::new("git").args(args), DEFAULT_COMMAND_TIMEOUT)?;
+    Ok(GitOutput {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        status: output.status,
+    })
+}
+
+/// Execute a Git command.
+fn execute_git_command(args: &[&str]) -> Result<bool, UnifiedError> {
+    let output: GitOutput = execute_git_capture(args)?;
+
+    if output.success() {
         Ok(true)
     } else {
         Err(UnifiedError::AisError(
@@ -124,8 +535,7 @@ fn execute_git_command(args: &[&str]) -> Result<bool, UnifiedError> {
                 true,
                 Some("execute_git_command".to_owned()),
             )),
-            AisError::SystemError(Some(String::from_utf8(output.stderr).unwrap())),
-            // AisError::SystemError(output.stderr),
+            AisError::SystemError(Some(output.stderr)),
         ))
     }
 }
@@ -152,23 +562,221 @@ fn check_remote_ahead(directory: &PathType) -> Result<bool, UnifiedError> {
     Ok(remote_hash != local_hash)
 }
 
+/// Fetches from the remote and lists the commits a pull would bring in, newest first.
+/// Nothing is merged, the checkout is left as it was.
+pub fn preview_pull(directory: &PathType) -> Result<Vec<IncomingCommit>, UnifiedError> {
+    path_present(directory)?;
+    execute_git_command(&["-C", directory.to_str().unwrap(), "fetch"])?;
+    let output: String = execute_git_hash_command(&[
+        "-C",
+        directory.to_str().unwrap(),
+        "log",
+        "--oneline",
+        "@..@{u}",
+    ])?;
+    Ok(parse_oneline_log(&output))
+}
+
+/// Lists the untracked files and directories a clean would remove, directories end with a
+/// slash. Nothing is removed.
+pub fn preview_clean(directory: &PathType) -> Result<Vec<String>, UnifiedError> {
+    path_present(directory)?;
+    let output: String =
+        execute_git_hash_command(&["-C", directory.to_str().unwrap(), "clean", "-nd"])?;
+    Ok(output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("Would remove "))
+        .map(|path| path.to_owned())
+        .collect())
+}
+
+/// Parses the `hash summary` lines printed by `git log --oneline`.
+fn parse_oneline_log(output: &str) -> Vec<IncomingCommit> {
+    output
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (hash, summary) = line.split_once(' ').unwrap_or((line, ""));
+            IncomingCommit {
+                hash: hash.to_owned(),
+                summary: summary.to_owned(),
+            }
+        })
+        .collect()
+}
+
+/// Fetch the commit hash currently checked out in the given directory.
+pub fn fetch_current_commit(directory: &PathType) -> Result<String, UnifiedError> {
+    path_present(directory)?;
+    execute_git_hash_command(&["-C", directory.to_str().unwrap(), "rev-parse", "HEAD"])
+}
+
+/// Checks if the checkout in the given directory is at a commit or tag rather than a branch.
+pub fn is_detached(directory: &PathType) -> Result<bool, UnifiedError> {
+    path_present(directory)?;
+    let output: GitOutput = execute_git_capture(&[
+        "-C",
+        directory.to_str().unwrap(),
+        "symbolic-ref",
+        "-q",
+        "HEAD",
+    ])?;
+    // symbolic-ref -q exits with 1 when HEAD isn't a branch, anything else is a real failure
+    match output.status.code() {
+        Some(0) => Ok(false),
+        Some(1) => Ok(true),
+        _ => Err(UnifiedError::from_git_error(GitError::CommandFailed(
+            output.status,
+        ))),
+    }
+}
+
+/// Fetch the upstream of the branch checked out in the given directory, None if it has none.
+pub fn fetch_upstream(directory: &PathType) -> Result<Option<String>, UnifiedError> {
+    path_present(directory)?;
+    let branch: String = execute_git_hash_command(&[
+        "-C",
+        directory.to_str().unwrap(),
+        "rev-parse",
+        "--abbrev-ref",
+        "HEAD",
+    ])?;
+    let upstream: String = execute_git_hash_command(&[
+        "-C",
+        directory.to_str().unwrap(),
+        "for-each-ref",
+        "--format=%(upstream:short)",
+        &format!("refs/heads/{}", branch),
+    ])?;
+    Ok(Some(upstream).filter(|upstream| !upstream.is_empty()))
+}
+
+/// Fetch the names of the tags matching the glob pattern in the given directory.
+pub fn fetch_tags(directory: &PathType, pattern: &str) -> Result<Vec<String>, UnifiedError> {
+    path_present(directory)?;
+    let output: String =
+        execute_git_hash_command(&["-C", directory.to_str().unwrap(), "tag", "--list", pattern])?;
+    Ok(output
+        .lines()
+        .map(|line| line.trim().to_owned())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+/// Fetch the commit a tag points at in the given directory.
+pub fn fetch_tag_commit(directory: &PathType, tag: &str) -> Result<String, UnifiedError> {
+    path_present(directory)?;
+    execute_git_hash_command(&[
+        "-C",
+        directory.to_str().unwrap(),
+        "rev-parse",
+        &format!("refs/tags/{}^{{commit}}", tag),
+    ])
+}
+
+/// Fetches the tags from the remote and returns the highest semver tag matching the pattern,
+/// if it isn't already checked out. Commits are compared since several tags can share one.
+pub fn check_tag_ahead(
+    directory: &PathType,
+    pattern: &str,
+) -> Result<Option<String>, UnifiedError> {
+    GitAction::Tags {
+        destination: directory.clone(),
+    }
+    .execute()?;
+    let tags: Vec<String> = fetch_tags(directory, pattern)?;
+    let latest: String = match latest_semver_tag(&tags) {
+        Some(latest) => latest.clone(),
+        None => return Ok(None),
+    };
+    match fetch_tag_commit(directory, &latest)? == fetch_current_commit(directory)? {
+        true => Ok(None),
+        false => Ok(Some(latest)),
+    }
+}
+
+/// Picks the highest version from a set of tags, tags that aren't a plain semver version like
+/// `v1.2.3` or `1.2` are ignored, so are pre-releases.
+pub fn latest_semver_tag(tags: &[String]) -> Option<&String> {
+    tags.iter()
+        .filter_map(|tag| parse_semver(tag).map(|version| (version, tag)))
+        .max_by_key(|(version, _)| *version)
+        .map(|(_, tag)| tag)
+}
+
+/// Parses a tag like `v1.2.3` into its major, minor and patch numbers.
+fn parse_semver(tag: &str) -> Option<(u64, u64, u64)> {
+    let version: &str = tag.strip_prefix('v').unwrap_or(tag);
+    let mut parts = version.split('.');
+    let major: u64 = parts.next()?.parse().ok()?;
+    let minor: u64 = match parts.next() {
+        Some(minor) => minor.parse().ok()?,
+        None => 0,
+    };
+    let patch: u64 = match parts.next() {
+        Some(patch) => patch.parse().ok()?,
+        None => 0,
+    };
+    match parts.next() {
+        Some(_) => None,
+        None => Some((major, minor, patch)),
+    }
+}
+
+/// Fetch the url of the origin remote in the given directory.
+pub fn fetch_remote_url(directory: &PathType) -> Result<String, UnifiedError> {
+    path_present(directory)?;
+    execute_git_hash_command(&[
+        "-C",
+        directory.to_str().unwrap(),
+        "remote",
+        "get-url",
+        "origin",
+    ])
+}
+
+/// Fetch the names of the local branches in the given directory.
+pub fn fetch_branches(directory: &PathType) -> Result<Vec<String>, UnifiedError> {
+    path_present(directory)?;
+    let output: String = execute_git_hash_command(&[
+        "-C",
+        directory.to_str().unwrap(),
+        "branch",
+        "--format=%(refname:short)",
+    ])?;
+    Ok(output
+        .lines()
+        .map(|line| line.trim().to_owned())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+/// Fetch the names of the branches on a remote, this works without a clone.
+pub fn fetch_remote_branches(
+    runner: &dyn CommandRunner,
+    repo_url: &str,
+) -> Result<Vec<String>, UnifiedError> {
+    let output: String = runner.run("git", &["ls-remote", "--heads", repo_url])?;
+    Ok(parse_remote_heads(&output))
+}
+
+/// Parses the `hash\trefs/heads/name` lines printed by `git ls-remote --heads`.
+fn parse_remote_heads(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .filter_map(|reference| reference.strip_prefix("refs/heads/"))
+        .map(|branch| branch.to_owned())
+        .collect()
+}
+
 /// Execute a Git hash command.
 fn execute_git_hash_command(args: &[&str]) -> Result<String, UnifiedError> {
-    let output: std::process::Output = match Command::new("git").args(args).output() {
-        Ok(output) => output,
-        Err(io_err) => {
-            return Err(UnifiedError::AisError(
-                ErrorInfo::new(Caller::Function(
-                    true,
-                    Some("execute_git_command_with_hash".to_owned()),
-                )),
-                AisError::GitCommandFailed(Some(io_err.to_string())),
-            ))
-        }
-    };
+    let output: GitOutput = execute_git_capture(args)?;
 
-    if output.status.success() {
-        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    if output.success() {
+        Ok(output.stdout.trim().to_string())
     } else {
         Err(UnifiedError::from_git_error(GitError::CommandFailed(
             output.status,
@@ -218,6 +826,520 @@ mod tests {
     //     assert_eq!(result, true);
     // }
 
+    #[test]
+    fn test_execute_timed() {
+        let directory = std::env::temp_dir().join("ais_git_timed");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("index.html"), b"<html></html>").unwrap();
+        let directory = PathType::PathBuf(directory);
+        execute_git_command(&["init", directory.to_str().unwrap()]).unwrap();
+
+        let (result, timing) = GitAction::Stage {
+            directory: directory.clone(),
+            files: vec!["index.html".to_owned()],
+        }
+        .execute_timed()
+        .unwrap();
+
+        assert!(result);
+        assert_eq!(timing.action, "stage");
+        assert_eq!(timing.target, directory.to_string());
+        assert!(timing.elapsed > Duration::ZERO);
+    }
+
+    /// Runs two threads that each hold the lock of their site for a while, returning when each
+    /// got and let go of it.
+    fn hold_site_locks(first: PathBuf, second: PathBuf) -> Vec<(Instant, Instant)> {
+        let handles: Vec<_> = [first, second]
+            .into_iter()
+            .map(|site| {
+                std::thread::spawn(move || {
+                    let lock = SiteLock::acquire(&site).unwrap();
+                    let acquired = Instant::now();
+                    std::thread::sleep(Duration::from_millis(200));
+                    let released = Instant::now();
+                    drop(lock);
+                    (acquired, released)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_site_lock_serializes_one_site() {
+        let root = std::env::temp_dir().join("ais_git_site_lock");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let overlaps =
+            |held: Vec<(Instant, Instant)>| held[0].0 < held[1].1 && held[1].0 < held[0].1;
+
+        let same = hold_site_locks(root.join("site_a"), root.join("site_a/"));
+        assert!(!overlaps(same));
+        assert!(root.join("site_a.lock").exists());
+
+        let different = hold_site_locks(root.join("site_a"), root.join("site_b"));
+        assert!(overlaps(different));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_capture_keeps_stdout_and_stderr_apart() {
+        let version = execute_git_capture(&["--version"]).unwrap();
+        assert!(version.success());
+        assert!(version.stdout.starts_with("git version"));
+        assert!(version.stderr.is_empty());
+
+        let missing = std::env::temp_dir().join("ais_git_capture_missing");
+        let _ = fs::remove_dir_all(&missing);
+        let failed = execute_git_capture(&["-C", missing.to_str().unwrap(), "status"]).unwrap();
+        assert!(!failed.success());
+        assert!(failed.stdout.is_empty());
+        assert!(failed.stderr.contains("ais_git_capture_missing"));
+    }
+
+    #[test]
+    fn test_init_creates_repo() {
+        let root = std::env::temp_dir().join("ais_git_init");
+        let _ = fs::remove_dir_all(&root);
+        let is_bare = |directory: &PathType| {
+            execute_git_hash_command(&[
+                "-C",
+                directory.to_str().unwrap(),
+                "rev-parse",
+                "--is-bare-repository",
+            ])
+        };
+
+        // The destination doesn't have to exist yet
+        let directory = PathType::PathBuf(root.join("generated/site"));
+        assert!(GitAction::Init {
+            directory: directory.clone(),
+            bare: false,
+        }
+        .execute()
+        .unwrap());
+        assert_eq!(is_bare(&directory).unwrap(), "false");
+        assert!(root.join("generated/site/.git").is_dir());
+
+        let bare = PathType::PathBuf(root.join("generated/remote.git"));
+        GitAction::Init {
+            directory: bare.clone(),
+            bare: true,
+        }
+        .execute()
+        .unwrap();
+        assert_eq!(is_bare(&bare).unwrap(), "true");
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_commit_message_validation() {
+        let rejected = |message: &str| match validate_commit_message(message) {
+            Err(UnifiedError::AisError(_, AisError::GitInvalidCommit(Some(problem)))) => problem,
+            other => panic!("Expected an invalid commit, got {:?}", other),
+        };
+
+        assert!(rejected("").contains("empty"));
+        assert!(rejected(" \n\t\n").contains("empty"));
+        assert!(rejected("Deploy\0").contains("nul"));
+        assert!(rejected(&"a".repeat(MAX_COMMIT_MESSAGE_BYTES + 1)).contains("bytes"));
+        assert!(validate_commit_message(&"a".repeat(MAX_COMMIT_MESSAGE_BYTES)).is_ok());
+        assert!(validate_commit_message("Deploy\n\nBuilt from main").is_ok());
+    }
+
+    #[test]
+    fn test_commit_keeps_multi_line_message() {
+        let root = std::env::temp_dir().join("ais_git_commit_message");
+        let _ = fs::remove_dir_all(&root);
+        let directory = PathType::PathBuf(root.clone());
+        GitAction::Init {
+            directory: directory.clone(),
+            bare: false,
+        }
+        .execute()
+        .unwrap();
+        let path: &str = directory.to_str().unwrap();
+        execute_git_command(&["-C", path, "config", "user.name", "ais"]).unwrap();
+        execute_git_command(&["-C", path, "config", "user.email", "ais@localhost"]).unwrap();
+        fs::write(root.join("index.html"), b"<html></html>").unwrap();
+        GitAction::Stage {
+            directory: directory.clone(),
+            files: vec!["index.html".to_owned()],
+        }
+        .execute()
+        .unwrap();
+
+        let message = "Deploy build 42\n\n# Generated by the build, not edited\n- index.html";
+        GitAction::Commit {
+            directory: directory.clone(),
+            message: message.to_owned(),
+        }
+        .execute()
+        .unwrap();
+        let committed = execute_git_hash_command(&["-C", path, "log", "-1", "--format=%B"]);
+        assert_eq!(committed.unwrap(), message);
+
+        // Rejected before git runs, so nothing else is committed
+        assert!(GitAction::Commit {
+            directory: directory.clone(),
+            message: String::new(),
+        }
+        .execute()
+        .is_err());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_fetch_branches() {
+        let directory = std::env::temp_dir().join("ais_git_branches");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("index.html"), b"<html></html>").unwrap();
+        let directory = PathType::PathBuf(directory);
+        let path = directory.to_str().unwrap();
+
+        execute_git_command(&["init", "-b", "main", path]).unwrap();
+        execute_git_command(&["-C", path, "add", "index.html"]).unwrap();
+        execute_git_command(&[
+            "-C",
+            path,
+            "-c",
+            "user.name=ais",
+            "-c",
+            "user.email=ais@localhost",
+            "commit",
+            "-m",
+            "initial",
+        ])
+        .unwrap();
+        execute_git_command(&["-C", path, "branch", "staging"]).unwrap();
+
+        let mut branches = fetch_branches(&directory).unwrap();
+        branches.sort();
+        assert_eq!(branches, vec!["main".to_owned(), "staging".to_owned()]);
+    }
+
+    #[test]
+    fn test_fetch_upstream_and_set_upstream() {
+        let origin = std::env::temp_dir().join("ais_git_upstream_origin");
+        let checkout = std::env::temp_dir().join("ais_git_upstream_checkout");
+        let _ = fs::remove_dir_all(&origin);
+        let _ = fs::remove_dir_all(&checkout);
+        fs::create_dir_all(&origin).unwrap();
+        fs::write(origin.join("index.html"), b"<html></html>").unwrap();
+        let origin_path = origin.to_str().unwrap();
+        let checkout = PathType::PathBuf(checkout);
+        let checkout_path = checkout.to_str().unwrap();
+
+        execute_git_command(&["init", "-b", "main", origin_path]).unwrap();
+        execute_git_command(&["-C", origin_path, "add", "index.html"]).unwrap();
+        execute_git_command(&[
+            "-C",
+            origin_path,
+            "-c",
+            "user.name=ais",
+            "-c",
+            "user.email=ais@localhost",
+            "commit",
+            "-m",
+            "initial",
+        ])
+        .unwrap();
+        execute_git_command(&["clone", origin_path, checkout_path]).unwrap();
+        assert_eq!(
+            fetch_upstream(&checkout).unwrap(),
+            Some("origin/main".to_owned())
+        );
+
+        // A local only branch is what's left behind after switching to one
+        execute_git_command(&["-C", checkout_path, "branch", "--unset-upstream"]).unwrap();
+        assert_eq!(fetch_upstream(&checkout).unwrap(), None);
+
+        GitAction::SetUpstream {
+            branch: "main".to_owned(),
+            destination: checkout.clone(),
+        }
+        .execute()
+        .unwrap();
+        assert_eq!(
+            fetch_upstream(&checkout).unwrap(),
+            Some("origin/main".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_preview_pull() {
+        let origin = std::env::temp_dir().join("ais_git_preview_origin");
+        let checkout = std::env::temp_dir().join("ais_git_preview_checkout");
+        let _ = fs::remove_dir_all(&origin);
+        let _ = fs::remove_dir_all(&checkout);
+        fs::create_dir_all(&origin).unwrap();
+        let origin_path = origin.to_str().unwrap();
+        let checkout = PathType::PathBuf(checkout);
+        let checkout_path = checkout.to_str().unwrap();
+
+        let commit = |message: &str| {
+            fs::write(origin.join("index.html"), message.as_bytes()).unwrap();
+            execute_git_command(&["-C", origin_path, "add", "index.html"]).unwrap();
+            execute_git_command(&[
+                "-C",
+                origin_path,
+                "-c",
+                "user.name=ais",
+                "-c",
+                "user.email=ais@localhost",
+                "commit",
+                "-m",
+                message,
+            ])
+            .unwrap();
+        };
+
+        execute_git_command(&["init", "-b", "main", origin_path]).unwrap();
+        commit("initial");
+        execute_git_command(&["clone", origin_path, checkout_path]).unwrap();
+        assert!(preview_pull(&checkout).unwrap().is_empty());
+
+        commit("add the header");
+        commit("fix the footer");
+        let before = fetch_current_commit(&checkout).unwrap();
+
+        let incoming = preview_pull(&checkout).unwrap();
+        assert_eq!(incoming.len(), 2);
+        assert_eq!(incoming[0].summary, "fix the footer");
+        assert_eq!(incoming[1].summary, "add the header");
+        assert_eq!(fetch_current_commit(&checkout).unwrap(), before);
+    }
+
+    #[test]
+    fn test_pull_conflict_recovered_by_reset() {
+        let origin = std::env::temp_dir().join("ais_git_conflict_origin");
+        let checkout_dir = std::env::temp_dir().join("ais_git_conflict_checkout");
+        let _ = fs::remove_dir_all(&origin);
+        let _ = fs::remove_dir_all(&checkout_dir);
+        fs::create_dir_all(&origin).unwrap();
+        let origin_path = origin.to_str().unwrap();
+        let checkout = PathType::PathBuf(checkout_dir.clone());
+        let checkout_path = checkout.to_str().unwrap();
+
+        let commit = |message: &str| {
+            fs::write(origin.join("index.html"), message.as_bytes()).unwrap();
+            execute_git_command(&["-C", origin_path, "add", "index.html"]).unwrap();
+            execute_git_command(&[
+                "-C",
+                origin_path,
+                "-c",
+                "user.name=ais",
+                "-c",
+                "user.email=ais@localhost",
+                "commit",
+                "-m",
+                message,
+            ])
+            .unwrap();
+        };
+
+        execute_git_command(&["init", "-b", "main", origin_path]).unwrap();
+        commit("initial");
+        execute_git_command(&["clone", origin_path, checkout_path]).unwrap();
+
+        // Someone edited the site on the box while the remote moved on
+        fs::write(checkout_dir.join("index.html"), b"edited on the server").unwrap();
+        commit("fix the footer");
+
+        let pull = GitAction::Pull {
+            target_branch: "main".to_owned(),
+            destination: checkout.clone(),
+        };
+        match pull.execute() {
+            Err(UnifiedError::GitError(_, GitError::MergeConflict(details))) => {
+                assert!(details.contains("index.html"))
+            }
+            other => panic!("Expected a merge conflict, got {:?}", other),
+        }
+
+        GitAction::Reset {
+            target_branch: "main".to_owned(),
+            destination: checkout.clone(),
+        }
+        .execute()
+        .unwrap();
+        assert!(pull.execute().unwrap());
+        assert_eq!(
+            fs::read_to_string(checkout_dir.join("index.html")).unwrap(),
+            "fix the footer"
+        );
+    }
+
+    #[test]
+    fn test_clean_removes_untracked_files() {
+        let checkout_dir = std::env::temp_dir().join("ais_git_clean_checkout");
+        let _ = fs::remove_dir_all(&checkout_dir);
+        fs::create_dir_all(&checkout_dir).unwrap();
+        let checkout = PathType::PathBuf(checkout_dir.clone());
+        let checkout_path = checkout.to_str().unwrap();
+
+        execute_git_command(&["init", "-b", "main", checkout_path]).unwrap();
+        fs::write(checkout_dir.join(".gitignore"), b"cache/\n").unwrap();
+        fs::write(checkout_dir.join("index.html"), b"site").unwrap();
+        execute_git_command(&["-C", checkout_path, "add", "."]).unwrap();
+        execute_git_command(&[
+            "-C",
+            checkout_path,
+            "-c",
+            "user.name=ais",
+            "-c",
+            "user.email=ais@localhost",
+            "commit",
+            "-m",
+            "initial",
+        ])
+        .unwrap();
+        assert!(preview_clean(&checkout).unwrap().is_empty());
+
+        // A build left an artifact behind, and a cache that's ignored
+        fs::create_dir_all(checkout_dir.join("dist")).unwrap();
+        fs::write(checkout_dir.join("dist/app.js"), b"built").unwrap();
+        fs::create_dir_all(checkout_dir.join("cache")).unwrap();
+        fs::write(checkout_dir.join("cache/page"), b"cached").unwrap();
+
+        GitAction::Clean {
+            directory: checkout.clone(),
+            dry_run: true,
+        }
+        .execute()
+        .unwrap();
+        assert!(checkout_dir.join("dist/app.js").exists());
+        assert_eq!(preview_clean(&checkout).unwrap(), vec!["dist/".to_owned()]);
+
+        GitAction::Clean {
+            directory: checkout.clone(),
+            dry_run: false,
+        }
+        .execute()
+        .unwrap();
+        assert!(!checkout_dir.join("dist").exists());
+        assert!(checkout_dir.join("cache/page").exists());
+        assert!(checkout_dir.join("index.html").exists());
+        assert!(preview_clean(&checkout).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_is_merge_conflict() {
+        assert!(is_merge_conflict(
+            "CONFLICT (content): Merge conflict in index.html\nAutomatic merge failed; fix conflicts and then commit the result."
+        ));
+        assert!(is_merge_conflict(
+            "error: Your local changes to the following files would be overwritten by merge:"
+        ));
+        assert!(!is_merge_conflict(
+            "fatal: unable to access 'https://github.com/': Could not resolve host"
+        ));
+    }
+
+    #[test]
+    fn test_parse_oneline_log() {
+        assert_eq!(
+            parse_oneline_log("1a2b3c4 fix the footer\n\n5d6e7f8 add the header\n"),
+            vec![
+                IncomingCommit {
+                    hash: "1a2b3c4".to_owned(),
+                    summary: "fix the footer".to_owned(),
+                },
+                IncomingCommit {
+                    hash: "5d6e7f8".to_owned(),
+                    summary: "add the header".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fetch_tags_and_latest_semver() {
+        let directory = std::env::temp_dir().join("ais_git_tags");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("index.html"), b"<html></html>").unwrap();
+        let directory = PathType::PathBuf(directory);
+        let path = directory.to_str().unwrap();
+
+        execute_git_command(&["init", "-b", "main", path]).unwrap();
+        execute_git_command(&["-C", path, "add", "index.html"]).unwrap();
+        execute_git_command(&[
+            "-C",
+            path,
+            "-c",
+            "user.name=ais",
+            "-c",
+            "user.email=ais@localhost",
+            "commit",
+            "-m",
+            "initial",
+        ])
+        .unwrap();
+        for tag in ["v1.2.0", "v1.9.3", "v2.0.0-rc1", "release-old"] {
+            execute_git_command(&["-C", path, "tag", tag]).unwrap();
+        }
+        let tagged = fetch_current_commit(&directory).unwrap();
+        fs::write(directory.as_ref().join("index.html"), b"<html>new</html>").unwrap();
+        execute_git_command(&[
+            "-C",
+            path,
+            "-c",
+            "user.name=ais",
+            "-c",
+            "user.email=ais@localhost",
+            "commit",
+            "-am",
+            "release",
+        ])
+        .unwrap();
+        execute_git_command(&["-C", path, "tag", "v1.10.0"]).unwrap();
+        execute_git_command(&["-C", path, "checkout", "--quiet", &tagged]).unwrap();
+
+        let mut tags = fetch_tags(&directory, "v*").unwrap();
+        tags.sort();
+        assert_eq!(tags.len(), 4);
+        assert!(!tags.contains(&"release-old".to_owned()));
+        // Numeric ordering, not the string ordering git lists them in
+        assert_eq!(latest_semver_tag(&tags), Some(&"v1.10.0".to_owned()));
+
+        GitAction::CheckoutTag {
+            tag: "v1.10.0".to_owned(),
+            destination: directory.clone(),
+        }
+        .execute()
+        .unwrap();
+        assert_eq!(
+            fetch_current_commit(&directory).unwrap(),
+            fetch_tag_commit(&directory, "v1.10.0").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_semver() {
+        assert_eq!(parse_semver("v1.2.3"), Some((1, 2, 3)));
+        assert_eq!(parse_semver("1.2"), Some((1, 2, 0)));
+        assert_eq!(parse_semver("v2.0.0-rc1"), None);
+        assert_eq!(parse_semver("release"), None);
+        assert_eq!(latest_semver_tag(&[]), None);
+    }
+
+    #[test]
+    fn test_parse_remote_heads() {
+        let output = "4b825dc642cb6eb9a060e54bf8d69288fbee4904\trefs/heads/main\n\
+                      9f1a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c\trefs/heads/feature/site";
+        assert_eq!(
+            parse_remote_heads(output),
+            vec!["main".to_owned(), "feature/site".to_owned()]
+        );
+    }
+
     #[test]
     fn test_check_remote_ahead() {
         // Assuming Git is configured with a remote repository
@@ -0,0 +1,75 @@
+use crate::{errors::UnifiedError, time};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Enum representing the state of a subsystem as of its last cycle.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum HealthState {
+    Ok,
+    /// The last cycle failed, with the error it failed with.
+    Degraded(String),
+}
+
+/// The state of a subsystem and when it was last checked.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SubsystemHealth {
+    pub state: HealthState,
+    /// When the subsystem's last cycle finished.
+    pub checked: String,
+}
+
+/// Answers "is this box healthy right now?", keyed by the subsystem's loop name.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Health {
+    subsystems: BTreeMap<String, SubsystemHealth>,
+}
+
+impl Health {
+    /// Creates a new Health with nothing checked yet.
+    pub const fn new() -> Self {
+        Health {
+            subsystems: BTreeMap::new(),
+        }
+    }
+
+    /// Records the outcome of a subsystem's cycle.
+    pub fn record(&mut self, subsystem: &str, result: &Result<(), UnifiedError>) {
+        let state: HealthState = match result {
+            Ok(_) => HealthState::Ok,
+            Err(e) => HealthState::Degraded(e.to_string()),
+        };
+        self.subsystems.insert(
+            subsystem.to_owned(),
+            SubsystemHealth {
+                state,
+                checked: time::now(),
+            },
+        );
+    }
+
+    /// Returns the health of a subsystem, None if it hasn't been checked yet.
+    pub fn get(&self, subsystem: &str) -> Option<&SubsystemHealth> {
+        self.subsystems.get(subsystem)
+    }
+
+    /// Checks if every subsystem was ok on its last cycle.
+    pub fn is_healthy(&self) -> bool {
+        self.subsystems
+            .values()
+            .all(|subsystem| subsystem.state == HealthState::Ok)
+    }
+
+    /// Lists every subsystem's state, one per line, for the daily summary.
+    pub fn summary(&self) -> String {
+        self.subsystems
+            .iter()
+            .map(|(name, subsystem)| match &subsystem.state {
+                HealthState::Ok => format!("  {}: ok (checked {})\n", name, subsystem.checked),
+                HealthState::Degraded(error) => format!(
+                    "  {}: degraded (checked {}): {}\n",
+                    name, subsystem.checked, error
+                ),
+            })
+            .collect()
+    }
+}
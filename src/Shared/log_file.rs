@@ -0,0 +1,207 @@
+use crate::{
+    ais_config::AisConfig,
+    retention::{Retention, RetentionCategory},
+    time,
+};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+/// The log file the output is mirrored to, set once at startup by `init`.
+static LOG_FILE: OnceLock<LogFile> = OnceLock::new();
+
+/// A log file rotated and pruned by its retention.
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    retention: Retention,
+    /// The file is kept open so threads that dropped to www-data can still append to it. Held
+    /// while writing so lines from different threads don't interleave a rotation.
+    file: Mutex<Option<File>>,
+}
+
+impl LogFile {
+    /// Creates a new LogFile, rotated and pruned by the retention. The file is opened by the
+    /// first write.
+    pub fn new(path: PathBuf, retention: Retention) -> Self {
+        LogFile {
+            path,
+            retention,
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Opens the file now rather than on the first write, so it's opened with the privileges
+    /// the caller has.
+    pub fn open(&self) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        *file = Some(self.open_file()?);
+        Ok(())
+    }
+
+    /// Appends a timestamped line, rotating first if it would push the file past the limit.
+    /// A thread without the privileges to rotate keeps appending to the open file, the next
+    /// write from one that has them rotates it.
+    pub fn write(&self, level: &str, message: &str) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let line: String = format!("{} {} {}\n", time::now(), level, message.trim_end());
+
+        match self.retention.make_room(&self.path, line.len() as u64) {
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied && file.is_some() => (),
+            Err(e) => return Err(e),
+        }
+        // A rotation moved the open file aside, the line goes in the new one
+        let open: &mut File = match file.take() {
+            Some(open) if is_current(&open, &self.path) => file.insert(open),
+            _ => file.insert(self.open_file()?),
+        };
+        open.write_all(line.as_bytes())
+    }
+
+    fn open_file(&self) -> io::Result<File> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+    }
+
+    /// The path of the current log file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Checks the open file is still the one at the path.
+fn is_current(file: &File, path: &Path) -> bool {
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(open), Ok(current)) => open.dev() == current.dev() && open.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+/// Mirrors the output to the log file in the config, if one is set. Later calls are ignored.
+/// Call it before any thread drops its privileges, the file is opened here.
+pub fn init(config: &AisConfig) {
+    if let Some(path) = &config.log_file {
+        let log_file = LogFile::new(path.clone(), config.retention(RetentionCategory::Log));
+        if let Err(e) = log_file.open() {
+            eprintln!("Failed to open {}: {}", path.display(), e);
+        }
+        let _ = LOG_FILE.set(log_file);
+    }
+}
+
+/// Writes the line to the log file, failing to doesn't stop the output reaching the journal.
+fn record(level: &str, message: &str) {
+    if let Some(log_file) = LOG_FILE.get() {
+        if let Err(e) = log_file.write(level, message) {
+            eprintln!("Failed to write to {}: {}", log_file.path().display(), e);
+        }
+    }
+}
+
+/// Prints the message in the color, see `pretty::output`.
+pub fn output(color: &str, message: &str) {
+    pretty::output(color, message);
+    record("INFO", message);
+}
+
+/// Prints a notice, see `pretty::notice`.
+pub fn notice(message: &str) {
+    pretty::notice(message);
+    record("NOTICE", message);
+}
+
+/// Prints a warning, see `pretty::warn`.
+pub fn warn(message: &str) {
+    pretty::warn(message);
+    record("WARN", message);
+}
+
+/// Prints a success message, see `pretty::pass`.
+pub fn pass(message: &str) {
+    pretty::pass(message);
+    record("PASS", message);
+}
+
+/// Prints an error, see `pretty::dump`.
+pub fn dump(message: &str) {
+    pretty::dump(message);
+    record("ERROR", message);
+}
+
+/// Prints a fatal error, see `pretty::halt`.
+pub fn halt(message: &str) {
+    // Recorded first in case halting ends the process
+    record("FATAL", message);
+    pretty::halt(message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_file_rotates_at_size_limit() {
+        let directory = std::env::temp_dir().join("ais_log_file");
+        let _ = fs::remove_dir_all(&directory);
+        let log_file = LogFile::new(
+            directory.join("ais.log"),
+            Retention {
+                max_bytes: 200,
+                max_files: 2,
+                max_age_days: 0,
+            },
+        );
+
+        log_file.write("NOTICE", "Operational").unwrap();
+        let contents = fs::read_to_string(directory.join("ais.log")).unwrap();
+        assert!(contents.ends_with(" NOTICE Operational\n"));
+
+        // Each line is around 50 bytes, so every four lines rotate
+        for line in 0..20 {
+            log_file.write("WARN", &format!("Line {}", line)).unwrap();
+        }
+        for name in ["ais.log", "ais.log.1", "ais.log.2"] {
+            let size = fs::metadata(directory.join(name)).unwrap().len();
+            assert!(size > 0 && size <= 200, "{} is {} bytes", name, size);
+        }
+        assert!(!directory.join("ais.log.3").exists());
+        assert!(fs::read_to_string(directory.join("ais.log"))
+            .unwrap()
+            .contains("WARN Line 19"));
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_log_file_reopens_after_outside_rotation() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("ais.log");
+        let log_file = LogFile::new(
+            path.clone(),
+            Retention {
+                max_bytes: 1024,
+                max_files: 2,
+                max_age_days: 0,
+            },
+        );
+        log_file.open().unwrap();
+        log_file.write("NOTICE", "Before").unwrap();
+
+        fs::rename(&path, scratch.path().join("ais.log.old")).unwrap();
+        log_file.write("NOTICE", "After").unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.ends_with(" NOTICE After\n"));
+        assert!(!contents.contains("Before"));
+    }
+}
@@ -0,0 +1,2106 @@
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::{self, SMTP_PORT, SUBMISSIONS_PORT, SUBMISSION_PORT};
+use lettre::{Message, SmtpTransport, Transport};
+use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+use serde::Serialize;
+use system::{create_hash, truncate, PathType};
+
+use std::time::Duration;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, OpenOptions},
+    io::{self, Read, Write},
+    net::TcpListener,
+    os::unix::{
+        fs::PermissionsExt,
+        io::AsRawFd,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::{mpsc::Receiver, Arc, RwLock},
+    thread,
+    time::Instant,
+};
+
+use crate::{
+    ais_config::AisConfig,
+    emails::{
+        DropPolicy, Email, EmailCategory, EmailPriority, Heartbeat, MailEndpoint, SigningKey,
+        SmtpTls, EMAIL_ID_SEPARATOR, MAX_FRAME_BYTES, PLAIN_PREFIX, SIGNING_KEY_PATH,
+    },
+    encrypt::{seal_record, Cipher, Commands, DusaCipher},
+    errors::{AisError, UnifiedError},
+    filesystem::make_dir_all,
+    log_file::{self, dump, halt, notice, warn},
+    retention::{Retention, RetentionCategory},
+    profile::Profile,
+    time,
+};
+
+/// An email waiting in the queue and when it arrived.
+#[derive(Debug)]
+pub struct TimedEmail {
+    pub email: Email,
+    received_at: Instant,
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct ErrorEmail {
+    hash: String,
+    subject: Option<String>,
+    occoured_at: Instant,
+    /// The email that failed, kept so it can be requeued.
+    email: Option<Email>,
+}
+
+/// Connection settings for the smtp relay, resolved from the config.
+#[derive(Debug, PartialEq)]
+struct SmtpSettings {
+    relay: String,
+    port: u16,
+    tls: SmtpTls,
+    timeout: Duration,
+}
+
+/// A line in the audit log, recording an email that was sent.
+#[derive(Debug, Serialize)]
+struct AuditRecord {
+    timestamp: String,
+    category: EmailCategory,
+    subject_hash: String,
+    recipient: String,
+}
+
+#[derive(Debug)]
+struct HeartbeatRecord {
+    last_seen: Instant,
+    alerted: bool,
+}
+
+/// How long a client can go without a heartbeat before we raise an alert
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(1800);
+
+/// How long an email id is remembered, a retransmit within this window is dropped
+const DEDUPE_WINDOW: Duration = Duration::from_secs(900);
+
+/// The most email ids we remember, the oldest are forgotten first
+const DEDUPE_CAPACITY: usize = 1024;
+
+/// The most emails listed in a storm digest, the rest are only counted
+const DIGEST_LISTED: usize = 50;
+
+/// The categories in a storm and the arrivals that tell when one starts and ends, see
+/// `AisConfig::mail_storm_threshold`. Only the queue's pass uses it, so it isn't shared.
+#[derive(Debug)]
+struct Storms {
+    threshold: usize,
+    window: Duration,
+    /// When the queue was last checked, emails received after it are new.
+    checked_at: Option<Instant>,
+    arrivals: HashMap<EmailCategory, VecDeque<Instant>>,
+    storms: HashMap<EmailCategory, Storm>,
+}
+
+/// A category in a storm, its emails are held for the next digest.
+#[derive(Debug)]
+struct Storm {
+    last_digest: Instant,
+    held: Vec<Email>,
+}
+
+/// The ids of recently received emails, so retransmits of one already queued are dropped.
+#[derive(Debug)]
+struct SeenIds {
+    window: Duration,
+    capacity: usize,
+    ids: VecDeque<(String, Instant)>,
+}
+
+/// The most emails sent in one pass of the queue, a pass runs every minute
+const RATE_LIMIT: usize = 7;
+
+/// How long an email waits in the queue before it's discarded
+const EMAIL_EXPIRY: Duration = Duration::from_secs(300);
+
+/// The most errors we hold on to, the oldest are dropped first
+const ERROR_BUFFER_LIMIT: usize = 100;
+
+/// Where control commands are accepted, the socket is only open to root.
+const CONTROL_SOCKET: &str = "/run/ais/mail_control.sock";
+
+/// Where every sent email is recorded, one json object per line
+const AUDIT_LOG_PATH: &str = "/var/log/ais/mail_audit.log";
+
+/// Adds an email to the queue, applying the drop policy when the queue is full.
+/// High priority emails are never dropped in favor of low priority ones.
+fn enqueue_email(
+    email_vec: &mut Vec<TimedEmail>,
+    timed_email: TimedEmail,
+    capacity: usize,
+    policy: DropPolicy,
+) -> Result<(), UnifiedError> {
+    if email_vec.len() < capacity {
+        email_vec.push(timed_email);
+        return Ok(());
+    }
+
+    let evict: bool = match (timed_email.email.priority, policy) {
+        (EmailPriority::High, _) => true,
+        (EmailPriority::Low, DropPolicy::DropOldestLow) => true,
+        (EmailPriority::Low, DropPolicy::RejectNew) => false,
+    };
+
+    let oldest_low: Option<usize> = email_vec
+        .iter()
+        .position(|queued| queued.email.priority == EmailPriority::Low);
+
+    match (evict, oldest_low) {
+        (true, Some(index)) => {
+            let dropped: TimedEmail = email_vec.remove(index);
+            warn(&format!("Queue full, dropping: {}", dropped.email.subject));
+            email_vec.push(timed_email);
+            Ok(())
+        }
+        _ => Err(UnifiedError::from_ais_error(AisError::new(format!(
+            "Email queue full, rejecting: {}",
+            timed_email.email.subject
+        )))),
+    }
+}
+
+/// Records an error, dropping the oldest once the buffer is full.
+fn record_error(email_errors: &mut Vec<ErrorEmail>, error: ErrorEmail) {
+    if email_errors.len() >= ERROR_BUFFER_LIMIT {
+        email_errors.remove(0);
+    }
+    email_errors.push(error);
+}
+
+impl SmtpSettings {
+    fn new(relay: &str, config: &AisConfig) -> Self {
+        let standard_port: u16 = match config.smtp_tls {
+            SmtpTls::Implicit => SUBMISSIONS_PORT,
+            SmtpTls::StartTls => SUBMISSION_PORT,
+            SmtpTls::None => SMTP_PORT,
+        };
+
+        SmtpSettings {
+            relay: relay.to_owned(),
+            port: config.smtp_port.unwrap_or(standard_port),
+            tls: config.smtp_tls,
+            timeout: Duration::from_secs(config.smtp_timeout_secs),
+        }
+    }
+
+    /// Builds the transport, nothing is sent until an email is.
+    fn transport(&self, credentials: Credentials) -> Result<SmtpTransport, UnifiedError> {
+        let builder = match self.tls {
+            SmtpTls::Implicit => SmtpTransport::relay(&self.relay),
+            SmtpTls::StartTls => SmtpTransport::starttls_relay(&self.relay),
+            SmtpTls::None => Ok(SmtpTransport::builder_dangerous(&self.relay)),
+        }
+        .map_err(|e| {
+            UnifiedError::from_ais_error(AisError::SmtpConnectionFailed(Some(format!(
+                "Failed to configure the mail server: {}",
+                e
+            ))))
+        })?;
+
+        Ok(builder
+            .port(self.port)
+            .timeout(Some(self.timeout))
+            .credentials(credentials)
+            .build())
+    }
+}
+
+/// Sorts smtp failures into connection and authentication errors so they can be told apart.
+fn classify_smtp_error(error: smtp::Error) -> UnifiedError {
+    let code: Option<String> = error.status().map(|code| code.to_string());
+    let ais_error: AisError = match code.as_deref() {
+        Some("530") | Some("534") | Some("535") => AisError::SmtpAuthFailed(Some(format!(
+            "The mail server rejected our credentials: {}",
+            error
+        ))),
+        Some(_) => AisError::new(error.to_string()),
+        None => AisError::SmtpConnectionFailed(Some(format!(
+            "Failed to connect to the mail server: {}",
+            error
+        ))),
+    };
+    UnifiedError::from_ais_error(ais_error)
+}
+
+#[allow(dead_code)]
+fn send_email(subject: String, body: String, config: &AisConfig) -> Result<(), UnifiedError> {
+    let profile: Profile = Profile::current();
+    let endpoints = profile.endpoints();
+
+    // Build the email
+    let email = Message::builder()
+        .to(endpoints.recipient.parse().map_err(|e| {
+            UnifiedError::from_ais_error(AisError::new(format!("Failed to build email: {}", e)))
+        })?)
+        .from(
+            "ArtisanBot <ais_bot@artisanhosting.net>"
+                .parse()
+                .map_err(|e| {
+                    UnifiedError::from_ais_error(AisError::new(format!(
+                        "Failed to build email: {}",
+                        e
+                    )))
+                })?,
+        )
+        .subject(profile.tag_subject(&subject))
+        .body(body)
+        .map_err(|e| {
+            UnifiedError::from_ais_error(AisError::new(format!("Failed to build email: {}", e)))
+        })?;
+
+    // The smpt credentials
+    let creds = Credentials::new(
+        "ais_bot@artisanhosting.net".to_owned(),
+        "&wvh\"x2)!62x93Cc-w".to_owned(), // This needed to be encrypted like the artisan.cf
+    );
+
+    let mailer = SmtpSettings::new(endpoints.mail_relay, config).transport(creds)?;
+
+    // Send the email
+    mailer.send(&email).map_err(classify_smtp_error)?;
+
+    Ok(())
+}
+
+/// Appends a record of a sent email to the audit log, rotating and pruning it by the retention.
+/// With a cipher the record is encrypted, see `seal_record`.
+fn record_audit(
+    path: &PathType,
+    retention: &Retention,
+    email: &Email,
+    recipient: &str,
+    cipher: Option<&dyn Cipher>,
+) -> Result<(), UnifiedError> {
+    let io_error =
+        |e: io::Error| UnifiedError::from_ais_error(AisError::new(format!("Audit log: {}", e)));
+
+    if let Some(parent) = path.as_ref().parent() {
+        make_dir_all(&PathType::PathBuf(parent.to_path_buf()))?;
+    }
+
+    let record = AuditRecord {
+        timestamp: time::now(),
+        category: email.category,
+        subject_hash: create_hash(email.subject.clone()),
+        recipient: recipient.to_owned(),
+    };
+    let line: String = serde_json::to_string(&record)
+        .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))?;
+    let line: String = seal_record(&line, email.category.requires_encryption(), cipher)?
+        .ok_or_else(|| {
+            UnifiedError::from_ais_error(AisError::EncryptionNotReady(Some(format!(
+                "Refusing to record a {} email in plain text",
+                email.category
+            ))))
+        })?;
+
+    retention
+        .make_room(path.as_ref(), line.len() as u64 + 1)
+        .map_err(io_error)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(io_error)?;
+    writeln!(file, "{}", line).map_err(io_error)
+}
+
+/// Sends an email and records it in the audit log once it's gone out.
+/// A failure to write the audit record is only logged, the email was still delivered.
+fn deliver<F>(
+    email: &Email,
+    audit_log: &PathType,
+    retention: &Retention,
+    cipher: Option<&dyn Cipher>,
+    send: F,
+) -> Result<(), UnifiedError>
+where
+    F: FnOnce(&Email) -> Result<(), UnifiedError>,
+{
+    send(email)?;
+
+    let recipient: &str = Profile::current().endpoints().recipient;
+    if let Err(e) = record_audit(audit_log, retention, email, recipient, cipher) {
+        warn(&format!("Failed to record sent email: {}", e));
+    }
+    Ok(())
+}
+
+/// Sends one pass of the queue, at most `RATE_LIMIT` emails and no more of a category than
+/// its limit in the config, so one noisy category can't starve the others. Emails over their
+/// category's limit stay queued for the next pass, expired ones are discarded.
+fn send_pass<F>(
+    email_vec: &mut Vec<TimedEmail>,
+    email_errors: &mut Vec<ErrorEmail>,
+    config: &AisConfig,
+    current_time: Instant,
+    mut send: F,
+) where
+    F: FnMut(&Email) -> Result<(), UnifiedError>,
+{
+    let mut sent_per_category: HashMap<EmailCategory, usize> = HashMap::new();
+    let mut i = 0;
+    let mut iteration_count = 0;
+
+    while i < email_vec.len() && iteration_count < RATE_LIMIT {
+        if current_time.duration_since(email_vec[i].received_at) > EMAIL_EXPIRY {
+            notice(&format!(
+                "Expired email discarding: {}",
+                email_vec[i].email.subject
+            ));
+            email_vec.remove(i); // Remove expired email from the vector
+            iteration_count += 1;
+            continue;
+        }
+
+        let category: EmailCategory = email_vec[i].email.category;
+        let sent: &mut usize = sent_per_category.entry(category).or_insert(0);
+        if config
+            .mail_category_limits
+            .get(&category)
+            .is_some_and(|limit| *sent >= *limit)
+        {
+            // Over its category's limit, it waits for the next pass
+            i += 1;
+            continue;
+        }
+        *sent += 1;
+
+        match send(&email_vec[i].email) {
+            Ok(_) => {
+                notice(&format!(
+                    "Sending Email: {}-{}",
+                    &iteration_count.to_string(),
+                    &RATE_LIMIT
+                ));
+                email_vec.remove(i); // Remove sent email from the vector
+            }
+            Err(e) => {
+                warn(&format!("An error occurred while sending email: {}", &e));
+                record_error(
+                    email_errors,
+                    ErrorEmail {
+                        hash: truncate(&create_hash(e.to_string()), 10).to_owned(),
+                        subject: Some(e.to_string()),
+                        occoured_at: Instant::now(),
+                        email: Some(email_vec[i].email.clone()),
+                    },
+                );
+                // Skip to the next email without removing the email from the vec i
+                i += 1;
+            }
+        }
+        // Increment the iteration count
+        iteration_count += 1;
+    }
+}
+
+fn process_emails(
+    emails: Arc<RwLock<Vec<TimedEmail>>>,
+    errors: Arc<RwLock<Vec<ErrorEmail>>>,
+    config: Arc<AisConfig>,
+) {
+    let mut storms = Storms::new(
+        config.mail_storm_threshold,
+        Duration::from_secs(config.mail_storm_window_secs),
+    );
+    loop {
+        // Sleep for 1 minute
+        thread::sleep(Duration::from_secs(60));
+
+        // Lock the emails vector
+        let mut email_errors = match errors.write() {
+            Ok(vec) => vec,
+            Err(_) => {
+                warn("Failed to acquire write lock on the error counter"); // Eventually add a uid and a phisical storage methode
+                continue;
+            }
+        };
+
+        // Lock the emails vector
+        let mut email_vec = match emails.try_write() {
+            Ok(vec) => vec,
+            Err(_) => {
+                warn("Failed to acquire write lock on emails vector");
+                record_error(
+                    &mut email_errors,
+                    ErrorEmail {
+                        hash: truncate(&create_hash("Failed to lock email array".to_owned()), 10)
+                            .to_owned(),
+                        subject: None,
+                        occoured_at: Instant::now(),
+                        email: None,
+                    },
+                );
+                continue;
+            }
+        };
+
+        // Digests are queued as if they'd just arrived, they take the place of what they hold
+        let now: Instant = Instant::now();
+        for digest in storms.collapse(&mut email_vec, now) {
+            email_vec.push(TimedEmail {
+                email: digest,
+                received_at: now,
+            });
+        }
+
+        let audit_log = PathType::Str(AUDIT_LOG_PATH.into());
+        let retention: Retention = config.retention(RetentionCategory::MailAudit);
+        let cipher: Option<&dyn Cipher> = match config.encrypt_at_rest {
+            true => Some(&DusaCipher),
+            false => None,
+        };
+        send_pass(&mut email_vec, &mut email_errors, &config, now, |email| {
+            deliver(email, &audit_log, &retention, cipher, |email| {
+                send_email(email.subject.to_owned(), email.body.to_owned(), &config)
+            })
+        });
+
+        match email_errors.is_empty() {
+            true => notice("No errors reported"),
+            false => warn(&format!("Current errors: {}", email_errors.len())),
+        }
+
+        drop(email_errors);
+        drop(email_vec);
+    }
+}
+
+impl SeenIds {
+    fn new(window: Duration, capacity: usize) -> Self {
+        SeenIds {
+            window,
+            capacity,
+            ids: VecDeque::new(),
+        }
+    }
+
+    /// Whether the id was already seen within the window.
+    fn contains(&self, id: &str, now: Instant) -> bool {
+        self.ids
+            .iter()
+            .any(|(seen, seen_at)| seen == id && now.duration_since(*seen_at) < self.window)
+    }
+
+    /// Records the id, returning false if it was already seen within the window.
+    fn record(&mut self, id: &str, now: Instant) -> bool {
+        while let Some((_, seen_at)) = self.ids.front() {
+            match now.duration_since(*seen_at) >= self.window {
+                true => self.ids.pop_front(),
+                false => break,
+            };
+        }
+        if self.ids.iter().any(|(seen, _)| seen == id) {
+            return false;
+        }
+        if self.ids.len() >= self.capacity {
+            self.ids.pop_front();
+        }
+        self.ids.push_back((id.to_owned(), now));
+        true
+    }
+}
+
+impl Storms {
+    fn new(threshold: usize, window: Duration) -> Self {
+        Storms {
+            threshold,
+            window,
+            checked_at: None,
+            arrivals: HashMap::new(),
+            storms: HashMap::new(),
+        }
+    }
+
+    /// Counts the emails queued since the last check, taking the ones of categories in a storm
+    /// out of the queue. Returns the digests that are due, a storm's last digest goes out as
+    /// soon as its rate drops back to the threshold.
+    fn collapse(&mut self, email_vec: &mut Vec<TimedEmail>, now: Instant) -> Vec<Email> {
+        if self.threshold == 0 {
+            return Vec::new();
+        }
+        let checked_at: Option<Instant> = self.checked_at.replace(now);
+        let is_new = |queued: &TimedEmail| checked_at.is_none_or(|at| queued.received_at > at);
+
+        for queued in email_vec.iter().filter(|queued| is_new(queued)) {
+            self.arrivals
+                .entry(queued.email.category)
+                .or_default()
+                .push_back(queued.received_at);
+        }
+        for arrivals in self.arrivals.values_mut() {
+            while arrivals
+                .front()
+                .is_some_and(|arrived| now.duration_since(*arrived) >= self.window)
+            {
+                arrivals.pop_front();
+            }
+        }
+        self.arrivals.retain(|_, arrivals| !arrivals.is_empty());
+
+        for (category, arrivals) in &self.arrivals {
+            if arrivals.len() > self.threshold && !self.storms.contains_key(category) {
+                warn(&format!(
+                    "{} {} emails in {}s, sending them as digests until it calms down",
+                    arrivals.len(),
+                    category,
+                    self.window.as_secs()
+                ));
+                self.storms.insert(
+                    *category,
+                    Storm {
+                        last_digest: now,
+                        held: Vec::new(),
+                    },
+                );
+            }
+        }
+
+        let mut i = 0;
+        while i < email_vec.len() {
+            match self.storms.get_mut(&email_vec[i].email.category) {
+                Some(storm) if is_new(&email_vec[i]) => storm.held.push(email_vec.remove(i).email),
+                _ => i += 1,
+            }
+        }
+
+        let mut digests: Vec<Email> = Vec::new();
+        self.storms.retain(|category, storm| {
+            let subsided: bool = self
+                .arrivals
+                .get(category)
+                .map_or(0, |arrivals| arrivals.len())
+                <= self.threshold;
+            let due: bool = subsided || now.duration_since(storm.last_digest) >= self.window;
+            if due && !storm.held.is_empty() {
+                digests.push(storm_digest(
+                    *category,
+                    &storm.held,
+                    now.duration_since(storm.last_digest),
+                ));
+                storm.held.clear();
+                storm.last_digest = now;
+            }
+            if subsided {
+                notice(&format!("The storm of {} emails is over", category));
+            }
+            !subsided
+        });
+        digests
+    }
+}
+
+/// Builds the digest of the emails held during a storm, it's high priority if any of them are.
+fn storm_digest(category: EmailCategory, held: &[Email], over: Duration) -> Email {
+    let minutes: u64 = over.as_secs().div_ceil(60).max(1);
+    let mut body: String = format!(
+        "{} {} emails arrived in the last {} minutes, more than are sent one by one during an incident:\n",
+        held.len(),
+        category,
+        minutes
+    );
+    for email in held.iter().take(DIGEST_LISTED) {
+        body.push_str(&format!(
+            "\n[{}] {}\n{}\n",
+            email.priority, email.subject, email.body
+        ));
+    }
+    if held.len() > DIGEST_LISTED {
+        body.push_str(&format!("\n...and {} more\n", held.len() - DIGEST_LISTED));
+    }
+
+    Email {
+        subject: format!(
+            "{} {} events in the last {} minutes",
+            held.len(),
+            category,
+            minutes
+        ),
+        body,
+        priority: match held
+            .iter()
+            .any(|email| email.priority == EmailPriority::High)
+        {
+            true => EmailPriority::High,
+            false => EmailPriority::Low,
+        },
+        category,
+    }
+}
+
+fn record_heartbeat(
+    heartbeat: Heartbeat,
+    heartbeats: &Arc<RwLock<HashMap<String, HeartbeatRecord>>>,
+) {
+    match heartbeats.write() {
+        Ok(mut records) => {
+            records.insert(
+                heartbeat.machine_id,
+                HeartbeatRecord {
+                    last_seen: Instant::now(),
+                    alerted: false,
+                },
+            );
+        }
+        Err(_) => warn("Failed to acquire write lock on heartbeats"),
+    }
+}
+
+/// Returns the machines that missed their heartbeat, each machine is only returned once per outage
+fn collect_missed_heartbeats(
+    heartbeats: &Arc<RwLock<HashMap<String, HeartbeatRecord>>>,
+    current_time: Instant,
+    timeout: Duration,
+) -> Vec<String> {
+    let mut records = match heartbeats.write() {
+        Ok(records) => records,
+        Err(_) => {
+            warn("Failed to acquire write lock on heartbeats");
+            return Vec::new();
+        }
+    };
+
+    let mut missed: Vec<String> = Vec::new();
+    for (machine_id, record) in records.iter_mut() {
+        if !record.alerted && current_time.duration_since(record.last_seen) > timeout {
+            record.alerted = true;
+            missed.push(machine_id.clone());
+        }
+    }
+    missed
+}
+
+fn monitor_heartbeats(
+    heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>>,
+    emails: Arc<RwLock<Vec<TimedEmail>>>,
+    config: Arc<AisConfig>,
+) {
+    loop {
+        thread::sleep(Duration::from_secs(60));
+
+        for machine_id in collect_missed_heartbeats(&heartbeats, Instant::now(), HEARTBEAT_TIMEOUT)
+        {
+            warn(&format!("Missed heartbeat from: {}", machine_id));
+            let timed_email: TimedEmail = TimedEmail {
+                email: Email {
+                    subject: format!("{}: Heartbeat missed", machine_id),
+                    body: format!(
+                        "The system: {} has not sent a heartbeat in over {} minutes. The client may be down.",
+                        machine_id,
+                        HEARTBEAT_TIMEOUT.as_secs() / 60
+                    ),
+                    priority: EmailPriority::High,
+                    category: EmailCategory::Heartbeat,
+                },
+                received_at: Instant::now(),
+            };
+            match emails.write() {
+                Ok(mut email_vec) => {
+                    if let Err(e) = enqueue_email(
+                        &mut email_vec,
+                        timed_email,
+                        config.mail_queue_capacity,
+                        config.mail_drop_policy,
+                    ) {
+                        warn(&e.to_string());
+                    }
+                }
+                Err(_) => warn("Failed to acquire write lock on emails vector"),
+            }
+        }
+    }
+}
+
+/// Reads one frame from the stream, recording heartbeats and queueing emails.
+/// Frames that aren't signed with the shared key are rejected, heartbeats included.
+/// The stream and decryption are injected so the path can be exercised without a socket or dusa.
+fn handle_client<S, D>(
+    mut stream: S,
+    emails: Arc<RwLock<Vec<TimedEmail>>>,
+    heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>>,
+    config: Arc<AisConfig>,
+    key: Arc<SigningKey>,
+    seen_ids: Arc<RwLock<SeenIds>>,
+    decrypt: D,
+) -> Result<(), UnifiedError>
+where
+    S: Read + Write,
+    D: Fn(&str) -> Result<String, UnifiedError>,
+{
+    // Clients close their end once the frame is written, so it's read through to the end
+    let mut buffer: Vec<u8> = Vec::new();
+    (&mut stream)
+        .take(MAX_FRAME_BYTES as u64)
+        .read_to_end(&mut buffer)
+        .map_err(|e| {
+            UnifiedError::from_ais_error(AisError::new(format!("Failed to read buffered: {}", e)))
+        })?;
+    // Clients checking the server is reachable connect without sending anything
+    if buffer.is_empty() {
+        return Ok(());
+    }
+    let received_data = String::from_utf8_lossy(&buffer);
+
+    let payload: &str = match key.open(&received_data) {
+        Ok(payload) => payload,
+        Err(e) => {
+            warn(&format!("Rejected a frame: {}", e));
+            return Err(e);
+        }
+    };
+    // Heartbeats are recorded, not relayed
+    if let Some(heartbeat) = Heartbeat::parse(payload) {
+        record_heartbeat(heartbeat, &heartbeats);
+        return Ok(());
+    }
+    notice("Emails recived");
+
+    // Emails from clients that don't send an id can't be deduplicated
+    let (payload, id): (&str, Option<&str>) = match payload.rsplit_once(EMAIL_ID_SEPARATOR) {
+        Some((data, id)) => (data, Some(id)),
+        None => (payload, None),
+    };
+    let seen_lock_error = || {
+        UnifiedError::from_ais_error(AisError::ThreadedDataError(Some(
+            "Failed to acquire the lock on seen email ids".to_owned(),
+        )))
+    };
+    if is_retransmit(&*seen_ids.read().map_err(|_| seen_lock_error())?, id) {
+        return acknowledge(&mut stream);
+    }
+
+    // Plain frames are read as is, everything else goes through dusa
+    let (decrypted_data, encrypted) = match payload.strip_prefix(PLAIN_PREFIX) {
+        Some(data) => (data.to_owned(), false),
+        None => (decrypt(payload)?, true),
+    };
+
+    let email_data_plain: String = hex::decode(decrypted_data)
+        .map_err(|e| {
+            UnifiedError::from_ais_error(AisError::new(format!(
+                "An error occoured while reading the hexed data: {}",
+                &e.to_string()
+            )))
+        })
+        .and_then(|data| {
+            String::from_utf8(data).map_err(|e| {
+                UnifiedError::from_ais_error(AisError::new(format!(
+                    "The email data isn't valid utf-8: {}",
+                    e
+                )))
+            })
+        })?;
+    let email: Email = parse_email_data(&email_data_plain)?;
+
+    if !encrypted && email.category.requires_encryption() {
+        warn(&format!(
+            "Rejected an unencrypted {} email: {}",
+            email.category, email.subject
+        ));
+        return Err(UnifiedError::from_ais_error(AisError::new(format!(
+            "{} emails must be encrypted",
+            email.category
+        ))));
+    }
+
+    // The id is only recorded once the email is queued, so the retransmit of one that failed
+    // on the way is still taken. The ids stay locked until then so two copies aren't both queued.
+    let mut seen = seen_ids.write().map_err(|_| seen_lock_error())?;
+    if is_retransmit(&seen, id) {
+        drop(seen);
+        return acknowledge(&mut stream);
+    }
+
+    // Add email to the vector with current timestamp
+    let timed_email: TimedEmail = TimedEmail {
+        email: email.clone(),
+        received_at: Instant::now(),
+    };
+    let mut email_vec = emails.write().map_err(|_| {
+        UnifiedError::from_ais_error(AisError::ThreadedDataError(Some(
+            "Failed to acquire write lock on emails vector".to_owned(),
+        )))
+    })?;
+    enqueue_email(
+        &mut email_vec,
+        timed_email,
+        config.mail_queue_capacity,
+        config.mail_drop_policy,
+    )?;
+    drop(email_vec);
+    drop(emails);
+    if let Some(id) = id {
+        seen.record(id, Instant::now());
+    }
+    drop(seen);
+
+    acknowledge(&mut stream)
+}
+
+/// Whether the email with the id was already queued, a retransmit is noted and dropped.
+fn is_retransmit(seen: &SeenIds, id: Option<&str>) -> bool {
+    match id {
+        Some(id) if seen.contains(id, Instant::now()) => {
+            notice(&format!("Dropped a retransmit of email {}", id));
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Lets the client know its email was received, so it doesn't send it again.
+fn acknowledge<S: Write>(stream: &mut S) -> Result<(), UnifiedError> {
+    stream.write_all(b"Email received").map_err(|e| {
+        UnifiedError::from_ais_error(AisError::new(format!("Error sending response: {}", e)))
+    })?;
+    stream.flush().map_err(|e| {
+        UnifiedError::from_ais_error(AisError::new(format!("Error while flushing buffer: {}", e)))
+    })
+}
+
+/// Reads an email laid out as subject-=-body-=-priority-=-category, the priority and category
+/// are optional.
+fn parse_email_data(data: &str) -> Result<Email, UnifiedError> {
+    let email_data: Vec<&str> = data.split("-=-").collect();
+    if email_data.len() < 2 {
+        return Err(UnifiedError::from_ais_error(AisError::new(
+            "Received email data is missing a subject or body",
+        )));
+    }
+    let priority: EmailPriority = email_data
+        .get(2)
+        .map(|data| EmailPriority::parse(data))
+        .unwrap_or_default();
+    let category: EmailCategory = email_data
+        .get(3)
+        .and_then(|data| EmailCategory::parse(data))
+        .unwrap_or_default();
+
+    Ok(Email {
+        subject: email_data[0].to_owned(),
+        body: email_data[1].to_owned(),
+        priority,
+        category,
+    })
+}
+
+fn decrypt_received_data(data: &str) -> Result<String, UnifiedError> {
+    let decrypt = Commands::DecryptText(data.to_owned());
+    let decrypted_data = decrypt.execute()?;
+    Ok(decrypted_data.unwrap_or_else(|| "no data provided".to_owned()))
+}
+
+/// Runs a control command against the email queue and returns the response.
+/// Supported commands are LIST, PURGE <category>, CLEAR, ERRORS and REQUEUE <index>.
+fn handle_control_command(
+    command: &str,
+    emails: &Arc<RwLock<Vec<TimedEmail>>>,
+    errors: &Arc<RwLock<Vec<ErrorEmail>>>,
+    config: &AisConfig,
+) -> String {
+    let parts: Vec<&str> = command.split_whitespace().collect();
+
+    // The error buffer is handled first so the two locks are never held together
+    match parts.as_slice() {
+        ["ERRORS"] => return list_errors(errors),
+        ["REQUEUE", index] => return requeue_error(index, emails, errors, config),
+        _ => (),
+    }
+
+    let mut email_vec = match emails.write() {
+        Ok(vec) => vec,
+        Err(_) => return "Failed to acquire write lock on emails vector".to_owned(),
+    };
+
+    match parts.as_slice() {
+        ["LIST"] => {
+            if email_vec.is_empty() {
+                return "No queued emails".to_owned();
+            }
+            let current_time = Instant::now();
+            email_vec
+                .iter()
+                .enumerate()
+                .map(|(i, queued)| {
+                    format!(
+                        "{} {} {} {}s {}",
+                        i,
+                        queued.email.priority,
+                        queued.email.category,
+                        current_time.duration_since(queued.received_at).as_secs(),
+                        queued.email.subject
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n")
+        }
+        ["PURGE", category] => match EmailCategory::parse(category) {
+            Some(category) => {
+                let queued = email_vec.len();
+                email_vec.retain(|queued| queued.email.category != category);
+                format!("Purged {} emails", queued - email_vec.len())
+            }
+            None => format!("Unknown category: {}", category),
+        },
+        ["CLEAR"] => {
+            let queued = email_vec.len();
+            email_vec.clear();
+            format!("Purged {} emails", queued)
+        }
+        _ => "Unknown command, expected LIST, PURGE <category>, CLEAR, ERRORS or REQUEUE <index>"
+            .to_owned(),
+    }
+}
+
+/// Lists the error buffer, marking the errors that still hold an email to requeue.
+fn list_errors(errors: &Arc<RwLock<Vec<ErrorEmail>>>) -> String {
+    let email_errors = match errors.read() {
+        Ok(vec) => vec,
+        Err(_) => return "Failed to acquire read lock on the error buffer".to_owned(),
+    };
+    if email_errors.is_empty() {
+        return "No errors recorded".to_owned();
+    }
+
+    let current_time = Instant::now();
+    email_errors
+        .iter()
+        .enumerate()
+        .map(|(i, error)| {
+            format!(
+                "{} {} {}s {} {}",
+                i,
+                error.hash,
+                current_time.duration_since(error.occoured_at).as_secs(),
+                match &error.email {
+                    Some(email) => email.subject.as_str(),
+                    None => "-",
+                },
+                error.subject.as_deref().unwrap_or("")
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Moves a failed email from the error buffer back into the send queue.
+fn requeue_error(
+    index: &str,
+    emails: &Arc<RwLock<Vec<TimedEmail>>>,
+    errors: &Arc<RwLock<Vec<ErrorEmail>>>,
+    config: &AisConfig,
+) -> String {
+    let index: usize = match index.parse() {
+        Ok(index) => index,
+        Err(_) => return format!("Invalid index: {}", index),
+    };
+
+    let email: Email = {
+        let mut email_errors = match errors.write() {
+            Ok(vec) => vec,
+            Err(_) => return "Failed to acquire write lock on the error buffer".to_owned(),
+        };
+        let email: Email = match email_errors.get_mut(index) {
+            Some(error) => match error.email.take() {
+                Some(email) => email,
+                None => return format!("Error {} has no email to requeue", index),
+            },
+            None => return format!("No error at index: {}", index),
+        };
+        email_errors.remove(index);
+        email
+    };
+
+    let mut email_vec = match emails.write() {
+        Ok(vec) => vec,
+        Err(_) => return "Failed to acquire write lock on emails vector".to_owned(),
+    };
+    let subject: String = email.subject.clone();
+    let timed_email: TimedEmail = TimedEmail {
+        email,
+        received_at: Instant::now(),
+    };
+    match enqueue_email(
+        &mut email_vec,
+        timed_email,
+        config.mail_queue_capacity,
+        config.mail_drop_policy,
+    ) {
+        Ok(_) => format!("Requeued: {}", subject),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Commands mutate the queue, so only the given user's connections are accepted. This covers
+/// the moment between binding the socket and narrowing its mode too.
+fn check_control_peer(stream: &UnixStream, allowed_uid: u32) -> Result<(), UnifiedError> {
+    let credentials = getsockopt(stream.as_raw_fd(), PeerCredentials).map_err(|e| {
+        UnifiedError::from_ais_error(AisError::new(format!(
+            "Failed to read the peer credentials: {}",
+            e
+        )))
+    })?;
+    match credentials.uid() == allowed_uid {
+        true => Ok(()),
+        false => Err(UnifiedError::from_ais_error(AisError::PermissionDenied(
+            Some(format!(
+                "Rejected control connection from uid {}",
+                credentials.uid()
+            )),
+        ))),
+    }
+}
+
+fn handle_control_client(
+    mut stream: UnixStream,
+    emails: Arc<RwLock<Vec<TimedEmail>>>,
+    errors: Arc<RwLock<Vec<ErrorEmail>>>,
+    config: Arc<AisConfig>,
+) -> Result<(), UnifiedError> {
+    check_control_peer(&stream, 0)?;
+
+    let mut buffer = [0; 256];
+    let bytes_read = stream.read(&mut buffer).map_err(|e| {
+        UnifiedError::from_ais_error(AisError::new(format!("Failed to read buffered: {}", e)))
+    })?;
+    let command = String::from_utf8_lossy(&buffer[..bytes_read]);
+    notice(&format!("Control command recived: {}", command.trim()));
+
+    let response: String = handle_control_command(&command, &emails, &errors, &config);
+    stream.write_all(response.as_bytes()).map_err(|e| {
+        UnifiedError::from_ais_error(AisError::new(format!("Error sending response: {}", e)))
+    })?;
+    stream.flush().map_err(|e| {
+        UnifiedError::from_ais_error(AisError::new(format!("Error while flushing buffer: {}", e)))
+    })?;
+
+    Ok(())
+}
+
+fn start_control_server(
+    emails: Arc<RwLock<Vec<TimedEmail>>>,
+    errors: Arc<RwLock<Vec<ErrorEmail>>>,
+    config: Arc<AisConfig>,
+) -> io::Result<()> {
+    let path: &Path = Path::new(CONTROL_SOCKET);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    notice(&format!("Control server listening on {}", CONTROL_SOCKET));
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(err) = handle_control_client(
+                    stream,
+                    Arc::clone(&emails),
+                    Arc::clone(&errors),
+                    Arc::clone(&config),
+                ) {
+                    warn(&format!("Error handling control client: {}", err));
+                }
+            }
+            Err(err) => {
+                warn(&format!("Error accepting control connection: {}", err));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Listens for clients on the endpoint, a socket file left behind by an earlier run is replaced.
+fn start_server(
+    endpoint: &MailEndpoint,
+    emails: Arc<RwLock<Vec<TimedEmail>>>,
+    heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>>,
+    config: Arc<AisConfig>,
+    key: Arc<SigningKey>,
+) -> io::Result<()> {
+    notice(&format!("Server listening on {}", endpoint));
+    match endpoint {
+        MailEndpoint::Tcp(address) => {
+            let listener = TcpListener::bind(address)?;
+            serve_clients(listener.incoming(), emails, heartbeats, config, key);
+        }
+        MailEndpoint::Unix(path) => {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let _ = fs::remove_file(path);
+            let listener = UnixListener::bind(path)?;
+            // Clients drop to www-data for the website loop, every frame is signed so that's safe
+            fs::set_permissions(path, fs::Permissions::from_mode(0o666))?;
+            serve_clients(listener.incoming(), emails, heartbeats, config, key);
+        }
+    }
+    Ok(())
+}
+
+/// Handles every client that connects on its own thread.
+fn serve_clients<S, I>(
+    incoming: I,
+    emails: Arc<RwLock<Vec<TimedEmail>>>,
+    heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>>,
+    config: Arc<AisConfig>,
+    key: Arc<SigningKey>,
+) where
+    S: Read + Write + Send + 'static,
+    I: Iterator<Item = io::Result<S>>,
+{
+    let seen_ids: Arc<RwLock<SeenIds>> =
+        Arc::new(RwLock::new(SeenIds::new(DEDUPE_WINDOW, DEDUPE_CAPACITY)));
+
+    for stream in incoming {
+        match stream {
+            Ok(stream) => {
+                let emails_clone = Arc::clone(&emails);
+                let heartbeats_clone = Arc::clone(&heartbeats);
+                let config_clone = Arc::clone(&config);
+                let key_clone = Arc::clone(&key);
+                let seen_ids_clone = Arc::clone(&seen_ids);
+                thread::spawn(move || {
+                    if let Err(err) = handle_client(
+                        stream,
+                        emails_clone,
+                        heartbeats_clone,
+                        config_clone,
+                        key_clone,
+                        seen_ids_clone,
+                        decrypt_received_data,
+                    ) {
+                        warn(&format!("Error handling client: {}", err));
+                    }
+                });
+            }
+            Err(err) => {
+                warn(&format!("Error accepting connection: {}", err));
+            }
+        }
+    }
+}
+
+/// Queues the emails a client in the same process hands over, see `route_mail_in_process`.
+pub fn receive_in_process(
+    receiver: Receiver<Email>,
+    emails: Arc<RwLock<Vec<TimedEmail>>>,
+    config: Arc<AisConfig>,
+) {
+    for email in receiver {
+        let timed_email = TimedEmail {
+            email,
+            received_at: Instant::now(),
+        };
+        let mut email_vec = match emails.write() {
+            Ok(email_vec) => email_vec,
+            Err(_) => {
+                warn("Failed to acquire write lock on emails vector");
+                continue;
+            }
+        };
+        if let Err(e) = enqueue_email(
+            &mut email_vec,
+            timed_email,
+            config.mail_queue_capacity,
+            config.mail_drop_policy,
+        ) {
+            warn(&e.to_string());
+        }
+    }
+}
+
+/// Entry point of the messaging server
+pub fn main() {
+    run(None)
+}
+
+/// Runs the messaging server until its listener fails. Emails from a client in the same
+/// process arrive on the receiver, others over the endpoint.
+pub fn run(in_process: Option<Receiver<Email>>) {
+    let config: Arc<AisConfig> = match AisConfig::load_and_validate() {
+        Ok(config) => Arc::new(config),
+        Err(problems) => {
+            for problem in &problems {
+                dump(&format!("Invalid config, {}", problem));
+            }
+            halt("Fix the config at /etc/artisan.config and restart");
+            return;
+        }
+    };
+    log_file::init(&config);
+    let endpoint: MailEndpoint = match &config.mail_endpoint {
+        Some(endpoint) => MailEndpoint::parse(endpoint),
+        None => MailEndpoint::Tcp(String::from("0.0.0.0:1827")),
+    };
+
+    // Without the key every email would be rejected, so don't start
+    let key: Arc<SigningKey> = match SigningKey::load(&PathType::Str(SIGNING_KEY_PATH.into())) {
+        Ok(key) => Arc::new(key),
+        Err(e) => {
+            halt(&format!("Error loading the signing key: {}", e));
+            return;
+        }
+    };
+
+    // Vector to store emails
+    let emails: Arc<RwLock<Vec<TimedEmail>>> = Arc::new(RwLock::new(Vec::new()));
+    let errors: Arc<RwLock<Vec<ErrorEmail>>> = Arc::new(RwLock::new(Vec::new()));
+
+    // Start the email processing loop in a separate thread
+    let emails_clone: Arc<RwLock<Vec<TimedEmail>>> = Arc::clone(&emails);
+    let errors_clone: Arc<RwLock<Vec<ErrorEmail>>> = Arc::clone(&errors);
+    let config_clone: Arc<AisConfig> = Arc::clone(&config);
+    thread::spawn(move || process_emails(emails_clone, errors_clone, config_clone));
+
+    // Start watching for clients that stopped heart-beating
+    let heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>> =
+        Arc::new(RwLock::new(HashMap::new()));
+    let heartbeats_clone = Arc::clone(&heartbeats);
+    let emails_clone: Arc<RwLock<Vec<TimedEmail>>> = Arc::clone(&emails);
+    let config_clone: Arc<AisConfig> = Arc::clone(&config);
+    thread::spawn(move || monitor_heartbeats(heartbeats_clone, emails_clone, config_clone));
+
+    // Emails from a client in this process skip the socket
+    if let Some(receiver) = in_process {
+        let emails_clone: Arc<RwLock<Vec<TimedEmail>>> = Arc::clone(&emails);
+        let config_clone: Arc<AisConfig> = Arc::clone(&config);
+        thread::spawn(move || receive_in_process(receiver, emails_clone, config_clone));
+    }
+
+    // Start the control server
+    let emails_clone: Arc<RwLock<Vec<TimedEmail>>> = Arc::clone(&emails);
+    let errors_clone: Arc<RwLock<Vec<ErrorEmail>>> = Arc::clone(&errors);
+    let config_clone: Arc<AisConfig> = Arc::clone(&config);
+    thread::spawn(move || {
+        if let Err(err) = start_control_server(emails_clone, errors_clone, config_clone) {
+            warn(&format!("Error starting control server: {}", err));
+        }
+    });
+
+    // Start the server
+    if let Err(err) = start_server(&endpoint, emails, heartbeats, config, key) {
+        halt(&format!("Error starting server: {}", err));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::loops::JOURNAL_EXCERPT_MAX_BYTES;
+    use crate::emails::{EmailSecure, SIGNATURE_SEPARATOR};
+    use crate::encrypt::{open_record, SEALED_PREFIX};
+    use crate::test_support::MockCipher;
+
+    fn test_key() -> Arc<SigningKey> {
+        Arc::new(SigningKey::new(b"test secret".to_vec()))
+    }
+
+    fn seen_ids() -> Arc<RwLock<SeenIds>> {
+        Arc::new(RwLock::new(SeenIds::new(DEDUPE_WINDOW, DEDUPE_CAPACITY)))
+    }
+
+    fn timed(subject: &str, priority: EmailPriority) -> TimedEmail {
+        timed_category(subject, priority, EmailCategory::General)
+    }
+
+    fn timed_category(
+        subject: &str,
+        priority: EmailPriority,
+        category: EmailCategory,
+    ) -> TimedEmail {
+        TimedEmail {
+            email: Email {
+                subject: subject.to_owned(),
+                body: "body".to_owned(),
+                priority,
+                category,
+            },
+            received_at: Instant::now(),
+        }
+    }
+
+    /// An in memory stream, reads come from the input and writes are kept for inspection.
+    struct MockStream {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl MockStream {
+        fn new(input: &str) -> Self {
+            MockStream {
+                input: io::Cursor::new(input.as_bytes().to_vec()),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn control(command: &str, emails: &Arc<RwLock<Vec<TimedEmail>>>) -> String {
+        let errors: Arc<RwLock<Vec<ErrorEmail>>> = Arc::new(RwLock::new(Vec::new()));
+        handle_control_command(command, emails, &errors, &AisConfig::default())
+    }
+
+    fn control_queue() -> Arc<RwLock<Vec<TimedEmail>>> {
+        Arc::new(RwLock::new(vec![
+            timed_category("ssh 0", EmailPriority::High, EmailCategory::SshAudit),
+            timed_category(
+                "service 0",
+                EmailPriority::Low,
+                EmailCategory::ServiceStatus,
+            ),
+            timed_category("ssh 1", EmailPriority::High, EmailCategory::SshAudit),
+        ]))
+    }
+
+    #[test]
+    fn test_control_list() {
+        let emails = control_queue();
+        let response = control("LIST", &emails);
+        let lines: Vec<&str> = response.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("0 High SshAudit"));
+        assert!(lines[1].ends_with("service 0"));
+        assert_eq!(emails.read().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_control_purge_category() {
+        let emails = control_queue();
+        let response = control("PURGE SshAudit\n", &emails);
+        assert_eq!(response, "Purged 2 emails");
+
+        let remaining = emails.read().unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].email.category, EmailCategory::ServiceStatus);
+    }
+
+    #[test]
+    fn test_control_rejects_unknown() {
+        let emails = control_queue();
+        assert_eq!(control("PURGE Nope", &emails), "Unknown category: Nope");
+        assert!(control("DROP TABLE", &emails).starts_with("Unknown command"));
+        assert_eq!(control("CLEAR", &emails), "Purged 3 emails");
+        assert_eq!(control("LIST", &emails), "No queued emails");
+    }
+
+    #[test]
+    fn test_control_peer_must_be_allowed() {
+        let (client, _server) = UnixStream::pair().unwrap();
+        let uid: u32 = nix::unistd::geteuid().as_raw();
+        assert!(check_control_peer(&client, uid).is_ok());
+
+        match check_control_peer(&client, uid + 1) {
+            Err(UnifiedError::AisError(_, AisError::PermissionDenied(Some(message)))) => {
+                assert_eq!(
+                    message,
+                    format!("Rejected control connection from uid {}", uid)
+                )
+            }
+            other => panic!("Expected the peer to be rejected, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_control_requeue_failed_email() {
+        let emails: Arc<RwLock<Vec<TimedEmail>>> = Arc::new(RwLock::new(Vec::new()));
+        let errors: Arc<RwLock<Vec<ErrorEmail>>> = Arc::new(RwLock::new(vec![
+            ErrorEmail {
+                hash: "0123456789".to_owned(),
+                subject: None,
+                occoured_at: Instant::now(),
+                email: None,
+            },
+            ErrorEmail {
+                hash: "abcdef0123".to_owned(),
+                subject: Some("relay unreachable".to_owned()),
+                occoured_at: Instant::now(),
+                email: Some(timed("ssh 0", EmailPriority::High).email),
+            },
+        ]));
+        let config = AisConfig::default();
+
+        let listing = handle_control_command("ERRORS", &emails, &errors, &config);
+        assert_eq!(listing.lines().count(), 2);
+        assert_eq!(
+            handle_control_command("REQUEUE 0", &emails, &errors, &config),
+            "Error 0 has no email to requeue"
+        );
+        assert_eq!(
+            handle_control_command("REQUEUE 1", &emails, &errors, &config),
+            "Requeued: ssh 0"
+        );
+
+        let queue = emails.read().unwrap();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].email.subject, "ssh 0");
+        assert_eq!(errors.read().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_flooded_category_doesnt_block_others() {
+        let config = AisConfig {
+            mail_category_limits: HashMap::from([(EmailCategory::SshAudit, 2)]),
+            ..AisConfig::default()
+        };
+        let mut email_vec: Vec<TimedEmail> = (0..10)
+            .map(|index| {
+                timed_category(
+                    &format!("ssh {}", index),
+                    EmailPriority::High,
+                    EmailCategory::SshAudit,
+                )
+            })
+            .collect();
+        email_vec.push(timed("update", EmailPriority::Low));
+        let mut email_errors: Vec<ErrorEmail> = Vec::new();
+        let mut sent: Vec<String> = Vec::new();
+
+        send_pass(
+            &mut email_vec,
+            &mut email_errors,
+            &config,
+            Instant::now(),
+            |email| {
+                sent.push(email.subject.clone());
+                Ok(())
+            },
+        );
+
+        assert_eq!(sent, vec!["ssh 0", "ssh 1", "update"]);
+        // The rest of the flood is queued for the next pass, not dropped
+        assert_eq!(email_vec.len(), 8);
+        assert_eq!(email_vec[0].email.subject, "ssh 2");
+    }
+
+    #[test]
+    fn test_storm_collapses_into_digests_until_it_calms_down() {
+        let window = Duration::from_secs(300);
+        let mut storms = Storms::new(3, window);
+        let service = |index: usize| {
+            timed_category(
+                &format!("service {}", index),
+                EmailPriority::High,
+                EmailCategory::ServiceStatus,
+            )
+        };
+        let start = Instant::now();
+
+        // Three in the window is still normal
+        let mut email_vec: Vec<TimedEmail> = (0..3).map(service).collect();
+        assert!(storms.collapse(&mut email_vec, start).is_empty());
+        assert_eq!(email_vec.len(), 3);
+        email_vec.clear();
+
+        // The fourth makes it a storm, the others keep flowing
+        let later = start + Duration::from_secs(60);
+        let mut email_vec: Vec<TimedEmail> = (3..8)
+            .map(|index| TimedEmail {
+                received_at: later - Duration::from_secs(1),
+                ..service(index)
+            })
+            .collect();
+        email_vec.push(TimedEmail {
+            received_at: later - Duration::from_secs(1),
+            ..timed("update", EmailPriority::Low)
+        });
+        assert!(storms.collapse(&mut email_vec, later).is_empty());
+        assert_eq!(email_vec.len(), 1);
+        assert_eq!(email_vec[0].email.subject, "update");
+
+        // Once the window passes quietly the held emails go out as one digest
+        let digests = storms.collapse(&mut Vec::new(), later + window);
+        assert_eq!(digests.len(), 1);
+        assert_eq!(digests[0].category, EmailCategory::ServiceStatus);
+        assert_eq!(digests[0].priority, EmailPriority::High);
+        assert!(digests[0].subject.starts_with("5 ServiceStatus events"));
+        assert!(digests[0].body.contains("service 3"));
+        assert!(digests[0].body.contains("service 7"));
+
+        // Back to normal, a single email is sent as is
+        let after = later + window + Duration::from_secs(60);
+        let mut email_vec = vec![TimedEmail {
+            received_at: after - Duration::from_secs(1),
+            ..service(8)
+        }];
+        assert!(storms.collapse(&mut email_vec, after).is_empty());
+        assert_eq!(email_vec.len(), 1);
+    }
+
+    #[test]
+    fn test_full_queue_admits_high_by_evicting_low() {
+        for policy in [DropPolicy::DropOldestLow, DropPolicy::RejectNew] {
+            let mut queue: Vec<TimedEmail> = Vec::new();
+            for i in 0..3 {
+                enqueue_email(
+                    &mut queue,
+                    timed(&format!("low {}", i), EmailPriority::Low),
+                    3,
+                    policy,
+                )
+                .unwrap();
+            }
+
+            enqueue_email(&mut queue, timed("high", EmailPriority::High), 3, policy).unwrap();
+
+            assert_eq!(queue.len(), 3);
+            assert_eq!(queue[0].email.subject, "low 1");
+            assert_eq!(queue[2].email.subject, "high");
+        }
+    }
+
+    #[test]
+    fn test_full_queue_drop_policy_for_low() {
+        let mut queue: Vec<TimedEmail> = vec![timed("low 0", EmailPriority::Low)];
+        assert!(enqueue_email(
+            &mut queue,
+            timed("low 1", EmailPriority::Low),
+            1,
+            DropPolicy::RejectNew
+        )
+        .is_err());
+        assert_eq!(queue[0].email.subject, "low 0");
+
+        enqueue_email(
+            &mut queue,
+            timed("low 1", EmailPriority::Low),
+            1,
+            DropPolicy::DropOldestLow,
+        )
+        .unwrap();
+        assert_eq!(queue[0].email.subject, "low 1");
+    }
+
+    #[test]
+    fn test_full_queue_of_high_rejects_new() {
+        let mut queue: Vec<TimedEmail> = vec![timed("high 0", EmailPriority::High)];
+        assert!(enqueue_email(
+            &mut queue,
+            timed("high 1", EmailPriority::High),
+            1,
+            DropPolicy::DropOldestLow
+        )
+        .is_err());
+        assert!(enqueue_email(
+            &mut queue,
+            timed("low", EmailPriority::Low),
+            1,
+            DropPolicy::DropOldestLow
+        )
+        .is_err());
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn test_smtp_settings() {
+        let mut config = AisConfig::default();
+        let settings = SmtpSettings::new("smtp.example.com", &config);
+        assert_eq!(settings.port, 465);
+        assert_eq!(settings.tls, SmtpTls::Implicit);
+        assert_eq!(settings.timeout, Duration::from_secs(60));
+
+        config.smtp_tls = SmtpTls::StartTls;
+        assert_eq!(SmtpSettings::new("smtp.example.com", &config).port, 587);
+        config.smtp_tls = SmtpTls::None;
+        assert_eq!(SmtpSettings::new("smtp.example.com", &config).port, 25);
+
+        config.smtp_tls = SmtpTls::StartTls;
+        config.smtp_port = Some(2525);
+        config.smtp_timeout_secs = 10;
+        let settings = SmtpSettings::new("smtp.example.com", &config);
+        assert_eq!(
+            settings,
+            SmtpSettings {
+                relay: "smtp.example.com".to_owned(),
+                port: 2525,
+                tls: SmtpTls::StartTls,
+                timeout: Duration::from_secs(10),
+            }
+        );
+
+        let credentials = Credentials::new("user".to_owned(), "password".to_owned());
+        assert!(settings.transport(credentials).is_ok());
+    }
+
+    #[test]
+    fn test_deliver_writes_one_audit_record() {
+        let path = std::env::temp_dir().join("ais_mail_audit_test.log");
+        let _ = fs::remove_file(&path);
+        let audit_log = PathType::PathBuf(path.clone());
+        let email = timed_category("ssh 0", EmailPriority::High, EmailCategory::SshAudit).email;
+
+        let retention = RetentionCategory::MailAudit.default_retention();
+        deliver(&email, &audit_log, &retention, None, |_| {
+            Err(UnifiedError::from_ais_error(AisError::EtNoHome(None)))
+        })
+        .unwrap_err();
+        assert!(!path.exists());
+
+        deliver(&email, &audit_log, &retention, None, |_| Ok(())).unwrap();
+        let log = fs::read_to_string(&path).unwrap();
+        assert_eq!(log.lines().count(), 1);
+
+        let record: serde_json::Value = serde_json::from_str(log.trim()).unwrap();
+        assert_eq!(record["category"], "SshAudit");
+        assert_eq!(record["subject_hash"], create_hash("ssh 0".to_owned()));
+        assert!(!log.contains("ssh 0"));
+        assert!(time::parse(record["timestamp"].as_str().unwrap()).is_ok());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_ssh_audit_record_encrypted_at_rest() {
+        let path = std::env::temp_dir().join("ais_mail_audit_sealed.log");
+        let _ = fs::remove_file(&path);
+        let audit_log = PathType::PathBuf(path.clone());
+        let ssh = timed_category("ssh 0", EmailPriority::High, EmailCategory::SshAudit).email;
+        let retention = RetentionCategory::MailAudit.default_retention();
+
+        record_audit(
+            &audit_log,
+            &retention,
+            &ssh,
+            "ops@example.com",
+            Some(&MockCipher::new()),
+        )
+        .unwrap();
+        let log = fs::read_to_string(&path).unwrap();
+        assert!(log.starts_with(SEALED_PREFIX));
+        assert!(!log.contains("SshAudit") && !log.contains("ops@example.com"));
+
+        let record: serde_json::Value =
+            serde_json::from_str(&open_record(log.trim(), &MockCipher::new()).unwrap()).unwrap();
+        assert_eq!(record["category"], "SshAudit");
+        assert_eq!(record["recipient"], "ops@example.com");
+
+        // With dusa down the ssh audit isn't written in plain text, a routine email still is
+        let routine = timed("low", EmailPriority::Low).email;
+        let down = Some(&MockCipher::unavailable() as &dyn Cipher);
+        assert!(record_audit(&audit_log, &retention, &ssh, "ops@example.com", down).is_err());
+        record_audit(&audit_log, &retention, &routine, "ops@example.com", down).unwrap();
+        let lines: Vec<String> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| line.to_owned())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains("\"General\""));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_audit_log_rotates() {
+        let path = std::env::temp_dir().join("ais_mail_audit_rotate.log");
+        let rotated = std::env::temp_dir().join("ais_mail_audit_rotate.log.1");
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&rotated);
+        let audit_log = PathType::PathBuf(path.clone());
+        let email = timed("low", EmailPriority::Low).email;
+
+        let retention = Retention {
+            max_bytes: 1,
+            max_files: 1,
+            max_age_days: 0,
+        };
+
+        record_audit(&audit_log, &retention, &email, "ops@example.com", None).unwrap();
+        record_audit(&audit_log, &retention, &email, "ops@example.com", None).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+        assert_eq!(fs::read_to_string(&rotated).unwrap().lines().count(), 1);
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&rotated);
+    }
+
+    #[test]
+    fn test_email_round_trips_over_unix_socket() {
+        let path = std::env::temp_dir().join("ais_mail_round_trip.sock");
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let emails: Arc<RwLock<Vec<TimedEmail>>> = Arc::new(RwLock::new(Vec::new()));
+
+        let server = {
+            let emails = Arc::clone(&emails);
+            thread::spawn(move || {
+                let (stream, _) = listener.accept().unwrap();
+                // The client doesn't wait for the acknowledgement, so it can't always be written
+                let _ = handle_client(
+                    stream,
+                    emails,
+                    Arc::new(RwLock::new(HashMap::new())),
+                    Arc::new(AisConfig::default()),
+                    test_key(),
+                    seen_ids(),
+                    |_: &str| panic!("plain emails aren't decrypted"),
+                );
+            })
+        };
+
+        let mut email = Email::new("Heads up".to_owned(), "Backups ran late".to_owned());
+        email.category = EmailCategory::Summary;
+        EmailSecure::new_plain(email)
+            .unwrap()
+            .send_to(
+                &MailEndpoint::parse(&format!("unix:{}", path.display())),
+                &test_key(),
+            )
+            .unwrap();
+        server.join().unwrap();
+
+        let queue = emails.read().unwrap();
+        assert_eq!(queue.len(), 1);
+        assert!(queue[0].email.subject.ends_with("Heads up"));
+        assert_eq!(queue[0].email.body, "Backups ran late");
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_handle_client_queues_email() {
+        let emails: Arc<RwLock<Vec<TimedEmail>>> = Arc::new(RwLock::new(Vec::new()));
+        let heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>> =
+            Arc::new(RwLock::new(HashMap::new()));
+        let mut stream = MockStream::new(&test_key().seal("canned-cipher-text"));
+
+        handle_client(
+            &mut stream,
+            Arc::clone(&emails),
+            Arc::clone(&heartbeats),
+            Arc::new(AisConfig::default()),
+            test_key(),
+            seen_ids(),
+            |data: &str| {
+                assert_eq!(data, "canned-cipher-text");
+                Ok(hex::encode("Disk full-=-Only 2% left-=-High-=-Resource"))
+            },
+        )
+        .unwrap();
+
+        let queue = emails.read().unwrap();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].email.subject, "Disk full");
+        assert_eq!(queue[0].email.body, "Only 2% left");
+        assert_eq!(queue[0].email.priority, EmailPriority::High);
+        assert_eq!(queue[0].email.category, EmailCategory::Resource);
+        assert_eq!(stream.output, b"Email received");
+    }
+
+    #[test]
+    fn test_handle_client_reads_a_full_size_alert() {
+        let emails: Arc<RwLock<Vec<TimedEmail>>> = Arc::new(RwLock::new(Vec::new()));
+        let heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>> =
+            Arc::new(RwLock::new(HashMap::new()));
+        let excerpt: String = "x".repeat(JOURNAL_EXCERPT_MAX_BYTES);
+        let mut email = Email::new("ufw.service failed".to_owned(), excerpt.clone());
+        email.category = EmailCategory::ServiceStatus;
+        let cipher = MockCipher::new();
+        let frame = EmailSecure::new_with(email, &cipher)
+            .unwrap()
+            .frame(&test_key());
+        // Longer than the single 2048 byte read that used to cut frames off
+        assert!(frame.len() > 2048);
+        let mut stream = MockStream::new(&frame);
+
+        handle_client(
+            &mut stream,
+            Arc::clone(&emails),
+            Arc::clone(&heartbeats),
+            Arc::new(AisConfig::default()),
+            test_key(),
+            seen_ids(),
+            |data: &str| Ok(cipher.decrypt(data)?.unwrap_or_default()),
+        )
+        .unwrap();
+
+        let queue = emails.read().unwrap();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].email.body, excerpt);
+        assert_eq!(stream.output, b"Email received");
+    }
+
+    #[test]
+    fn test_handle_client_reads_plain_email() {
+        let emails: Arc<RwLock<Vec<TimedEmail>>> = Arc::new(RwLock::new(Vec::new()));
+        let heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>> =
+            Arc::new(RwLock::new(HashMap::new()));
+        let mut email = Email::new("Heads up".to_owned(), "Backups ran late".to_owned());
+        email.category = EmailCategory::Summary;
+        let email_secure = EmailSecure::new_plain(email).unwrap();
+        let mut stream = MockStream::new(&email_secure.frame(&test_key()));
+
+        handle_client(
+            &mut stream,
+            Arc::clone(&emails),
+            Arc::clone(&heartbeats),
+            Arc::new(AisConfig::default()),
+            test_key(),
+            seen_ids(),
+            |_: &str| panic!("plain emails aren't decrypted"),
+        )
+        .unwrap();
+
+        let queue = emails.read().unwrap();
+        assert_eq!(queue.len(), 1);
+        assert!(queue[0].email.subject.ends_with("Heads up"));
+        assert_eq!(queue[0].email.category, EmailCategory::Summary);
+        assert_eq!(stream.output, b"Email received");
+    }
+
+    #[test]
+    fn test_handle_client_rejects_plain_sensitive_email() {
+        let emails: Arc<RwLock<Vec<TimedEmail>>> = Arc::new(RwLock::new(Vec::new()));
+        let heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>> =
+            Arc::new(RwLock::new(HashMap::new()));
+        let frame = format!(
+            "{}{}",
+            PLAIN_PREFIX,
+            hex::encode("Ssh login-=-root from 10.0.0.5-=-High-=-SshAudit")
+        );
+        let mut stream = MockStream::new(&test_key().seal(&frame));
+
+        assert!(handle_client(
+            &mut stream,
+            Arc::clone(&emails),
+            Arc::clone(&heartbeats),
+            Arc::new(AisConfig::default()),
+            test_key(),
+            seen_ids(),
+            |_: &str| panic!("plain emails aren't decrypted"),
+        )
+        .is_err());
+        assert!(emails.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_handle_client_rejects_plain_email_that_isnt_utf8() {
+        let emails: Arc<RwLock<Vec<TimedEmail>>> = Arc::new(RwLock::new(Vec::new()));
+        let heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>> =
+            Arc::new(RwLock::new(HashMap::new()));
+        let mut data: Vec<u8> = b"Heads up-=-".to_vec();
+        data.extend_from_slice(&[0xff, 0xfe]);
+        data.extend_from_slice(b"-=-Low-=-Summary");
+        let frame = format!("{}{}", PLAIN_PREFIX, hex::encode(data));
+        let mut stream = MockStream::new(&test_key().seal(&frame));
+
+        assert!(handle_client(
+            &mut stream,
+            Arc::clone(&emails),
+            Arc::clone(&heartbeats),
+            Arc::new(AisConfig::default()),
+            test_key(),
+            seen_ids(),
+            |_: &str| panic!("plain emails aren't decrypted"),
+        )
+        .is_err());
+        assert!(emails.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_handle_client_rejects_tampered_email() {
+        let emails: Arc<RwLock<Vec<TimedEmail>>> = Arc::new(RwLock::new(Vec::new()));
+        let heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>> =
+            Arc::new(RwLock::new(HashMap::new()));
+        let email = Email::new("Heads up".to_owned(), "Backups ran late".to_owned());
+        let frame = EmailSecure::new_plain(email).unwrap().frame(&test_key());
+
+        // Swap the payload but keep the original signature
+        let (_, signature) = frame.rsplit_once(SIGNATURE_SEPARATOR).unwrap();
+        let forged_payload = format!(
+            "{}{}",
+            PLAIN_PREFIX,
+            hex::encode("Send money-=-Wire it to me-=-High-=-General")
+        );
+        let forged = format!("{}{}{}", forged_payload, SIGNATURE_SEPARATOR, signature);
+
+        for received in [forged, forged_payload] {
+            let mut stream = MockStream::new(&received);
+            assert!(handle_client(
+                &mut stream,
+                Arc::clone(&emails),
+                Arc::clone(&heartbeats),
+                Arc::new(AisConfig::default()),
+                test_key(),
+                seen_ids(),
+                |_: &str| panic!("forged emails aren't decrypted"),
+            )
+            .is_err());
+        }
+        assert!(emails.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_handle_client_drops_retransmits() {
+        let emails: Arc<RwLock<Vec<TimedEmail>>> = Arc::new(RwLock::new(Vec::new()));
+        let heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>> =
+            Arc::new(RwLock::new(HashMap::new()));
+        let seen = seen_ids();
+        let email = Email::new("Heads up".to_owned(), "Backups ran late".to_owned());
+        let frame = EmailSecure::new_plain(email).unwrap().frame(&test_key());
+
+        for _ in 0..2 {
+            let mut stream = MockStream::new(&frame);
+            handle_client(
+                &mut stream,
+                Arc::clone(&emails),
+                Arc::clone(&heartbeats),
+                Arc::new(AisConfig::default()),
+                test_key(),
+                Arc::clone(&seen),
+                |_: &str| panic!("plain emails aren't decrypted"),
+            )
+            .unwrap();
+            // The retransmit is still acknowledged so the client stops sending it
+            assert_eq!(stream.output, b"Email received");
+        }
+
+        assert_eq!(emails.read().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_handle_client_takes_a_retransmit_that_failed_to_decrypt() {
+        let emails: Arc<RwLock<Vec<TimedEmail>>> = Arc::new(RwLock::new(Vec::new()));
+        let heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>> =
+            Arc::new(RwLock::new(HashMap::new()));
+        let seen = seen_ids();
+        let frame = test_key().seal(&format!("canned-cipher-text{}a1b2c3", EMAIL_ID_SEPARATOR));
+        let dusa_up = std::cell::Cell::new(false);
+
+        for _ in 0..2 {
+            let mut stream = MockStream::new(&frame);
+            let _ = handle_client(
+                &mut stream,
+                Arc::clone(&emails),
+                Arc::clone(&heartbeats),
+                Arc::new(AisConfig::default()),
+                test_key(),
+                Arc::clone(&seen),
+                |_: &str| match dusa_up.replace(true) {
+                    true => Ok(hex::encode("Disk full-=-Only 2% left-=-High-=-Resource")),
+                    false => Err(UnifiedError::from_ais_error(AisError::EncryptionNotReady(
+                        None,
+                    ))),
+                },
+            );
+        }
+
+        let queue = emails.read().unwrap();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].email.subject, "Disk full");
+        assert!(seen.read().unwrap().contains("a1b2c3", Instant::now()));
+    }
+
+    #[test]
+    fn test_seen_ids_window_and_capacity() {
+        let now = Instant::now();
+        let mut seen = SeenIds::new(Duration::from_secs(60), 2);
+
+        assert!(seen.record("a", now));
+        assert!(!seen.record("a", now));
+        // Once the window passes the id is forgotten
+        assert!(seen.record("a", now + Duration::from_secs(60)));
+
+        let mut seen = SeenIds::new(Duration::from_secs(60), 2);
+        assert!(seen.record("a", now));
+        assert!(seen.record("b", now));
+        assert!(seen.record("c", now));
+        // The oldest id was dropped to stay within capacity
+        assert!(seen.record("a", now));
+    }
+
+    #[test]
+    fn test_handle_client_records_heartbeat() {
+        let emails: Arc<RwLock<Vec<TimedEmail>>> = Arc::new(RwLock::new(Vec::new()));
+        let heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>> =
+            Arc::new(RwLock::new(HashMap::new()));
+        let mut stream = MockStream::new(&Heartbeat::new("a1b2c3d4".to_owned()).frame(&test_key()));
+
+        handle_client(
+            &mut stream,
+            Arc::clone(&emails),
+            Arc::clone(&heartbeats),
+            Arc::new(AisConfig::default()),
+            test_key(),
+            seen_ids(),
+            |_: &str| panic!("heartbeats aren't decrypted"),
+        )
+        .unwrap();
+
+        assert!(emails.read().unwrap().is_empty());
+        assert!(heartbeats.read().unwrap().contains_key("a1b2c3d4"));
+        assert!(stream.output.is_empty());
+    }
+
+    #[test]
+    fn test_handle_client_rejects_unsigned_heartbeat() {
+        let emails: Arc<RwLock<Vec<TimedEmail>>> = Arc::new(RwLock::new(Vec::new()));
+        let heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>> =
+            Arc::new(RwLock::new(HashMap::new()));
+        let forged: String = format!("{}a1b2c3d4", crate::emails::HEARTBEAT_PREFIX);
+        let mut stream = MockStream::new(&forged);
+
+        let result = handle_client(
+            &mut stream,
+            Arc::clone(&emails),
+            Arc::clone(&heartbeats),
+            Arc::new(AisConfig::default()),
+            test_key(),
+            seen_ids(),
+            |_: &str| panic!("heartbeats aren't decrypted"),
+        );
+
+        assert!(result.is_err());
+        assert!(heartbeats.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_missed_heartbeat_alerts_once() {
+        let heartbeats: Arc<RwLock<HashMap<String, HeartbeatRecord>>> =
+            Arc::new(RwLock::new(HashMap::new()));
+        record_heartbeat(Heartbeat::new("a1b2c3d4".to_owned()), &heartbeats);
+
+        let now = Instant::now();
+        assert!(collect_missed_heartbeats(&heartbeats, now, Duration::from_secs(60)).is_empty());
+
+        let later = now + Duration::from_secs(120);
+        let missed = collect_missed_heartbeats(&heartbeats, later, Duration::from_secs(60));
+        assert_eq!(missed, vec!["a1b2c3d4".to_owned()]);
+
+        // Already alerted for this outage
+        assert!(collect_missed_heartbeats(&heartbeats, later, Duration::from_secs(60)).is_empty());
+
+        // A new heartbeat resets the record
+        record_heartbeat(Heartbeat::new("a1b2c3d4".to_owned()), &heartbeats);
+        assert!(!heartbeats.read().unwrap()["a1b2c3d4"].alerted);
+    }
+}
@@ -1,26 +1,80 @@
 /// The `encrypt` module contains functionality related to encryption and decryption.
 pub mod encrypt;
 
+/// The `command` module runs external programs behind a trait so they can be mocked.
+pub mod command;
+
 /// The `errors` module provides error types and error handling utilities for the project.
 pub mod errors;
 
+/// The `time` module formats and parses the canonical timestamps used across the AIS.
+pub mod time;
+
 /// The `service` module encompasses services and processes management functionalities.
 pub mod service;
 
 /// The `emails` module deals with email-related functionalities such as sending and securing emails.
 pub mod emails;
 
+/// The `notify` module delivers alerts through email and the other configured channels.
+pub mod notify;
+
+/// The `dead_letter` module holds the emails that couldn't be encrypted until dusa recovers.
+/// The ones that require encryption are only held in memory and are lost on a restart.
+pub mod dead_letter;
+
+/// The `log_file` module mirrors the operational output into a rotating log file.
+pub mod log_file;
+
+/// The `resources` module reads the host's load, memory and disk use and watches them
+/// against the alert thresholds.
+pub mod resources;
+
+/// The `retention` module rotates and prunes the logs kept on disk.
+pub mod retention;
+
+/// The `site_health` module checks the health urls of the sites and counts their failures.
+pub mod site_health;
+
+/// The `health` module records how each monitoring loop did on its last cycle.
+pub mod health;
+
+/// The `maintenance` module tells the loops when planned maintenance is underway.
+pub mod maintenance;
+
 /// The `git_data` module includes data structures and operations related to Git repositories.
 pub mod git_data;
 
 /// The `ais_data` module holds data structures and utilities specific to the AIS.
 pub mod ais_data;
 
+/// The `ais_config` module holds the operator configurable settings for the AIS.
+pub mod ais_config;
+
+/// The `profile` module selects the endpoints used on staging and production systems.
+pub mod profile;
+
 /// The `site_info` module holds data structures and utilities specific to the website information.
 pub mod site_info;
 
 /// The `git_data` module holds data structures and utilities specific to manipulating git repos.
 pub mod git_actions;
 
+/// The `filesystem` module holds helpers for creating and managing paths on disk.
+pub mod filesystem;
+
+/// The `client` module runs the client's monitoring loops, `ais_client` starts it.
+pub mod client;
+
+/// The `mail_server` module relays the clients' emails through smtp, `mail_server` starts it.
+pub mod mail_server;
+
+/// The `ssh_logger` module receives the sshd entries relayed over syslog.
+pub mod ssh_logger;
+
 /// The `ais_security` module holds functions to run to verity that the ais is running in a controlled enviornment
-pub mod ais_security;
\ No newline at end of file
+pub mod ais_security;
+
+/// The `test_support` module holds the test doubles the unit tests share.
+#[cfg(test)]
+pub(crate) mod test_support;
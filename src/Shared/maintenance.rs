@@ -0,0 +1,71 @@
+use crate::time;
+use chrono::{DateTime, Duration, Utc};
+use std::{fs, path::Path};
+
+/// The flag file that turns maintenance mode on. It holds the time maintenance ends as a
+/// canonical timestamp, e.g. `echo 2024-04-29T18:00:00Z > /run/artisan/maintenance`.
+pub const MAINTENANCE_FLAG: &str = "/run/artisan/maintenance";
+
+/// How long maintenance lasts when the flag doesn't say when it ends.
+pub const DEFAULT_MAINTENANCE_HOURS: i64 = 2;
+
+/// The longest a flag can keep maintenance on, so a typo can't silence alerts for a year.
+pub const MAX_MAINTENANCE_HOURS: i64 = 24;
+
+/// Returns when maintenance ends, or None if maintenance mode is off or has expired.
+pub fn maintenance_until(flag: &Path, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let metadata = fs::metadata(flag).ok()?;
+    let set_at: DateTime<Utc> = metadata.modified().ok()?.into();
+    let latest: DateTime<Utc> = set_at + Duration::hours(MAX_MAINTENANCE_HOURS);
+
+    let until: DateTime<Utc> = match fs::read_to_string(flag)
+        .ok()
+        .and_then(|contents| time::parse(&contents).ok())
+    {
+        Some(until) => until.min(latest),
+        None => set_at + Duration::hours(DEFAULT_MAINTENANCE_HOURS),
+    };
+
+    Some(until).filter(|until| now < *until)
+}
+
+/// Checks if maintenance mode is on.
+pub fn in_maintenance(flag: &Path) -> bool {
+    maintenance_until(flag, Utc::now()).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maintenance_expires() {
+        let flag = std::env::temp_dir().join("ais_maintenance_expires");
+        let now = Utc::now();
+
+        let _ = fs::remove_file(&flag);
+        assert_eq!(maintenance_until(&flag, now), None);
+
+        let until = now + Duration::hours(1);
+        fs::write(&flag, time::format(until)).unwrap();
+        assert!(maintenance_until(&flag, now).is_some());
+        assert_eq!(maintenance_until(&flag, until + Duration::seconds(1)), None);
+
+        // Without an end time the default applies
+        fs::write(&flag, "").unwrap();
+        assert!(maintenance_until(&flag, now).is_some());
+        assert_eq!(
+            maintenance_until(&flag, now + Duration::hours(DEFAULT_MAINTENANCE_HOURS + 1)),
+            None
+        );
+
+        // An end time far out is capped
+        fs::write(&flag, time::format(now + Duration::days(365))).unwrap();
+        assert_eq!(
+            maintenance_until(&flag, now + Duration::hours(MAX_MAINTENANCE_HOURS + 1)),
+            None
+        );
+
+        let _ = fs::remove_file(&flag);
+    }
+}
@@ -0,0 +1,400 @@
+use crate::{
+    ais_config::AisConfig,
+    dead_letter::DeadLetters,
+    emails::{Email, EmailPriority, EmailSecure},
+    encrypt::DusaCipher,
+    errors::{AisError, UnifiedError},
+    log_file::warn,
+    maintenance::{in_maintenance, MAINTENANCE_FLAG},
+    retention::Retention,
+    time,
+};
+use isahc::{prelude::*, Request};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Sender},
+        Arc, OnceLock,
+    },
+    thread,
+};
+
+/// Where routine notices held back during maintenance are recorded.
+pub const SUPPRESSED_LOG_PATH: &str = "/var/log/ais/suppressed.log";
+
+/// Emails handed straight to a messaging server in the same process, set by the combined
+/// binary so they skip the socket.
+static IN_PROCESS_MAIL: OnceLock<Sender<Email>> = OnceLock::new();
+
+/// A channel alerts and notices are delivered through.
+pub trait Notifier: Send + Sync {
+    /// Delivers the email, the category and priority are read from it.
+    fn notify(&self, email: &Email) -> Result<(), UnifiedError>;
+}
+
+/// Enum representing a notification channel in the config.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum NotifierConfig {
+    /// Emails relayed by the messaging server, encrypted when their category requires it.
+    Email,
+    /// A json POST to a webhook, like a slack incoming webhook.
+    Webhook {
+        /// The url the notification is posted to.
+        url: String,
+        /// Only deliver high priority notifications.
+        #[serde(default)]
+        high_priority_only: bool,
+    },
+}
+
+/// A step in the escalation chain for conditions that stay unresolved.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EscalationStage {
+    /// How long the condition has to last, in minutes, before this stage is notified.
+    pub after_mins: u64,
+    /// The channels this stage is delivered through.
+    pub notifiers: Vec<NotifierConfig>,
+}
+
+/// Delivers notifications through the messaging server.
+pub struct EmailNotifier;
+
+/// Delivers notifications by posting them to a webhook.
+pub struct WebhookNotifier {
+    url: String,
+    high_priority_only: bool,
+}
+
+/// Delivers notifications through every configured channel.
+pub struct Notifiers(Vec<Box<dyn Notifier>>);
+
+/// Hands notifications to a thread that delivers them through another channel, for threads
+/// that dropped their privileges and can't reach dusa, the config or the logs themselves.
+pub struct RelayNotifier(Sender<Email>);
+
+/// Holds back routine notices while maintenance mode is on, recording them instead.
+pub struct MaintenanceNotifier {
+    inner: Box<dyn Notifier>,
+    flag: PathBuf,
+    record: PathBuf,
+    retention: Retention,
+}
+
+impl Notifier for EmailNotifier {
+    fn notify(&self, email: &Email) -> Result<(), UnifiedError> {
+        if let Some(sender) = IN_PROCESS_MAIL.get() {
+            return sender.send(email.clone()).map_err(|_| {
+                UnifiedError::from_ais_error(AisError::EtNoHome(Some(String::from(
+                    "The messaging server in this process has stopped",
+                ))))
+            });
+        }
+        // Notices that don't need encrypting go out plain over the internal link, so they
+        // still arrive while dusa is down
+        if !email.category.requires_encryption() {
+            return EmailSecure::new_plain(email.clone())?.send();
+        }
+        // An email dusa can't encrypt waits in the dead letters instead of being lost
+        match DeadLetters::current() {
+            Some(dead_letters) => dead_letters.deliver(email, &DusaCipher, EmailSecure::send),
+            None => EmailSecure::new(email.clone())?.send(),
+        }
+    }
+}
+
+/// Hands every email sent through `EmailNotifier` to a messaging server running in the same
+/// process instead of its socket. Returns false if emails were already routed.
+pub fn route_mail_in_process(sender: Sender<Email>) -> bool {
+    IN_PROCESS_MAIL.set(sender).is_ok()
+}
+
+impl WebhookNotifier {
+    /// Creates a new WebhookNotifier posting to the given url.
+    pub fn new(url: String, high_priority_only: bool) -> Self {
+        WebhookNotifier {
+            url,
+            high_priority_only,
+        }
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, email: &Email) -> Result<(), UnifiedError> {
+        if self.high_priority_only && email.priority != EmailPriority::High {
+            return Ok(());
+        }
+
+        // The text field is what slack shows, the rest is there for other receivers
+        let payload = json!({
+            "text": format!("{}\n{}", email.subject, email.body),
+            "subject": email.subject,
+            "body": email.body,
+            "priority": email.priority.to_string(),
+            "category": email.category.to_string(),
+        });
+        let response = Request::post(&self.url)
+            .header("Content-Type", "application/json")
+            .body(payload.to_string())
+            .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))?
+            .send()
+            .map_err(|e| {
+                UnifiedError::from_ais_error(AisError::EtNoHome(Some(format!(
+                    "Unable to contact the webhook: {}",
+                    e
+                ))))
+            })?;
+
+        match response.status().is_success() {
+            true => Ok(()),
+            false => Err(UnifiedError::from_ais_error(AisError::new(format!(
+                "The webhook responded with {}",
+                response.status()
+            )))),
+        }
+    }
+}
+
+impl Notifiers {
+    /// Creates a new Notifiers delivering through the given channels.
+    pub fn new(notifiers: Vec<Box<dyn Notifier>>) -> Self {
+        Notifiers(notifiers)
+    }
+
+    /// Builds the channels listed in the config.
+    pub fn from_config(config: &AisConfig) -> Self {
+        Self::from_list(&config.notifiers)
+    }
+
+    /// Builds the given channels.
+    pub fn from_list(notifiers: &[NotifierConfig]) -> Self {
+        let notifiers: Vec<Box<dyn Notifier>> = notifiers
+            .iter()
+            .map(|notifier| -> Box<dyn Notifier> {
+                match notifier {
+                    NotifierConfig::Email => Box::new(EmailNotifier),
+                    NotifierConfig::Webhook {
+                        url,
+                        high_priority_only,
+                    } => Box::new(WebhookNotifier::new(url.clone(), *high_priority_only)),
+                }
+            })
+            .collect();
+        Notifiers::new(notifiers)
+    }
+}
+
+impl Notifier for Notifiers {
+    /// Tries every channel so one being down doesn't stop the others, returning the first error.
+    fn notify(&self, email: &Email) -> Result<(), UnifiedError> {
+        let mut result: Result<(), UnifiedError> = Ok(());
+        for notifier in &self.0 {
+            if let Err(e) = notifier.notify(email) {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl RelayNotifier {
+    /// Starts the thread delivering through the channel. It runs with the privileges of the
+    /// calling thread and stops once the RelayNotifier is dropped.
+    pub fn spawn(inner: Arc<dyn Notifier>) -> Self {
+        let (sender, receiver) = mpsc::channel::<Email>();
+        thread::spawn(move || {
+            for email in receiver {
+                if let Err(e) = inner.notify(&email) {
+                    warn(&format!("Failed to relay '{}': {}", email.subject, e));
+                }
+            }
+        });
+        RelayNotifier(sender)
+    }
+}
+
+impl Notifier for RelayNotifier {
+    /// Queues the email for the relay thread, delivery failures are logged there.
+    fn notify(&self, email: &Email) -> Result<(), UnifiedError> {
+        self.0.send(email.clone()).map_err(|_| {
+            UnifiedError::from_ais_error(AisError::ThreadedDataError(Some(String::from(
+                "The notification relay has stopped",
+            ))))
+        })
+    }
+}
+
+impl MaintenanceNotifier {
+    /// Creates a new MaintenanceNotifier in front of the given channel, the record of held
+    /// back notices is kept by the retention.
+    pub fn new(inner: Box<dyn Notifier>, retention: Retention) -> Self {
+        Self::with_paths(
+            inner,
+            PathBuf::from(MAINTENANCE_FLAG),
+            PathBuf::from(SUPPRESSED_LOG_PATH),
+            retention,
+        )
+    }
+
+    /// Creates a new MaintenanceNotifier using the given flag and record files.
+    pub fn with_paths(
+        inner: Box<dyn Notifier>,
+        flag: PathBuf,
+        record: PathBuf,
+        retention: Retention,
+    ) -> Self {
+        MaintenanceNotifier {
+            inner,
+            flag,
+            record,
+            retention,
+        }
+    }
+}
+
+impl Notifier for MaintenanceNotifier {
+    fn notify(&self, email: &Email) -> Result<(), UnifiedError> {
+        match email.category.is_routine() && in_maintenance(&self.flag) {
+            true => record_suppressed(&self.record, &self.retention, email),
+            false => self.inner.notify(email),
+        }
+    }
+}
+
+/// Appends a notice held back during maintenance to the record, one json object per line.
+fn record_suppressed(
+    path: &Path,
+    retention: &Retention,
+    email: &Email,
+) -> Result<(), UnifiedError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))?;
+    }
+    let line: String = json!({
+        "timestamp": time::now(),
+        "priority": email.priority.to_string(),
+        "category": email.category.to_string(),
+        "subject": email.subject,
+    })
+    .to_string();
+    retention
+        .make_room(path, line.len() as u64 + 1)
+        .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))?;
+    writeln!(file, "{}", line)
+        .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emails::EmailCategory;
+    use crate::retention::RetentionCategory;
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Vec<EmailCategory>>>);
+
+    impl Notifier for Recorder {
+        fn notify(&self, email: &Email) -> Result<(), UnifiedError> {
+            self.0.lock().unwrap().push(email.category);
+            Ok(())
+        }
+    }
+
+    struct Failing;
+
+    impl Notifier for Failing {
+        fn notify(&self, _: &Email) -> Result<(), UnifiedError> {
+            Err(UnifiedError::from_ais_error(AisError::new("channel down")))
+        }
+    }
+
+    #[test]
+    fn test_notifiers_try_every_channel() {
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let notifiers = Notifiers::new(vec![
+            Box::new(Failing),
+            Box::new(Recorder(Arc::clone(&delivered))),
+        ]);
+
+        let mut email = Email::new("Subject".to_string(), "Body".to_string());
+        email.category = EmailCategory::Resource;
+        assert!(notifiers.notify(&email).is_err());
+        assert_eq!(*delivered.lock().unwrap(), vec![EmailCategory::Resource]);
+    }
+
+    #[test]
+    fn test_maintenance_suppresses_routine_notices() {
+        let flag = std::env::temp_dir().join("ais_notify_maintenance");
+        let record = std::env::temp_dir().join("ais_notify_suppressed.log");
+        let _ = fs::remove_file(&record);
+        fs::write(&flag, "").unwrap();
+
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let notifier = MaintenanceNotifier::with_paths(
+            Box::new(Recorder(Arc::clone(&delivered))),
+            flag.clone(),
+            record.clone(),
+            RetentionCategory::Suppressed.default_retention(),
+        );
+
+        let mut service_change = Email::new("Service stopped".to_string(), "Body".to_string());
+        service_change.category = EmailCategory::ServiceStatus;
+        let mut ssh_audit = Email::new("Ssh login".to_string(), "Body".to_string());
+        ssh_audit.category = EmailCategory::SshAudit;
+
+        notifier.notify(&service_change).unwrap();
+        notifier.notify(&ssh_audit).unwrap();
+        assert_eq!(*delivered.lock().unwrap(), vec![EmailCategory::SshAudit]);
+        let recorded = fs::read_to_string(&record).unwrap();
+        assert_eq!(recorded.lines().count(), 1);
+        assert!(recorded.contains("Service stopped"));
+
+        // Out of maintenance everything is delivered
+        fs::remove_file(&flag).unwrap();
+        notifier.notify(&service_change).unwrap();
+        assert_eq!(delivered.lock().unwrap().len(), 2);
+
+        let _ = fs::remove_file(&record);
+    }
+
+    #[test]
+    fn test_relay_delivers_on_its_thread() {
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let relay = RelayNotifier::spawn(Arc::new(Recorder(Arc::clone(&delivered))));
+
+        let mut email = Email::new("Update failed".to_string(), "Body".to_string());
+        email.category = EmailCategory::UpdateFailed;
+        relay.notify(&email).unwrap();
+        drop(relay);
+
+        let started = std::time::Instant::now();
+        while delivered.lock().unwrap().is_empty()
+            && started.elapsed() < std::time::Duration::from_secs(5)
+        {
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(
+            *delivered.lock().unwrap(),
+            vec![EmailCategory::UpdateFailed]
+        );
+    }
+
+    #[test]
+    fn test_webhook_skips_low_priority() {
+        // Nothing listens here, so only a skipped notification can succeed
+        let webhook = WebhookNotifier::new("http://127.0.0.1:9/hook".to_string(), true);
+        let email = Email::new("Subject".to_string(), "Body".to_string());
+        assert!(webhook.notify(&email).is_ok());
+    }
+}
@@ -0,0 +1,101 @@
+use crate::emails::MAIL_SERVER_ADDRESS;
+use std::{env, fmt};
+
+/// Env var that selects the profile a binary runs under.
+pub const PROFILE_VAR: &str = "AIS_ENV";
+
+/// Enum representing the environment a system is deployed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// Production boxes, the default.
+    Production,
+    /// Staging boxes, kept away from the production mailbox.
+    Staging,
+}
+
+/// Struct representing the endpoints a profile reports to, they're always swapped as a set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoints {
+    /// The messaging server clients send emails and heartbeats to.
+    pub aggregator_address: &'static str,
+    /// The smtp relay the messaging server sends through.
+    pub mail_relay: &'static str,
+    /// Who the messaging server sends emails to.
+    pub recipient: &'static str,
+    /// Prepended to every email subject.
+    pub subject_prefix: &'static str,
+}
+
+impl Profile {
+    /// Selects the profile from the `AIS_ENV` env var, defaulting to production.
+    pub fn current() -> Self {
+        Self::from_name(env::var(PROFILE_VAR).ok().as_deref())
+    }
+
+    /// Selects the profile by name, anything that isn't staging is treated as production.
+    pub fn from_name(name: Option<&str>) -> Self {
+        match name.map(|name| name.trim().to_lowercase()).as_deref() {
+            Some("staging") => Profile::Staging,
+            _ => Profile::Production,
+        }
+    }
+
+    /// The endpoints used by this profile.
+    pub fn endpoints(&self) -> Endpoints {
+        match self {
+            Profile::Production => Endpoints {
+                aggregator_address: MAIL_SERVER_ADDRESS,
+                mail_relay: "mail.ramfield.net",
+                recipient: "Enlightened One <enlightened@artisanhosting.net>",
+                subject_prefix: "",
+            },
+            Profile::Staging => Endpoints {
+                aggregator_address: "10.1.0.12:1827",
+                mail_relay: "mail.ramfield.net",
+                recipient: "Staging <staging@artisanhosting.net>",
+                subject_prefix: "[STAGING] ",
+            },
+        }
+    }
+
+    /// Marks a subject with the profile's prefix.
+    pub fn tag_subject(&self, subject: &str) -> String {
+        format!("{}{}", self.endpoints().subject_prefix, subject)
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let profile: &str = match self {
+            Profile::Production => "production",
+            Profile::Staging => "staging",
+        };
+        write!(f, "{}", profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_staging_profile() {
+        let profile = Profile::from_name(Some("staging"));
+        assert_eq!(profile, Profile::Staging);
+        assert_eq!(profile.endpoints().aggregator_address, "10.1.0.12:1827");
+        assert_eq!(
+            profile.tag_subject("Service stopped"),
+            "[STAGING] Service stopped"
+        );
+    }
+
+    #[test]
+    fn test_production_is_the_default() {
+        assert_eq!(Profile::from_name(None), Profile::Production);
+        assert_eq!(Profile::from_name(Some("garbage")), Profile::Production);
+        assert_eq!(
+            Profile::Production.tag_subject("Service stopped"),
+            "Service stopped"
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use crate::ais_config::AisConfig;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+use systemstat::{Filesystem, Platform, System};
+
+/// Enum representing the host resources watched against a threshold.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Resource {
+    /// The five minute load average per cpu.
+    Load,
+    /// The memory in use, as a percentage.
+    Memory,
+    /// How full the filesystem holding the site root is, as a percentage.
+    Disk,
+}
+
+/// A reading of the host's load, memory and disk use. Readings that failed are None.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ResourceUsage {
+    /// The one, five and fifteen minute load averages.
+    pub load: Option<(f32, f32, f32)>,
+    /// The number of cpus the load is spread over.
+    pub cpus: usize,
+    /// The memory in use, as a percentage.
+    pub memory_percent: Option<f64>,
+    /// How full the filesystem holding the watched path is, as a percentage.
+    pub disk_percent: Option<f64>,
+}
+
+/// A resource that stayed over its threshold for the configured number of samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResourceAlert {
+    /// The resource that's over.
+    pub resource: Resource,
+    /// The latest reading.
+    pub value: f64,
+    /// The threshold it's over.
+    pub threshold: f64,
+    /// How many samples in a row it's been over.
+    pub samples: u32,
+}
+
+/// Reads the host's resource usage, tests swap in a mock.
+pub trait ResourceSource: Sync {
+    /// Returns the current usage, the disk reading is for the filesystem holding the path.
+    fn usage(&self, disk_path: &Path) -> ResourceUsage;
+}
+
+/// Reads the usage of the host through systemstat.
+pub struct SystemResources;
+
+/// Counts how many samples in a row each resource has been over its threshold, so only
+/// sustained readings are alerted on, once until the resource drops back under.
+#[derive(Debug)]
+pub struct ResourceMonitor {
+    interval: Duration,
+    last_sample: Option<Instant>,
+    over_count: BTreeMap<Resource, u32>,
+    alerted: BTreeSet<Resource>,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let resource = match self {
+            Resource::Load => "Load",
+            Resource::Memory => "Memory",
+            Resource::Disk => "Disk",
+        };
+        write!(f, "{}", resource)
+    }
+}
+
+impl fmt::Display for ResourceAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit: &str = match self.resource {
+            Resource::Load => " per cpu",
+            Resource::Memory | Resource::Disk => "%",
+        };
+        write!(
+            f,
+            "{} at {:.2}{} has been over {:.2}{} for {} samples in a row",
+            self.resource, self.value, unit, self.threshold, unit, self.samples
+        )
+    }
+}
+
+impl ResourceUsage {
+    /// Reads the host's current usage, the disk reading is for the filesystem holding the path.
+    pub fn gather(disk_path: &Path) -> Self {
+        let sys: System = System::new();
+        ResourceUsage {
+            load: sys
+                .load_average()
+                .ok()
+                .map(|load| (load.one, load.five, load.fifteen)),
+            cpus: thread::available_parallelism()
+                .map(|cpus| cpus.get())
+                .unwrap_or(1),
+            memory_percent: sys.memory().ok().map(|mem| {
+                let used_memory: u64 = mem.total.as_u64() - mem.free.as_u64();
+                (used_memory as f64 / mem.total.as_u64() as f64) * 100.0
+            }),
+            disk_percent: sys.mounts().ok().and_then(|mounts| {
+                mount_for(&mounts, disk_path).map(|mount| {
+                    let total: u64 = mount.total.as_u64().max(1);
+                    ((total - mount.avail.as_u64().min(total)) as f64 / total as f64) * 100.0
+                })
+            }),
+        }
+    }
+
+    /// The reading of the resource, load is the five minute average spread over the cpus.
+    pub fn reading(&self, resource: Resource) -> Option<f64> {
+        match resource {
+            Resource::Load => self
+                .load
+                .map(|(_, five, _)| five as f64 / self.cpus.max(1) as f64),
+            Resource::Memory => self.memory_percent,
+            Resource::Disk => self.disk_percent,
+        }
+    }
+}
+
+impl ResourceSource for SystemResources {
+    fn usage(&self, disk_path: &Path) -> ResourceUsage {
+        ResourceUsage::gather(disk_path)
+    }
+}
+
+impl ResourceMonitor {
+    /// Creates a new ResourceMonitor with nothing over, taking at most one sample per interval.
+    pub const fn new(interval: Duration) -> Self {
+        ResourceMonitor {
+            interval,
+            last_sample: None,
+            over_count: BTreeMap::new(),
+            alerted: BTreeSet::new(),
+        }
+    }
+
+    /// Checks if the next sample is due, the loops run far more often than the interval.
+    /// A due sample is counted as taken.
+    pub fn sample_due(&mut self, now: Instant) -> bool {
+        match self.last_sample {
+            Some(last) if now.duration_since(last) < self.interval => false,
+            _ => {
+                self.last_sample = Some(now);
+                true
+            }
+        }
+    }
+
+    /// The resources alerted on that haven't come back under their threshold.
+    pub fn alerted(&self) -> Vec<Resource> {
+        self.alerted.iter().copied().collect()
+    }
+
+    /// Marks the resources as already alerted on, so they aren't alerted again after a
+    /// restart while they stay over.
+    pub fn restore_alerted(&mut self, alerted: &[Resource]) {
+        self.alerted.extend(alerted.iter().copied());
+    }
+
+    /// Records a sample, returning the resources that have now been over their threshold for
+    /// the config's number of samples in a row. A resource with a threshold of 0 is never
+    /// alerted on.
+    pub fn observe(&mut self, usage: &ResourceUsage, config: &AisConfig) -> Vec<ResourceAlert> {
+        let mut alerts: Vec<ResourceAlert> = Vec::new();
+
+        for (resource, threshold) in [
+            (Resource::Load, config.load_alert_threshold),
+            (Resource::Memory, config.memory_alert_percent),
+            (Resource::Disk, config.disk_alert_percent),
+        ] {
+            let value: f64 = match usage.reading(resource) {
+                Some(value) if threshold > 0.0 && value > threshold => value,
+                _ => {
+                    self.over_count.remove(&resource);
+                    self.alerted.remove(&resource);
+                    continue;
+                }
+            };
+
+            let samples: &mut u32 = self.over_count.entry(resource).or_insert(0);
+            *samples += 1;
+            if *samples >= config.resource_alert_samples && self.alerted.insert(resource) {
+                alerts.push(ResourceAlert {
+                    resource,
+                    value,
+                    threshold,
+                    samples: *samples,
+                });
+            }
+        }
+        alerts
+    }
+}
+
+/// Finds the filesystem holding the path. The path is rarely a mount point itself, so the
+/// deepest mount holding it is used.
+pub fn mount_for<'a>(mounts: &'a [Filesystem], path: &Path) -> Option<&'a Filesystem> {
+    mounts
+        .iter()
+        .filter(|mount| path.starts_with(&mount.fs_mounted_on))
+        .max_by_key(|mount| mount.fs_mounted_on.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sustained_reading_alerts_once() {
+        let config = AisConfig::default();
+        let mut monitor = ResourceMonitor::new(Duration::ZERO);
+        let usage = |memory_percent: f64| ResourceUsage {
+            load: Some((0.5, 0.5, 0.5)),
+            cpus: 4,
+            memory_percent: Some(memory_percent),
+            disk_percent: Some(40.0),
+        };
+
+        // A brief spike isn't alerted on
+        assert!(monitor.observe(&usage(95.0), &config).is_empty());
+        assert!(monitor.observe(&usage(50.0), &config).is_empty());
+
+        // Staying over for the configured samples is
+        for _ in 1..config.resource_alert_samples {
+            assert!(monitor.observe(&usage(96.0), &config).is_empty());
+        }
+        let alerts = monitor.observe(&usage(97.0), &config);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].resource, Resource::Memory);
+        assert_eq!(alerts[0].value, 97.0);
+        assert_eq!(alerts[0].samples, 5);
+        assert_eq!(
+            alerts[0].to_string(),
+            "Memory at 97.00% has been over 90.00% for 5 samples in a row"
+        );
+
+        // Only once until it recovers
+        assert!(monitor.observe(&usage(97.0), &config).is_empty());
+        assert!(monitor.observe(&usage(50.0), &config).is_empty());
+        for _ in 1..config.resource_alert_samples {
+            assert!(monitor.observe(&usage(97.0), &config).is_empty());
+        }
+        assert_eq!(monitor.observe(&usage(97.0), &config).len(), 1);
+    }
+
+    #[test]
+    fn test_samples_limited_to_interval() {
+        let mut monitor = ResourceMonitor::new(Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert!(monitor.sample_due(start));
+        assert!(!monitor.sample_due(start + Duration::from_secs(30)));
+        assert!(monitor.sample_due(start + Duration::from_secs(60)));
+    }
+}
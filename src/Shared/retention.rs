@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Enum representing the kinds of state kept on disk, each with its own retention.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RetentionCategory {
+    /// The operational log, see `AisConfig::log_file`.
+    Log,
+    /// The messaging server's record of sent emails.
+    MailAudit,
+    /// Notices held back during maintenance.
+    Suppressed,
+    /// The per-site deploy logs.
+    Deploys,
+    /// The emails held until they can be encrypted, see `DeadLetters`. They're trimmed in
+    /// place rather than rotated, so only `max_bytes` applies.
+    DeadLetters,
+}
+
+/// How big a file grows before it's rotated, and how many and how old rotated copies are
+/// kept. `log.1` is the newest rotated copy and `log.N` the oldest.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Retention {
+    /// The file is rotated once appending would grow it past this many bytes.
+    pub max_bytes: u64,
+    /// How many rotated copies are kept, 0 drops the file instead of rotating it.
+    pub max_files: usize,
+    /// Rotated copies older than this many days are removed, 0 keeps them regardless of age.
+    #[serde(default)]
+    pub max_age_days: u64,
+}
+
+impl fmt::Display for RetentionCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let category = match self {
+            RetentionCategory::Log => "Log",
+            RetentionCategory::MailAudit => "MailAudit",
+            RetentionCategory::Suppressed => "Suppressed",
+            RetentionCategory::Deploys => "Deploys",
+            RetentionCategory::DeadLetters => "DeadLetters",
+        };
+        write!(f, "{}", category)
+    }
+}
+
+impl RetentionCategory {
+    /// The retention used when the config doesn't set one for the category.
+    pub fn default_retention(&self) -> Retention {
+        let (max_bytes, max_files, max_age_days) = match self {
+            RetentionCategory::Log => (10 * 1024 * 1024, 5, 0),
+            RetentionCategory::MailAudit => (10 * 1024 * 1024, 1, 90),
+            RetentionCategory::Suppressed => (1024 * 1024, 3, 30),
+            RetentionCategory::Deploys => (1024 * 1024, 5, 365),
+            RetentionCategory::DeadLetters => (1024 * 1024, 0, 0),
+        };
+        Retention {
+            max_bytes,
+            max_files,
+            max_age_days,
+        }
+    }
+}
+
+impl Retention {
+    /// Makes room to append the bytes to the file, rotating it if it would grow past the limit
+    /// and pruning the rotated copies. An empty file is never rotated, so a single line bigger
+    /// than the limit is still written.
+    pub fn make_room(&self, path: &Path, incoming: u64) -> io::Result<()> {
+        let size: u64 = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        if size > 0 && size + incoming > self.max_bytes {
+            self.rotate(path)?;
+        }
+        self.prune(path, SystemTime::now()).map(|_| ())
+    }
+
+    /// Shifts every rotated copy up one and moves the file to `.1`.
+    fn rotate(&self, path: &Path) -> io::Result<()> {
+        if self.max_files == 0 {
+            return fs::remove_file(path);
+        }
+        for index in (1..self.max_files).rev() {
+            let from: PathBuf = rotated(path, index);
+            if from.exists() {
+                fs::rename(&from, rotated(path, index + 1))?;
+            }
+        }
+        fs::rename(path, rotated(path, 1))
+    }
+
+    /// Removes the rotated copies of the file past the count or older than the max age,
+    /// returning how many were removed.
+    pub fn prune(&self, path: &Path, now: SystemTime) -> io::Result<usize> {
+        let max_age: Option<Duration> = match self.max_age_days {
+            0 => None,
+            days => Some(Duration::from_secs(days * 24 * 60 * 60)),
+        };
+
+        let mut removed: usize = 0;
+        for (index, copy) in rotated_copies(path)? {
+            let expired: bool = match max_age {
+                Some(max_age) => fs::metadata(&copy)
+                    .and_then(|m| m.modified())
+                    .map(|modified| now.duration_since(modified).unwrap_or_default() > max_age)
+                    .unwrap_or(false),
+                None => false,
+            };
+            if index > self.max_files || expired {
+                fs::remove_file(&copy)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// The path of the file's rotated copy at the index.
+pub fn rotated(path: &Path, index: usize) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(format!(".{}", index));
+    PathBuf::from(path)
+}
+
+/// Lists the file's rotated copies with their index, a missing directory has none.
+fn rotated_copies(path: &Path) -> io::Result<Vec<(usize, PathBuf)>> {
+    let (directory, name) = match (path.parent(), path.file_name()) {
+        (Some(directory), Some(name)) => (directory, name.to_string_lossy()),
+        _ => return Ok(Vec::new()),
+    };
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let prefix: String = format!("{}.", name);
+    let mut copies: Vec<(usize, PathBuf)> = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let index: Option<usize> = entry
+            .file_name()
+            .to_string_lossy()
+            .strip_prefix(&prefix)
+            .and_then(|index| index.parse().ok());
+        if let Some(index) = index {
+            copies.push((index, entry.path()));
+        }
+    }
+    copies.sort();
+    Ok(copies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn test_prunes_copies_past_the_count() {
+        let directory = scratch("ais_retention_count");
+        let path = directory.join("deploys.log");
+        for index in 1..=5 {
+            fs::write(rotated(&path, index), "record\n").unwrap();
+        }
+        let retention = Retention {
+            max_bytes: 1024,
+            max_files: 2,
+            max_age_days: 0,
+        };
+
+        assert_eq!(retention.prune(&path, SystemTime::now()).unwrap(), 3);
+        assert!(rotated(&path, 1).exists());
+        assert!(rotated(&path, 2).exists());
+        assert!(!rotated(&path, 3).exists());
+        assert!(!rotated(&path, 5).exists());
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_prunes_copies_past_the_age() {
+        let directory = scratch("ais_retention_age");
+        let path = directory.join("suppressed.log");
+        fs::write(&path, "live\n").unwrap();
+        fs::write(rotated(&path, 1), "recent\n").unwrap();
+        fs::write(rotated(&path, 2), "old\n").unwrap();
+        let now = SystemTime::now();
+        fs::File::options()
+            .write(true)
+            .open(rotated(&path, 2))
+            .unwrap()
+            .set_modified(now - Duration::from_secs(10 * 24 * 60 * 60))
+            .unwrap();
+        let retention = Retention {
+            max_bytes: 1024,
+            max_files: 5,
+            max_age_days: 7,
+        };
+
+        assert_eq!(retention.prune(&path, now).unwrap(), 1);
+        // The live file is never pruned, only its rotated copies
+        assert!(path.exists());
+        assert!(rotated(&path, 1).exists());
+        assert!(!rotated(&path, 2).exists());
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+}
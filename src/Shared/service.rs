@@ -1,5 +1,8 @@
+use crate::ais_config::AisConfig;
+use crate::command::{CommandRunner, SystemRunner};
 use crate::errors::{AisError, UnifiedError};
-use chrono::{DateTime, Utc};
+use crate::time;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 use systemctl::{self, Unit};
 
@@ -14,14 +17,19 @@ pub enum Services {
     LOCKER,
     DATABASE,
     DOCKER,
+    /// A unit that isn't built in, like an instance of a templated unit matched by
+    /// `AisConfig::service_patterns`.
+    Unit(String),
 }
 
 /// Enum representing the status of a service.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Status {
     Running,
     Stopped,
     Error,
+    /// The unit isn't installed, usually because its package was removed.
+    Missing,
 }
 
 /// Enum representing memory information.
@@ -47,6 +55,11 @@ pub struct ProcessInfo {
     pub children: SubProcesses,
     pub timestamp: String,
     pub optional: bool,
+    /// If the service starts on boot, None if systemctl couldn't tell.
+    pub enabled: Option<bool>,
+    /// Set on the status read when the client starts, there's nothing before it to compare
+    /// against so a change from it isn't a genuine transition.
+    pub baseline: bool,
 }
 
 /// Enum representing different types of processes.
@@ -58,14 +71,29 @@ pub enum Processes {
 impl Processes {
     /// Creates a new Processes instance containing information about various services.
     pub fn new() -> Result<Self, UnifiedError> {
-        let mut data: Vec<ProcessInfo> = Vec::new();
-        data.push(ProcessInfo::get_info(Services::WEBSERVER)?);
-        data.push(ProcessInfo::get_info(Services::PhpProcessor)?);
-        data.push(ProcessInfo::get_info(Services::FIREWALL)?);
-        data.push(ProcessInfo::get_info(Services::MONITOR)?);
-        data.push(ProcessInfo::get_info(Services::SSHSERVER)?);
-        data.push(ProcessInfo::get_info(Services::LOCKER)?);
+        let mut data: Vec<ProcessInfo> = vec![
+            ProcessInfo::get_info(Services::WEBSERVER)?,
+            ProcessInfo::get_info(Services::PhpProcessor)?,
+            ProcessInfo::get_info(Services::FIREWALL)?,
+            ProcessInfo::get_info(Services::MONITOR)?,
+            ProcessInfo::get_info(Services::SSHSERVER)?,
+            ProcessInfo::get_info(Services::LOCKER)?,
+        ];
+
+        // Instances can't be listed up front, the ones running now are monitored from here on
+        let config: AisConfig = AisConfig::new().unwrap_or_default();
+        for pattern in &config.service_patterns {
+            for service in Services::expand(pattern, &SystemRunner)? {
+                let unit_name: String = service.to_string();
+                if !data.iter().any(|info| info.service == unit_name) {
+                    data.push(ProcessInfo::get_info(service)?);
+                }
+            }
+        }
 
+        for info in data.iter_mut() {
+            info.baseline = true;
+        }
         Ok(Self::Services(data))
     }
 
@@ -83,9 +111,41 @@ impl Processes {
 }
 
 impl Services {
+    /// Lists the active units matching the pattern, like `app@*.service`, as systemd globs them.
+    pub fn expand(
+        pattern: &str,
+        runner: &dyn CommandRunner,
+    ) -> Result<Vec<Services>, UnifiedError> {
+        let units: String = runner.run(
+            "systemctl",
+            &[
+                "list-units",
+                "--type=service",
+                "--state=active",
+                "--plain",
+                "--no-legend",
+                "--no-pager",
+                pattern,
+            ],
+        )?;
+        Ok(units
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .map(|unit| Services::Unit(unit.to_owned()))
+            .collect())
+    }
+
     /// Retrieves information about the service.
     pub fn get_info(&self) -> Result<ProcessInfo, UnifiedError> {
         let unit_name: String = format!("{}", self.clone());
+        // An expected unit that isn't installed can't be queried, it's reported as missing
+        if !self.is_installed(&SystemRunner)? {
+            return Ok(ProcessInfo::new(
+                self.clone(),
+                Status::Missing,
+                Memory::MemoryConsumed(String::from("0B")),
+            ));
+        }
         let unit: Unit = match systemctl::Unit::from_systemctl(&unit_name) {
             Ok(d) => d,
             Err(e) => {
@@ -105,7 +165,7 @@ impl Services {
         let memory_data: Option<String> = unit.memory;
         let memory: Memory = match memory_data {
             Some(d) => Memory::MemoryConsumed(d),
-            None => Memory::MemoryConsumed(format!("{}B", 0.00.to_string())),
+            None => Memory::MemoryConsumed(format!("{}B", 0.00)),
         };
 
         let (tasks, pid) = (unit.tasks, unit.pid);
@@ -122,63 +182,83 @@ impl Services {
             timestamp: timestamp(),
             refered: self.clone(),
             optional: false, // TODO implement matching
+            enabled: self.is_enabled(&SystemRunner).ok(),
+            baseline: false,
         })
     }
 
+    /// Checks if the service is enabled to start on boot, separate from it running right now.
+    /// Static and indirect units are started by other units, so they count as enabled.
+    pub fn is_enabled(&self, runner: &dyn CommandRunner) -> Result<bool, UnifiedError> {
+        let unit_name: String = format!("{}", self);
+        // show exits cleanly for disabled units, unlike is-enabled
+        let state: String = runner.run(
+            "systemctl",
+            &["show", "-p", "UnitFileState", "--value", &unit_name],
+        )?;
+        match state.trim() {
+            "enabled" | "enabled-runtime" | "static" | "indirect" | "alias" | "generated" => {
+                Ok(true)
+            }
+            "disabled" | "masked" | "masked-runtime" => Ok(false),
+            other => Err(UnifiedError::from_ais_error(AisError::SystemError(Some(
+                format!("Unknown unit file state for {}: {:?}", unit_name, other),
+            )))),
+        }
+    }
+
+    /// Checks if the unit is installed at all, it's missing once its package is removed.
+    pub fn is_installed(&self, runner: &dyn CommandRunner) -> Result<bool, UnifiedError> {
+        let unit_name: String = format!("{}", self);
+        let state: String = runner.run(
+            "systemctl",
+            &["show", "-p", "LoadState", "--value", &unit_name],
+        )?;
+        match state.trim() {
+            "" => Err(UnifiedError::from_ais_error(AisError::SystemError(Some(
+                format!("No load state for {}", unit_name),
+            )))),
+            state => Ok(state != "not-found"),
+        }
+    }
+
     /// Restarts the service and returns a bool based on the running status after the restart.
     pub fn restart(&self) -> Result<bool, UnifiedError> {
         let unit_name: String = format!("{}", self.clone());
-        return match systemctl::restart(&unit_name) {
+        match systemctl::restart(&unit_name) {
             Ok(_) => match systemctl::is_active(&unit_name) {
                 Ok(d) => Ok(d),
-                Err(e) => Err(UnifiedError::from_ais_error(AisError::new(&e.to_string()))),
+                Err(e) => Err(UnifiedError::from_ais_error(AisError::new(e.to_string()))),
             },
-            Err(e) => Err(UnifiedError::from_ais_error(AisError::new(&e.to_string()))),
-        };
+            Err(e) => Err(UnifiedError::from_ais_error(AisError::new(e.to_string()))),
+        }
     }
 }
 
 impl ProcessInfo {
-    /// Retrieves information about a specific service.
-    pub fn get_info(service: Services) -> Result<Self, UnifiedError> {
-        let unit_name: String = format!("{}", &service);
-        let unit: Unit = match systemctl::Unit::from_systemctl(&unit_name) {
-            Ok(d) => d,
-            Err(e) => {
-                return Err(UnifiedError::from_ais_error(AisError::SystemError(Some(
-                    e.to_string(),
-                ))));
-            }
-        };
-
-        let status_data: Result<bool, std::io::Error> = unit.is_active();
-        let status: Status = match status_data {
-            Ok(true) => Status::Running,
-            Ok(false) => Status::Stopped,
-            Err(_) => Status::Error,
-        };
-
-        let memory_data: Option<String> = unit.memory;
-        let memory: Memory = match memory_data {
-            Some(d) => Memory::MemoryConsumed(d),
-            None => Memory::MemoryConsumed(format!("{}B", 0.00.to_string())),
-        };
-
-        let (tasks, pid) = (unit.tasks, unit.pid);
-        let children: SubProcesses = match (tasks, pid) {
-            (Some(t), Some(_p)) => SubProcesses::Tasks(t),
-            (_, _) => SubProcesses::Pid(0),
-        };
-
-        Ok(Self {
-            service: unit_name,
+    /// Creates a new ProcessInfo for the service in the given state, without asking systemctl.
+    pub fn new(service: Services, status: Status, memory: Memory) -> Self {
+        Self {
+            service: service.to_string(),
             status,
             memory,
-            children,
+            children: SubProcesses::Pid(0),
             timestamp: timestamp(),
             refered: service,
             optional: false,
-        })
+            enabled: Some(true),
+            baseline: false,
+        }
+    }
+
+    /// Retrieves information about a specific service.
+    pub fn get_info(service: Services) -> Result<Self, UnifiedError> {
+        service.get_info()
+    }
+
+    /// Checks if the service is running now but won't come back after a reboot.
+    pub fn is_active_but_disabled(&self) -> bool {
+        self.status == Status::Running && self.enabled == Some(false)
     }
 }
 
@@ -195,6 +275,7 @@ impl fmt::Display for Services {
             Services::LOCKER => "dusad.service",
             Services::DATABASE => "mysql.service",
             Services::DOCKER => "docker.service",
+            Services::Unit(name) => name,
         };
         write!(f, "{}", name)
     }
@@ -206,11 +287,31 @@ impl fmt::Display for Status {
             Status::Running => "active",
             Status::Stopped => "stopped",
             Status::Error => "Error occurred while checking",
+            Status::Missing => "not installed",
         };
         write!(f, "{}", status)
     }
 }
 
+impl Memory {
+    /// Returns the memory in bytes, parsed from the systemctl format like `20.5M` or `1.2G`.
+    pub fn bytes(&self) -> Option<u64> {
+        let Memory::MemoryConsumed(data) = self;
+        let data: &str = data.trim();
+        let (number, unit) = data.split_at(data.char_indices().last()?.0);
+        let multiplier: f64 = match unit {
+            "B" => 1.0,
+            "K" => 1024.0,
+            "M" => 1024.0 * 1024.0,
+            "G" => 1024.0 * 1024.0 * 1024.0,
+            "T" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+            _ => return None,
+        };
+        let number: f64 = number.parse().ok()?;
+        Some((number * multiplier) as u64)
+    }
+}
+
 impl fmt::Display for Memory {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -228,16 +329,121 @@ impl fmt::Display for SubProcesses {
     }
 }
 
-/// Generates a timestamp string in the format: YYYY-MM-DD HH:MM:SS.
+/// Generates a canonical timestamp, see `time::TIMESTAMP_FORMAT`.
 pub fn timestamp() -> String {
-    let now: DateTime<Utc> = Utc::now();
-    now.format("%Y-%m-%d %H:%M:%S").to_string()
+    time::now()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Answers `systemctl show -p UnitFileState` with a fixed state.
+    struct MockSystemctl(&'static str);
+
+    impl CommandRunner for MockSystemctl {
+        fn run(&self, program: &str, args: &[&str]) -> Result<String, UnifiedError> {
+            assert_eq!(program, "systemctl");
+            assert_eq!(args[..4], ["show", "-p", "UnitFileState", "--value"]);
+            Ok(self.0.to_owned())
+        }
+    }
+
+    #[test]
+    fn test_is_enabled() {
+        let enabled = |state: &'static str| Services::WEBSERVER.is_enabled(&MockSystemctl(state));
+        assert!(enabled("enabled").unwrap());
+        assert!(enabled("static").unwrap());
+        assert!(!enabled("disabled").unwrap());
+        assert!(!enabled("masked").unwrap());
+        assert!(enabled("").is_err());
+    }
+
+    /// Answers `systemctl show -p LoadState` with a fixed state.
+    struct MockLoadState(&'static str);
+
+    impl CommandRunner for MockLoadState {
+        fn run(&self, program: &str, args: &[&str]) -> Result<String, UnifiedError> {
+            assert_eq!(program, "systemctl");
+            assert_eq!(args[..4], ["show", "-p", "LoadState", "--value"]);
+            Ok(self.0.to_owned())
+        }
+    }
+
+    #[test]
+    fn test_configured_but_absent_unit() {
+        let installed =
+            |state: &'static str| Services::DATABASE.is_installed(&MockLoadState(state));
+        assert!(installed("loaded").unwrap());
+        assert!(installed("masked").unwrap());
+        assert!(!installed("not-found").unwrap());
+        assert!(installed("").is_err());
+        assert_eq!(format!("{}", Status::Missing), "not installed");
+    }
+
+    #[test]
+    fn test_active_but_disabled() {
+        let process = |status: Status, state: &'static str| ProcessInfo {
+            enabled: Services::WEBSERVER.is_enabled(&MockSystemctl(state)).ok(),
+            ..ProcessInfo::new(
+                Services::WEBSERVER,
+                status,
+                Memory::MemoryConsumed("20M".to_owned()),
+            )
+        };
+
+        assert!(!process(Status::Running, "enabled").is_active_but_disabled());
+        assert!(process(Status::Running, "disabled").is_active_but_disabled());
+        assert!(!process(Status::Stopped, "enabled").is_active_but_disabled());
+    }
+
+    /// Answers `systemctl list-units` with the units matching the pattern.
+    struct MockListUnits(&'static [&'static str]);
+
+    impl CommandRunner for MockListUnits {
+        fn run(&self, program: &str, args: &[&str]) -> Result<String, UnifiedError> {
+            assert_eq!(program, "systemctl");
+            assert_eq!(args[0], "list-units");
+            let prefix: &str = args.last().unwrap().split('*').next().unwrap();
+            Ok(self
+                .0
+                .iter()
+                .filter(|unit| unit.starts_with(prefix))
+                .map(|unit| format!("{} loaded active running {}", unit, unit))
+                .collect::<Vec<_>>()
+                .join("\n"))
+        }
+    }
+
+    #[test]
+    fn test_pattern_expands_to_instances() {
+        let systemd = MockListUnits(&["apache2.service", "app@1.service", "app@2.service"]);
+
+        let services = Services::expand("app@*.service", &systemd).unwrap();
+        assert_eq!(
+            services,
+            vec![
+                Services::Unit("app@1.service".to_owned()),
+                Services::Unit("app@2.service".to_owned())
+            ]
+        );
+        assert_eq!(services[1].to_string(), "app@2.service");
+        assert!(Services::expand("worker@*.service", &systemd)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_memory_bytes() {
+        let bytes = |data: &str| Memory::MemoryConsumed(data.to_owned()).bytes();
+        assert_eq!(bytes("0B"), Some(0));
+        assert_eq!(bytes("512K"), Some(512 * 1024));
+        assert_eq!(bytes("20.5M"), Some(21_495_808));
+        assert_eq!(bytes("2G"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(bytes(""), None);
+        assert_eq!(bytes("lots"), None);
+    }
+
     #[test]
     fn test_services_display() {
         assert_eq!(format!("{}", Services::PhpProcessor), "php7.4-fpm.service");
@@ -269,7 +475,8 @@ mod tests {
     #[test]
     fn test_timestamp() {
         let timestamp = timestamp();
-        assert!(timestamp.len() > 0);
+        assert!(!timestamp.is_empty());
+        assert!(time::parse(&timestamp).is_ok());
     }
 
     // Additional tests can be added for other functions and scenarios.
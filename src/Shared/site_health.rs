@@ -0,0 +1,147 @@
+use crate::{
+    ais_config::AisConfig,
+    errors::{AisError, UnifiedError},
+};
+use isahc::{config::Configurable, Request, RequestExt};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Enum representing the outcome of one GET of a site's health url.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HealthCheck {
+    /// The site answered with a 2xx.
+    Healthy,
+    /// The site answered with anything else, or didn't answer, and why.
+    Unhealthy(String),
+}
+
+/// Counts how many checks in a row each health url has failed, so only sustained failures
+/// are alerted on, once until the site answers again.
+#[derive(Debug)]
+pub struct SiteHealthMonitor {
+    interval: Duration,
+    last_check: Option<Instant>,
+    failures: BTreeMap<String, u32>,
+    alerted: BTreeSet<String>,
+}
+
+impl fmt::Display for HealthCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthCheck::Healthy => write!(f, "healthy"),
+            HealthCheck::Unhealthy(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl HealthCheck {
+    /// GETs the url, anything but a 2xx within the timeout is unhealthy.
+    pub fn fetch(url: &str, timeout: Duration) -> Self {
+        let response = Request::get(url)
+            .timeout(timeout)
+            .body(())
+            .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string())))
+            .and_then(|request| {
+                request.send().map_err(|e| {
+                    UnifiedError::from_ais_error(AisError::new(format!(
+                        "Unable to reach {}: {}",
+                        url, e
+                    )))
+                })
+            });
+
+        match response {
+            Ok(response) if response.status().is_success() => HealthCheck::Healthy,
+            Ok(response) => {
+                HealthCheck::Unhealthy(format!("{} responded with {}", url, response.status()))
+            }
+            Err(e) => HealthCheck::Unhealthy(e.to_string()),
+        }
+    }
+}
+
+impl SiteHealthMonitor {
+    /// Creates a new SiteHealthMonitor with nothing failing, checking at most once per interval.
+    pub const fn new(interval: Duration) -> Self {
+        SiteHealthMonitor {
+            interval,
+            last_check: None,
+            failures: BTreeMap::new(),
+            alerted: BTreeSet::new(),
+        }
+    }
+
+    /// Checks if the next round of checks is due, a due round is counted as taken.
+    pub fn check_due(&mut self, now: Instant) -> bool {
+        match self.last_check {
+            Some(last) if now.duration_since(last) < self.interval => false,
+            _ => {
+                self.last_check = Some(now);
+                true
+            }
+        }
+    }
+
+    /// Records a check of the url, returning how many checks in a row it's failed when that
+    /// has just reached the config's limit.
+    pub fn observe(&mut self, url: &str, check: &HealthCheck, config: &AisConfig) -> Option<u32> {
+        if *check == HealthCheck::Healthy {
+            self.failures.remove(url);
+            self.alerted.remove(url);
+            return None;
+        }
+
+        let failures: &mut u32 = self.failures.entry(url.to_owned()).or_insert(0);
+        *failures += 1;
+        match *failures >= config.health_check_failures && self.alerted.insert(url.to_owned()) {
+            true => Some(*failures),
+            false => None,
+        }
+    }
+
+    /// The urls alerted on that haven't answered since.
+    pub fn alerted(&self) -> Vec<String> {
+        self.alerted.iter().cloned().collect()
+    }
+
+    /// Marks the urls as already alerted on, so they aren't alerted again after a restart
+    /// while they keep failing.
+    pub fn restore_alerted(&mut self, alerted: &[String]) {
+        self.alerted.extend(alerted.iter().cloned());
+    }
+
+    /// Forgets the urls no longer configured, so a site that's added back starts fresh.
+    pub fn retain(&mut self, urls: &[&str]) {
+        self.failures.retain(|url, _| urls.contains(&url.as_str()));
+        self.alerted.retain(|url| urls.contains(&url.as_str()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failures_alert_once_until_recovered() {
+        let config = AisConfig::default();
+        let mut monitor = SiteHealthMonitor::new(Duration::ZERO);
+        let url = "http://127.0.0.1/health";
+        let down = HealthCheck::Unhealthy("responded with 500".to_owned());
+
+        for _ in 1..config.health_check_failures {
+            assert_eq!(monitor.observe(url, &down, &config), None);
+        }
+        assert_eq!(
+            monitor.observe(url, &down, &config),
+            Some(config.health_check_failures)
+        );
+        assert_eq!(monitor.observe(url, &down, &config), None);
+
+        // Answering again resets the count
+        assert_eq!(monitor.observe(url, &HealthCheck::Healthy, &config), None);
+        assert_eq!(monitor.observe(url, &down, &config), None);
+    }
+}
@@ -2,15 +2,21 @@
 //!
 //! This module defines structures and functions related to site information.
 
-use std::path::PathBuf;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 use crate::{
-    git_actions::GitAction,
-    errors::UnifiedError,
+    git_actions::{fetch_remote_url, fetch_upstream, is_detached, GitAction},
+    errors::{AisError, UnifiedError},
     git_data::GitAuth,
 };
 use system::{create_hash, errors::SystemError, path_present, truncate, PathType};
 
+/// Records which repo a site folder was cloned from. It's kept in .git so it's never served.
+const SITE_MARKER: &str = ".git/ais_site";
+
 /// Enum representing the update status of a site.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Updates {
@@ -18,6 +24,10 @@ pub enum Updates {
     UpToDate,
     /// The site is out of date and needs updates.
     OutOfDate,
+    /// The checked out branch has no upstream, so there's nothing to compare against.
+    NoUpstream,
+    /// A commit or tag is checked out rather than a branch, the site is frozen there on purpose.
+    Pinned,
 }
 
 /// Struct holding information about a site.
@@ -35,30 +45,54 @@ impl SiteInfo {
     /// # Arguments
     ///
     /// * `git_cred` - A reference-counted lock containing Git credentials.
+    /// * `site_root` - The directory sites are checked out under, see `AisConfig::site_root`.
     ///
     /// # Returns
     ///
     /// A Result containing the new SiteInfo instance if successful, or an error.
-    pub fn new(git_creds: &GitAuth) -> Result<Self, UnifiedError> {
+    pub fn new(git_creds: &GitAuth, site_root: &Path) -> Result<Self, UnifiedError> {
         let _results: Vec<Self> = Vec::new();
 
-        let application_folder = PathType::PathBuf(Self::get_site_folder(&git_creds)?);
+        let application_folder = PathType::PathBuf(Self::get_site_folder(git_creds, site_root)?);
 
-        let check_remote_ahead_action = GitAction::CheckRemoteAhead(application_folder.clone());
-        let application_status: Updates = match check_remote_ahead_action.execute() {
-            Ok(is_ahead) => match is_ahead {
-                true => Updates::OutOfDate,
-                false => Updates::UpToDate,
-            },
-            Err(e) => return Err(e),
-        };
+        let application_status: Updates = Self::fetch_status(&application_folder)?;
 
         let git_cred_data = Self {
             application_folder,
             application_status,
         };
 
-        return Ok(git_cred_data);
+        Ok(git_cred_data)
+    }
+
+    /// Checks the checkout in the given folder against its upstream.
+    ///
+    /// # Arguments
+    ///
+    /// * `application_folder` - The folder holding the checkout.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the update status if successful, or an error.
+    pub fn fetch_status(application_folder: &PathType) -> Result<Updates, UnifiedError> {
+        // A detached HEAD has no branch to pull or upstream to compare
+        if is_detached(application_folder)? {
+            return Ok(Updates::Pinned);
+        }
+
+        // Without an upstream rev-parse @{u} fails, that isn't worth alerting on
+        if fetch_upstream(application_folder)?.is_none() {
+            return Ok(Updates::NoUpstream);
+        }
+
+        let check_remote_ahead_action = GitAction::CheckRemoteAhead(application_folder.clone());
+        match check_remote_ahead_action.execute() {
+            Ok(is_ahead) => match is_ahead {
+                true => Ok(Updates::OutOfDate),
+                false => Ok(Updates::UpToDate),
+            },
+            Err(e) => Err(e),
+        }
     }
 
     /// Retrieves the path to the site folder.
@@ -66,30 +100,163 @@ impl SiteInfo {
     /// # Arguments
     ///
     /// * `git_auth` - A read guard containing Git authentication information.
+    /// * `site_root` - The directory sites are checked out under, see `AisConfig::site_root`.
     ///
     /// # Returns
     ///
     /// A Result containing the path to the site folder if successful, or an error.
-    pub fn get_site_folder(git_auth: &GitAuth) -> Result<PathBuf, UnifiedError> {
-        let site_folder_string: String = format!("{}-{}", git_auth.user, git_auth.repo,);
-
-        let site_folder: String = truncate(&create_hash(site_folder_string), 8).to_owned();
-
-        let site_path: String = format!("/var/www/current/{}", site_folder);
+    pub fn get_site_folder(git_auth: &GitAuth, site_root: &Path) -> Result<PathBuf, UnifiedError> {
+        let site_path: String = Self::site_path(git_auth, site_root)
+            .to_string_lossy()
+            .into_owned();
 
         match path_present(&PathType::Content(site_path.clone())) {
             Ok(d) => match d {
-                true => return Ok(PathBuf::from(site_path.clone())),
-                false => {
-                    return Err(UnifiedError::from_system_error(SystemError::new_details(
-                        system::errors::SystemErrorType::ErrorCreatingDir,
-                        &format!("Dir: {} not found", site_path.clone()),
-                    )))
+                true => {
+                    Self::verify_site_folder(Path::new(&site_path), git_auth)?;
+                    Ok(PathBuf::from(site_path.clone()))
                 }
+                false => Err(UnifiedError::from_system_error(SystemError::new_details(
+                    system::errors::SystemErrorType::ErrorCreatingDir,
+                    &format!("Dir: {} not found", site_path.clone()),
+                ))),
             },
-            Err(e) => return Err(UnifiedError::from_system_error(e)),
+            Err(e) => Err(UnifiedError::from_system_error(e)),
+        }
+    }
+
+    /// Computes where a repo is checked out under the given site root, whether it exists or not.
+    ///
+    /// # Arguments
+    ///
+    /// * `git_auth` - The repo the site is checked out from.
+    /// * `site_root` - The directory sites are checked out under, see `AisConfig::site_root`.
+    ///
+    /// # Returns
+    ///
+    /// The path to the site folder.
+    pub fn site_path(git_auth: &GitAuth, site_root: &Path) -> PathBuf {
+        let site_folder_string: String = format!("{}-{}", git_auth.user, git_auth.repo,);
+        let site_folder: String = truncate(&create_hash(site_folder_string), 8).to_owned();
+        site_root.join(site_folder)
+    }
+
+    /// Records that the site folder holds a checkout of the repo, so a different repo hashing
+    /// to the same folder is caught instead of overwriting it.
+    ///
+    /// # Arguments
+    ///
+    /// * `site_folder` - The folder the repo was cloned into.
+    /// * `git_auth` - The repo that was cloned.
+    pub fn write_site_marker(site_folder: &Path, git_auth: &GitAuth) -> Result<(), UnifiedError> {
+        fs::write(site_folder.join(SITE_MARKER), Self::site_owner(git_auth)).map_err(|e| {
+            UnifiedError::from_ais_error(AisError::SiteInitializationFailed(Some(format!(
+                "Failed to mark {}: {}",
+                site_folder.display(),
+                e
+            ))))
+        })
+    }
+
+    /// Checks that an existing site folder belongs to the repo. Folders cloned before the
+    /// marker existed have none and are trusted.
+    ///
+    /// # Arguments
+    ///
+    /// * `site_folder` - The folder to check.
+    /// * `git_auth` - The repo expected in the folder.
+    ///
+    /// # Returns
+    ///
+    /// An error naming both repos if the folder belongs to a different one.
+    pub fn verify_site_folder(site_folder: &Path, git_auth: &GitAuth) -> Result<(), UnifiedError> {
+        let expected: String = Self::site_owner(git_auth);
+        match fs::read_to_string(site_folder.join(SITE_MARKER)) {
+            Ok(owner) if owner.trim() != expected => Err(UnifiedError::from_ais_error(
+                AisError::SiteInfoInvalid(Some(format!(
+                    "Site folder {} belongs to {}, not {}",
+                    site_folder.display(),
+                    owner.trim(),
+                    expected
+                ))),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Moves a checkout of the repo left under an older folder name to its canonical folder, so
+    /// changing how folders are named doesn't orphan checkouts. The checkout is found by its
+    /// origin url.
+    ///
+    /// # Arguments
+    ///
+    /// * `git_auth` - The repo to look for.
+    /// * `site_root` - The directory sites are checked out under, see `AisConfig::site_root`.
+    ///
+    /// # Returns
+    ///
+    /// The folder the checkout was moved from, or None if there was nothing to move.
+    pub fn relocate_site_folder(
+        git_auth: &GitAuth,
+        site_root: &Path,
+    ) -> Result<Option<PathBuf>, UnifiedError> {
+        let canonical: PathBuf = Self::site_path(git_auth, site_root);
+        if canonical.exists() {
+            return Ok(None);
+        }
+
+        let entries = match fs::read_dir(site_root) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(None),
+        };
+        let found: Option<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|folder| folder.join(".git").is_dir())
+            .find(|folder| {
+                fetch_remote_url(&PathType::PathBuf(folder.clone()))
+                    .map(|url| Self::remote_matches(&url, git_auth))
+                    .unwrap_or(false)
+            });
+
+        match found {
+            Some(folder) => {
+                fs::rename(&folder, &canonical).map_err(|e| {
+                    UnifiedError::from_ais_error(AisError::SiteFailed(Some(format!(
+                        "Failed to move {} to {}: {}",
+                        folder.display(),
+                        canonical.display(),
+                        e
+                    ))))
+                })?;
+                Self::write_site_marker(&canonical, git_auth)?;
+                Ok(Some(folder))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Checks the checkout in the site folder was cloned from the repo, a folder reused from a
+    /// previous tenant can hold another repo entirely.
+    pub fn origin_matches(site_folder: &Path, git_auth: &GitAuth) -> Result<bool, UnifiedError> {
+        let url: String = fetch_remote_url(&PathType::PathBuf(site_folder.to_path_buf()))?;
+        Ok(Self::remote_matches(&url, git_auth))
+    }
+
+    /// Checks if a remote url, https or ssh, points at the repo.
+    fn remote_matches(url: &str, git_auth: &GitAuth) -> bool {
+        let path: &str = url.trim().trim_end_matches('/').trim_end_matches(".git");
+        let expected: String = Self::site_owner(git_auth);
+        match path.strip_suffix(expected.as_str()) {
+            Some(prefix) => prefix.ends_with('/') || prefix.ends_with(':'),
+            None => false,
         }
     }
+
+    /// The repo a site folder is recorded as belonging to.
+    fn site_owner(git_auth: &GitAuth) -> String {
+        format!("{}/{}", git_auth.user, git_auth.repo)
+    }
 }
 
 // #[cfg(test)]
@@ -110,3 +277,206 @@ impl SiteInfo {
 //     }
 
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ais_config::AisConfig;
+    use crate::git_data::DeployMode;
+
+    fn git_auth(user: &str, repo: &str) -> GitAuth {
+        GitAuth {
+            user: user.to_owned(),
+            repo: repo.to_owned(),
+            branch: "main".to_owned(),
+            token: String::new(),
+            deploy: DeployMode::Branch,
+            health_url: None,
+        }
+    }
+
+    #[test]
+    fn test_site_path_honors_site_root() {
+        let auth = git_auth("artisan", "website");
+        let default_path = SiteInfo::site_path(&auth, &AisConfig::default().site_root);
+        assert_eq!(default_path.parent(), Some(Path::new("/var/www/current")));
+
+        let custom_path = SiteInfo::site_path(&auth, Path::new("/srv/www"));
+        assert_eq!(custom_path.parent(), Some(Path::new("/srv/www")));
+        assert_eq!(custom_path.file_name(), default_path.file_name());
+        assert_eq!(custom_path.file_name().unwrap().len(), 8);
+    }
+
+    #[test]
+    fn test_remote_matches() {
+        let auth = git_auth("artisan", "website");
+        assert!(SiteInfo::remote_matches(
+            "https://token@github.com/artisan/website.git",
+            &auth
+        ));
+        assert!(SiteInfo::remote_matches(
+            "git@github.com:artisan/website.git",
+            &auth
+        ));
+        assert!(!SiteInfo::remote_matches(
+            "https://github.com/artisan/old-website.git",
+            &auth
+        ));
+        assert!(!SiteInfo::remote_matches(
+            "https://github.com/artisan/shop.git",
+            &auth
+        ));
+    }
+
+    #[cfg(feature = "git")]
+    #[test]
+    fn test_relocate_site_folder() {
+        use std::process::Command;
+
+        let site_root = std::env::temp_dir().join("ais_site_relocate");
+        let _ = fs::remove_dir_all(&site_root);
+        let old_folder = site_root.join("1a2b3c");
+        fs::create_dir_all(&old_folder).unwrap();
+        fs::write(old_folder.join("index.html"), b"<html></html>").unwrap();
+        let git = |args: &[&str]| {
+            assert!(Command::new("git")
+                .arg("-C")
+                .arg(&old_folder)
+                .args(args)
+                .status()
+                .unwrap()
+                .success());
+        };
+        git(&["init"]);
+        git(&[
+            "remote",
+            "add",
+            "origin",
+            "https://github.com/artisan/website.git",
+        ]);
+
+        let auth = git_auth("artisan", "website");
+        let moved = SiteInfo::relocate_site_folder(&auth, &site_root).unwrap();
+        assert_eq!(moved, Some(old_folder.clone()));
+
+        let canonical = SiteInfo::site_path(&auth, &site_root);
+        assert!(!old_folder.exists());
+        assert!(canonical.join("index.html").exists());
+        assert!(SiteInfo::verify_site_folder(&canonical, &auth).is_ok());
+
+        // Once in place there's nothing left to move
+        assert_eq!(
+            SiteInfo::relocate_site_folder(&auth, &site_root).unwrap(),
+            None
+        );
+
+        let _ = fs::remove_dir_all(&site_root);
+    }
+
+    #[cfg(feature = "git")]
+    #[test]
+    fn test_detached_checkout_is_pinned() {
+        use std::process::Command;
+
+        let folder = std::env::temp_dir().join("ais_site_detached");
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        let git = |args: &[&str]| {
+            assert!(Command::new("git")
+                .arg("-C")
+                .arg(&folder)
+                .args(args)
+                .status()
+                .unwrap()
+                .success());
+        };
+        git(&["init", "--quiet", "-b", "main"]);
+        for message in ["first", "second"] {
+            fs::write(folder.join("index.html"), message).unwrap();
+            git(&["add", "index.html"]);
+            git(&[
+                "-c",
+                "user.name=ais",
+                "-c",
+                "user.email=ais@localhost",
+                "commit",
+                "--quiet",
+                "-m",
+                message,
+            ]);
+        }
+        let site_folder = PathType::PathBuf(folder.clone());
+
+        // On a branch without an upstream there's nothing to compare, but it isn't pinned
+        assert_eq!(
+            SiteInfo::fetch_status(&site_folder).unwrap(),
+            Updates::NoUpstream
+        );
+
+        git(&[
+            "-c",
+            "advice.detachedHead=false",
+            "checkout",
+            "--quiet",
+            "HEAD~1",
+        ]);
+        assert_eq!(
+            SiteInfo::fetch_status(&site_folder).unwrap(),
+            Updates::Pinned
+        );
+
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[cfg(feature = "git")]
+    #[test]
+    fn test_origin_matches_detects_other_repo() {
+        use std::process::Command;
+
+        let folder = std::env::temp_dir().join("ais_site_origin");
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        let git = |args: &[&str]| {
+            assert!(Command::new("git")
+                .arg("-C")
+                .arg(&folder)
+                .args(args)
+                .status()
+                .unwrap()
+                .success());
+        };
+        git(&["init", "--quiet"]);
+        git(&[
+            "remote",
+            "add",
+            "origin",
+            "https://github.com/old-tenant/shop.git",
+        ]);
+
+        assert!(!SiteInfo::origin_matches(&folder, &git_auth("artisan", "website")).unwrap());
+        assert!(SiteInfo::origin_matches(&folder, &git_auth("old-tenant", "shop")).unwrap());
+
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_verify_site_folder_detects_collisions() {
+        let site_folder = std::env::temp_dir().join("ais_site_collision");
+        let _ = fs::remove_dir_all(&site_folder);
+        fs::create_dir_all(site_folder.join(".git")).unwrap();
+        let owner = git_auth("artisan", "website");
+        let other = git_auth("artisan", "shop");
+
+        // Unmarked checkouts predate the marker and are trusted
+        assert!(SiteInfo::verify_site_folder(&site_folder, &other).is_ok());
+
+        SiteInfo::write_site_marker(&site_folder, &owner).unwrap();
+        assert!(SiteInfo::verify_site_folder(&site_folder, &owner).is_ok());
+
+        // Force a second repo into the same folder
+        let collision = SiteInfo::verify_site_folder(&site_folder, &other).unwrap_err();
+        assert!(collision.to_string().contains("belongs to artisan/website"));
+
+        let _ = fs::remove_dir_all(&site_folder);
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crate::ais_config::AisConfig;
+
+/// How long a source's packet count is kept before it resets
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Decides which sources are heard. Only localhost and the configured relays are allowed, and
+/// each is limited to a number of packets per window so a flood can't drown the audit trail.
+struct SourceFilter {
+    relays: Vec<IpAddr>,
+    limit: u32,
+    windows: HashMap<IpAddr, (Instant, u32)>,
+}
+
+impl SourceFilter {
+    fn new(relays: Vec<IpAddr>, limit: u32) -> Self {
+        SourceFilter {
+            relays,
+            limit,
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Returns true if a packet from the source should be processed.
+    fn admit(&mut self, source: IpAddr, now: Instant) -> bool {
+        if !source.is_loopback() && !self.relays.contains(&source) {
+            return false;
+        }
+
+        let (started, count) = self.windows.entry(source).or_insert((now, 0));
+        if now.duration_since(*started) >= RATE_LIMIT_WINDOW {
+            *started = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= self.limit
+    }
+}
+
+fn parse_syslog_message(message: &str) -> Option<(String, String, String)> {
+    // Example parsing logic for SSH connection data
+    // Customize this function based on your syslog message format
+    let parts: Vec<&str> = message.split_whitespace().collect();
+    if parts.len() >= 11 && parts[4] == "sshd" {
+        let remote_ip = parts[7].to_string();
+        let duration = parts[9].to_string();
+        let user = parts[10].to_string();
+        Some((remote_ip, duration, user))
+    } else {
+        None
+    }
+}
+
+/// Receives and parses a single packet. Packets that can't be received or aren't valid
+/// utf-8 are logged and skipped so one bad packet can't take the receiver down, packets the
+/// filter doesn't admit are dropped silently.
+fn receive_packet(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+    filter: &mut SourceFilter,
+) -> Option<(String, String, String)> {
+    let (num_bytes, src_addr) = match socket.recv_from(buf) {
+        Ok(received) => received,
+        Err(e) => {
+            eprintln!("Failed to receive data: {}", e);
+            return None;
+        }
+    };
+
+    if !filter.admit(src_addr.ip(), Instant::now()) {
+        return None;
+    }
+
+    match std::str::from_utf8(&buf[..num_bytes]) {
+        Ok(message) => parse_syslog_message(message),
+        Err(e) => {
+            eprintln!("Skipping malformed packet from {}: {}", src_addr, e);
+            None
+        }
+    }
+}
+
+fn syslog_receiver(host: &str, port: u16) {
+    // Create a UDP socket
+    let socket = UdpSocket::bind(format!("{}:{}", host, port)).expect("Failed to bind socket");
+
+    println!("Syslog receiver listening on {}:{}", host, port);
+
+    let config: AisConfig = AisConfig::new().unwrap_or_default();
+    let mut filter = SourceFilter::new(config.syslog_relays, config.syslog_rate_limit);
+
+    // Buffer to store incoming data
+    let mut buf = [0; 1024];
+
+    loop {
+        // Receive incoming syslog messages and extract SSH connection data
+        if let Some((remote_ip, duration, user)) = receive_packet(&socket, &mut buf, &mut filter) {
+            // Print extracted SSH connection data
+            println!("Remote IP: {}, Duration: {}, User: {}", remote_ip, duration, user);
+        }
+    }
+}
+
+/// Entry point of the syslog receiver
+pub fn main() {
+    // Define the host and port to listen on
+    let host = "0.0.0.0";  // Listen on all available interfaces
+    let port = 1514;         // Default syslog port
+
+    // Start the syslog receiver
+    syslog_receiver(host, port);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_receive_packet_skips_invalid_utf8() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = receiver.local_addr().unwrap();
+        let mut buf = [0; 1024];
+        let mut filter = SourceFilter::new(Vec::new(), 10);
+
+        sender.send_to(&[0xff, 0xfe, 0x00, 0xc3], address).unwrap();
+        sender
+            .send_to(
+                b"Jan 1 00:00:00 host sshd connection from 10.0.0.5 duration 42 admin",
+                address,
+            )
+            .unwrap();
+
+        assert_eq!(receive_packet(&receiver, &mut buf, &mut filter), None);
+        assert_eq!(
+            receive_packet(&receiver, &mut buf, &mut filter),
+            Some((
+                "10.0.0.5".to_string(),
+                "42".to_string(),
+                "admin".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_source_filter_drops_unknown_sources() {
+        let relay: IpAddr = "10.1.0.5".parse().unwrap();
+        let stranger: IpAddr = "203.0.113.7".parse().unwrap();
+        let mut filter = SourceFilter::new(vec![relay], 10);
+        let now = Instant::now();
+
+        assert!(filter.admit("127.0.0.1".parse().unwrap(), now));
+        assert!(filter.admit(relay, now));
+        assert!(!filter.admit(stranger, now));
+    }
+
+    #[test]
+    fn test_source_filter_rate_limits() {
+        let relay: IpAddr = "10.1.0.5".parse().unwrap();
+        let mut filter = SourceFilter::new(vec![relay], 2);
+        let now = Instant::now();
+
+        assert!(filter.admit(relay, now));
+        assert!(filter.admit(relay, now));
+        assert!(!filter.admit(relay, now));
+
+        // The count resets once the window passes
+        assert!(filter.admit(relay, now + RATE_LIMIT_WINDOW));
+    }
+}
@@ -0,0 +1,87 @@
+use crate::emails::Email;
+use crate::encrypt::Cipher;
+use crate::errors::{AisError, UnifiedError};
+use crate::notify::Notifier;
+use std::cell::Cell;
+use std::sync::Mutex;
+use system::errors::{SystemError, SystemErrorType};
+
+/// Reverses the text instead of encrypting it and hex encodes the plain text it decrypts,
+/// like dusa does.
+pub struct MockCipher {
+    /// Encrypting fails like dusa being down while this is false.
+    pub available: Cell<bool>,
+    /// Decrypting fails like an unreachable dusa socket until this runs out.
+    pub failures: Cell<u32>,
+    /// How many times something was encrypted.
+    pub calls: Cell<usize>,
+}
+
+impl MockCipher {
+    pub fn new() -> Self {
+        Self {
+            available: Cell::new(true),
+            failures: Cell::new(0),
+            calls: Cell::new(0),
+        }
+    }
+
+    pub fn unavailable() -> Self {
+        let cipher = Self::new();
+        cipher.available.set(false);
+        cipher
+    }
+
+    pub fn failing(failures: u32) -> Self {
+        let cipher = Self::new();
+        cipher.failures.set(failures);
+        cipher
+    }
+
+    /// What the cipher text of the plain text looks like on disk.
+    pub fn seal(plain_text: &str) -> String {
+        plain_text.chars().rev().collect()
+    }
+}
+
+impl Default for MockCipher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cipher for MockCipher {
+    fn encrypt(&self, plain_text: &str) -> Result<Option<String>, UnifiedError> {
+        if !self.available.get() {
+            return Err(UnifiedError::from_ais_error(AisError::EncryptionNotReady(
+                None,
+            )));
+        }
+        self.calls.set(self.calls.get() + 1);
+        Ok(Some(Self::seal(plain_text)))
+    }
+
+    fn decrypt(&self, cipher_data: &str) -> Result<Option<String>, UnifiedError> {
+        match self.failures.get() {
+            0 => Ok(Some(hex::encode(Self::seal(cipher_data)))),
+            left => {
+                self.failures.set(left - 1);
+                Err(UnifiedError::from_system_error(SystemError::new_details(
+                    SystemErrorType::ErrorOpeningFile,
+                    "Connection refused",
+                )))
+            }
+        }
+    }
+}
+
+/// Keeps what it's asked to send instead of delivering it.
+#[derive(Default)]
+pub struct RecordingNotifier(pub Mutex<Vec<Email>>);
+
+impl Notifier for RecordingNotifier {
+    fn notify(&self, email: &Email) -> Result<(), UnifiedError> {
+        self.0.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use crate::errors::{AisError, UnifiedError};
+
+/// The canonical timestamp format, UTC with second precision, e.g. 2024-04-29T13:05:09Z.
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// Returns the current time as a canonical timestamp.
+pub fn now() -> String {
+    format(Utc::now())
+}
+
+/// Formats a time as a canonical timestamp.
+pub fn format(time: DateTime<Utc>) -> String {
+    time.format(TIMESTAMP_FORMAT).to_string()
+}
+
+/// Parses a canonical timestamp.
+pub fn parse(timestamp: &str) -> Result<DateTime<Utc>, UnifiedError> {
+    NaiveDateTime::parse_from_str(timestamp.trim(), TIMESTAMP_FORMAT)
+        .map(|time| time.and_utc())
+        .map_err(|e| {
+            UnifiedError::from_ais_error(AisError::new(format!(
+                "Invalid timestamp {:?}: {}",
+                timestamp, e
+            )))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_format_is_stable() {
+        let time = Utc.with_ymd_and_hms(2024, 4, 29, 13, 5, 9).unwrap();
+        assert_eq!(format(time), "2024-04-29T13:05:09Z");
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        let time = Utc.with_ymd_and_hms(2024, 4, 29, 13, 5, 9).unwrap();
+        assert_eq!(parse(&format(time)).unwrap(), time);
+
+        let current = now();
+        assert_eq!(format(parse(&current).unwrap()), current);
+    }
+
+    #[test]
+    fn test_parse_rejects_other_formats() {
+        assert!(parse("2024-04-29 13:05:09").is_err());
+        assert!(parse("garbage").is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    env,
+    io::{self, Read},
+};
+
+use pretty::{dump, halt};
+use shared::{
+    encrypt::{Cipher, DusaCipher},
+    errors::{AisError, UnifiedError},
+};
+
+const USAGE: &str = "Usage: ais_encrypt [--decrypt] < secret";
+
+/// Whether the value read from stdin is encrypted or decrypted.
+#[derive(Debug, PartialEq)]
+enum Mode {
+    Encrypt,
+    Decrypt,
+}
+
+/// Reads the args, the only one is the optional `--decrypt`.
+fn parse_args(args: &[String]) -> Option<Mode> {
+    match args.get(1..).unwrap_or_default() {
+        [] => Some(Mode::Encrypt),
+        [flag] if flag == "--decrypt" => Some(Mode::Decrypt),
+        _ => None,
+    }
+}
+
+/// Encrypts or decrypts the value through the cipher. The newline ending the value is
+/// dropped, so `echo secret | ais_encrypt` encrypts `secret`.
+fn run(mode: &Mode, input: &str, cipher: &dyn Cipher) -> Result<String, UnifiedError> {
+    let value: &str = input.strip_suffix('\n').unwrap_or(input);
+    let value: &str = value.strip_suffix('\r').unwrap_or(value);
+    if value.is_empty() {
+        return Err(UnifiedError::from_ais_error(AisError::new(
+            "Nothing was read from stdin",
+        )));
+    }
+    let nothing_returned = || {
+        UnifiedError::from_ais_error(AisError::EncryptionNotReady(Some(String::from(
+            "dusa returned nothing",
+        ))))
+    };
+
+    match mode {
+        Mode::Encrypt => cipher.encrypt(value)?.ok_or_else(nothing_returned),
+        Mode::Decrypt => {
+            // Dusa hands back the plain text hex encoded
+            let plain_hex: String = cipher.decrypt(value.trim())?.ok_or_else(nothing_returned)?;
+            let plain: Vec<u8> = hex::decode(plain_hex.trim()).map_err(|e| {
+                UnifiedError::from_ais_error(AisError::with_context("decoding the value", e))
+            })?;
+            String::from_utf8(plain).map_err(|e| {
+                UnifiedError::from_ais_error(AisError::with_context("decoding the value", e))
+            })
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mode: Mode = match parse_args(&args) {
+        Some(mode) => mode,
+        None => {
+            halt(USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let mut input: String = String::new();
+    if let Err(e) = io::stdin().read_to_string(&mut input) {
+        dump(&format!("Error reading stdin: {}", e));
+        std::process::exit(1);
+    }
+
+    // Only the value goes to stdout so it can be pasted or piped into the config as is
+    match run(&mode, &input, &DusaCipher) {
+        Ok(output) => println!("{}", output),
+        Err(e) => {
+            dump(&format!("Error running dusa: {}", e));
+            std::process::exit(e.exit_code());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reverses the text like a cipher would scramble it, decrypting hex encodes like dusa.
+    struct MockCipher;
+
+    impl Cipher for MockCipher {
+        fn encrypt(&self, plain_text: &str) -> Result<Option<String>, UnifiedError> {
+            Ok(Some(plain_text.chars().rev().collect()))
+        }
+
+        fn decrypt(&self, cipher_data: &str) -> Result<Option<String>, UnifiedError> {
+            Ok(Some(hex::encode(
+                cipher_data.chars().rev().collect::<String>(),
+            )))
+        }
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |list: &[&str]| list.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(parse_args(&args(&["ais_encrypt"])), Some(Mode::Encrypt));
+        assert_eq!(
+            parse_args(&args(&["ais_encrypt", "--decrypt"])),
+            Some(Mode::Decrypt)
+        );
+        assert_eq!(parse_args(&args(&["ais_encrypt", "secret"])), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let cipher_data: String = run(&Mode::Encrypt, "smtp password\n", &MockCipher).unwrap();
+        assert_eq!(cipher_data, "drowssap ptms");
+
+        let plain: String =
+            run(&Mode::Decrypt, &format!("{}\n", cipher_data), &MockCipher).unwrap();
+        assert_eq!(plain, "smtp password");
+
+        assert!(run(&Mode::Encrypt, "\n", &MockCipher).is_err());
+    }
+}
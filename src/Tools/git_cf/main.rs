@@ -1,7 +1,16 @@
-use std::io::{self, Write};
+use std::{
+    env,
+    io::{self, Write},
+    path::Path,
+};
 
-use pretty::{halt, pass};
-use shared::git_data::{GitAuth, GitCredentials};
+use pretty::{halt, pass, warn};
+use shared::{
+    ais_config::AisConfig,
+    command::SystemRunner,
+    errors::{AisError, UnifiedError},
+    git_data::{CloneScheme, DeployMode, GitAuth, GitCredentials, CREDENTIALS_PATH},
+};
 
 fn prompt_input(prompt: &str) -> String {
     print!("{}", prompt);
@@ -13,7 +22,48 @@ fn prompt_input(prompt: &str) -> String {
 }
 
 fn main() {
-    let mut git_creds = GitCredentials::bootstrap_git_credentials().unwrap();
+    // Bulk provisioning skips the prompts, `ais_credentials --import credentials.json`
+    let args: Vec<String> = env::args().collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--import") {
+        match args.get(index + 1) {
+            Some(source) => match GitCredentials::import_from_json(Path::new(source)) {
+                Ok(creds) => pass(&format!(
+                    "Imported the credentials, the credential file holds {} now",
+                    creds.auths.len()
+                )),
+                Err(e) => halt(&format!("Error while importing: {}", &e.to_string())),
+            },
+            None => halt("Usage: ais_credentials --import <credentials.json>"),
+        }
+        return;
+    }
+
+    // Per customer credentials go in their own file in the credential directory
+    let file_path: String = match prompt_input(
+        "Credential file name (blank for the main credential file): ",
+    )
+    .as_str()
+    {
+        "" => CREDENTIALS_PATH.to_owned(),
+        name => match GitCredentials::named_path(name) {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(e) => {
+                halt(&e.to_string());
+                return;
+            }
+        },
+    };
+    let mut git_creds = GitCredentials::bootstrap_at(&file_path).unwrap();
+    let config = match AisConfig::new() {
+        Ok(d) => d,
+        Err(e) => {
+            halt(&format!(
+                "Error while loading the config: {}",
+                &e.to_string()
+            ));
+            return;
+        }
+    };
 
     let num_instances: usize = prompt_input("Enter the number of GitAuth instances to create: ")
         .parse()
@@ -24,20 +74,75 @@ fn main() {
 
         let user = prompt_input("User: ");
         let repo = prompt_input("Repo: ");
-        let branch = prompt_input("Branch: ");
-        let token = prompt_input("Token: ");
-
-        let auth = GitAuth {
-            user,
-            repo,
-            branch,
-            token,
+        // Keep asking until the token can read the repo, it's only used to clone over https
+        let token = loop {
+            let token = prompt_input("Token: ");
+            if config.clone_scheme != CloneScheme::Https {
+                break token;
+            }
+            let unverified = GitAuth {
+                user: user.clone(),
+                repo: repo.clone(),
+                branch: String::new(),
+                token: token.clone(),
+                deploy: DeployMode::Branch,
+                health_url: None,
+            };
+            match unverified.verify_token(&SystemRunner) {
+                Ok(_) => break token,
+                Err(UnifiedError::AisError(_, AisError::GitCredentialsInvalid(Some(message)))) => {
+                    warn(&message)
+                }
+                Err(e) => {
+                    halt(&format!(
+                        "Error while checking the token: {}",
+                        &e.to_string()
+                    ));
+                    return;
+                }
+            }
+        };
+        let deploy = match prompt_input("Tag pattern (blank to follow the branch): ").as_str() {
+            "" => DeployMode::Branch,
+            pattern => DeployMode::Tag {
+                pattern: pattern.to_owned(),
+            },
+        };
+        let health_url = match prompt_input("Health check url (blank for none): ").as_str() {
+            "" => None,
+            url => Some(url.to_owned()),
+        };
+
+        // Keep asking until the branch is one the remote actually has
+        let auth = loop {
+            let auth = GitAuth {
+                user: user.clone(),
+                repo: repo.clone(),
+                branch: prompt_input("Branch: "),
+                token: token.clone(),
+                deploy: deploy.clone(),
+                health_url: health_url.clone(),
+            };
+
+            match auth.validate_branch(&SystemRunner, &config.clone_scheme) {
+                Ok(_) => break auth,
+                Err(UnifiedError::AisError(_, AisError::GitInvalidRelease(Some(message)))) => {
+                    warn(&message)
+                }
+                Err(e) => {
+                    halt(&format!(
+                        "Error while checking the branch: {}",
+                        &e.to_string()
+                    ));
+                    return;
+                }
+            }
         };
 
         git_creds.add_auth(auth);
     }
 
-    match git_creds.save("/etc/artisan.cf") {
+    match git_creds.save(&file_path) {
         Ok(_) => pass("New multiplexed file created"),
         Err(e) => halt(&format!("Error while creating manifest: {}", &e.to_string())),
     }
@@ -1,60 +1,125 @@
-use pretty::{dump, notice};
+use std::fs;
+
+use pretty::{dump, notice, warn};
 use shared::{
-    errors::{Caller, ErrorInfo, UnifiedError},
-    git_actions,
+    ais_config::AisConfig,
+    errors::{AisError, Caller, ErrorInfo, UnifiedError},
+    filesystem::make_dir_all,
+    git_actions::{self, inspect_clone_destination, CloneDestination},
     git_data::{GitAuth, GitCredentials},
     site_info::SiteInfo,
 };
-use system::{chown_recursive, create_hash, make_dir, truncate, ClonePath, PathType, SystemError};
+use system::{chown_recursive, ClonePath, PathType, SystemError};
 
 // Structs representing GitCredentials and GitAuth omitted for brevity
 
+/// Removes a destination left behind without a usable checkout, so it's cloned fresh.
+/// Returns true if the destination had to be removed.
+fn reset_clone_destination(path: &PathType) -> Result<bool, UnifiedError> {
+    match inspect_clone_destination(path)? {
+        CloneDestination::Missing | CloneDestination::Repository => Ok(false),
+        state @ (CloneDestination::Empty | CloneDestination::Stale) => {
+            warn(&format!("Removing {:?} clone destination: {}", state, path));
+            fs::remove_dir_all(path).map_err(|e| {
+                UnifiedError::from_ais_error(AisError::new(format!(
+                    "Failed to remove {}: {}",
+                    path, e
+                )))
+            })?;
+            Ok(true)
+        }
+    }
+}
+
+/// Creates the directory a site is cloned into, it's an error for it to already exist.
+fn create_clone_destination(path: &PathType) -> Result<(), UnifiedError> {
+    match make_dir_all(path)? {
+        true => Ok(()),
+        false => Err(UnifiedError::from_ais_error(AisError::new(format!(
+            "Error while making dirs, {} already exists",
+            path
+        )))),
+    }
+}
+
 fn create_directories_for_git_auth(git_auth: &GitAuth) -> Result<(), UnifiedError> {
-    let site_folder_string: String = format!("{}-{}", git_auth.user, git_auth.repo,);
-    let site_folder: String = truncate(&create_hash(site_folder_string), 8).to_owned();
-    let ais_progect_path: PathType = PathType::Content(format!("/var/www/current/{}", site_folder));
+    let config = AisConfig::new()?;
+    let ais_progect_path: PathType =
+        PathType::PathBuf(SiteInfo::site_path(git_auth, &config.site_root));
+
+    // A checkout under an older folder name is moved instead of cloned again
+    if let Some(old_folder) = SiteInfo::relocate_site_folder(git_auth, &config.site_root)? {
+        notice(&format!(
+            "Moved {} to {}",
+            old_folder.display(),
+            ais_progect_path
+        ));
+    }
+
+    // A previous failed run can leave the directory without a checkout in it
+    reset_clone_destination(&ais_progect_path)?;
 
-    match SiteInfo::new(&git_auth) {
+    match SiteInfo::new(git_auth, &config.site_root) {
         Ok(_) => (),
         Err(e) => match e {
             UnifiedError::SystemError(_, data) => match data.kind {
                 system::errors::SystemErrorType::ErrorCreatingDir => {
                     // Create directories recursively if they don't exist
-                    match make_dir(ais_progect_path.clone_path()) {
-                        Ok(b) => match b {
-                            true => {
-                                // Once the directory is created we clone the data into it
-                                let action = git_actions::GitAction::Clone {
-                                    repo_url: format!(
-                                        "git@github.com:{}/{}.git",
-                                        git_auth.user, git_auth.repo
-                                    ),
-                                    destination: ais_progect_path.clone_path(),
-                                };
-                                match action.execute() {
-                                    Ok(_) => {
-                                        git_actions::GitAction::SetSafe(ais_progect_path.clone_path()).execute()?;
-                                        chown_recursive(ais_progect_path.clone(), Some(33), Some(33))?
-                                    },
-                                    Err(e) => { 
-                                        // Repacking error
-                                        let err: UnifiedError = match e {
-                                            UnifiedError::LoggerError(_, e) => UnifiedError::LoggerError(ErrorInfo::new(Caller::Function(true, Some("Logger Error".to_string()))), e),
-                                            UnifiedError::SystemError(_, e) => UnifiedError::SystemError(ErrorInfo::new(Caller::Function(true, Some("System Error".to_string()))), e),
-                                            UnifiedError::RecsError(_, e) => UnifiedError::RecsError(ErrorInfo::new(Caller::Function(true, Some("Recs Error".to_string()))), e),
-                                            UnifiedError::GitError(_, e) => UnifiedError::GitError(ErrorInfo::new(Caller::Function(true, Some("Git action execute".to_string()))), e),
-                                            UnifiedError::AisError(_, e) => UnifiedError::AisError(ErrorInfo::new(Caller::Function(true, Some("AIS error".to_string()))), e),
-                                        };
-                                        return Err(err)
-                                    },
-                                }
-                            }
-                            false => {
-                                dump("error while making dirs");
-                                panic!()
-                            }
-                        },
-                        Err(e) => return Err(UnifiedError::from_system_error(e)),
+                    create_clone_destination(&ais_progect_path)?;
+                    // Once the directory is created we clone the data into it
+                    config.clone_scheme.verify()?;
+                    let action = git_actions::GitAction::Clone {
+                        repo_url: git_auth.repo_url(&config.clone_scheme),
+                        destination: ais_progect_path.clone_path(),
+                    };
+                    match action.execute() {
+                        Ok(_) => {
+                            SiteInfo::write_site_marker(ais_progect_path.as_ref(), git_auth)?;
+                            git_actions::GitAction::SetSafe(ais_progect_path.clone_path())
+                                .execute()?;
+                            chown_recursive(ais_progect_path.clone(), Some(33), Some(33))?
+                        }
+                        Err(e) => {
+                            // Repacking error
+                            let err: UnifiedError = match e {
+                                UnifiedError::LoggerError(_, e) => UnifiedError::LoggerError(
+                                    ErrorInfo::new(Caller::Function(
+                                        true,
+                                        Some("Logger Error".to_string()),
+                                    )),
+                                    e,
+                                ),
+                                UnifiedError::SystemError(_, e) => UnifiedError::SystemError(
+                                    ErrorInfo::new(Caller::Function(
+                                        true,
+                                        Some("System Error".to_string()),
+                                    )),
+                                    e,
+                                ),
+                                UnifiedError::RecsError(_, e) => UnifiedError::RecsError(
+                                    ErrorInfo::new(Caller::Function(
+                                        true,
+                                        Some("Recs Error".to_string()),
+                                    )),
+                                    e,
+                                ),
+                                UnifiedError::GitError(_, e) => UnifiedError::GitError(
+                                    ErrorInfo::new(Caller::Function(
+                                        true,
+                                        Some("Git action execute".to_string()),
+                                    )),
+                                    e,
+                                ),
+                                UnifiedError::AisError(_, e) => UnifiedError::AisError(
+                                    ErrorInfo::new(Caller::Function(
+                                        true,
+                                        Some("AIS error".to_string()),
+                                    )),
+                                    e,
+                                ),
+                            };
+                            return Err(err);
+                        }
                     }
                 }
                 e => {
@@ -72,16 +137,8 @@ fn create_directories_for_git_auth(git_auth: &GitAuth) -> Result<(), UnifiedErro
     // let git_progect_path: PathType = site_data.application_folder;
 
     // Create directories recursively if they don't exist
-    match make_dir(ais_progect_path) {
-        Ok(b) => match b {
-            true => return Ok(()),
-            false => {
-                dump("error while making dirs");
-                panic!()
-            }
-        },
-        Err(e) => return Err(UnifiedError::from_system_error(e)),
-    }
+    make_dir_all(&ais_progect_path)?;
+    Ok(())
 }
 
 fn create_directories_for_git_credentials(
@@ -109,3 +166,65 @@ fn main() {
         Err(err) => dump(&format!("Error creating directories: {:?}", err)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_reset_empty_destination() {
+        let path = env::temp_dir().join("ais_clone_empty");
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        let destination = PathType::PathBuf(path.clone());
+
+        assert_eq!(
+            inspect_clone_destination(&destination).unwrap(),
+            CloneDestination::Empty
+        );
+        assert!(reset_clone_destination(&destination).unwrap());
+        assert_eq!(
+            inspect_clone_destination(&destination).unwrap(),
+            CloneDestination::Missing
+        );
+    }
+
+    #[test]
+    fn test_reset_stale_partial_clone() {
+        let path = env::temp_dir().join("ais_clone_stale");
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(path.join(".git")).unwrap();
+        fs::write(path.join("index.html"), b"half written").unwrap();
+        let destination = PathType::PathBuf(path.clone());
+
+        assert_eq!(
+            inspect_clone_destination(&destination).unwrap(),
+            CloneDestination::Stale
+        );
+        assert!(reset_clone_destination(&destination).unwrap());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_create_clone_destination_failure_is_err() {
+        let blocker = env::temp_dir().join("ais_clone_blocker");
+        let _ = fs::remove_dir_all(&blocker);
+        let _ = fs::remove_file(&blocker);
+        fs::write(&blocker, b"not a directory").unwrap();
+
+        let destination = PathType::PathBuf(blocker.join("site"));
+        assert!(create_clone_destination(&destination).is_err());
+
+        // A destination that already exists is reported instead of panicking
+        let existing = PathType::PathBuf(env::temp_dir());
+        assert!(create_clone_destination(&existing).is_err());
+    }
+
+    #[test]
+    fn test_reset_missing_destination() {
+        let destination = PathType::PathBuf(env::temp_dir().join("ais_clone_missing"));
+        let _ = fs::remove_dir_all(&destination);
+        assert!(!reset_clone_destination(&destination).unwrap());
+    }
+}
@@ -0,0 +1,101 @@
+use std::{env, fs::OpenOptions, io::Write, os::unix::fs::OpenOptionsExt};
+
+use pretty::{dump, halt, pass, warn};
+use shared::{
+    errors::{AisError, UnifiedError},
+    git_data::GitCredentials,
+};
+
+const USAGE: &str = "Usage: ais_export <file> [--include-tokens]";
+
+/// What to export and where, read from the command line.
+#[derive(Debug, PartialEq)]
+struct ExportArgs {
+    file: String,
+    include_tokens: bool,
+}
+
+/// Reads the args, the output file is the only positional one.
+fn parse_args(args: &[String]) -> Option<ExportArgs> {
+    let include_tokens: bool = args.iter().any(|arg| arg == "--include-tokens");
+    let mut files = args.iter().skip(1).filter(|arg| !arg.starts_with("--"));
+    match (files.next(), files.next()) {
+        (Some(file), None) => Some(ExportArgs {
+            file: file.clone(),
+            include_tokens,
+        }),
+        _ => None,
+    }
+}
+
+/// Writes the export readable by root only, an existing file isn't overwritten.
+fn write_export(file: &str, data: &str) -> Result<(), UnifiedError> {
+    let mut output = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(file)
+        .map_err(|e| {
+            UnifiedError::from_ais_error(AisError::with_context("creating the export", e))
+        })?;
+    writeln!(output, "{}", data)
+        .map_err(|e| UnifiedError::from_ais_error(AisError::with_context("writing the export", e)))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let export_args: ExportArgs = match parse_args(&args) {
+        Some(d) => d,
+        None => {
+            halt(USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if export_args.include_tokens {
+        warn("!!! The export includes every access token in plain text !!!");
+        warn(&format!(
+            "Anyone who can read {} can push to the repos, delete it once it's imported",
+            export_args.file
+        ));
+    }
+
+    let result = GitCredentials::new()
+        .and_then(|git_creds| git_creds.export(export_args.include_tokens))
+        .and_then(|data| write_export(&export_args.file, &data));
+
+    match result {
+        Ok(_) => pass(&format!("Credentials exported to {}", export_args.file)),
+        Err(e) => {
+            dump(&format!("Error exporting the credentials: {}", e));
+            std::process::exit(e.exit_code());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        let args = |list: &[&str]| list.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            parse_args(&args(&["ais_export", "backup.json"])),
+            Some(ExportArgs {
+                file: "backup.json".to_owned(),
+                include_tokens: false,
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["ais_export", "--include-tokens", "backup.json"])),
+            Some(ExportArgs {
+                file: "backup.json".to_owned(),
+                include_tokens: true,
+            })
+        );
+        assert_eq!(parse_args(&args(&["ais_export"])), None);
+        assert_eq!(parse_args(&args(&["ais_export", "a.json", "b.json"])), None);
+    }
+}
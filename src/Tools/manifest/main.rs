@@ -1,33 +1,155 @@
-use pretty::pass;
-use shared::{ais_data::AisInfo, errors::UnifiedError};
-use system::{create_hash, truncate};
+use std::env;
 
-fn main() -> Result<(), UnifiedError> {
+use pretty::{dump, pass};
+use shared::{
+    ais_data::{AisInfo, FieldChange},
+    errors::{AisError, UnifiedError},
+};
+
+/// Creates the manifest, returning the manifest it replaced alongside the new one.
+/// The existing label is kept unless a new one is given.
+fn create_manifest(label: Option<&str>) -> Result<(AisInfo, AisInfo), UnifiedError> {
     // Create an instance of AisInfo
     let mut ais_info: AisInfo = AisInfo::new()?;
+    let previous: AisInfo = ais_info.clone();
 
-    ais_info.machine_id = Some(
-        truncate(
-            &create_hash(format!(
-                "{}{}",
-                &ais_info
-                    .clone()
-                    .machine_ip
-                    .unwrap_or(String::from("Uninitialized")),
-                &ais_info
-                    .clone()
-                    .machine_id
-                    .unwrap_or(String::from("Uninitialized"))
-            )),
-            16,
-        )
-        .to_owned(),
-    );
+    if let Some(label) = label {
+        ais_info.label = Some(AisInfo::validate_label(label)?);
+    }
+    ais_info.machine_id = Some(ais_info.machine_fingerprint());
     ais_info.system_version = AisInfo::current_version();
     // Generate the manifest file
     ais_info.create_manifest()?;
 
-    pass("Manifest file created successfully");
+    Ok((previous, ais_info))
+}
+
+/// Renders what was written, as JSON for automation or as a line for operators.
+fn render_report(
+    previous: &AisInfo,
+    ais_info: &AisInfo,
+    json: bool,
+) -> Result<String, UnifiedError> {
+    let previous_machine_id: &str = previous.machine_id.as_deref().unwrap_or("Uninitialized");
+    let machine_id: &str = ais_info.machine_id.as_deref().unwrap_or("Uninitialized");
+    let changes: Vec<FieldChange> = previous.diff(ais_info);
+
+    match json {
+        true => serde_json::to_string_pretty(&serde_json::json!({
+            "machine_id_before": previous_machine_id,
+            "machine_id_after": machine_id,
+            "changes": changes,
+            "manifest": ais_info,
+        }))
+        .map_err(|e| UnifiedError::from_ais_error(AisError::new(e.to_string()))),
+        false => {
+            let mut report: String =
+                format!("Machine id: {} -> {}", previous_machine_id, machine_id);
+            for change in changes {
+                report.push_str(&format!("\n  {}", change));
+            }
+            Ok(report)
+        }
+    }
+}
+
+/// Renders an error for automation.
+fn render_error(error: &UnifiedError) -> String {
+    serde_json::json!({
+        "error": error.to_string(),
+        "exit_code": error.exit_code(),
+    })
+    .to_string()
+}
+
+/// Returns the value given with --label, if any.
+fn label_arg(args: &[String]) -> Option<&str> {
+    args.iter()
+        .position(|arg| arg == "--label")
+        .and_then(|index| args.get(index + 1))
+        .map(String::as_str)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let json: bool = args.iter().any(|arg| arg == "--json");
+
+    let result = create_manifest(label_arg(&args))
+        .and_then(|(previous, ais_info)| render_report(&previous, &ais_info, json));
+
+    match (result, json) {
+        (Ok(report), true) => println!("{}", report),
+        (Ok(report), false) => {
+            println!("{}", report);
+            pass("Manifest file created successfully");
+        }
+        (Err(e), true) => {
+            println!("{}", render_error(&e));
+            std::process::exit(e.exit_code());
+        }
+        (Err(e), false) => {
+            dump(&format!("Error creating the manifest: {}", e));
+            std::process::exit(e.exit_code());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::health::Health;
+
+    fn test_info() -> AisInfo {
+        AisInfo {
+            pages_id: None,
+            client_id: None,
+            machine_id: Some("a1b2c3d4e5f60718".to_string()),
+            machine_mac: Some("00:11:22:33:44:55".to_string()),
+            machine_ip: Some("192.168.1.100".to_string()),
+            label: None,
+            interfaces: Vec::new(),
+            ssh_events: 0,
+            health: Health::new(),
+            system_version: AisInfo::current_version(),
+        }
+    }
+
+    #[test]
+    fn test_render_report_shows_the_derivation() {
+        let mut previous = test_info();
+        previous.machine_id = Some("0000000000000000".to_string());
+
+        let report = render_report(&previous, &test_info(), false).unwrap();
+        assert_eq!(
+            report,
+            "Machine id: 0000000000000000 -> a1b2c3d4e5f60718\n  machine_id: 0000000000000000 -> a1b2c3d4e5f60718"
+        );
+
+        previous.machine_id = None;
+        let report = render_report(&previous, &test_info(), true).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&report).unwrap();
+        assert_eq!(value["machine_id_before"], "Uninitialized");
+        assert_eq!(value["machine_id_after"], "a1b2c3d4e5f60718");
+        assert_eq!(value["changes"][0]["field"], "machine_id");
+        assert_eq!(value["manifest"]["machine_mac"], "00:11:22:33:44:55");
+    }
+
+    #[test]
+    fn test_label_arg() {
+        let args = |list: &[&str]| list.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            label_arg(&args(&["ais_manifest", "--label", "cust-acme-web01"])),
+            Some("cust-acme-web01")
+        );
+        assert_eq!(label_arg(&args(&["ais_manifest", "--label"])), None);
+        assert_eq!(label_arg(&args(&["ais_manifest", "--json"])), None);
+    }
 
-    Ok(())
+    #[test]
+    fn test_render_error() {
+        let error = UnifiedError::from_ais_error(AisError::InvalidManifest(None));
+        let value: serde_json::Value = serde_json::from_str(&render_error(&error)).unwrap();
+        assert_eq!(value["exit_code"], 22);
+    }
 }
@@ -1,19 +1,15 @@
 use lsb_release::LsbRelease;
 use pretty::output;
-use shared::ais_data;
-use systemstat::{Platform, System};
+use shared::{ais_config::AisConfig, ais_data, resources::ResourceUsage};
 
 fn main() {
-    let sys: System = System::new();
-    let ais_info: ais_data::AisInfo = ais_data::AisInfo::new().unwrap();
+    let ais_info: ais_data::AisInfo = ais_data::AisInfo::new_cached().unwrap();
+    let usage: ResourceUsage =
+        ResourceUsage::gather(&AisConfig::new().unwrap_or_default().site_root);
 
-    let system_mem: String = match sys.memory() {
-        Ok(mem) => {
-            let used_memory: u64 = mem.total.as_u64() - mem.free.as_u64();
-            let percentage_used: f64 = (used_memory as f64 / mem.total.as_u64() as f64) * 100.0;
-            format!("{}", percentage_used)
-        }
-        Err(x) => format!("\nMemory: error: {}", x),
+    let system_mem: String = match usage.memory_percent {
+        Some(percentage_used) => format!("{}", percentage_used),
+        None => String::from("\nMemory: error"),
     };
 
     let lsb_failsafe: LsbRelease = LsbRelease {
@@ -29,14 +25,7 @@ fn main() {
         .unwrap_or(String::from("error parsing manifest"));
     let system_version = lsb_release::info().unwrap_or(lsb_failsafe);
     let system_hostname = gethostname::gethostname();
-    let (system_load_1, system_load_5, system_load_15) = match sys.load_average() {
-        Ok(l) => (l.one, l.five, l.fifteen),
-
-        Err(_) => {
-            let val: f32 = 0.0;
-            (val, val, val)
-        }
-    };
+    let (system_load_1, system_load_5, system_load_15) = usage.load.unwrap_or((0.0, 0.0, 0.0));
 
     let welcome_text = format!(
         r#"
@@ -51,8 +40,8 @@ fn main() {
  
 Your machine at a glance:
 
-Os Version   : {}
-AIS Version  : {}
+Os Version   : {} - {}
+AIS Version  : {}_{}
 AIS id       : {}
 Hostname     : {:?}
 System Load  : {:.2}, {:.2}, {:.2}
@@ -65,8 +54,10 @@ to contact me at dwhitfield@artisanhosting.net or shoot me a text at 414-578-098
 supporting me and Artisan Hosting.
 
 "#,
-        format!("{} - {}", system_version.version, system_version.code_name),
-        format!("{}_{}", ais_version.version_number.to_string(), ais_version.version_code),
+        system_version.version,
+        system_version.code_name,
+        ais_version.version_number,
+        ais_version.version_code,
         ais_identyfi.trim_end(),
         system_hostname,
         system_load_1,
@@ -75,5 +66,5 @@ supporting me and Artisan Hosting.
         system_mem
     );
 
-    output("BLUE", &format!("{}", welcome_text));
+    output("BLUE", &welcome_text.to_string());
 }